@@ -0,0 +1,37 @@
+//! Parse arbitrary headers in every syntax and edit them, checking that directives point at the
+//! lines they were parsed from and that edits never touch anything else.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rustc_less_ignore_debug::run::directives::{self, Syntax};
+use rustc_less_ignore_debug::run::{remove_directive, replace_directive};
+
+fuzz_target!(|src: &str| {
+    for syntax in [Syntax::RUST, Syntax::MAKEFILE, Syntax::ASSEMBLY] {
+        for directive in directives::parse_with(src, syntax) {
+            let line = &src[directive.span.clone()];
+            assert!(!line.contains('\n'));
+            assert!(line.contains(directive.name));
+            assert!(line.starts_with(directive.prefix));
+            assert!(directive.prefix.trim_start().starts_with(directive.marker));
+        }
+    }
+
+    for edit in [remove_directive(src), replace_directive(src)] {
+        let edited: Vec<&str> = edit.src.split_inclusive('\n').collect();
+        let mut edited = edited.into_iter();
+        for (idx, line) in src.split_inclusive('\n').enumerate() {
+            match edit.changes.iter().find(|c| c.line == idx + 1) {
+                Some(change) => {
+                    assert_eq!(change.original, line.trim_end_matches(['\r', '\n']));
+                    for _ in &change.replacement {
+                        edited.next().unwrap();
+                    }
+                }
+                None => assert_eq!(edited.next(), Some(line)),
+            }
+        }
+        assert_eq!(edited.next(), None);
+    }
+});
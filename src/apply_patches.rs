@@ -0,0 +1,434 @@
+//! Applying patches written by `run --emit patch` (or `combined-patch`) to another checkout of the
+//! `rustc` repo, test by test: a test whose hunks don't all match is left alone and reported,
+//! the others are applied.
+
+use std::path::{Path, PathBuf};
+
+use console::style;
+use miette::{bail, Context, IntoDiagnostic, Result};
+use tracing::*;
+
+use crate::exit_status::ExitStatus;
+
+/// Changes to a single file in a patch.
+#[derive(Debug)]
+struct FileDiff {
+    /// Repo-relative path.
+    path: String,
+    old_exists: bool,
+    new_exists: bool,
+    hunks: Vec<Hunk>,
+}
+
+#[derive(Debug)]
+struct Hunk {
+    /// 1-based line the hunk starts at in the old file, or the line after which it inserts if
+    /// `old` is empty.
+    old_start: usize,
+    /// Lines (with their line endings) which must be present in the file, and what they become.
+    old: Vec<String>,
+    new: Vec<String>,
+}
+
+/// What became of the changes to a single test.
+enum Outcome {
+    Applied,
+    AlreadyApplied,
+    Conflict(String),
+}
+
+/// Apply the patches in `patches` (files, or directories of `*.patch` files applied in name
+/// order) to `rustc_repo_path`. With `check`, only report what would happen.
+pub fn apply_patches(
+    rustc_repo_path: &Path,
+    patches: &[PathBuf],
+    check: bool,
+) -> Result<ExitStatus> {
+    let mut diffs = Vec::new();
+    for patch_path in patch_files(patches)? {
+        let patch = std::fs::read_to_string(&patch_path)
+            .into_diagnostic()
+            .wrap_err(format!("failed to read `{}`", patch_path.display()))?;
+        diffs
+            .extend(parse(&patch).wrap_err(format!("failed to parse `{}`", patch_path.display()))?);
+    }
+
+    let mut applied = 0;
+    let mut already_applied = 0;
+    let mut conflicts = 0;
+    for (test, diffs) in group_by_test(diffs) {
+        let outcome = match edits(rustc_repo_path, &diffs, false) {
+            Ok(edits) => {
+                if !check {
+                    write(rustc_repo_path, edits)?;
+                }
+                Outcome::Applied
+            }
+            Err(conflict) => match edits(rustc_repo_path, &diffs, true) {
+                Ok(_) => Outcome::AlreadyApplied,
+                Err(_) => Outcome::Conflict(conflict),
+            },
+        };
+        match outcome {
+            Outcome::Applied => {
+                applied += 1;
+                println!("{}  {test}", style("applied ").green());
+            }
+            Outcome::AlreadyApplied => {
+                already_applied += 1;
+                println!("{}  {test} (already applied)", style("skipped ").dim());
+            }
+            Outcome::Conflict(reason) => {
+                conflicts += 1;
+                println!("{}  {test}: {reason}", style("conflict").red());
+            }
+        }
+    }
+
+    info!(
+        "{} {applied} test(s), {already_applied} already applied, {conflicts} conflict(s)",
+        if check { "would apply" } else { "applied" }
+    );
+    Ok(if conflicts > 0 {
+        ExitStatus::NeedsManualReview
+    } else {
+        ExitStatus::Success
+    })
+}
+
+/// `paths`, with directories replaced by the `*.patch` files in them, sorted by name.
+fn patch_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        let mut in_dir: Vec<PathBuf> = std::fs::read_dir(path)
+            .into_diagnostic()
+            .wrap_err(format!("failed to read `{}`", path.display()))?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "patch"))
+            .collect();
+        in_dir.sort();
+        files.extend(in_dir);
+    }
+    Ok(files)
+}
+
+/// The file diffs of a (possibly multi-file) patch in `git diff` format. Anything outside of the
+/// diffs (mail headers, commit message, signature) is skipped.
+fn parse(patch: &str) -> Result<Vec<FileDiff>> {
+    let mut diffs: Vec<FileDiff> = Vec::new();
+    // Lines left in the old and new side of the current hunk.
+    let mut remaining = (0, 0);
+    // Whether the previous hunk line was in the old and new side, for "\ No newline".
+    let mut last = (false, false);
+
+    for line in patch.split_inclusive('\n') {
+        if remaining != (0, 0) || line.starts_with('\\') {
+            let Some(hunk) = diffs.last_mut().and_then(|d| d.hunks.last_mut()) else {
+                bail!("hunk line outside of a hunk: {line:?}");
+            };
+            if line.starts_with('\\') {
+                if last.0 {
+                    strip_newline(hunk.old.last_mut());
+                }
+                if last.1 {
+                    strip_newline(hunk.new.last_mut());
+                }
+                continue;
+            }
+            let content = line.get(1..).unwrap_or("\n").to_string();
+            last = match line.as_bytes()[0] {
+                b' ' | b'\n' => (true, true),
+                b'-' => (true, false),
+                b'+' => (false, true),
+                _ => bail!("unexpected line in hunk: {line:?}"),
+            };
+            if (last.0 && remaining.0 == 0) || (last.1 && remaining.1 == 0) {
+                bail!("hunk is longer than its header says: {line:?}");
+            }
+            if last.0 {
+                hunk.old.push(content.clone());
+                remaining.0 -= 1;
+            }
+            if last.1 {
+                hunk.new.push(content);
+                remaining.1 -= 1;
+            }
+            continue;
+        }
+
+        let line = line.trim_end_matches('\n');
+        if line.starts_with("diff --git ") {
+            diffs.push(FileDiff {
+                path: String::new(),
+                old_exists: true,
+                new_exists: true,
+                hunks: Vec::new(),
+            });
+        } else if let Some(diff) = diffs.last_mut() {
+            if let Some(old) = line.strip_prefix("--- ") {
+                diff.old_exists = old != "/dev/null";
+                if let Some(path) = old.strip_prefix("a/") {
+                    diff.path = path.to_string();
+                }
+            } else if let Some(new) = line.strip_prefix("+++ ") {
+                diff.new_exists = new != "/dev/null";
+                if let Some(path) = new.strip_prefix("b/") {
+                    diff.path = path.to_string();
+                }
+            } else if line.starts_with("@@ ") {
+                let (old_start, old_len, new_len) = parse_hunk_header(line)
+                    .ok_or_else(|| miette::miette!("malformed hunk header: {line:?}"))?;
+                diff.hunks.push(Hunk {
+                    old_start,
+                    old: Vec::new(),
+                    new: Vec::new(),
+                });
+                remaining = (old_len, new_len);
+            }
+        }
+    }
+    if remaining != (0, 0) {
+        bail!("patch ends in the middle of a hunk");
+    }
+    if let Some(diff) = diffs.iter().find(|d| d.path.is_empty()) {
+        bail!("file diff without a path: {diff:?}");
+    }
+    Ok(diffs)
+}
+
+fn strip_newline(line: Option<&mut String>) {
+    if let Some(line) = line {
+        if line.ends_with('\n') {
+            line.pop();
+        }
+    }
+}
+
+/// `(old start, old length, new length)` of `@@ -1,3 +1,2 @@`.
+fn parse_hunk_header(line: &str) -> Option<(usize, usize, usize)> {
+    let mut ranges = line.strip_prefix("@@ ")?.split(' ');
+    let range = |r: &str| -> Option<(usize, usize)> {
+        match r.split_once(',') {
+            Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+            None => Some((r.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_len) = range(ranges.next()?.strip_prefix('-')?)?;
+    let (_, new_len) = range(ranges.next()?.strip_prefix('+')?)?;
+    Some((old_start, old_len, new_len))
+}
+
+/// Split the file diffs into tests: a test file followed by diffs of its snapshots, which are
+/// named `<test stem>.*` in the same directory.
+fn group_by_test(diffs: Vec<FileDiff>) -> Vec<(String, Vec<FileDiff>)> {
+    let mut tests: Vec<(String, Vec<FileDiff>)> = Vec::new();
+    for diff in diffs {
+        match tests.last_mut() {
+            Some((test, group)) if is_snapshot_of(test, &diff.path) => group.push(diff),
+            _ => tests.push((diff.path.clone(), vec![diff])),
+        }
+    }
+    tests
+}
+
+fn is_snapshot_of(test: &str, path: &str) -> bool {
+    let (test_dir, test_name) = test.rsplit_once('/').unwrap_or(("", test));
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let stem = test_name.strip_suffix(".rs").unwrap_or(test_name);
+    dir == test_dir && !name.ends_with(".rs") && name.starts_with(&format!("{stem}."))
+}
+
+/// The new contents (`None` for deletion) of every file touched by `diffs`, or why they don't
+/// apply. With `reverse`, checks whether the diffs have been applied already instead.
+fn edits(
+    rustc_repo_path: &Path,
+    diffs: &[FileDiff],
+    reverse: bool,
+) -> Result<Vec<(PathBuf, Option<String>)>, String> {
+    let mut edits = Vec::new();
+    for diff in diffs {
+        let path = rustc_repo_path.join(&diff.path);
+        let (old_exists, new_exists) = if reverse {
+            (diff.new_exists, diff.old_exists)
+        } else {
+            (diff.old_exists, diff.new_exists)
+        };
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => Some(contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(format!("failed to read `{}`: {e}", diff.path)),
+        };
+        let contents = match (contents, old_exists) {
+            (Some(contents), true) => contents,
+            (None, false) => String::new(),
+            (Some(_), false) => return Err(format!("`{}` already exists", diff.path)),
+            (None, true) => return Err(format!("`{}` does not exist", diff.path)),
+        };
+
+        let mut lines: Vec<String> = contents.split_inclusive('\n').map(String::from).collect();
+        let mut offset: isize = 0;
+        let mut min_start = 0;
+        for (idx, hunk) in diff.hunks.iter().enumerate() {
+            let (old, new) = if reverse {
+                (&hunk.new, &hunk.old)
+            } else {
+                (&hunk.old, &hunk.new)
+            };
+            let expected = if hunk.old.is_empty() {
+                hunk.old_start
+            } else {
+                hunk.old_start.saturating_sub(1)
+            };
+            let expected = expected.saturating_add_signed(offset);
+            let Some(start) = find_lines(&lines, old, expected, min_start) else {
+                return Err(format!(
+                    "hunk {} of `{}` does not match (expected around line {})",
+                    idx + 1,
+                    diff.path,
+                    expected + 1
+                ));
+            };
+            lines.splice(start..start + old.len(), new.iter().cloned());
+            offset += start as isize - expected as isize + new.len() as isize - old.len() as isize;
+            min_start = start + new.len();
+        }
+
+        let contents = lines.concat();
+        if new_exists {
+            edits.push((path, Some(contents)));
+        } else if contents.is_empty() {
+            edits.push((path, None));
+        } else {
+            return Err(format!(
+                "`{}` should be deleted but has unexpected contents",
+                diff.path
+            ));
+        }
+    }
+    Ok(edits)
+}
+
+/// Start of the occurrence of `needle` in `lines` closest to `expected`, at or after `min_start`.
+fn find_lines(
+    lines: &[String],
+    needle: &[String],
+    expected: usize,
+    min_start: usize,
+) -> Option<usize> {
+    let last = lines.len().checked_sub(needle.len())?;
+    (min_start..=last)
+        .filter(|&start| lines[start..start + needle.len()] == *needle)
+        .min_by_key(|&start| start.abs_diff(expected))
+}
+
+fn write(rustc_repo_path: &Path, edits: Vec<(PathBuf, Option<String>)>) -> Result<()> {
+    for (path, contents) in edits {
+        match contents {
+            Some(contents) => std::fs::write(&path, contents),
+            None => std::fs::remove_file(&path),
+        }
+        .into_diagnostic()
+        .wrap_err(format!(
+            "failed to write `{}` in `{}`",
+            path.display(),
+            rustc_repo_path.display()
+        ))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run::tests::Repo;
+
+    const PATCH: &str = "\
+From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001
+Subject: [PATCH] Remove `ignore-debug` from tests
+
+diff --git a/tests/ui/a.rs b/tests/ui/a.rs
+--- a/tests/ui/a.rs
++++ b/tests/ui/a.rs
+@@ -1,2 +1,1 @@
+-//@ ignore-debug
+ //@ check-pass
+diff --git a/tests/ui/a.stderr b/tests/ui/a.stderr
+deleted file mode 100644
+--- a/tests/ui/a.stderr
++++ /dev/null
+@@ -1 +0,0 @@
+-old
+diff --git a/tests/ui/b.rs b/tests/ui/b.rs
+--- a/tests/ui/b.rs
++++ b/tests/ui/b.rs
+@@ -1,2 +1,1 @@
+-//@ ignore-debug
+ //@ check-pass
+diff --git a/tests/ui/c.rs b/tests/ui/c.rs
+--- a/tests/ui/c.rs
++++ b/tests/ui/c.rs
+@@ -1,2 +1,1 @@
+-//@ ignore-debug
+ //@ check-pass
+-- 
+2.47.0
+";
+
+    #[test]
+    fn groups_snapshots_with_their_test() {
+        let tests = group_by_test(parse(PATCH).unwrap());
+        let paths: Vec<Vec<&str>> = tests
+            .iter()
+            .map(|(_, diffs)| diffs.iter().map(|d| d.path.as_str()).collect())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                vec!["tests/ui/a.rs", "tests/ui/a.stderr"],
+                vec!["tests/ui/b.rs"],
+                vec!["tests/ui/c.rs"],
+            ]
+        );
+        assert!(!tests[0].1[1].new_exists);
+
+        assert!(parse("diff --git a/x b/x\n--- a/x\n+++ b/x\n@@ -1,2 +1,1 @@\n-a\n").is_err());
+    }
+
+    #[test]
+    fn applies_the_tests_whose_hunks_all_match() {
+        let repo = Repo::new("apply-patches");
+        let read = |rel_path: &str| std::fs::read_to_string(repo.0.join(rel_path)).ok();
+        // Moved down a line since the patch was made.
+        repo.add(
+            "tests/ui/a.rs",
+            "//@ edition: 2021\n//@ ignore-debug\n//@ check-pass\n",
+        );
+        repo.add("tests/ui/a.stderr", "old\n");
+        // Already applied.
+        repo.add("tests/ui/b.rs", "//@ check-pass\n");
+        // Changed since in a way the patch doesn't account for.
+        repo.add("tests/ui/c.rs", "//@ ignore-debug\n//@ run-pass\n");
+        let patch_path = repo.add("0001-remove.patch", PATCH);
+
+        let status = apply_patches(&repo.0, std::slice::from_ref(&repo.0), true).unwrap();
+        assert_eq!(status, ExitStatus::NeedsManualReview);
+        assert_eq!(read("tests/ui/a.stderr").as_deref(), Some("old\n"));
+
+        let status = apply_patches(&repo.0, &[patch_path], false).unwrap();
+        assert_eq!(status, ExitStatus::NeedsManualReview);
+        assert_eq!(
+            read("tests/ui/a.rs").as_deref(),
+            Some("//@ edition: 2021\n//@ check-pass\n")
+        );
+        assert_eq!(read("tests/ui/a.stderr"), None);
+        assert_eq!(read("tests/ui/b.rs").as_deref(), Some("//@ check-pass\n"));
+        assert_eq!(
+            read("tests/ui/c.rs").as_deref(),
+            Some("//@ ignore-debug\n//@ run-pass\n")
+        );
+    }
+}
@@ -1,12 +1,30 @@
 use clap::{Parser, Subcommand};
 
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
+use std::time::Duration;
+
+use rustc_less_ignore_debug::config::LogFormat;
+use rustc_less_ignore_debug::run::rules::Ruleset;
+use rustc_less_ignore_debug::run::Emit;
 
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
 pub(crate) struct Cli {
     #[command(subcommand)]
     pub(crate) command: Cmd,
+    /// Format of the log on stderr: `json` prints one JSON object per line, with the fields of the
+    /// enclosing spans (e.g. the path of the test being processed and the rule being tried), for
+    /// log aggregation systems.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub(crate) log_format: LogFormat,
+}
+
+impl Cli {
+    /// Whether `run --ci` was given.
+    pub(crate) fn ci(&self) -> bool {
+        matches!(self.command, Cmd::Run { ci: true, .. })
+    }
 }
 
 #[derive(Debug, PartialEq, Subcommand)]
@@ -17,8 +35,285 @@ pub(crate) enum Cmd {
     Run {
         /// Path to the `rustc` repo.
         rustc_repo_path: PathBuf,
-        /// Path to generate the run report. If not specified, will default to `run_summary.md`
-        /// under the same directory as the executable.
+        /// Path to write the run report to. If not specified, will default to a timestamped
+        /// `run_summary-<timestamp>.md` under the current working directory. A copy is always
+        /// written to the run directory under `runs/` next to the executable.
         report_path: Option<PathBuf>,
+        /// Emit GitHub Actions `::warning` workflow commands for tests which still carry the
+        /// directive, so that they show up inline on the PR diff when run in CI.
+        #[arg(long)]
+        github_annotations: bool,
+        /// Write JSON-lines progress events (files scanned, current test, percent complete, ETA)
+        /// to this already-open file descriptor, e.g. `--progress-fd 3` with `3>progress.jsonl`.
+        #[arg(long, value_name = "FD")]
+        progress_fd: Option<i32>,
+        /// Write the versioned events of the run (scan started, test started, edit applied,
+        /// outcome decided, report written) as JSON lines to this already-open file descriptor.
+        /// Unlike `--progress-fd`, their schema is stable, for orchestrators building on top of
+        /// the tool.
+        #[arg(long, value_name = "FD")]
+        events_fd: Option<i32>,
+        /// Remove the directive from every candidate of a suite at once, run them with a single
+        /// `x test` invocation and only revert the failures (which are then retried with
+        /// `-Cdebug-assertions=no`, again as a batch). Much faster when the stage 1 rebuild
+        /// dominates, at the cost of skipping the per-test sanity check.
+        #[arg(long)]
+        batch: bool,
+        /// Process this many tests concurrently, each with its own `x test` invocation. The
+        /// stage 1 compiler must already be built (see the `prebuild` config option), as
+        /// concurrent invocations bypass bootstrap's build directory lock. Unless running under a
+        /// `make` jobserver, this is also the jobserver budget handed down to `x`. Defaults to 1.
+        #[arg(long, short, value_name = "N")]
+        jobs: Option<NonZeroUsize>,
+        /// Re-verify every test instead of reusing outcomes of previous runs for tests whose
+        /// contents, snapshots and toolchain did not change since.
+        #[arg(long)]
+        no_cache: bool,
+        /// Skip tests which a previous run left with their directive and which (along with their
+        /// snapshots) were not touched since, according to `git diff` against the commit that run
+        /// was performed at.
+        #[arg(long)]
+        incremental: bool,
+        /// Echo the output of every `x` invocation to stderr as it is produced.
+        #[arg(long)]
+        show_output: bool,
+        /// Write the full output of every `x` invocation to `x-logs/` in the run directory. Only
+        /// the test results and the last lines of output are kept in memory otherwise.
+        #[arg(long)]
+        log_output: bool,
+        /// Save the arguments and output of every `x test` invocation, along with the snapshots
+        /// it left, to this directory, to be replayed with `--replay`.
+        #[arg(long, value_name = "DIR")]
+        record: Option<PathBuf>,
+        /// Answer every `x test` invocation from a recording made with `--record` instead of
+        /// running `x`, for deterministic debugging and working on reports offline. Runs whose
+        /// tests or config changed since the recording fail on the first invocation missing from
+        /// it.
+        #[arg(long, value_name = "DIR", conflicts_with = "record")]
+        replay: Option<PathBuf>,
+        /// Run compiletest directly against the already built stage 1 toolchain instead of going
+        /// through `x test`, skipping bootstrap's startup and its checks of what needs rebuilding.
+        /// Nothing is rebuilt, so build the compiler and compiletest beforehand. See the
+        /// `compiletest_path`, `stage1_sysroot` and `compiletest_args` config options.
+        #[arg(long, conflicts_with = "replay")]
+        direct_compiletest: bool,
+        /// Wall-clock budget for the run, e.g. `90m`, `8h` or `3600` (seconds). Tests which would
+        /// not finish in time going by previous runs are not started, and tests still running
+        /// when the budget runs out are stopped and restored. Running again resumes, reusing the
+        /// outcomes of the tests that finished from the cache.
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        deadline: Option<Duration>,
+        /// Only run one test of each family of identical tests (same suite, same source up to
+        /// whitespace and same snapshots), applying its outcome to the others. Copies of a test
+        /// whose snapshots were rewritten by `--bless` are flagged for manual review instead.
+        #[arg(long)]
+        dedup: bool,
+        /// Commit every removed or replaced directive, along with the snapshots blessed for it, as
+        /// an individual commit on this branch of the `rustc` repo (created from HEAD if it
+        /// doesn't exist yet). See `commit_message_template` in the config.
+        #[arg(long, value_name = "BRANCH")]
+        commit_branch: Option<String>,
+        /// Do all the work on a dedicated `less-ignore-debug/<date>` branch of the `rustc` repo,
+        /// created from HEAD (or reused if it already exists from an earlier run that day).
+        #[arg(long, conflicts_with = "commit_branch")]
+        branch: bool,
+        /// Run even though HEAD is detached in the `rustc` repo, where the edits are easily lost
+        /// track of.
+        #[arg(long)]
+        allow_detached: bool,
+        /// Where the changes go: `in-place` leaves them in the working tree, `patch` writes one
+        /// `git format-patch`-style file per test under `patches/` in the run directory and
+        /// `combined-patch` a single `changes.patch`. Both patch modes leave the working tree
+        /// untouched.
+        #[arg(long, value_enum, default_value_t, conflicts_with = "commit_branch")]
+        emit: Emit,
+        /// Show a desktop notification with the outcome counts when the run completes.
+        #[arg(long)]
+        notify: bool,
+        /// Also write the report as JSON (the full state of the run) next to each Markdown
+        /// report, as `<report>.json`.
+        #[arg(long)]
+        json_report: bool,
+        /// Treat problems which are otherwise only warned about as failures: a missing bootstrap
+        /// `x` fails the preflight, and failing to post the results (tracking issue, webhook,
+        /// Zulip) fails the run.
+        #[arg(long)]
+        strict: bool,
+        /// Print how long was spent scanning, editing tests, running `x`, parsing its results and
+        /// reverting edits at the end, summed over the tests.
+        #[arg(long)]
+        timings: bool,
+        /// Built-in rules to run instead of the `ruleset` of the config, ignoring its `rules`.
+        #[arg(long, value_enum)]
+        ruleset: Option<Ruleset>,
+        /// Ignore the `target_directories` of the config, and instead process every suite under
+        /// `tests/` with tests carrying the directive.
+        #[arg(long)]
+        auto_discover: bool,
+        /// Bootstrap config to pass to every `x` invocation as `--config`, instead of the
+        /// `bootstrap_config` of the config or the one of the `rustc` repo.
+        #[arg(long, value_name = "PATH")]
+        bootstrap_config: Option<PathBuf>,
+        /// Build directory to pass to every `x` invocation as `--build-dir`, instead of the
+        /// `build_dir` of the config.
+        #[arg(long, value_name = "PATH")]
+        build_dir: Option<PathBuf>,
+        /// If the `rustc` repo has no bootstrap config, create one with `x setup` without asking
+        /// first. Otherwise this is only offered when running interactively.
+        #[arg(long)]
+        auto_setup: bool,
+        /// If a previous run died while tests were edited, put them back without asking first.
+        /// Otherwise this is only offered when running interactively.
+        #[arg(long)]
+        roll_back: bool,
+        /// Defaults for CI pipelines: no colors, `--json-report`, `--github-annotations`,
+        /// `--strict`, and a conservative acceptance policy where every test whose snapshots were
+        /// rewritten by `--bless` is flagged for manual review. The tool never prompts.
+        #[arg(long)]
+        ci: bool,
+        /// Show a live table of the tests in flight and completed, with the output of `x` for the
+        /// selected test, in the terminal. Tests in flight can be skipped (left unmodified and
+        /// flagged for manual review) and the run aborted from there.
+        #[arg(long, conflicts_with_all = ["batch", "ci", "show_output"])]
+        tui: bool,
+    },
+    /// Apply patches written by `run --emit patch` to a `rustc` repo. The changes to a test and
+    /// its snapshots are only applied if every hunk matches, otherwise the test is reported as a
+    /// conflict.
+    ApplyPatches {
+        /// Path to the `rustc` repo.
+        rustc_repo_path: PathBuf,
+        /// Patch files, or directories of `*.patch` files which are applied in name order.
+        #[arg(required = true)]
+        patches: Vec<PathBuf>,
+        /// Only report which tests would apply and which conflict.
+        #[arg(long)]
+        check: bool,
+    },
+    /// Push the branch checked out in the `rustc` repo and open a draft PR for it with `gh` (or
+    /// the GitHub API, given a `GH_TOKEN` or `GITHUB_TOKEN`), prefilled with the commit message
+    /// and PR description drafted by a run.
+    Submit {
+        /// Path to the `rustc` repo.
+        rustc_repo_path: PathBuf,
+        /// Run whose drafts to use, as listed by `history`. Defaults to the latest finished run.
+        #[arg(long, value_name = "ID")]
+        run: Option<String>,
+        /// Remote to push the branch to, usually your fork.
+        #[arg(long, default_value = "origin")]
+        remote: String,
+        /// Repository to open the PR against.
+        #[arg(long, value_name = "OWNER/REPO", default_value = "rust-lang/rust")]
+        repo: String,
+        /// Branch the PR is to be merged into.
+        #[arg(long, default_value = "master")]
+        base: String,
+    },
+    /// Find out with `cargo-bisect-rustc` since which nightly the tests a run left with their
+    /// directive fail without it when compiled with `-Cdebug-assertions=yes`, and note the findings
+    /// in the report and issue drafts of the run. Needs `cargo-bisect-rustc`
+    /// and `rustup`, and only handles standalone `run-pass`, `build-pass` and `check-pass` tests.
+    Bisect {
+        /// Path to the `rustc` repo.
+        rustc_repo_path: PathBuf,
+        /// Run whose tests to bisect, as listed by `history`. Defaults to the latest run.
+        #[arg(long, value_name = "ID")]
+        run: Option<String>,
+        /// Test to bisect, relative to the root of the `rustc` repo. Can be repeated. Defaults to
+        /// every test the run left with its directive after it failed without.
+        #[arg(long, value_name = "PATH")]
+        test: Vec<String>,
+        /// First nightly to try, e.g. `2024-01-01`. Searches back from the last one if unset.
+        #[arg(long, value_name = "DATE")]
+        start: Option<String>,
+        /// Last nightly to try. Defaults to the latest one.
+        #[arg(long, value_name = "DATE")]
+        end: Option<String>,
+    },
+    /// Count the tests a `run` would process and estimate how long it would take and how many
+    /// times it would invoke `x`, going by the durations measured by previous runs.
+    Estimate {
+        /// Path to the `rustc` repo.
+        rustc_repo_path: PathBuf,
+        /// Number of tests the run would process concurrently. Defaults to 1.
+        #[arg(long, short, value_name = "N")]
+        jobs: Option<NonZeroUsize>,
+        /// Estimate a `run --batch`.
+        #[arg(long)]
+        batch: bool,
     },
+    /// List previous runs recorded under `runs/` next to the executable.
+    History,
+    /// Explain what the last run which came across a test did with it, why it settled on its
+    /// outcome, and what to do next.
+    Explain {
+        /// Path to the `rustc` repo.
+        rustc_repo_path: PathBuf,
+        /// Path to the test, or its path relative to the `rustc` repo.
+        test: PathBuf,
+        /// Run to explain the outcome of, as listed by `history`. Defaults to the latest run which
+        /// came across the test.
+        #[arg(long, value_name = "ID")]
+        run: Option<String>,
+    },
+    /// Run the tests the last run removed or replaced the directive of again, as they are in the
+    /// working tree and without blessing anything, to check that the applied changes pass before
+    /// pushing them. Exits with 1 unless every one of them passes.
+    Verify {
+        /// Path to the `rustc` repo.
+        rustc_repo_path: PathBuf,
+        /// Run to verify the changes of, as listed by `history`. Defaults to the latest run which
+        /// changed any test.
+        #[arg(long, value_name = "ID")]
+        run: Option<String>,
+    },
+    /// Draft a follow-up issue for each test the last run left with its directive, as unmodified
+    /// or for manual review, with the edit tried and an excerpt of its failure.
+    IssueDrafts {
+        /// Path to the `rustc` repo.
+        rustc_repo_path: PathBuf,
+        /// Run to draft issues for, as listed by `history`. Defaults to the latest run.
+        #[arg(long, value_name = "ID")]
+        run: Option<String>,
+        /// Print the drafts as JSON rather than Markdown.
+        #[arg(long)]
+        json: bool,
+        /// File the drafts as issues in this GitHub repo (`<owner>/<repo>`) instead of printing
+        /// them, through `gh` or `GH_TOKEN`/`GITHUB_TOKEN`.
+        #[arg(long, value_name = "OWNER/REPO")]
+        file_in: Option<String>,
+        /// Label to put on the filed issues. Can be given several times.
+        #[arg(long, value_name = "LABEL", requires = "file_in")]
+        label: Vec<String>,
+    },
+    /// Revert the tests the most recent run edited, along with the snapshots it blessed, from its
+    /// journal if it died midway or else from its backup archive. Tests changed since are left
+    /// alone.
+    UndoLastRun {
+        /// Path to the `rustc` repo.
+        rustc_repo_path: PathBuf,
+    },
+    /// Render all recorded runs into a single HTML page with a burn-down chart of the remaining
+    /// `ignore-debug` directives.
+    Dashboard {
+        /// Path of the HTML file to write. If not specified, will default to `dashboard.html`
+        /// under `runs/`.
+        output: Option<PathBuf>,
+    },
+}
+
+/// `<number>[s|m|h]`, in seconds if there is no unit.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, unit_secs) = match s.strip_suffix('h') {
+        Some(hours) => (hours, 3600),
+        None => match s.strip_suffix('m') {
+            Some(minutes) => (minutes, 60),
+            None => (s.strip_suffix('s').unwrap_or(s), 1),
+        },
+    };
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid duration `{s}`, expected e.g. `90m`, `8h` or `3600`"))?;
+    Duration::try_from_secs_f64(number * unit_secs as f64).map_err(|e| format!("{e}"))
 }
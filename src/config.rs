@@ -1,11 +1,239 @@
 use confique::Config as DeriveConfig;
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeSet;
 use std::path::PathBuf;
 
-#[derive(Debug, Default, DeriveConfig)]
+use crate::run::rules::{Rule, Ruleset};
+use crate::run::suites::SuiteProfile;
+
+#[derive(Debug, Default, DeriveConfig, Serialize)]
 pub struct Config {
     /// `rustc` test directories to perform the attempted reduction of `// ignore-debug` for.
     /// They need to be paths relative to the root of the `rustc` repo, e.g. `tests/run-make`.
     #[config(default = [])]
     pub target_directories: BTreeSet<PathBuf>,
+    /// Files or directories under the target directories to leave alone. Like
+    /// `target_directories`, they are relative to the root of the `rustc` repo.
+    #[config(default = [])]
+    pub exclude: BTreeSet<PathBuf>,
+    /// Follow symlinks in the target directories which lead outside of the `rustc` repo. By
+    /// default the files they lead to are skipped. Either way, tests outside of the target
+    /// directories are never edited, only flagged for manual review.
+    #[config(default = false)]
+    pub follow_external_symlinks: bool,
+    /// Rewrite rules tried in order on each candidate test, the first one under which the test
+    /// passes being kept. Each rule has a `name`, a `match` with the `directive` name and an
+    /// optional `value` pattern (`*` matching anything), and an `action`: `"remove"`,
+    /// `{ replace-with = "<directive>" }` or `{ append-flag = "<flag>" }` (added to the
+    /// `compile-flags` of the same revisions). Tests are candidates if any rule matches one of
+    /// their directives. Defaults to removing `ignore-debug`, then replacing it with
+    /// `compile-flags: -Cdebug-assertions=no`.
+    #[config(default = [])]
+    pub rules: Vec<Rule>,
+    /// Built-in rules used when `rules` is empty: `ignore-debug`, `ignore-test` (remove it from
+    /// tests which pass again) or `ignore-target` (replace `ignore-wasm32` and friends by the
+    /// `needs-*` directive matching their reason). Overridden by `--ruleset`.
+    #[config(default = "ignore-debug")]
+    pub ruleset: Ruleset,
+    /// Keep the reason given by a replaced directive as a comment above what replaces it, e.g.
+    /// `// (previously ignored in debug: <reason>)` for `ignore-debug: <reason>`, rather than
+    /// losing it. Applies to every rule; rules can also set `keep_reason` individually.
+    #[config(default = false)]
+    pub keep_directive_reasons: bool,
+    /// Blessed tests whose snapshot files changed by more than this many lines in total are
+    /// flagged as outliers in the report.
+    #[config(default = 20)]
+    pub snapshot_outlier_lines: usize,
+    /// Repository URL used to link test paths in reports to their source at the `rustc` repo's
+    /// HEAD commit. Set to an empty string to disable links.
+    #[config(default = "https://github.com/rust-lang/rust")]
+    pub source_link_base: String,
+    /// Try tests checked with FileCheck (`tests/codegen`, `tests/assembly` and any test with
+    /// `CHECK` lines) like any other, keeping the edit if they pass. By default they are flagged
+    /// for manual review without being run: removing `ignore-debug` often calls for updating
+    /// their `CHECK` lines, which a passing run does not reveal.
+    #[config(default = false)]
+    pub try_filecheck_tests: bool,
+    /// Overrides of how the tests of a suite are run, e.g.
+    /// `{ suite = "tests/run-make", stage = 2 }`. `stage` is the stage to test with, `bless`
+    /// whether to pass `--bless` and `filecheck` whether the whole suite is checked with
+    /// FileCheck. By default, every suite is tested at stage 1, with `--bless` except for
+    /// `tests/codegen`, `tests/assembly` and `tests/run-make`, and the first two are checked with
+    /// FileCheck. `prebuild` only builds stage 1.
+    #[config(default = [])]
+    pub suite_profiles: Vec<SuiteProfile>,
+    /// Run `x test tidy` after each edited test passes, rejecting the edit like a failure if tidy
+    /// reports errors about the test. Tidy always checks the whole tree, so this is slow. Not
+    /// supported with `--batch`.
+    #[config(default = false)]
+    pub tidy: bool,
+    /// Before each `x` invocation, wait for the `x` invocations of the user in the same `rustc`
+    /// repo (found among the running processes, on Linux) to finish, rather than interleaving with
+    /// them. Bootstrap's build directory lock is waited for either way.
+    #[config(default = true)]
+    pub detect_user_bootstrap: bool,
+    /// Build the stage 1 compiler once with `x build --stage 1` before processing any test.
+    #[config(default = true)]
+    pub prebuild: bool,
+    /// Extra arguments passed to every `x test` invocation to reuse the prebuilt stage instead of
+    /// checking it for rebuilds each time, e.g. `["--keep-stage", "1"]`. Set to `[]` to let
+    /// bootstrap decide what to rebuild.
+    #[config(default = ["--keep-stage-std", "1"])]
+    pub keep_stage_args: Vec<String>,
+    /// Kill `x` invocations running for longer than this many seconds, treating the test as
+    /// failing. No timeout if unset.
+    pub timeout_secs: Option<u64>,
+    /// Niceness to run `x` with, from 0 (default priority) to 19 (lowest priority). Inherited by
+    /// everything `x` spawns.
+    pub niceness: Option<i32>,
+    /// Memory cap for each `x` invocation, in MiB. Enforced with a transient systemd scope (a
+    /// cgroup covering everything `x` spawns) where available, and otherwise by limiting the
+    /// address space of each process.
+    pub memory_limit_mib: Option<u64>,
+    /// Maximum number of concurrent LLVM link jobs, passed to bootstrap as `llvm.link-jobs`.
+    pub link_jobs: Option<u32>,
+    /// Number of tests compiletest runs concurrently within an `x` invocation
+    /// (`RUST_TEST_THREADS`). Defaults to the number of CPUs.
+    pub test_threads: Option<usize>,
+    /// Cache of outcomes shared with other machines (e.g. CI shards), in addition to the local
+    /// one: an `http://` or `https://` URL accepting `GET` and `PUT` of `<url>/<name>`, or a
+    /// directory, e.g. on a network filesystem. Disabled by `--no-cache`.
+    pub remote_cache: Option<String>,
+    /// Build directory dedicated to this tool, passed to every `x` invocation as `--build-dir`,
+    /// so that its builds and incremental state don't interfere with the ones of your own
+    /// development builds (at the cost of building the compiler separately). Relative paths are
+    /// relative to the root of the `rustc` repo. Uses bootstrap's default (`build/`) if unset.
+    pub build_dir: Option<PathBuf>,
+    /// Bootstrap config passed to every `x` invocation as `--config`, e.g. a dedicated one known
+    /// to enable debug assertions, instead of the `bootstrap.toml` (or `config.toml`) of the
+    /// `rustc` repo, whatever it says. Relative paths are relative to the root of the `rustc` repo.
+    pub bootstrap_config: Option<PathBuf>,
+    /// compiletest binary run by `--direct-compiletest`. Relative paths are relative to the root
+    /// of the `rustc` repo. Defaults to the one bootstrap builds,
+    /// `<build dir>/<host>/stage0-tools-bin/compiletest`.
+    pub compiletest_path: Option<PathBuf>,
+    /// Sysroot of the stage 1 toolchain tested by `--direct-compiletest`. Relative paths are
+    /// relative to the root of the `rustc` repo. Defaults to `<build dir>/<host>/stage1`.
+    pub stage1_sysroot: Option<PathBuf>,
+    /// Further arguments to pass to compiletest with `--direct-compiletest`, for the options the
+    /// compiletest of the `rustc` checkout requires beyond the paths of the toolchain and of the
+    /// suite, e.g. `["--llvm-components", "x86", "--channel", "nightly"]`. Running `x test -v`
+    /// once shows the ones bootstrap passes.
+    #[config(default = [])]
+    pub compiletest_args: Vec<String>,
+    /// Other checkouts of rust-lang/rust (e.g. `git worktree`s on other branches, or with another
+    /// bootstrap config) to run every edited test in as well. A change is only kept if the test
+    /// fails in none of them, and the report shows how it fared in each. Relative paths are
+    /// relative to the root of the `rustc` repo. The worktrees are left unmodified. Not supported
+    /// with `--batch`.
+    #[config(default = [])]
+    pub worktrees: Vec<PathBuf>,
+    /// Run `x` on this machine over SSH (e.g. `me@buildbox`, or a host from `~/.ssh/config`)
+    /// rather than locally. Needs `ssh_repo_path` too. The tests are still edited locally, and
+    /// copied over with `rsync` before each `x test`, along with their snapshots, which are copied
+    /// back once blessed. Authentication must not prompt, e.g. through an SSH agent.
+    pub ssh_host: Option<String>,
+    /// Path of the checkout of rust-lang/rust on `ssh_host`, ideally at the same commit as the
+    /// local one.
+    pub ssh_repo_path: Option<String>,
+    /// Run `x` in a fresh container of this Docker or Podman image, with the `rustc` repo
+    /// bind-mounted at the same path, e.g. for a reproducible toolchain environment. The image
+    /// needs to provide `x` on its `PATH`. `memory_limit_mib` is enforced on the container.
+    pub container_image: Option<String>,
+    /// Container engine to run `container_image` with, e.g. `podman`.
+    #[config(default = "docker")]
+    pub container_engine: String,
+    /// Extra arguments to `<container_engine> run`, e.g. `["--network=none"]` or volumes for
+    /// caches.
+    #[config(default = [])]
+    pub container_args: Vec<String>,
+    /// Message of the commit made for each removed or replaced directive with `--commit-branch`.
+    /// `{action}` stands for what was done (e.g. "Remove `ignore-debug` from"), `{path}` for the
+    /// repo-relative path of the test and `{outcome}` for the outcome (e.g. "Directive removed").
+    #[config(default = "{action} `{path}`")]
+    pub commit_message_template: String,
+    /// Issue or PR to keep a comment with the outcome counts of the latest run on, as
+    /// `<owner>/<repo>#<number>`. Posted with `gh` where available, and otherwise with
+    /// `GH_TOKEN` or `GITHUB_TOKEN`.
+    pub tracking_issue: Option<String>,
+    /// URL to POST a JSON summary of each run (outcome counts, duration, exit status, report
+    /// path) to when it finishes or aborts, e.g. for chat bots or dashboards.
+    pub webhook_url: Option<String>,
+    /// Zulip server to post the report of each run to, e.g. `https://rust-lang.zulipchat.com`.
+    /// Needs `zulip_stream` and `zulip_bot_email` too, and the bot's API key in the
+    /// `ZULIP_API_KEY` environment variable. Long reports are split into several messages.
+    pub zulip_site: Option<String>,
+    /// Stream to post the reports to.
+    pub zulip_stream: Option<String>,
+    /// Topic within `zulip_stream` to post the reports to.
+    #[config(default = "ignore-debug reduction")]
+    pub zulip_topic: String,
+    /// Email address of the Zulip bot posting the reports.
+    pub zulip_bot_email: Option<String>,
+    /// Shell command run from the root of the `rustc` repo before each test is run, e.g. for
+    /// logging or gating. It gets the repo-relative path of the test in `LESS_IGNORE_DEBUG_TEST`,
+    /// and as `path` in a JSON object on stdin. If it exits unsuccessfully, the test is left
+    /// alone and flagged for manual review.
+    pub pre_test: Option<String>,
+    /// Shell command run from the root of the `rustc` repo after each test is run, e.g. for
+    /// logging or metrics. On top of what `pre_test` gets, the name of the rule which was kept
+    /// (by default `remove` or `replace`, or `none`) and the outcome are passed in
+    /// `LESS_IGNORE_DEBUG_STRATEGY` and `LESS_IGNORE_DEBUG_OUTCOME`, and as `strategy`, `outcome`
+    /// and `duration_secs` on stdin.
+    pub post_test: Option<String>,
+    /// Shell command run from the root of the `rustc` repo to classify ambiguous results of an
+    /// edited test: a failure (or timeout), or a pass whose snapshot changes would be flagged for
+    /// manual review. On top of what `pre_test` gets, it gets the result in
+    /// `LESS_IGNORE_DEBUG_RESULT`, and as `result`, `diff` (of the test and its snapshots),
+    /// `stdout` and `stderr` (of `x`) on stdin. The first word it prints decides: `accept` keeps
+    /// the edit, `reject` reverts it, and `manual` flags the test for manual review, with the rest
+    /// of the line as the reason. Anything else leaves the result to the tool. Not consulted with
+    /// `--batch`.
+    pub classifier: Option<String>,
+    /// Also write the log of each run to files in this directory, e.g. `"logs"`, relative to the
+    /// executable, regardless of what `RUST_LOG` shows on the terminal.
+    pub log_dir: Option<PathBuf>,
+    /// Most verbose level written to the log files: `error`, `warn`, `info`, `debug` or `trace`.
+    #[config(default = "debug")]
+    pub log_level: String,
+    /// When to start a new log file: `"run"` for one per run, `"daily"`, or `{ size = <bytes> }`
+    /// once the current one reaches that size.
+    #[config(default = "run")]
+    pub log_rotation: LogRotation,
+    /// Format of the log files: `text`, or `json` for one JSON object per line, with the fields of
+    /// the enclosing spans (e.g. the path of the test being processed and the rule being tried),
+    /// for log aggregation systems. See also `--log-format`.
+    #[config(default = "text")]
+    pub log_format: LogFormat,
+    /// Log files to keep in `log_dir`, the oldest ones being deleted.
+    #[config(default = 20)]
+    pub log_files_kept: usize,
+    /// Append a line of JSON with the metrics of each run (counts of each outcome, durations,
+    /// cache hits, outcomes which changed since the previous runs) to this file, e.g.
+    /// `"metrics.jsonl"`, relative to the executable. Fields are only ever added to the format.
+    pub metrics_file: Option<PathBuf>,
+}
+
+/// Format of log lines, see `log_format` in the config and `--log-format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, along with the fields of the enclosing spans.
+    Json,
+}
+
+/// When to start a new log file, see `log_rotation` in the config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogRotation {
+    /// A new file for each run.
+    #[default]
+    Run,
+    /// A new file every day.
+    Daily,
+    /// A new file once the current one reaches this many bytes.
+    Size(u64),
 }
@@ -0,0 +1,173 @@
+//! The `dashboard` command: a single static HTML page summarizing all recorded runs, with a
+//! burn-down chart of the `ignore-debug` directives remaining after each run.
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use miette::{Context, IntoDiagnostic, Result};
+use tracing::*;
+
+use crate::history::{self, RunDir};
+use crate::run::state::RunState;
+use crate::run::RunOutcome;
+
+const CHART_WIDTH: f64 = 800.0;
+const CHART_HEIGHT: f64 = 300.0;
+const CHART_MARGIN: f64 = 40.0;
+
+pub fn write_dashboard(runs_root: &Path, output: &Path) -> Result<()> {
+    let runs: Vec<(RunDir, RunState)> = history::list_runs(runs_root)?
+        .into_iter()
+        .filter_map(|(dir, state)| Some((dir, state?)))
+        .collect();
+    if runs.is_empty() {
+        warn!("no runs recorded under `{}`", runs_root.display());
+    }
+
+    std::fs::write(output, render(&runs))
+        .into_diagnostic()
+        .wrap_err(format!(
+            "failed to write dashboard to `{}`",
+            output.display()
+        ))?;
+    info!("dashboard written to `{}`", output.display());
+    Ok(())
+}
+
+/// Directives still present after the run.
+fn remaining(state: &RunState) -> usize {
+    state
+        .records
+        .values()
+        .filter(|r| r.outcome.keeps_directive())
+        .count()
+}
+
+fn render(runs: &[(RunDir, RunState)]) -> String {
+    let mut out = String::new();
+    out.push_str(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>ignore-debug reduction dashboard</title>\n<style>\n\
+         body { font-family: sans-serif; margin: 2em; }\n\
+         table { border-collapse: collapse; }\n\
+         th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: right; }\n\
+         th:first-child, td:first-child { text-align: left; }\n\
+         </style>\n</head>\n<body>\n<h1><code>ignore-debug</code> reduction dashboard</h1>\n",
+    );
+
+    out.push_str("<h2>Remaining directives</h2>\n");
+    render_chart(&mut out, runs);
+
+    out.push_str("<h2>Runs</h2>\n<table>\n<tr><th>Run</th><th>Status</th>");
+    for outcome in RunOutcome::ALL {
+        write!(out, "<th>{}</th>", escape(outcome.description())).unwrap();
+    }
+    out.push_str("<th>Remaining</th></tr>\n");
+    for (dir, state) in runs.iter().rev() {
+        let status = state
+            .status
+            .map_or_else(|| "unfinished".to_string(), |s| format!("{s:?}"));
+        write!(
+            out,
+            "<tr><td><code>{}</code></td><td>{}</td>",
+            escape(&dir.id),
+            escape(&status)
+        )
+        .unwrap();
+        for outcome in RunOutcome::ALL {
+            let count = state
+                .records
+                .values()
+                .filter(|r| r.outcome == outcome)
+                .count();
+            write!(out, "<td>{count}</td>").unwrap();
+        }
+        writeln!(out, "<td>{}</td></tr>", remaining(state)).unwrap();
+    }
+    out.push_str("</table>\n</body>\n</html>\n");
+    out
+}
+
+/// Inline SVG line chart of remaining directives per run, oldest run on the left.
+fn render_chart(out: &mut String, runs: &[(RunDir, RunState)]) {
+    if runs.is_empty() {
+        out.push_str("<p>No runs recorded yet.</p>\n");
+        return;
+    }
+
+    let max = runs
+        .iter()
+        .map(|(_, s)| remaining(s))
+        .max()
+        .unwrap_or(0)
+        .max(1) as f64;
+    let plot_width = CHART_WIDTH - 2.0 * CHART_MARGIN;
+    let plot_height = CHART_HEIGHT - 2.0 * CHART_MARGIN;
+    let step = if runs.len() > 1 {
+        plot_width / (runs.len() - 1) as f64
+    } else {
+        0.0
+    };
+    let point = |idx: usize, value: usize| {
+        (
+            CHART_MARGIN + idx as f64 * step,
+            CHART_MARGIN + plot_height * (1.0 - value as f64 / max),
+        )
+    };
+
+    writeln!(
+        out,
+        "<svg width=\"{CHART_WIDTH}\" height=\"{CHART_HEIGHT}\" \
+         xmlns=\"http://www.w3.org/2000/svg\">"
+    )
+    .unwrap();
+    // Axes.
+    let bottom = CHART_HEIGHT - CHART_MARGIN;
+    writeln!(
+        out,
+        "<line x1=\"{CHART_MARGIN}\" y1=\"{CHART_MARGIN}\" x2=\"{CHART_MARGIN}\" y2=\"{bottom}\" \
+         stroke=\"#888\"/>\n\
+         <line x1=\"{CHART_MARGIN}\" y1=\"{bottom}\" x2=\"{}\" y2=\"{bottom}\" stroke=\"#888\"/>\n\
+         <text x=\"{}\" y=\"{}\" text-anchor=\"end\" font-size=\"12\">{max}</text>\n\
+         <text x=\"{}\" y=\"{bottom}\" text-anchor=\"end\" font-size=\"12\">0</text>",
+        CHART_WIDTH - CHART_MARGIN,
+        CHART_MARGIN - 5.0,
+        CHART_MARGIN + 4.0,
+        CHART_MARGIN - 5.0,
+    )
+    .unwrap();
+
+    let points: Vec<String> = runs
+        .iter()
+        .enumerate()
+        .map(|(idx, (_, state))| {
+            let (x, y) = point(idx, remaining(state));
+            format!("{x:.1},{y:.1}")
+        })
+        .collect();
+    writeln!(
+        out,
+        "<polyline fill=\"none\" stroke=\"#d33\" stroke-width=\"2\" points=\"{}\"/>",
+        points.join(" ")
+    )
+    .unwrap();
+    for (idx, (dir, state)) in runs.iter().enumerate() {
+        let (x, y) = point(idx, remaining(state));
+        writeln!(
+            out,
+            "<circle cx=\"{x:.1}\" cy=\"{y:.1}\" r=\"4\" fill=\"#d33\">\
+             <title>{}: {} remaining</title></circle>",
+            escape(&dir.id),
+            remaining(state)
+        )
+        .unwrap();
+    }
+    out.push_str("</svg>\n");
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,32 @@
+use std::process::ExitCode;
+
+use serde::{Deserialize, Serialize};
+
+/// Exit codes of the tool, so that wrapper scripts and CI can branch on the result without parsing
+/// any output.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExitStatus {
+    /// Every candidate test had its `// ignore-debug` directive removed or replaced.
+    Success = 0,
+    /// Some tests still carry the directive and need to be looked at by a human.
+    NeedsManualReview = 1,
+    /// The environment or configuration is unusable, e.g. missing config, wrong repo path or
+    /// missing bootstrap. No test was touched.
+    PreflightFailure = 2,
+    /// The run was interrupted by Ctrl-C. A partial report was written.
+    Interrupted = 3,
+    /// An unexpected error occurred partway through the run.
+    Error = 4,
+    /// The `--deadline` budget ran out before every test was processed. A partial report was
+    /// written.
+    DeadlineReached = 5,
+    /// Some tests a previous run had found not to need the directive fail without it, e.g. after
+    /// a rebase. They keep the directive.
+    Regression = 6,
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
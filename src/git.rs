@@ -0,0 +1,174 @@
+//! Thin wrappers around the `git` CLI for inspecting the `rustc` repo, committing to it with
+//! `--commit-branch` and pushing it for `submit`.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::process::Command;
+
+use miette::{bail, Context, IntoDiagnostic, Result};
+use tracing::*;
+
+/// The full hash of the commit checked out in `repo`, or `None` if it can't be determined (e.g.
+/// `git` is missing or `repo` isn't a git checkout).
+pub(crate) fn head_commit(repo: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .current_dir(repo)
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .inspect_err(|e| debug!(?e, "failed to invoke `git rev-parse HEAD`"))
+        .ok()?;
+    if !output.status.success() {
+        debug!(
+            "`git rev-parse HEAD` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return None;
+    }
+    let commit = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!commit.is_empty()).then_some(commit)
+}
+
+/// The full hash of the last commit reachable from HEAD which touched any of `paths`.
+pub(crate) fn last_commit_touching(repo: &Path, paths: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .current_dir(repo)
+        .args(["log", "-1", "--format=%H", "HEAD", "--"])
+        .args(paths)
+        .output()
+        .inspect_err(|e| debug!(?e, "failed to invoke `git log`"))
+        .ok()?;
+    if !output.status.success() {
+        debug!(
+            "`git log` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return None;
+    }
+    let commit = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!commit.is_empty()).then_some(commit)
+}
+
+/// The uncommitted changes to the tracked files under `paths`, as a diff against HEAD which is
+/// empty if there are none.
+pub(crate) fn uncommitted_diff(repo: &Path, paths: &[&str]) -> Option<String> {
+    let mut args = vec!["diff", "--no-ext-diff", "HEAD", "--"];
+    args.extend_from_slice(paths);
+    git(repo, &args)
+        .inspect_err(|e| debug!("failed to diff the working tree: {e}"))
+        .ok()
+}
+
+/// Repo-relative paths of the files which differ between `commit` and the working tree, including
+/// uncommitted changes.
+pub(crate) fn changed_files_since(repo: &Path, commit: &str) -> Option<BTreeSet<String>> {
+    let output = Command::new("git")
+        .current_dir(repo)
+        .args(["diff", "--name-only", "--no-renames", commit, "--"])
+        .output()
+        .inspect_err(|e| debug!(?e, "failed to invoke `git diff`"))
+        .ok()?;
+    if !output.status.success() {
+        debug!(
+            "`git diff` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    Some(stdout.lines().map(str::to_string).collect())
+}
+
+/// Whether HEAD is detached in `repo`, or `None` if `repo` isn't a git checkout.
+pub(crate) fn is_detached(repo: &Path) -> Option<bool> {
+    head_commit(repo)?;
+    Some(git(repo, &["symbolic-ref", "-q", "HEAD"]).is_err())
+}
+
+/// The configured identity in `repo` as `Name <email>`, or `None` if it isn't set.
+pub(crate) fn author(repo: &Path) -> Option<String> {
+    let name = git(repo, &["config", "user.name"]).ok()?;
+    let email = git(repo, &["config", "user.email"]).ok()?;
+    Some(format!("{} <{}>", name.trim(), email.trim()))
+}
+
+/// The name of the branch checked out in `repo`, or `None` if HEAD is detached.
+pub(crate) fn current_branch(repo: &Path) -> Option<String> {
+    let branch = git(repo, &["symbolic-ref", "--short", "-q", "HEAD"]).ok()?;
+    Some(branch.trim().to_string())
+}
+
+/// Check out `branch` in `repo`, creating it from HEAD if it doesn't exist yet.
+pub(crate) fn switch_branch(repo: &Path, branch: &str) -> Result<()> {
+    if current_branch(repo).as_deref() == Some(branch) {
+        return Ok(());
+    }
+    let exists = git(
+        repo,
+        &[
+            "rev-parse",
+            "--verify",
+            "-q",
+            &format!("refs/heads/{branch}"),
+        ],
+    )
+    .is_ok();
+    if exists {
+        git(repo, &["switch", branch])?;
+    } else {
+        git(repo, &["switch", "-c", branch])?;
+    }
+    Ok(())
+}
+
+/// Commit the changes to `paths` (including new and deleted files) and nothing else. Returns
+/// `false` without committing if none of them changed.
+pub(crate) fn commit_paths(repo: &Path, paths: &[&str], message: &str) -> Result<bool> {
+    let mut status = vec!["status", "--porcelain", "--"];
+    status.extend_from_slice(paths);
+    if git(repo, &status)?.trim().is_empty() {
+        return Ok(false);
+    }
+    let mut add = vec!["add", "-A", "--"];
+    add.extend_from_slice(paths);
+    git(repo, &add)?;
+    let mut commit = vec!["commit", "-q", "-m", message, "--"];
+    commit.extend_from_slice(paths);
+    git(repo, &commit)?;
+    Ok(true)
+}
+
+/// Whether tracked files in `repo` have staged or unstaged changes.
+pub(crate) fn has_uncommitted_changes(repo: &Path) -> Result<bool> {
+    let status = git(repo, &["status", "--porcelain", "--untracked-files=no"])?;
+    Ok(!status.trim().is_empty())
+}
+
+/// The URL of `remote`, or `None` if there is no such remote.
+pub(crate) fn remote_url(repo: &Path, remote: &str) -> Option<String> {
+    let url = git(repo, &["remote", "get-url", remote]).ok()?;
+    Some(url.trim().to_string())
+}
+
+/// Push `branch` to `remote`, setting it as the upstream.
+pub(crate) fn push(repo: &Path, remote: &str, branch: &str) -> Result<()> {
+    git(repo, &["push", "-q", "-u", remote, branch])?;
+    Ok(())
+}
+
+/// Run `git <args>` in `repo`, returning its stdout.
+fn git(repo: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .current_dir(repo)
+        .args(args)
+        .output()
+        .into_diagnostic()
+        .wrap_err(format!("failed to invoke `git {}`", args.join(" ")))?;
+    if !output.status.success() {
+        bail!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,118 @@
+//! Minimal GitHub REST API client for `submit` and for posting run summaries, going through the
+//! `gh` CLI (and its login) where available, and otherwise through `GH_TOKEN` or `GITHUB_TOKEN`.
+
+use std::io::Write as _;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use miette::{bail, Context, IntoDiagnostic, Result};
+use serde_json::Value;
+
+const API_URL: &str = "https://api.github.com";
+
+#[derive(Debug)]
+pub(crate) enum GitHub {
+    Gh,
+    Token { token: String, agent: ureq::Agent },
+}
+
+impl GitHub {
+    pub(crate) fn new() -> Result<Self> {
+        let gh_available = Command::new("gh")
+            .arg("--version")
+            .output()
+            .is_ok_and(|output| output.status.success());
+        if gh_available {
+            return Ok(GitHub::Gh);
+        }
+        let Some(token) = ["GH_TOKEN", "GITHUB_TOKEN"]
+            .into_iter()
+            .find_map(|var| std::env::var(var).ok())
+        else {
+            bail!("neither `gh` nor a `GH_TOKEN`/`GITHUB_TOKEN` is available to talk to GitHub");
+        };
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(30)))
+            .http_status_as_error(false)
+            .build()
+            .into();
+        Ok(GitHub::Token { token, agent })
+    }
+
+    /// `method` the API endpoint `path` (e.g. `repos/rust-lang/rust/pulls`) with `body` as the
+    /// JSON request body, returning the JSON response.
+    pub(crate) fn request(&self, method: &str, path: &str, body: Option<&Value>) -> Result<Value> {
+        let response = match self {
+            GitHub::Gh => {
+                let mut child = Command::new("gh")
+                    .args(["api", "-X", method, path])
+                    .args(body.map(|_| ["--input", "-"]).into_iter().flatten())
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .into_diagnostic()
+                    .wrap_err("failed to invoke `gh api`")?;
+                if let Some(body) = body {
+                    let mut stdin = child.stdin.take().unwrap();
+                    stdin
+                        .write_all(body.to_string().as_bytes())
+                        .into_diagnostic()?;
+                }
+                drop(child.stdin.take());
+                let output = child.wait_with_output().into_diagnostic()?;
+                if !output.status.success() {
+                    bail!(
+                        "`gh api -X {method} {path}` failed: {}",
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                }
+                String::from_utf8_lossy(&output.stdout).into_owned()
+            }
+            GitHub::Token { token, agent } => {
+                let url = format!("{API_URL}/{path}");
+                let request = ureq::http::Request::builder()
+                    .method(method)
+                    .uri(&url)
+                    .header("Authorization", format!("Bearer {token}"))
+                    .header("Accept", "application/vnd.github+json")
+                    .header("User-Agent", "rustc-less-ignore-debug");
+                let response = match body {
+                    Some(body) => agent.run(
+                        request
+                            .header("Content-Type", "application/json")
+                            .body(body.to_string())
+                            .into_diagnostic()?,
+                    ),
+                    None => agent.run(request.body(()).into_diagnostic()?),
+                };
+                let mut response = response
+                    .into_diagnostic()
+                    .wrap_err(format!("failed to {method} `{url}`"))?;
+                let status = response.status();
+                let text = response
+                    .body_mut()
+                    .read_to_string()
+                    .into_diagnostic()
+                    .wrap_err(format!("failed to read the response of `{url}`"))?;
+                if !status.is_success() {
+                    bail!("{method} `{url}` failed with {status}: {}", text.trim());
+                }
+                text
+            }
+        };
+        if response.trim().is_empty() {
+            return Ok(Value::Null);
+        }
+        serde_json::from_str(&response)
+            .into_diagnostic()
+            .wrap_err(format!("unexpected response to {method} `{path}`"))
+    }
+}
+
+/// Split `<owner>/<repo>#<number>` into `<owner>/<repo>` and the number.
+pub(crate) fn parse_issue(issue: &str) -> Option<(&str, u64)> {
+    let (repo, number) = issue.split_once('#')?;
+    let number = number.parse().ok()?;
+    repo.contains('/').then_some((repo, number))
+}
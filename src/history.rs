@@ -0,0 +1,166 @@
+//! Per-run artifact directories under `runs/`, and the `history` command listing them.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use miette::{Context, IntoDiagnostic, Result};
+use tracing::*;
+
+use crate::run::state::RunState;
+use crate::run::RunOutcome;
+
+/// The directory holding the artifacts of a single run.
+#[derive(Debug, Clone)]
+pub struct RunDir {
+    /// `<timestamp>-<short-id>`, sorts chronologically.
+    pub id: String,
+    pub path: PathBuf,
+}
+
+impl RunDir {
+    /// Create a fresh run directory under `runs_root`.
+    pub fn create(runs_root: &Path) -> Result<Self> {
+        let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+        let id = format!("{timestamp}-{}", short_id());
+        let path = runs_root.join(&id);
+        std::fs::create_dir_all(&path)
+            .into_diagnostic()
+            .wrap_err(format!(
+                "failed to create run directory `{}`",
+                path.display()
+            ))?;
+        Ok(RunDir { id, path })
+    }
+
+    pub fn state_path(&self) -> PathBuf {
+        self.path.join("state.json")
+    }
+
+    pub fn report_path(&self) -> PathBuf {
+        self.path.join("report.md")
+    }
+
+    /// Directory of the logs of the `x` invocations of each test.
+    pub fn logs_path(&self) -> PathBuf {
+        self.path.join("logs")
+    }
+}
+
+/// Six hex digits to tell apart runs started within the same second.
+fn short_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    format!(
+        "{:06x}",
+        (nanos ^ std::process::id().rotate_left(12)) & 0xff_ffff
+    )
+}
+
+/// All run directories under `runs_root` with their state, oldest first. The state is `None` if
+/// it could not be loaded, e.g. because the run crashed before writing it.
+pub fn list_runs(runs_root: &Path) -> Result<Vec<(RunDir, Option<RunState>)>> {
+    if !runs_root.exists() {
+        return Ok(Vec::new());
+    }
+    let mut runs = Vec::new();
+    for entry in std::fs::read_dir(runs_root)
+        .into_diagnostic()
+        .wrap_err(format!("failed to read `{}`", runs_root.display()))?
+    {
+        let entry = entry.into_diagnostic()?;
+        // `journal/` holds the edits of the run in progress, see `Engine::interrupted_edits`.
+        if !entry.file_type().into_diagnostic()?.is_dir() || entry.file_name() == "journal" {
+            continue;
+        }
+        let run_dir = RunDir {
+            id: entry.file_name().to_string_lossy().into_owned(),
+            path: entry.path(),
+        };
+        let state = RunState::load(&run_dir.state_path())
+            .inspect_err(|e| debug!(?e, "no usable state for run `{}`", run_dir.id))
+            .ok();
+        runs.push((run_dir, state));
+    }
+    runs.sort_by(|(a, _), (b, _)| a.id.cmp(&b.id));
+    Ok(runs)
+}
+
+/// The most recently measured duration of every test processed by a previous run, by
+/// repo-relative path. Outcomes reused from the cache don't count as measurements.
+pub fn last_durations(runs_root: &Path) -> Result<BTreeMap<String, Duration>> {
+    let mut durations = BTreeMap::new();
+    for (_, state) in list_runs(runs_root)?.into_iter().rev() {
+        let Some(state) = state else {
+            continue;
+        };
+        for (rel_path, record) in state.records {
+            if !record.duration.is_zero() {
+                durations.entry(rel_path).or_insert(record.duration);
+            }
+        }
+    }
+    Ok(durations)
+}
+
+/// The outcome most recently measured for every test processed by a previous run, along with the
+/// id of that run, by repo-relative path. Outcomes reused from the cache don't count as
+/// measurements.
+pub fn last_outcomes(runs_root: &Path) -> Result<BTreeMap<String, (String, RunOutcome)>> {
+    let mut outcomes = BTreeMap::new();
+    for (run_dir, state) in list_runs(runs_root)?.into_iter().rev() {
+        let Some(state) = state else {
+            continue;
+        };
+        for (rel_path, record) in state.records {
+            if !record.duration.is_zero() {
+                outcomes
+                    .entry(rel_path)
+                    .or_insert_with(|| (run_dir.id.clone(), record.outcome));
+            }
+        }
+    }
+    Ok(outcomes)
+}
+
+/// Print a table of previous runs to stdout.
+pub fn print_history(runs_root: &Path) -> Result<()> {
+    let runs = list_runs(runs_root)?;
+    if runs.is_empty() {
+        info!("no runs recorded under `{}`", runs_root.display());
+        return Ok(());
+    }
+
+    println!(
+        "{:<22}  {:<18}  {:>7}  {:>8}  {:>10}  {:>7}",
+        "RUN", "STATUS", "REMOVED", "REPLACED", "UNMODIFIED", "IGNORED"
+    );
+    for (run_dir, state) in runs {
+        let Some(state) = state else {
+            println!("{:<22}  {:<18}", run_dir.id, "(no state)");
+            continue;
+        };
+        let count = |outcome| {
+            state
+                .records
+                .values()
+                .filter(|r| r.outcome == outcome)
+                .count()
+        };
+        let status = state
+            .status
+            .map_or_else(|| "(unfinished)".to_string(), |s| format!("{s:?}"));
+        println!(
+            "{:<22}  {:<18}  {:>7}  {:>8}  {:>10}  {:>7}",
+            run_dir.id,
+            status,
+            count(RunOutcome::RemoveOk),
+            count(RunOutcome::ReplaceOk),
+            count(RunOutcome::UnmodifiedOk),
+            count(RunOutcome::Ignored),
+        );
+    }
+    Ok(())
+}
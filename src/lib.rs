@@ -0,0 +1,19 @@
+//! Reducing the number of `// ignore-debug` tests in the `rustc` repo, by either removing the
+//! directive or replacing it with `-Cdebug-assertions=no` wherever the test still passes.
+//!
+//! [`Engine`] drives a run: scanning the configured target directories for candidates, editing
+//! their directives, running them through bootstrap with each strategy and reporting on the
+//! outcomes. The `rustc-less-ignore-debug` binary is a thin CLI over it, and the building blocks
+//! ([`run::remove_directive`], [`run::format_report`], ...) are available on their own.
+
+pub mod apply_patches;
+pub mod config;
+pub mod dashboard;
+pub mod exit_status;
+mod git;
+mod github;
+pub mod history;
+pub mod run;
+pub mod submit;
+
+pub use run::{Engine, RunOptions, RunOutcome as Outcome};
@@ -1,24 +1,194 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use miette::{miette, IntoDiagnostic};
+use tracing::metadata::LevelFilter;
 use tracing::*;
 
-pub(crate) fn setup_logging() {
-    use tracing::metadata::LevelFilter;
+use rustc_less_ignore_debug::config::{Config, LogFormat, LogRotation};
+
+/// Log to stderr in `format`, with colors unless `ansi` is false. Lines are printed above the
+/// progress bar of a run rather than over it. Once [`log_to_file`] is called, the log also goes to
+/// files.
+pub(crate) fn setup_logging(ansi: bool, format: LogFormat) {
+    use tracing_subscriber::filter::{self, FilterExt};
     use tracing_subscriber::prelude::*;
     use tracing_subscriber::{fmt, EnvFilter};
 
-    let stderr_log = fmt::layer()
-        .with_writer(std::io::stderr)
-        .compact()
-        .with_level(true)
+    let env_filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env_lossy();
+    let stderr_log = match format {
+        // The fields of spans are left out of text lines, which already name the test they are
+        // about.
+        LogFormat::Text => fmt::layer()
+            .with_writer(|| Stderr)
+            .compact()
+            .with_level(true)
+            .with_target(true)
+            .without_time()
+            .with_ansi(ansi)
+            .with_filter(env_filter.and(filter::filter_fn(|metadata| !metadata.is_span())))
+            .boxed(),
+        LogFormat::Json => fmt::layer()
+            .with_writer(|| Stderr)
+            .json()
+            .with_filter(env_filter)
+            .boxed(),
+    };
+    let file_enabled = |format| {
+        filter::dynamic_filter_fn(move |metadata, _| {
+            FILE_LOG
+                .get()
+                .is_some_and(|&(level, f)| f == format && *metadata.level() <= level)
+        })
+    };
+    let text_file_log = fmt::layer()
+        .with_writer(|| FileWriter)
         .with_target(true)
-        .without_time()
-        .with_filter(
-            EnvFilter::builder()
-                .with_default_directive(LevelFilter::INFO.into())
-                .from_env_lossy(),
-        );
-    let subscriber = tracing_subscriber::registry().with(stderr_log);
+        .with_ansi(false)
+        .with_filter(file_enabled(LogFormat::Text));
+    let json_file_log = fmt::layer()
+        .with_writer(|| FileWriter)
+        .json()
+        .with_filter(file_enabled(LogFormat::Json));
+    let subscriber = tracing_subscriber::registry().with(vec![
+        stderr_log,
+        text_file_log.boxed(),
+        json_file_log.boxed(),
+    ]);
 
     tracing::subscriber::set_global_default(subscriber).unwrap();
 
     debug!("tracing subscriber set up");
 }
+
+/// Stderr, with the progress bar hidden while writing, or the log pane of the TUI while it is
+/// shown.
+struct Stderr;
+
+impl Write for Stderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if crate::tui::forward_log(&String::from_utf8_lossy(buf)) {
+            return Ok(buf.len());
+        }
+        rustc_less_ignore_debug::run::suspend_progress_bar(|| io::stderr().write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// Most verbose level written to the log files and their format, set by [`log_to_file`].
+static FILE_LOG: OnceLock<(LevelFilter, LogFormat)> = OnceLock::new();
+
+static LOG_FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+
+/// Also write the log to files in `log_dir` of `config`, relative to `base`, rotated as set by
+/// `log_rotation`. Does nothing without a `log_dir`.
+pub(crate) fn log_to_file(config: &Config, base: &Path) -> miette::Result<()> {
+    let Some(log_dir) = &config.log_dir else {
+        return Ok(());
+    };
+    let level: LevelFilter = config
+        .log_level
+        .parse()
+        .map_err(|_| miette!("invalid `log_level` `{}`", config.log_level))?;
+    let dir = base.join(log_dir);
+    std::fs::create_dir_all(&dir).into_diagnostic()?;
+    *LOG_FILE.lock().unwrap() = Some(LogFile {
+        dir: dir.clone(),
+        rotation: config.log_rotation,
+        kept: config.log_files_kept.max(1),
+        started: chrono::Local::now().format("%Y%m%d-%H%M%S").to_string(),
+        current: None,
+        written: 0,
+        part: 0,
+    });
+    let _ = FILE_LOG.set((level, config.log_format));
+    info!("writing the log to `{}`", dir.display());
+    Ok(())
+}
+
+/// The log file being written, started anew as set by `log_rotation`.
+struct LogFile {
+    dir: PathBuf,
+    rotation: LogRotation,
+    kept: usize,
+    /// When the run started, naming its log file(s).
+    started: String,
+    /// The current file, along with the part of its name which changes on rotation.
+    current: Option<(File, String)>,
+    /// Bytes written to the current file.
+    written: u64,
+    /// Files started so far with [`LogRotation::Size`].
+    part: usize,
+}
+
+impl LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        let period = match self.rotation {
+            LogRotation::Run => self.started.clone(),
+            LogRotation::Daily => chrono::Local::now().format("%Y-%m-%d").to_string(),
+            LogRotation::Size(max) => {
+                if self.written > 0 && self.written + buf.len() as u64 > max {
+                    self.part += 1;
+                }
+                format!("{}.{}", self.started, self.part)
+            }
+        };
+        let file = match &mut self.current {
+            Some((file, current)) if *current == period => file,
+            _ => {
+                let path = self
+                    .dir
+                    .join(format!("rustc-less-ignore-debug-{period}.log"));
+                let file = File::options().create(true).append(true).open(path)?;
+                self.written = file.metadata()?.len();
+                self.current = Some((file, period));
+                self.prune()?;
+                &mut self.current.as_mut().unwrap().0
+            }
+        };
+        file.write_all(buf)?;
+        self.written += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Delete the oldest log files beyond the `kept` most recent ones.
+    fn prune(&self) -> io::Result<()> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with("rustc-less-ignore-debug-") && name.ends_with(".log") {
+                files.push((entry.metadata()?.modified()?, entry.path()));
+            }
+        }
+        files.sort();
+        for (_, path) in files.iter().rev().skip(self.kept) {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// The current log file, if [`log_to_file`] was called.
+struct FileWriter;
+
+impl Write for FileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(file) = LOG_FILE.lock().unwrap().as_mut() {
+            file.write(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,54 +1,89 @@
-#![feature(let_chains)]
-
 mod cli;
-mod config;
 mod logging;
-mod run;
+mod tui;
 
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Instant;
 
 use clap::Parser as _;
 use confique::toml::FormatOptions;
 use confique::Config as _;
-use miette::{bail, IntoDiagnostic};
+use miette::{bail, Context, IntoDiagnostic};
 use tracing::*;
 
+use rustc_less_ignore_debug::config::Config;
+use rustc_less_ignore_debug::exit_status::ExitStatus;
+use rustc_less_ignore_debug::{apply_patches, dashboard, history, run, submit, Engine, RunOptions};
+
 use crate::cli::{Cli, Cmd};
-use crate::config::Config;
 
 #[allow(dead_code)]
 const TARGET_TRIPLE: &str = env!("TARGET");
 
-fn main() -> miette::Result<()> {
-    logging::setup_logging();
-
+fn main() -> ExitCode {
+    run::tokens::inherit_jobserver();
     let cli = Cli::parse();
+    logging::setup_logging(!cli.ci(), cli.log_format);
+    if cli.ci() {
+        console::set_colors_enabled(false);
+        console::set_colors_enabled_stderr(false);
+        let _ = miette::set_hook(Box::new(|_| {
+            Box::new(miette::MietteHandlerOpts::new().color(false).build())
+        }));
+    }
+
+    match try_main(cli) {
+        Ok(status) => status.into(),
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitStatus::Error.into()
+        }
+    }
+}
+
+fn try_main(cli: Cli) -> miette::Result<ExitStatus> {
     debug!(?cli);
 
     let exe_path = std::env::current_exe().into_diagnostic()?;
     let config_path = exe_path.parent().unwrap().join("config.toml");
+    let runs_root = exe_path.parent().unwrap().join("runs");
     debug!(?config_path);
     debug!("config exists: {}", config_path.exists());
-    let config = if cli.command != Cmd::GenerateConfig {
+    let config = if matches!(
+        cli.command,
+        Cmd::Run { .. } | Cmd::Estimate { .. } | Cmd::Verify { .. } | Cmd::Bisect { .. }
+    ) {
         info!("trying to read config from `{}`", config_path.display());
         if !config_path.exists() {
             info!("no existing config detected");
             info!("you can generate a default config via `generate-config` command");
             info!("the tool will now exit");
-            return Ok(());
+            return Ok(ExitStatus::PreflightFailure);
         }
 
-        let config = Config::from_file(&config_path)
-            .inspect_err(|e| {
-                warn!("failed to load config from `{}`", config_path.display());
-                warn!("default config values will be used");
-                warn!(?e);
-            })
-            .unwrap_or_default();
+        let config = match Config::from_file(&config_path) {
+            Ok(config) => config,
+            Err(e) => {
+                error!(
+                    "failed to load config from `{}`: {e}",
+                    config_path.display()
+                );
+                info!("fix the config, or regenerate it via `generate-config`");
+                return Ok(ExitStatus::PreflightFailure);
+            }
+        };
         debug!(?config);
+        if let Err(e) = logging::log_to_file(&config, exe_path.parent().unwrap()) {
+            warn!("not writing the log to a file: {e}");
+        }
         config
     } else {
-        Config::default()
+        Config::builder()
+            .load()
+            .into_diagnostic()
+            .wrap_err("failed to build the default config")?
     };
 
     match &cli.command {
@@ -65,15 +100,309 @@ fn main() -> miette::Result<()> {
         Cmd::Run {
             rustc_repo_path,
             report_path,
+            github_annotations,
+            progress_fd,
+            events_fd,
+            batch,
+            jobs,
+            no_cache,
+            incremental,
+            show_output,
+            log_output,
+            record,
+            replay,
+            direct_compiletest,
+            deadline,
+            dedup,
+            commit_branch,
+            branch,
+            allow_detached,
+            emit,
+            notify,
+            json_report,
+            strict,
+            timings,
+            ruleset,
+            auto_discover,
+            bootstrap_config,
+            build_dir,
+            auto_setup,
+            roll_back,
+            ci,
+            tui,
         } => {
-            run::run(
+            let mut config = config;
+            if let Some(ruleset) = ruleset {
+                if !config.rules.is_empty() {
+                    warn!("ignoring the `rules` of the config in favor of `--ruleset`");
+                    config.rules.clear();
+                }
+                config.ruleset = *ruleset;
+            }
+            // Unlike those of the config, paths given on the command line are relative to the
+            // current directory.
+            if let Some(path) = bootstrap_config {
+                config.bootstrap_config = Some(std::path::absolute(path).into_diagnostic()?);
+            }
+            if let Some(path) = build_dir {
+                config.build_dir = Some(std::path::absolute(path).into_diagnostic()?);
+            }
+            if *auto_discover {
+                let suites = run::discover_target_directories(&config, rustc_repo_path);
+                info!(
+                    "discovered {} test suite(s) with candidates under `tests/`",
+                    suites.len()
+                );
+                for (suite, count) in &suites {
+                    info!("  {}: {count} candidate(s)", suite.display());
+                }
+                if suites.is_empty() {
+                    info!("no test under `tests/` has a directive to rewrite, nothing to do");
+                    return Ok(ExitStatus::Success);
+                }
+                config.target_directories = suites.into_keys().collect();
+            }
+            if *ci {
+                // Leave every change whose snapshots had to be blessed to a human.
+                config.snapshot_outlier_lines = 0;
+            }
+            let options = RunOptions {
+                report_path: Some(match report_path {
+                    Some(path) => path.clone(),
+                    None => default_report_path()?,
+                }),
+                github_annotations: *github_annotations || *ci,
+                progress_fd: *progress_fd,
+                progress_bar: !*ci && !*show_output && !*tui && console::user_attended_stderr(),
+                events_fd: *events_fd,
+                batch: *batch,
+                jobs: jobs.unwrap_or(NonZeroUsize::MIN),
+                job_budget: *jobs,
+                incremental: *incremental,
+                show_output: *show_output,
+                log_output: *log_output,
+                record: record.clone(),
+                replay: replay.clone(),
+                direct_compiletest: *direct_compiletest,
+                deadline: deadline.map(|budget| Instant::now() + budget),
+                dedup: *dedup,
+                branch: commit_branch.clone().or_else(|| {
+                    branch.then(|| {
+                        format!(
+                            "less-ignore-debug/{}",
+                            chrono::Local::now().format("%Y-%m-%d")
+                        )
+                    })
+                }),
+                commit_changes: commit_branch.is_some(),
+                allow_detached: *allow_detached,
+                emit: *emit,
+                notify: *notify,
+                json_report: *json_report || *ci,
+                strict: *strict || *ci,
+                timings: *timings,
+                cache_path: (!*no_cache).then(|| runs_root.join("cache.json")),
+                metrics_path: config
+                    .metrics_file
+                    .as_ref()
+                    .map(|metrics_file| exe_path.parent().unwrap().join(metrics_file)),
+                runs_root,
+            };
+            let engine = Engine::new(config, rustc_repo_path.clone(), options);
+            if engine.needs_setup() {
+                let setup = *auto_setup
+                    || (!*ci
+                        && console::user_attended()
+                        && confirm(
+                            "The rustc repo has no bootstrap config. Create one with `x setup`?",
+                        ));
+                if setup {
+                    if let Err(e) = engine.setup() {
+                        eprintln!("Error: {e:?}");
+                        return Ok(ExitStatus::PreflightFailure);
+                    }
+                }
+            }
+            match engine.interrupted_edits() {
+                Ok(interrupted) if !interrupted.is_empty() => {
+                    warn!(
+                        "the previous run died while {} test(s) were edited",
+                        interrupted.len()
+                    );
+                    let roll_back = *roll_back
+                        || (!*ci
+                            && console::user_attended()
+                            && confirm("Roll back the edits of the previous run?"));
+                    if roll_back {
+                        match engine.roll_back() {
+                            Ok(rolled_back) => {
+                                info!("rolled back {} test(s)", rolled_back.len())
+                            }
+                            Err(e) => {
+                                eprintln!("Error: {e:?}");
+                                return Ok(ExitStatus::PreflightFailure);
+                            }
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => warn!("failed to read the journal of the previous run: {e:?}"),
+            }
+            let run_dir = match engine.preflight() {
+                Ok(run_dir) => run_dir,
+                Err(e) => {
+                    eprintln!("Error: {e:?}");
+                    return Ok(ExitStatus::PreflightFailure);
+                }
+            };
+            let runtime = tokio::runtime::Runtime::new()
+                .into_diagnostic()
+                .wrap_err("failed to start the async runtime")?;
+            if *tui {
+                return tui::run(engine, run_dir, runtime);
+            }
+            return runtime.block_on(engine.run(&run_dir));
+        }
+        Cmd::ApplyPatches {
+            rustc_repo_path,
+            patches,
+            check,
+        } => {
+            return apply_patches::apply_patches(rustc_repo_path, patches, *check);
+        }
+        Cmd::Submit {
+            rustc_repo_path,
+            run,
+            remote,
+            repo,
+            base,
+        } => {
+            let options = submit::SubmitOptions {
+                run: run.clone(),
+                remote: remote.clone(),
+                repo: repo.clone(),
+                base: base.clone(),
+            };
+            submit::submit(rustc_repo_path, &runs_root, &options)?;
+        }
+        Cmd::History => {
+            history::print_history(&runs_root)?;
+        }
+        Cmd::Estimate {
+            rustc_repo_path,
+            jobs,
+            batch,
+        } => {
+            let estimate = run::estimate(
                 &config,
-                &exe_path,
-                rustc_repo_path.as_path(),
-                report_path.as_ref().map(PathBuf::as_path),
+                rustc_repo_path,
+                &runs_root,
+                jobs.unwrap_or(NonZeroUsize::MIN),
+                *batch,
             )?;
+            print!("{estimate}");
+        }
+        Cmd::Verify {
+            rustc_repo_path,
+            run,
+        } => {
+            let runtime = tokio::runtime::Runtime::new()
+                .into_diagnostic()
+                .wrap_err("failed to start the async runtime")?;
+            let verification = runtime.block_on(run::verify(
+                &config,
+                rustc_repo_path,
+                &runs_root,
+                run.as_deref(),
+            ))?;
+            print!("{verification}");
+            if !verification.is_green() {
+                return Ok(ExitStatus::NeedsManualReview);
+            }
+        }
+        Cmd::Bisect {
+            rustc_repo_path,
+            run,
+            test,
+            start,
+            end,
+        } => {
+            let options = run::BisectOptions {
+                run: run.clone(),
+                tests: test.clone(),
+                start: start.clone(),
+                end: end.clone(),
+            };
+            let runtime = tokio::runtime::Runtime::new()
+                .into_diagnostic()
+                .wrap_err("failed to start the async runtime")?;
+            let (run, findings) =
+                runtime.block_on(run::bisect(&config, rustc_repo_path, &runs_root, &options))?;
+            for (rel_path, finding) in &findings {
+                println!("{rel_path}: {finding}");
+            }
+            info!("noted in the report of run `{run}`");
+        }
+        Cmd::Explain {
+            rustc_repo_path,
+            test,
+            run,
+        } => {
+            print!(
+                "{}",
+                run::explain(rustc_repo_path, &runs_root, test, run.as_deref())?
+            );
+        }
+        Cmd::IssueDrafts {
+            rustc_repo_path,
+            run,
+            json,
+            file_in,
+            label,
+        } => {
+            let (run, drafts) = run::issue_drafts(rustc_repo_path, &runs_root, run.as_deref())?;
+            if let Some(repo) = file_in {
+                for url in run::file_issues(repo, &drafts, label)? {
+                    println!("{url}");
+                }
+            } else if *json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&drafts).into_diagnostic()?
+                );
+            } else {
+                print!("{}", run::format_issue_drafts(&run, &drafts));
+            }
+        }
+        Cmd::UndoLastRun { rustc_repo_path } => {
+            run::undo_last_run(rustc_repo_path, &runs_root)?;
+        }
+        Cmd::Dashboard { output } => {
+            let output = output
+                .clone()
+                .unwrap_or_else(|| runs_root.join("dashboard.html"));
+            dashboard::write_dashboard(&runs_root, &output)?;
         }
     }
 
-    Ok(())
+    Ok(ExitStatus::Success)
+}
+
+/// `run_summary-<timestamp>.md` under the current working directory.
+fn default_report_path() -> miette::Result<PathBuf> {
+    let cwd = std::env::current_dir()
+        .into_diagnostic()
+        .wrap_err("failed to determine current working directory")?;
+    let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    Ok(cwd.join(format!("run_summary-{timestamp}.md")))
+}
+
+/// Ask a yes/no `question` on the terminal, defaulting to no.
+fn confirm(question: &str) -> bool {
+    let term = console::Term::stderr();
+    if term.write_str(&format!("{question} [y/N] ")).is_err() {
+        return false;
+    }
+    term.read_line()
+        .is_ok_and(|answer| matches!(answer.trim(), "y" | "Y" | "yes"))
 }
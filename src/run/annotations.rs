@@ -0,0 +1,82 @@
+//! GitHub Actions workflow commands, see
+//! <https://docs.github.com/en/actions/using-workflows/workflow-commands-for-github-actions>.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use tracing::*;
+
+use super::directives::{self, Syntax};
+use super::{rules, RunOutcome, TestRecord};
+use crate::config::Config;
+
+/// Print a `::warning` annotation to stdout for every directive of the rules of `config` which is
+/// still present after the run.
+pub(super) fn emit(
+    config: &Config,
+    rustc_repo_path: &Path,
+    records: &BTreeMap<String, TestRecord>,
+) {
+    let rules = rules::resolve(config);
+    for (path, record) in records {
+        if !record.outcome.keeps_directive() {
+            continue;
+        }
+        let src = match std::fs::read_to_string(rustc_repo_path.join(path)) {
+            Ok(src) => src,
+            Err(e) => {
+                warn!("failed to read `{path}` for annotations: {e}");
+                continue;
+            }
+        };
+        for directive in directives::parse_with(&src, Syntax::of(Path::new(path))) {
+            if !rules.iter().any(|rule| rule.matches(&directive)) {
+                continue;
+            }
+            let Some(message) = message(directive.name, record) else {
+                continue;
+            };
+            println!(
+                "{}",
+                warning(path, directive.line, directive.name, &message)
+            );
+        }
+    }
+}
+
+/// Why the `name` directive of a test decided as `record` is still there.
+fn message(name: &str, record: &TestRecord) -> Option<String> {
+    let message = match record.outcome {
+        RunOutcome::UnmodifiedOk => format!(
+            "`{name}` could not be removed or replaced: the test fails without it either way"
+        ),
+        RunOutcome::Ignored => {
+            format!("`{name}` was kept: the test is ignored for other reasons")
+        }
+        RunOutcome::NeedsManualReview => format!(
+            "`{name}` needs manual review: {}",
+            record.review_hint.as_deref().unwrap_or("no hint recorded")
+        ),
+        RunOutcome::RemoveOk | RunOutcome::ReplaceOk => return None,
+    };
+    Some(message)
+}
+
+fn warning(path: &str, line: usize, name: &str, message: &str) -> String {
+    format!(
+        "::warning file={},line={line},title={}::{}",
+        escape_property(path),
+        escape_property(name),
+        escape_data(message)
+    )
+}
+
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
@@ -0,0 +1,44 @@
+//! A compressed archive of the candidate tests and their snapshots, taken before a run edits any of
+//! them, so that they can be recovered without git (e.g. with uncommitted changes in the tree):
+//! `tar -xzf runs/<run>/backup.tar.gz -C <rustc repo>`.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use miette::{Context, IntoDiagnostic, Result};
+use tracing::*;
+
+use super::{paths, snapshots};
+
+const ARCHIVE: &str = "backup.tar.gz";
+
+/// Archive the `targets` and their snapshots under `run_dir`, with repo-relative paths. Returns the
+/// path of the archive.
+pub(super) fn archive<'a>(
+    rustc_repo_path: &Path,
+    run_dir: &Path,
+    targets: impl IntoIterator<Item = &'a PathBuf>,
+) -> Result<PathBuf> {
+    let path = run_dir.join(ARCHIVE);
+    let write = || -> std::io::Result<usize> {
+        let file = File::create(&path)?;
+        let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+        let mut archived = 0;
+        for target in targets {
+            for file in std::iter::once(target.clone()).chain(snapshots::companions(target)) {
+                builder
+                    .append_path_with_name(&file, paths::repo_relative(rustc_repo_path, &file))?;
+                archived += 1;
+            }
+        }
+        builder.into_inner()?.finish()?.sync_all()?;
+        Ok(archived)
+    };
+    let archived = write()
+        .into_diagnostic()
+        .wrap_err(format!("failed to write `{}`", path.display()))?;
+    info!("backed up {archived} file(s) to `{}`", path.display());
+    Ok(path)
+}
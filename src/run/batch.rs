@@ -0,0 +1,264 @@
+//! The `--batch` strategy: instead of one `x test` invocation per strategy per test, edit every
+//! candidate of a suite at once, run the suite's candidates in a single invocation (filtered with
+//! `--test-args`) and only revert the tests which failed.
+//!
+//! For each rewrite rule in turn (by default: remove the directive, then replace it), apply the
+//! rule to every candidate of the suite it applies to which has not passed yet, and run them all.
+//! Passing tests keep the edit, the others are tried with the next rule, and are left unmodified
+//! once no rule is left.
+//!
+//! The per-test sanity check of the unmodified test is skipped: its whole point is to be cheap
+//! relative to a full run, which it isn't when the stage 1 rebuild dominates.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use miette::{Context, IntoDiagnostic, Result};
+use tracing::*;
+
+use super::directives::Syntax;
+use super::rules::{self, Rule};
+use super::runner::TestRunner;
+use super::test_results::{self, TestStatus};
+use super::{
+    diagnostics, hooks, paths, review, write_guard, AppliedEdit, Decision, RunError, RunOutcome,
+    INTERRUPTED,
+};
+use super::{test_logs, timings};
+use crate::config::Config;
+
+/// Decisions and the `x` time attributed to each test. Tests which were not processed
+/// because the run was interrupted are missing.
+pub(super) async fn run_batch(
+    config: &Config,
+    rustc_repo_path: &Path,
+    runner: &dyn TestRunner,
+    targets: impl IntoIterator<Item = impl AsRef<Path>>,
+) -> Result<BTreeMap<PathBuf, (Decision, Duration)>> {
+    let mut suites: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for target in targets {
+        let target = target.as_ref();
+        let rel_path = paths::repo_relative(rustc_repo_path, target);
+        suites
+            .entry(paths::suite_of(&rel_path).to_string())
+            .or_default()
+            .push(target.to_path_buf());
+    }
+
+    let mut results = BTreeMap::new();
+    for (suite, targets) in suites {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            break;
+        }
+        info!(
+            "running {} candidate(s) of `{suite}` as a batch",
+            targets.len()
+        );
+        run_suite(
+            config,
+            rustc_repo_path,
+            runner,
+            &suite,
+            &targets,
+            &mut results,
+        )
+        .await?;
+    }
+    Ok(results)
+}
+
+async fn run_suite(
+    config: &Config,
+    rustc_repo_path: &Path,
+    runner: &dyn TestRunner,
+    suite: &str,
+    targets: &[PathBuf],
+    results: &mut BTreeMap<PathBuf, (Decision, Duration)>,
+) -> Result<()> {
+    let mut candidates = Vec::new();
+    let mut sources = BTreeMap::new();
+    for target in targets {
+        let src = std::fs::read_to_string(target)
+            .into_diagnostic()
+            .wrap_err(format!("failed to read `{}`", target.display()))?;
+        let rel_path = paths::repo_relative(rustc_repo_path, target);
+        if let Err(e) = write_guard::check(target) {
+            warn!("{e}");
+            results.insert(
+                target.clone(),
+                (Decision::needs_review(e.to_string()), Duration::ZERO),
+            );
+            continue;
+        }
+        let flag = review::conflicting_flags(&src)
+            .or_else(|| review::filecheck_sensitive(config, &rel_path, &src));
+        let hint = match flag {
+            Some(flag) => {
+                let rules = rules::resolve(config);
+                diagnostics::flagged(&rel_path, &src, Syntax::of(target), &rules, &flag);
+                Some(flag.hint)
+            }
+            None => hooks::pre_test(config, rustc_repo_path, target).await?,
+        };
+        match hint {
+            Some(hint) => {
+                results.insert(
+                    target.clone(),
+                    (Decision::needs_review(hint), Duration::ZERO),
+                );
+            }
+            None => {
+                candidates.push(target.as_path());
+                sources.insert(target.as_path(), src);
+            }
+        }
+    }
+
+    let mut pending: BTreeMap<&Path, Duration> = candidates
+        .into_iter()
+        .map(|t| (t, Duration::ZERO))
+        .collect();
+    for rule in rules::resolve(config) {
+        if pending.is_empty() || INTERRUPTED.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let applicable: Vec<&Path> = pending
+            .keys()
+            .copied()
+            .filter(|t| !rule.apply(&sources[t], Syntax::of(t)).changes.is_empty())
+            .collect();
+        for (target, (decision, duration)) in
+            run_pass(config, rustc_repo_path, runner, suite, &applicable, &rule).await?
+        {
+            let duration = duration + pending[target];
+            match decision {
+                Ok(decision) => {
+                    pending.remove(target);
+                    results.insert(target.to_path_buf(), (decision.kept_with(&rule), duration));
+                }
+                Err(RunError::TestFailure) => {
+                    pending.insert(target, duration);
+                }
+                Err(e) => Err(e)?,
+            }
+        }
+    }
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    for (target, duration) in pending {
+        results.insert(
+            target.to_path_buf(),
+            (Decision::unmodified(RunOutcome::UnmodifiedOk), duration),
+        );
+    }
+    Ok(())
+}
+
+/// A test of a pass, its decision and its duration: as measured by libtest if reported, its share
+/// of the pass duration otherwise.
+type Settled<'a> = (&'a Path, (Result<Decision, RunError>, Duration));
+
+/// Apply `rule` to every target, run them all with a single `x` invocation on their suite and settle each edit
+/// according to its own result. If the run is interrupted, every edit is reverted and nothing is
+/// returned.
+async fn run_pass<'a>(
+    config: &Config,
+    rustc_repo_path: &Path,
+    runner: &dyn TestRunner,
+    suite: &str,
+    targets: &[&'a Path],
+    rule: &Rule,
+) -> Result<Vec<Settled<'a>>> {
+    if targets.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut applied = Vec::with_capacity(targets.len());
+    for target in targets {
+        match AppliedEdit::apply(target, |src| rule.apply(src, Syntax::of(target))) {
+            Ok(a) => {
+                a.announce(rustc_repo_path);
+                applied.push(a);
+            }
+            Err(e) => {
+                revert_all(&applied)?;
+                Err(e)?;
+            }
+        }
+    }
+
+    let start = Instant::now();
+    let output = match runner
+        .run_suite(config, rustc_repo_path, suite, targets)
+        .await
+    {
+        Ok(output) => output,
+        Err(e) => {
+            revert_all(&applied)?;
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                return Ok(Vec::new());
+            }
+            return Err(e);
+        }
+    };
+    let share = start.elapsed() / targets.len() as u32;
+
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        revert_all(&applied)?;
+        return Ok(Vec::new());
+    }
+
+    let results = {
+        let _timer = timings::start(timings::Phase::Parse);
+        test_results::parse_events(&output.stdout)
+    };
+    if results.is_empty() && !output.status.success() {
+        revert_all(&applied)?;
+        miette::bail!(
+            "`x test` failed without reporting any test results:\n{}",
+            output.stderr
+        );
+    }
+
+    let what = format!("the batch of `{suite}` edited by `{}`", rule.name);
+    let mut settled = Vec::with_capacity(applied.len());
+    for applied in applied {
+        let target = applied.target;
+        let rel_path = paths::repo_relative(rustc_repo_path, target);
+        test_logs::append(&rel_path, &what, &output);
+        let result = results.get(paths::test_name(&rel_path));
+        let duration = result.and_then(|r| r.exec_time).unwrap_or(share);
+        let decision = match result.map(|r| r.status) {
+            Some(TestStatus::Passed) => {
+                applied.settle(config, rustc_repo_path, Ok(RunOutcome::RemoveOk))
+            }
+            Some(TestStatus::Ignored) => {
+                applied.settle(config, rustc_repo_path, Ok(RunOutcome::Ignored))
+            }
+            Some(TestStatus::Failed) => {
+                applied.settle(config, rustc_repo_path, Err(RunError::TestFailure))
+            }
+            None => {
+                warn!("no result for `{rel_path}` in the batch output");
+                applied.revert().map(|()| {
+                    Decision::needs_review(
+                        "no result for the test was found in the output of the batch run"
+                            .to_string(),
+                    )
+                })
+            }
+        };
+        settled.push((target, (decision, duration)));
+    }
+    Ok(settled)
+}
+
+fn revert_all(applied: &[AppliedEdit<'_>]) -> Result<()> {
+    for a in applied {
+        a.revert()?;
+    }
+    Ok(())
+}
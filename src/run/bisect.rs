@@ -0,0 +1,316 @@
+//! The `bisect` command: find out with `cargo-bisect-rustc` since when the tests a run left with
+//! their directive (`UnmodifiedOk`) fail without it, to tell long-standing failures apart from
+//! recent regressions worth filing upstream. The findings are stored in the state of the run and
+//! shown in its report and issue drafts.
+//!
+//! Each test is bisected over nightlies by compiling it (and running it if it is a `run-pass` test)
+//! with `rustc` as edited by the first rule which changes it. Nightlies are built without debug
+//! assertions, so the test is compiled with `-Cdebug-assertions=yes`, which turns on the checks of
+//! the standard library code instantiated in it. Tests needing more than that, e.g. auxiliary
+//! crates, are not bisected.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use miette::{bail, Context, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tracing::*;
+
+use super::directives::{self, Syntax};
+use super::explain::rules_of;
+use super::issue_drafts::tried_edit;
+use super::{process, report, RunOutcome};
+use crate::config::Config;
+use crate::history;
+
+/// Which tests of which run to bisect, and over which nightlies.
+#[derive(Debug, Default)]
+pub struct BisectOptions {
+    /// Run whose tests to bisect, the latest one with a state if `None`.
+    pub run: Option<String>,
+    /// Repo-relative paths of the tests to bisect, all the `UnmodifiedOk` ones of the run if empty.
+    pub tests: Vec<String>,
+    /// First nightly to try, e.g. `2024-01-01`. `cargo-bisect-rustc` searches back from `end` if
+    /// `None`.
+    pub start: Option<String>,
+    /// Last nightly to try, the latest one if `None`.
+    pub end: Option<String>,
+}
+
+/// What `cargo-bisect-rustc` found out about a test.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "finding", rename_all = "snake_case")]
+pub enum Bisection {
+    /// The test started failing without its directive with this nightly (and commit, if it could
+    /// be narrowed down).
+    Regressed {
+        nightly: Option<String>,
+        commit: Option<String>,
+    },
+    /// The test already failed with the first nightly tried.
+    FailsThroughout { start: Option<String> },
+    /// The test passed with every nightly tried, e.g. because it depends on something else than
+    /// the toolchain.
+    NotReproduced { end: Option<String> },
+}
+
+impl fmt::Display for Bisection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bisection::Regressed { nightly, commit } => {
+                write!(f, "started failing with ")?;
+                match nightly {
+                    Some(nightly) => write!(f, "`{nightly}`")?,
+                    None => write!(f, "an unknown nightly")?,
+                }
+                if let Some(commit) = commit {
+                    write!(f, " (rust-lang/rust@{commit})")?;
+                }
+                Ok(())
+            }
+            Bisection::FailsThroughout { start } => match start {
+                Some(start) => write!(f, "already failed with `{start}`, not a recent regression"),
+                None => write!(
+                    f,
+                    "failed with every nightly tried, not a recent regression"
+                ),
+            },
+            Bisection::NotReproduced { end } => match end {
+                Some(end) => write!(f, "passed with every nightly up to `{end}`"),
+                None => write!(f, "passed with every nightly tried"),
+            },
+        }
+    }
+}
+
+/// Bisect the tests selected by `options`, recording the findings in the state and report of
+/// their run, and return the id of that run along with the findings.
+pub async fn bisect(
+    config: &Config,
+    rustc_repo_path: &Path,
+    runs_root: &Path,
+    options: &BisectOptions,
+) -> Result<(String, BTreeMap<String, Bisection>)> {
+    let found = history::list_runs(runs_root)?
+        .into_iter()
+        .rev()
+        .filter(|(run_dir, _)| options.run.as_deref().is_none_or(|id| run_dir.id == id))
+        .find_map(|(run_dir, state)| Some((run_dir, state?)));
+    let Some((run_dir, mut state)) = found else {
+        match &options.run {
+            Some(id) => bail!("no run `{id}` with a state under `{}`", runs_root.display()),
+            None => bail!("no run with a state under `{}`", runs_root.display()),
+        }
+    };
+    let tests: Vec<String> = if options.tests.is_empty() {
+        state
+            .records
+            .iter()
+            .filter(|(_, record)| record.outcome == RunOutcome::UnmodifiedOk)
+            .map(|(rel_path, _)| rel_path.clone())
+            .collect()
+    } else {
+        for rel_path in &options.tests {
+            if !state.records.contains_key(rel_path) {
+                bail!("run `{}` did not process `{rel_path}`", run_dir.id);
+            }
+        }
+        options.tests.clone()
+    };
+
+    let rules = rules_of(&state);
+    let mut findings = BTreeMap::new();
+    for rel_path in tests {
+        let src = std::fs::read_to_string(rustc_repo_path.join(&rel_path))
+            .into_diagnostic()
+            .wrap_err(format!("failed to read `{rel_path}`"))?;
+        let Some(edited) = tried_edit(&src, &rel_path, &rules) else {
+            warn!("not bisecting `{rel_path}`: no rule changes it");
+            continue;
+        };
+        let Some(script) = script(&edited) else {
+            warn!(
+                "not bisecting `{rel_path}`: only standalone `run-pass`, `build-pass` and \
+                 `check-pass` tests can be"
+            );
+            continue;
+        };
+        info!("bisecting `{rel_path}`, this takes a while");
+        let dir = run_dir
+            .path
+            .join("bisect")
+            .join(rel_path.replace('/', "__"));
+        let finding = bisect_one(&dir, &edited, &script, options).await?;
+        match finding {
+            Some(finding) => {
+                info!("`{rel_path}` {finding}");
+                findings.insert(rel_path, finding);
+            }
+            None => warn!(
+                "`cargo bisect-rustc` did not reach a conclusion about `{rel_path}`, see `{}`",
+                dir.join("bisect.log").display()
+            ),
+        }
+    }
+
+    state.bisections.extend(findings.clone());
+    state.save(&run_dir.state_path())?;
+    let report_path = run_dir.report_path();
+    std::fs::write(&report_path, report::format_report(config, &state))
+        .into_diagnostic()
+        .wrap_err(format!("failed to write `{}`", report_path.display()))?;
+    Ok((run_dir.id, findings))
+}
+
+/// Run `cargo bisect-rustc` on `edited` in `dir`, keeping its output in `bisect.log` there.
+async fn bisect_one(
+    dir: &Path,
+    edited: &str,
+    script: &str,
+    options: &BisectOptions,
+) -> Result<Option<Bisection>> {
+    std::fs::create_dir_all(dir)
+        .into_diagnostic()
+        .wrap_err(format!("failed to create `{}`", dir.display()))?;
+    std::fs::write(dir.join("test.rs"), edited).into_diagnostic()?;
+    let script_path = dir.join("bisect.sh");
+    std::fs::write(&script_path, script).into_diagnostic()?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&script_path, std::fs::Permissions::from_mode(0o755))
+            .into_diagnostic()?;
+    }
+
+    let mut command = Command::new("cargo");
+    command
+        .current_dir(dir)
+        .args(["bisect-rustc", "--without-cargo", "--test-dir"])
+        .arg(dir)
+        .arg("--script")
+        .arg(&script_path);
+    if let Some(start) = &options.start {
+        command.arg("--start").arg(start);
+    }
+    if let Some(end) = &options.end {
+        command.arg("--end").arg(end);
+    }
+    let output = process::run(&mut command)
+        .await
+        .into_diagnostic()
+        .wrap_err("error trying to invoke `cargo bisect-rustc`, is it installed?")?;
+    let log = format!("{}\n{}", output.stdout, output.stderr);
+    std::fs::write(dir.join("bisect.log"), &log).into_diagnostic()?;
+    Ok(parse_output(&log, options))
+}
+
+/// The shell script failing if the toolchain under test fails the edited test, or `None` if the
+/// test cannot be checked that way.
+fn script(edited: &str) -> Option<String> {
+    let mut mode = None;
+    // First, so that the flags of the test override it.
+    let mut args = vec!["-Cdebug-assertions=yes".to_string()];
+    for directive in directives::parse_with(edited, Syntax::RUST) {
+        if !directive.revisions.is_empty() {
+            continue;
+        }
+        match directive.name {
+            "run-pass" | "build-pass" | "check-pass" => mode = Some(directive.name),
+            "edition" => args.push(format!("--edition={}", directive.value?.trim())),
+            "compile-flags" => args.extend(directive.value?.split_whitespace().map(String::from)),
+            name if name.starts_with("aux-") || name == "revisions" => return None,
+            _ => {}
+        }
+    }
+    let mode = mode?;
+    if mode == "check-pass" {
+        args.push("--emit=metadata".to_string());
+    }
+    let args: String = args.iter().map(|arg| format!(" {}", quote(arg))).collect();
+    let mut script = format!("#!/bin/sh\nset -e\nrustc test.rs -o test{args}\n");
+    if mode == "run-pass" {
+        script.push_str("./test\n");
+    }
+    Some(script)
+}
+
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// The finding reported by `cargo bisect-rustc` in `output`.
+fn parse_output(output: &str, options: &BisectOptions) -> Option<Bisection> {
+    let value_of = |prefix: &str| {
+        output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(prefix))
+            .map(|value| value.trim().to_string())
+    };
+    if let Some(nightly) = value_of("regressed nightly:") {
+        let commit = value_of("regressed commit:")
+            .map(|url| url.rsplit('/').next().unwrap_or(&url).to_string());
+        return Some(Bisection::Regressed {
+            nightly: Some(nightly),
+            commit,
+        });
+    }
+    if output.contains("must not reproduce the regression") {
+        return Some(Bisection::FailsThroughout {
+            start: options.start.clone(),
+        });
+    }
+    if output.contains("does not reproduce the regression") {
+        return Some(Bisection::NotReproduced {
+            end: options.end.clone(),
+        });
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bisects_standalone_tests_and_reads_the_findings() {
+        assert_eq!(
+            script("//@ run-pass\n//@ edition: 2021\n//@ compile-flags: -O\n\nfn main() {}\n")
+                .as_deref(),
+            Some(
+                "#!/bin/sh\nset -e\nrustc test.rs -o test '-Cdebug-assertions=yes' '--edition=2021' \
+                 '-O'\n./test\n"
+            )
+        );
+        assert!(script("//@ check-pass\n//@ aux-build: a.rs\n").is_none());
+        assert!(script("//@ run-fail\n").is_none());
+
+        let options = BisectOptions {
+            start: Some("2024-01-01".to_string()),
+            ..Default::default()
+        };
+        let found = parse_output(
+            "searched nightlies: from nightly-2024-01-01 to nightly-2024-06-01\n\
+             regressed nightly: nightly-2024-03-15\n\
+             searched commit range: https://github.com/rust-lang/rust/compare/a...b\n\
+             regressed commit: https://github.com/rust-lang/rust/commit/0123abc\n",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(
+            found.to_string(),
+            "started failing with `nightly-2024-03-15` (rust-lang/rust@0123abc)"
+        );
+        let found = parse_output(
+            "ERROR: the start of the range (nightly-2024-01-01) must not reproduce the regression\n",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(
+            found.to_string(),
+            "already failed with `2024-01-01`, not a recent regression"
+        );
+        assert!(parse_output("error: could not download\n", &options).is_none());
+    }
+}
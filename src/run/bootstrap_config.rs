@@ -0,0 +1,160 @@
+//! Checking the bootstrap config of the `rustc` repo for settings which silently change what a
+//! passing test means.
+
+use std::io::Write as _;
+use std::path::Path;
+
+use miette::{bail, Context, IntoDiagnostic, Result};
+use toml::{Table, Value};
+use tracing::*;
+
+use super::{manifest, platform};
+use crate::config::Config;
+
+/// A setting of the bootstrap config which distorts the results, with what to do about it.
+#[derive(Debug, PartialEq, Eq)]
+struct Finding {
+    problem: String,
+    suggestion: String,
+}
+
+/// Warn about the risky settings of the bootstrap config the runs use: the `bootstrap_config` of
+/// `config`, which must exist, or else the one of the repo at `rustc_repo_path`, if it has one.
+pub(super) fn check(config: &Config, rustc_repo_path: &Path) -> Result<()> {
+    let Some((name, path)) = manifest::bootstrap_config_in_use(config, rustc_repo_path) else {
+        warn!(
+            "no `bootstrap.toml` or `config.toml` in `{}`, bootstrap will use its defaults, which \
+             don't enable debug assertions",
+            rustc_repo_path.display()
+        );
+        warn!("  suggestion: pass `--auto-setup` to create one with `x setup`");
+        return Ok(());
+    };
+    if config.bootstrap_config.is_some() && !path.is_file() {
+        bail!("the bootstrap config `{}` does not exist", path.display());
+    }
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            warn!("failed to read `{name}`: {e}");
+            return Ok(());
+        }
+    };
+    let bootstrap_config: Table = match contents.parse() {
+        Ok(bootstrap_config) => bootstrap_config,
+        Err(e) => {
+            warn!("failed to parse `{name}`, not checking it for risky settings: {e}");
+            return Ok(());
+        }
+    };
+    for finding in findings(&bootstrap_config) {
+        warn!("`{name}`: {}", finding.problem);
+        warn!("  suggestion: {}", finding.suggestion);
+    }
+    Ok(())
+}
+
+fn findings(config: &Table) -> Vec<Finding> {
+    let get = |section: &str, key: &str| config.get(section)?.as_table()?.get(key);
+    let mut findings = Vec::new();
+
+    if matches!(
+        get("rust", "optimize"),
+        Some(Value::Boolean(false) | Value::Integer(0))
+    ) {
+        findings.push(Finding {
+            problem:
+                "`rust.optimize = false` builds an unoptimized compiler and standard library, \
+                      which makes tests slow enough to time out and changes the output of \
+                      codegen and assembly tests"
+                    .to_string(),
+            suggestion: "remove `rust.optimize` or set it to `true`".to_string(),
+        });
+    }
+
+    match get("rust", "download-rustc") {
+        None | Some(Value::Boolean(false)) => {}
+        Some(value) => findings.push(Finding {
+            problem: format!(
+                "`rust.download-rustc = {value}` tests a compiler built by CI, with CI's debug \
+                 assertions settings rather than yours, so tests may pass or fail regardless of \
+                 `ignore-debug`"
+            ),
+            suggestion: "set `rust.download-rustc = false`".to_string(),
+        }),
+    }
+
+    match get("llvm", "download-ci-llvm") {
+        Some(Value::Boolean(true)) | Some(Value::String(_)) => {}
+        Some(value) => findings.push(Finding {
+            problem: format!(
+                "`llvm.download-ci-llvm = {value}` builds LLVM from source, which dominates the \
+                 first run and uses your `llvm.assertions` rather than the ones the tests are \
+                 usually run with"
+            ),
+            suggestion: "set `llvm.download-ci-llvm = \"if-unchanged\"`".to_string(),
+        }),
+        None if config.get("profile").is_none() => findings.push(Finding {
+            problem: "`llvm.download-ci-llvm` is not set and there is no `profile` to pick a \
+                      default, so LLVM may be built from source"
+                .to_string(),
+            suggestion: "set `llvm.download-ci-llvm = \"if-unchanged\"`".to_string(),
+        }),
+        None => {}
+    }
+
+    findings
+}
+
+/// The `x setup` profile used by [`setup`]: it builds the compiler from source against CI's LLVM,
+/// which is what the tests need to be run against.
+const SETUP_PROFILE: &str = "compiler";
+
+/// Whether the repo at `rustc_repo_path` has no bootstrap config yet, see [`setup`].
+pub(super) fn is_missing(rustc_repo_path: &Path) -> bool {
+    manifest::find_bootstrap_config(rustc_repo_path).is_none()
+}
+
+/// Create a bootstrap config suitable for running the tests with debug assertions with
+/// `x setup`, which may ask its own questions (git hooks, editor settings) on the terminal.
+pub(super) fn setup(rustc_repo_path: &Path) -> Result<()> {
+    info!(
+        "running `x setup {SETUP_PROFILE}` in `{}`",
+        rustc_repo_path.display()
+    );
+    let launcher = platform::launcher().unwrap_or(platform::Launcher::X);
+    let status = platform::x_command(launcher, rustc_repo_path, "setup")
+        .arg(SETUP_PROFILE)
+        .status()
+        .into_diagnostic()
+        .wrap_err("failed to invoke `x setup`")?;
+    if !status.success() {
+        bail!("`x setup {SETUP_PROFILE}` failed ({status})");
+    }
+    let Some((name, path)) = manifest::find_bootstrap_config(rustc_repo_path) else {
+        bail!("`x setup {SETUP_PROFILE}` did not create a bootstrap config");
+    };
+
+    let contents = std::fs::read_to_string(&path)
+        .into_diagnostic()
+        .wrap_err(format!("failed to read `{name}`"))?;
+    let config: Table = contents
+        .parse()
+        .into_diagnostic()
+        .wrap_err(format!("failed to parse `{name}`"))?;
+    if config.contains_key("rust") {
+        warn!("`{name}` already has a `[rust]` section, make sure it enables `debug-assertions`");
+        return Ok(());
+    }
+    // The profile doesn't enable debug assertions, which is the whole point of the runs.
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .into_diagnostic()
+        .wrap_err(format!("failed to open `{name}`"))?;
+    writeln!(file, "\n[rust]\ndebug-assertions = true")
+        .into_diagnostic()
+        .wrap_err(format!("failed to write `{name}`"))?;
+    info!("created `{name}` with the `{SETUP_PROFILE}` profile and debug assertions");
+    Ok(())
+}
@@ -0,0 +1,268 @@
+//! Waiting for bootstrap activity of the user in the same `rustc` repo (typically a manual
+//! `x build` going on in parallel) before invoking `x`, rather than interleaving with it, which
+//! makes for slow and flaky results.
+//!
+//! Bootstrap takes an exclusive `flock` on `<build dir>/lock` (which contains the pid of the holder)
+//! and otherwise blocks until it is released. Probing the lock before spawning `x` keeps the wait
+//! out of [`Config::timeout_secs`](crate::config::Config::timeout_secs) and lets us say what we
+//! are waiting for. The lock is only held while bootstrap builds, and not at all with
+//! `--bypass-bootstrap-lock`, so on Linux the `x` processes running in the repo are looked for
+//! too, see `detect_user_bootstrap` in the config.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use tracing::*;
+
+use super::{build_dir, summary, INTERRUPTED};
+use crate::config::Config;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// What bootstrap prints when it finds the lock taken, see [`waited_for_lock`].
+const LOCKED_MESSAGE: &str = "build directory locked by process";
+
+/// Wait until no other process holds the build directory lock and, unless disabled, until no
+/// `x` the run did not start is running in the `rustc` repo, polling with exponential backoff.
+/// Returns early if the run is interrupted.
+pub(super) async fn wait_until_free(config: &Config, rustc_repo_path: &Path) {
+    let lock_path = build_dir(config, rustc_repo_path).join("lock");
+    // `x` runs elsewhere than among our descendants there, looking just like the user's.
+    let detect_processes = config.detect_user_bootstrap
+        && config.ssh_host.is_none()
+        && config.container_image.is_none();
+    let busy = || {
+        holder(&lock_path)
+            .map(|pid| {
+                format!(
+                    "`{}` is held by process {pid} (another `x` invocation?)",
+                    lock_path.display()
+                )
+            })
+            .or_else(|| {
+                let (pid, command) = detect_processes
+                    .then(|| user_bootstrap(rustc_repo_path))
+                    .flatten()?;
+                Some(format!(
+                    "`{command}` (process {pid}) is running bootstrap in the `rustc` repo"
+                ))
+            })
+    };
+    let Some(reason) = busy() else {
+        return;
+    };
+    warn!("pausing: {reason}, resuming once it is done");
+    let start = Instant::now();
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        tokio::time::sleep(backoff).await;
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            break;
+        }
+        match busy() {
+            Some(reason) => debug!("still waiting: {reason}"),
+            None => break,
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+    info!(
+        "resumed after waiting {}",
+        summary::format_duration(start.elapsed())
+    );
+}
+
+/// Whether bootstrap reported waiting for the lock in `output`, i.e. it was taken by someone else
+/// between [`wait_until_free`] and the start of `x`.
+pub(super) fn waited_for_lock(output: &str) -> bool {
+    output.contains(LOCKED_MESSAGE)
+}
+
+/// The pid recorded in the lock file if the lock is held, `"unknown"` if it can't be read.
+#[cfg(unix)]
+fn holder(lock_path: &Path) -> Option<String> {
+    use std::os::fd::AsRawFd;
+
+    let file = std::fs::File::open(lock_path).ok()?;
+    // SAFETY: `file` stays open across both calls.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        // Not held by anyone else. Closing the file would release our lock too.
+        unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) };
+        return None;
+    }
+    let error = std::io::Error::last_os_error();
+    if error.raw_os_error() != Some(libc::EWOULDBLOCK) {
+        debug!("cannot probe `{}`: {error}", lock_path.display());
+        return None;
+    }
+    let pid = std::fs::read_to_string(lock_path).unwrap_or_default();
+    let pid = pid.trim();
+    Some(if pid.is_empty() { "unknown" } else { pid }.to_string())
+}
+
+#[cfg(not(unix))]
+fn holder(_lock_path: &Path) -> Option<String> {
+    None
+}
+
+/// A bootstrap process running in `rustc_repo_path` which is not a descendant of this one, with
+/// its command line.
+#[cfg(target_os = "linux")]
+fn user_bootstrap(rustc_repo_path: &Path) -> Option<(u32, String)> {
+    let repo = rustc_repo_path.canonicalize().ok()?;
+    let mut parents = BTreeMap::new();
+    let mut bootstraps = Vec::new();
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|pid| pid.parse().ok()) else {
+            continue;
+        };
+        let proc_dir = entry.path();
+        let Some(ppid) = std::fs::read_to_string(proc_dir.join("stat"))
+            .ok()
+            .and_then(|stat| parent_pid(&stat))
+        else {
+            continue;
+        };
+        parents.insert(pid, ppid);
+        let args: Vec<String> = std::fs::read(proc_dir.join("cmdline"))
+            .unwrap_or_default()
+            .split(|&b| b == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+        let in_repo =
+            std::fs::read_link(proc_dir.join("cwd")).is_ok_and(|cwd| cwd.starts_with(&repo));
+        if in_repo && is_bootstrap(&args) {
+            bootstraps.push((pid, args.join(" ")));
+        }
+    }
+    let this = std::process::id();
+    bootstraps
+        .into_iter()
+        .find(|(pid, _)| !descends_from(*pid, this, &parents))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn user_bootstrap(_rustc_repo_path: &Path) -> Option<(u32, String)> {
+    None
+}
+
+/// The parent pid in the contents of `/proc/<pid>/stat`, after the command name in parentheses
+/// (which may itself contain spaces and parentheses) and the state.
+fn parent_pid(stat: &str) -> Option<u32> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Whether the command line `args` runs bootstrap: `x`, `x.py` or `x.ps1` (directly or through an
+/// interpreter), or the `bootstrap` binary they start.
+fn is_bootstrap(args: &[String]) -> bool {
+    let name = |arg: &String| {
+        Path::new(arg)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    let Some(program) = args.first().map(name) else {
+        return false;
+    };
+    match program.as_str() {
+        "x" | "x.py" | "x.ps1" | "bootstrap" => true,
+        interpreter
+            if interpreter.starts_with("python")
+                || matches!(interpreter, "py" | "sh" | "bash" | "pwsh" | "powershell") =>
+        {
+            args.iter()
+                .skip(1)
+                .find(|arg| !arg.starts_with('-'))
+                .is_some_and(|script| matches!(name(script).as_str(), "x" | "x.py" | "x.ps1"))
+        }
+        _ => false,
+    }
+}
+
+/// Whether `pid` is `ancestor` or one of its descendants, going by the `parents` of each process.
+fn descends_from(mut pid: u32, ancestor: u32, parents: &BTreeMap<u32, u32>) -> bool {
+    // Guards against cycles in a table read while processes come and go.
+    for _ in 0..parents.len() + 1 {
+        if pid == ancestor {
+            return true;
+        }
+        match parents.get(&pid) {
+            Some(&parent) if parent != 0 => pid = parent,
+            _ => return false,
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use confique::Config as _;
+
+    use super::*;
+    use crate::run::tests::Repo;
+
+    #[test]
+    fn recognizes_bootstrap_processes_of_the_user() {
+        let args = |line: &str| line.split(' ').map(String::from).collect::<Vec<_>>();
+        assert!(is_bootstrap(&args("x build --stage 1")));
+        assert!(is_bootstrap(&args(
+            "/usr/bin/python3 -B ./x.py test tests/ui"
+        )));
+        assert!(is_bootstrap(&args(
+            "/rust/build/bootstrap/debug/bootstrap test"
+        )));
+        assert!(!is_bootstrap(&args("python3 manage.py runserver")));
+        assert!(!is_bootstrap(&args("rustc --edition 2021 x.rs")));
+        assert!(!is_bootstrap(&[]));
+
+        assert_eq!(
+            parent_pid("1234 (x (weird) name) S 42 1234 1234 0 -1"),
+            Some(42)
+        );
+        // 30 → 20 → 10 → 1, with 99 elsewhere.
+        let parents = BTreeMap::from([(30, 20), (20, 10), (10, 1), (99, 1), (1, 0)]);
+        assert!(descends_from(30, 10, &parents));
+        assert!(descends_from(10, 10, &parents));
+        assert!(!descends_from(99, 10, &parents));
+        assert!(!descends_from(5, 10, &parents));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn waits_until_the_build_directory_lock_is_released() {
+        use std::os::fd::AsRawFd;
+
+        let repo = Repo::new("build-lock");
+        let lock_path = repo.add("build/lock", "4242\n");
+        assert_eq!(holder(&lock_path), None);
+
+        // `flock` locks belong to the open file, so this one conflicts with those of `holder`.
+        let held = std::fs::File::open(&lock_path).unwrap();
+        // SAFETY: `held` is open.
+        assert_eq!(
+            unsafe { libc::flock(held.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) },
+            0
+        );
+        assert_eq!(holder(&lock_path).as_deref(), Some("4242"));
+        assert!(waited_for_lock(
+            "Waiting for lock on build directory locked by process 4242"
+        ));
+
+        let config = Config {
+            detect_user_bootstrap: false,
+            ..Config::builder().load().unwrap()
+        };
+        let start = Instant::now();
+        let release = async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            drop(held);
+        };
+        tokio::join!(wait_until_free(&config, &repo.0), release);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(holder(&lock_path), None);
+    }
+}
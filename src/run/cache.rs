@@ -0,0 +1,347 @@
+//! Outcomes of previous runs, reused for tests whose inputs did not change since, see
+//! `--no-cache`.
+//!
+//! Entries are keyed by the contents of a test and its snapshot files. The whole cache is dropped
+//! when the toolchain changes: the last commit touching it, uncommitted changes to it, the
+//! bootstrap config or the host. Rebases which only bring in unrelated changes keep it valid.
+//!
+//! Besides outcomes, the cache records which unmodified tests passed their sanity check, so that
+//! the check can be skipped when the outcome itself has to be determined again (e.g. because the
+//! config changed).
+//!
+//! With a [`RemoteCache`], entries missing locally are looked up there and new ones are uploaded,
+//! named after their key and the toolchain so that machines at different commits or on different
+//! hosts can share a cache.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use miette::{Context, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::*;
+
+use super::directives::Syntax;
+use super::manifest::{self, sha256_hex};
+use super::remote_cache::RemoteCache;
+use super::rules::{self, Rule};
+use super::{compiletest, paths, snapshots, write_guard, TestRecord};
+use crate::config::Config;
+use crate::git;
+
+/// Paths whose changes can affect the outcome of any test.
+const TOOLCHAIN_PATHS: [&str; 3] = ["compiler", "library", "src/tools/compiletest"];
+
+/// Identifies everything the toolchain the tests run against is built from: the last commit
+/// touching [`TOOLCHAIN_PATHS`], their uncommitted changes, the bootstrap config and the host.
+/// `None` if the repo isn't a git checkout.
+fn toolchain_id(config: &Config, rustc_repo_path: &Path) -> Option<String> {
+    let commit = git::last_commit_touching(rustc_repo_path, &TOOLCHAIN_PATHS)?;
+    let mut hasher = Sha256::new();
+    hasher.update(commit.as_bytes());
+    hasher.update(compiletest::HOST.as_bytes());
+    let diff = git::uncommitted_diff(rustc_repo_path, &TOOLCHAIN_PATHS)?;
+    hasher.update(sha256_hex(diff.as_bytes()).as_bytes());
+    let bootstrap_config = manifest::bootstrap_config_in_use(config, rustc_repo_path)
+        .and_then(|(_, path)| std::fs::read(path).ok())
+        .unwrap_or_default();
+    hasher.update(sha256_hex(&bootstrap_config).as_bytes());
+    Some(sha256_hex(&hasher.finalize()))
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(super) struct Cache {
+    #[serde(skip)]
+    path: PathBuf,
+    /// [`toolchain_id`] when the entries were recorded.
+    #[serde(default)]
+    toolchain: String,
+    entries: BTreeMap<String, CacheEntry>,
+    /// Baseline keys of the tests whose unmodified version passed the sanity check.
+    #[serde(default)]
+    baselines: BTreeSet<String>,
+    #[serde(skip)]
+    remote: Option<RemoteCache>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    record: TestRecord,
+    /// Contents of the snapshot files rewritten by the kept edit, keyed by repo-relative path.
+    /// `None` if the snapshot was deleted.
+    snapshots: BTreeMap<String, Option<String>>,
+}
+
+impl Cache {
+    /// Load the cache at `path`, starting afresh if it doesn't exist, can't be parsed or was
+    /// recorded against a different toolchain. Returns `None` if the toolchain can't be
+    /// determined, in which case nothing can be safely cached.
+    pub(super) fn load(
+        path: &Path,
+        config: &Config,
+        rustc_repo_path: &Path,
+        remote: Option<RemoteCache>,
+    ) -> Result<Option<Self>> {
+        let Some(toolchain) = toolchain_id(config, rustc_repo_path) else {
+            warn!("could not determine the toolchain commit, outcomes will not be cached");
+            return Ok(None);
+        };
+
+        let mut cache = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .inspect_err(|e| warn!("ignoring unreadable cache `{}`: {e}", path.display()))
+                .unwrap_or_default(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Cache::default(),
+            Err(e) => Err(e)
+                .into_diagnostic()
+                .wrap_err(format!("failed to read cache `{}`", path.display()))?,
+        };
+        if cache.toolchain != toolchain {
+            if !cache.entries.is_empty() {
+                info!("toolchain changed since outcomes were cached, discarding the cache");
+            }
+            cache.entries.clear();
+            cache.baselines.clear();
+        }
+        cache.path = path.to_path_buf();
+        cache.toolchain = toolchain;
+        cache.remote = remote;
+        Ok(Some(cache))
+    }
+
+    pub(super) fn save(&self) -> Result<()> {
+        let contents = serde_json::to_string(self).into_diagnostic()?;
+        std::fs::write(&self.path, contents)
+            .into_diagnostic()
+            .wrap_err(format!("failed to write cache `{}`", self.path.display()))
+    }
+
+    /// Key of `target` and its snapshots in their current state, independent of the config. Must
+    /// be computed before the test is processed.
+    pub(super) fn baseline_key(target: &Path) -> Result<String> {
+        let mut hasher = Sha256::new();
+        let src = std::fs::read(target)
+            .into_diagnostic()
+            .wrap_err(format!("failed to read `{}`", target.display()))?;
+        hasher.update(sha256_hex(&src).as_bytes());
+        for companion in snapshots::companions(target) {
+            let contents = std::fs::read(&companion)
+                .into_diagnostic()
+                .wrap_err(format!("failed to read `{}`", companion.display()))?;
+            hasher.update(companion.file_name().unwrap_or_default().as_encoded_bytes());
+            hasher.update(sha256_hex(&contents).as_bytes());
+        }
+        Ok(sha256_hex(&hasher.finalize()))
+    }
+
+    /// Key of the outcome of a test with the given [`Cache::baseline_key`] under `config`.
+    pub(super) fn key(config: &Config, baseline_key: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.update(config.snapshot_outlier_lines.to_le_bytes());
+        hasher.update([config.try_filecheck_tests as u8]);
+        hasher.update([config.tidy as u8]);
+        // The classifier may decide differently for the same results.
+        hasher.update(config.classifier.as_deref().unwrap_or_default().as_bytes());
+        hasher.update(
+            serde_json::to_string(&config.suite_profiles)
+                .unwrap_or_default()
+                .as_bytes(),
+        );
+        hasher.update(
+            serde_json::to_string(&rules::resolve(config))
+                .unwrap_or_default()
+                .as_bytes(),
+        );
+        for worktree in &config.worktrees {
+            hasher.update(worktree.as_os_str().as_encoded_bytes());
+        }
+        hasher.update(baseline_key.as_bytes());
+        sha256_hex(&hasher.finalize())
+    }
+
+    pub(super) fn baseline_passed(&mut self, baseline_key: &str) -> bool {
+        if self.baselines.contains(baseline_key) {
+            return true;
+        }
+        let name = self.remote_name("baseline", baseline_key);
+        let found = self
+            .with_remote(|remote| remote.get(&name))
+            .flatten()
+            .is_some();
+        if found {
+            self.baselines.insert(baseline_key.to_string());
+        }
+        found
+    }
+
+    pub(super) fn record_baseline(&mut self, baseline_key: String) {
+        let name = self.remote_name("baseline", &baseline_key);
+        if self.baselines.insert(baseline_key) {
+            self.with_remote(|remote| remote.put(&name, &[]));
+        }
+    }
+
+    /// The cached record for `target`, if any, with the kept edit and its snapshots written back
+    /// to disk.
+    pub(super) fn reuse(
+        &mut self,
+        rustc_repo_path: &Path,
+        target: &Path,
+        key: &str,
+        rules: &[Rule],
+    ) -> Result<Option<TestRecord>> {
+        if !self.entries.contains_key(key) {
+            self.fetch(key);
+        }
+        let Some(entry) = self.entries.get(key) else {
+            return Ok(None);
+        };
+
+        let Some(rule) = rules::kept_rule(rules, &entry.record) else {
+            if !entry.record.outcome.keeps_directive() {
+                // The rule the edit was made with is gone.
+                return Ok(None);
+            }
+            return Ok(Some(TestRecord {
+                duration: Default::default(),
+                ..entry.record.clone()
+            }));
+        };
+        let src = std::fs::read_to_string(target)
+            .into_diagnostic()
+            .wrap_err(format!("failed to read `{}`", target.display()))?;
+        let edit = rule.apply(&src, Syntax::of(target));
+        if edit.changes != entry.record.changes {
+            debug!("cached edit of `{}` does not apply", target.display());
+            return Ok(None);
+        }
+
+        write_guard::write(target, &edit.src)
+            .into_diagnostic()
+            .wrap_err(format!("failed to write `{}`", target.display()))?;
+        for (rel_path, contents) in &entry.snapshots {
+            let path = rustc_repo_path.join(rel_path);
+            match contents {
+                Some(contents) => write_guard::write(&path, contents),
+                None => write_guard::remove_file(&path),
+            }
+            .into_diagnostic()
+            .wrap_err(format!("failed to restore snapshot `{}`", path.display()))?;
+        }
+        Ok(Some(TestRecord {
+            duration: Default::default(),
+            ..entry.record.clone()
+        }))
+    }
+
+    /// Record the outcome of a test processed in this run under the `key` computed beforehand.
+    /// The snapshots it kept are read back from disk.
+    pub(super) fn insert(&mut self, rustc_repo_path: &Path, key: String, record: &TestRecord) {
+        let mut snapshots = BTreeMap::new();
+        for change in &record.snapshot_changes {
+            let path = rustc_repo_path.join(&change.path);
+            let contents = match std::fs::read(&path) {
+                Ok(contents) => match String::from_utf8(contents) {
+                    Ok(contents) => Some(contents),
+                    Err(_) => {
+                        debug!("not caching `{}`: non-UTF-8 snapshot", change.path);
+                        return;
+                    }
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    debug!("not caching `{}`: {e}", change.path);
+                    return;
+                }
+            };
+            snapshots.insert(paths::repo_relative(rustc_repo_path, &path), contents);
+        }
+        let entry = CacheEntry {
+            record: record.clone(),
+            snapshots,
+        };
+        let name = self.remote_name("outcome", &key);
+        if self.remote.is_some() {
+            match serde_json::to_vec(&entry) {
+                Ok(contents) => {
+                    self.with_remote(|remote| remote.put(&name, &contents));
+                }
+                Err(e) => debug!("not uploading `{name}`: {e}"),
+            }
+        }
+        self.entries.insert(key, entry);
+    }
+
+    /// Look up the entry for `key` in the remote cache, keeping it locally if found.
+    fn fetch(&mut self, key: &str) {
+        let name = self.remote_name("outcome", key);
+        let Some(Some(contents)) = self.with_remote(|remote| remote.get(&name)) else {
+            return;
+        };
+        match serde_json::from_slice(&contents) {
+            Ok(entry) => {
+                debug!("fetched `{name}` from the remote cache");
+                self.entries.insert(key.to_string(), entry);
+            }
+            Err(e) => warn!("ignoring unreadable remote cache entry `{name}`: {e}"),
+        }
+    }
+
+    /// Name of the remote object of the given `kind` for `key`, which also covers the toolchain.
+    fn remote_name(&self, kind: &str, key: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.toolchain.as_bytes());
+        hasher.update(key.as_bytes());
+        format!("{kind}-{}.json", sha256_hex(&hasher.finalize()))
+    }
+
+    /// Run `f` against the remote cache, if any. The remote cache is given up on after the first
+    /// error, so that an unreachable server doesn't slow down every lookup.
+    fn with_remote<T>(&mut self, f: impl FnOnce(&RemoteCache) -> Result<T>) -> Option<T> {
+        let remote = self.remote.as_ref()?;
+        match f(remote) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                warn!("disabling the remote cache for the rest of the run: {e:?}");
+                self.remote = None;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use confique::Config as _;
+
+    use super::toolchain_id;
+    use crate::config::Config;
+    use crate::run::tests::{git, Repo};
+
+    #[test]
+    fn toolchain_covers_uncommitted_changes_and_the_bootstrap_config() {
+        let repo = Repo::new("toolchain");
+        repo.add("compiler/lib.rs", "");
+        git(&repo.0, &["init", "-q"]);
+        git(&repo.0, &["config", "user.name", "test"]);
+        git(&repo.0, &["config", "user.email", "test@example.com"]);
+        git(&repo.0, &["add", "-A"]);
+        git(&repo.0, &["commit", "-q", "-m", "init"]);
+        let config = Config::builder().load().unwrap();
+        let clean = toolchain_id(&config, &repo.0).unwrap();
+
+        repo.add("compiler/lib.rs", "fn f() {}");
+        let dirty = toolchain_id(&config, &repo.0).unwrap();
+        assert_ne!(dirty, clean);
+        repo.add("compiler/lib.rs", "");
+        assert_eq!(toolchain_id(&config, &repo.0).unwrap(), clean);
+
+        // Changes outside of the toolchain don't matter.
+        repo.add("README.md", "");
+        assert_eq!(toolchain_id(&config, &repo.0).unwrap(), clean);
+
+        repo.add("bootstrap.toml", "profile = \"compiler\"");
+        assert_ne!(toolchain_id(&config, &repo.0).unwrap(), clean);
+    }
+}
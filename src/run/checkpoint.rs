@@ -0,0 +1,76 @@
+//! The state and report of a run, rewritten after every test, so that a run dying hours in (a crash,
+//! an OOM kill, a reboot) still leaves a partial report and a state later runs build on, e.g. for
+//! `--incremental` or the outcome history.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tracing::*;
+
+use super::state::RunState;
+use super::{report, TestRecord};
+use crate::config::Config;
+
+pub(super) struct Checkpoint<'a> {
+    config: &'a Config,
+    /// The state of the run so far: without a status, and with the tests not decided yet pending.
+    state: RunState,
+    state_path: PathBuf,
+    report_path: PathBuf,
+    /// Whether a failure to write was already warned about, to only do so once.
+    failed: bool,
+}
+
+impl<'a> Checkpoint<'a> {
+    pub(super) fn new(
+        config: &'a Config,
+        state: RunState,
+        state_path: PathBuf,
+        report_path: PathBuf,
+    ) -> Self {
+        let mut checkpoint = Checkpoint {
+            config,
+            state,
+            state_path,
+            report_path,
+            failed: false,
+        };
+        checkpoint.flush();
+        checkpoint
+    }
+
+    /// Record the outcome of the test at `rel_path` and write the state and report.
+    pub(super) fn record(&mut self, rel_path: &str, record: &TestRecord) {
+        self.state.pending.remove(rel_path);
+        self.state
+            .records
+            .insert(rel_path.to_string(), record.clone());
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+        let result = serde_json::to_string_pretty(&self.state)
+            .map_err(io::Error::from)
+            .and_then(|state| write_atomically(&self.state_path, state.as_bytes()))
+            .and_then(|()| {
+                let report = report::format_report(self.config, &self.state);
+                write_atomically(&self.report_path, report.as_bytes())
+            });
+        if let Err(e) = result {
+            if !self.failed {
+                warn!("failed to write the partial results of the run: {e}");
+                self.failed = true;
+            }
+        }
+    }
+}
+
+/// Write `contents` to a temporary file next to `path`, then rename it over `path`, so that `path`
+/// is never left half-written.
+pub(super) fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)
+}
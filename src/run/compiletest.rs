@@ -0,0 +1,143 @@
+//! Running compiletest directly against an already built stage 1 toolchain (see
+//! `--direct-compiletest`), rather than through `x test`, which spends seconds per invocation in
+//! bootstrap's Python wrapper and in checking whether anything needs rebuilding.
+//!
+//! Bootstrap passes compiletest a few dozen options describing the toolchain and the checkout;
+//! the ones which can be derived from the paths in the config are passed here, and
+//! `compiletest_args` covers whatever else the compiletest of the checkout requires.
+
+use std::path::{Path, PathBuf};
+
+use miette::{bail, miette, Context, IntoDiagnostic, Result};
+use tokio::process::Command;
+
+use super::{build_dir, process, test_results};
+use crate::config::Config;
+
+/// Triple of the machine the tool was built for, assumed to be the host of the toolchain.
+pub(super) const HOST: &str = env!("TARGET");
+
+/// The compiletest binary, see [`Config::compiletest_path`].
+fn compiletest_path(config: &Config, rustc_repo_path: &Path) -> PathBuf {
+    match &config.compiletest_path {
+        Some(path) => rustc_repo_path.join(path),
+        None => build_dir(config, rustc_repo_path)
+            .join(HOST)
+            .join("stage0-tools-bin")
+            .join(format!("compiletest{}", std::env::consts::EXE_SUFFIX)),
+    }
+}
+
+/// The sysroot of the stage 1 toolchain, see [`Config::stage1_sysroot`].
+fn sysroot(config: &Config, rustc_repo_path: &Path) -> PathBuf {
+    match &config.stage1_sysroot {
+        Some(path) => rustc_repo_path.join(path),
+        None => build_dir(config, rustc_repo_path).join(HOST).join("stage1"),
+    }
+}
+
+fn rustc_path(sysroot: &Path) -> PathBuf {
+    sysroot
+        .join("bin")
+        .join(format!("rustc{}", std::env::consts::EXE_SUFFIX))
+}
+
+/// Fail unless compiletest and the stage 1 toolchain are there, and the tests run locally.
+pub(super) fn check(config: &Config, rustc_repo_path: &Path) -> Result<()> {
+    if config.ssh_host.is_some() || config.container_image.is_some() {
+        bail!(
+            "`--direct-compiletest` runs compiletest locally, which `ssh_host` and \
+             `container_image` are not supported with"
+        );
+    }
+    let compiletest = compiletest_path(config, rustc_repo_path);
+    if !compiletest.is_file() {
+        return Err(miette!(
+            help = "build it with `x build src/tools/compiletest`, or set `compiletest_path`",
+            "compiletest not found at `{}`",
+            compiletest.display()
+        ));
+    }
+    let rustc = rustc_path(&sysroot(config, rustc_repo_path));
+    if !rustc.is_file() {
+        return Err(miette!(
+            help = "build it with `x build --stage 1 library`, or set `stage1_sysroot`",
+            "the stage 1 compiler was not found at `{}`",
+            rustc.display()
+        ));
+    }
+    Ok(())
+}
+
+/// The compiletest mode of the tests of `suite`, e.g. `codegen` for `tests/codegen-llvm`.
+fn mode_of(suite: &str) -> &str {
+    let name = suite.rsplit('/').next().unwrap_or(suite);
+    match name {
+        "codegen-llvm" => "codegen",
+        "assembly-llvm" => "assembly",
+        "run-make-cargo" => "run-make",
+        "ui-fulldeps" => "ui",
+        "coverage-run-rustdoc" => "coverage-run",
+        _ => name,
+    }
+}
+
+/// Run the tests of `suite` matching `filters` (paths within the suite), blessing snapshots if
+/// `bless` is set.
+pub(super) async fn run(
+    config: &Config,
+    rustc_repo_path: &Path,
+    suite: &str,
+    filters: &[String],
+    bless: bool,
+) -> Result<process::Captured> {
+    // compiletest runs in the repo, which the paths passed to it must not be relative to.
+    let rustc_repo_path = &std::path::absolute(rustc_repo_path).into_diagnostic()?;
+    let sysroot = sysroot(config, rustc_repo_path);
+    let build_root = build_dir(config, rustc_repo_path);
+    let name = suite.rsplit('/').next().unwrap_or(suite);
+    let target = HOST;
+    let mut command = Command::new(compiletest_path(config, rustc_repo_path));
+    command
+        .current_dir(rustc_repo_path)
+        .arg("--compile-lib-path")
+        .arg(sysroot.join("lib"))
+        .arg("--run-lib-path")
+        .arg(sysroot.join("lib/rustlib").join(target).join("lib"))
+        .arg("--rustc-path")
+        .arg(rustc_path(&sysroot))
+        .arg("--sysroot-base")
+        .arg(&sysroot)
+        .arg("--src-root")
+        .arg(rustc_repo_path)
+        .arg("--src-test-suite-root")
+        .arg(rustc_repo_path.join(suite))
+        .arg("--build-root")
+        .arg(&build_root)
+        .arg("--build-test-suite-root")
+        .arg(build_root.join(target).join("test").join(name))
+        .args(["--stage", "1", "--stage-id"])
+        .arg(format!("stage1-{target}"))
+        .args(["--suite", name, "--mode", mode_of(suite)])
+        .args(["--target", target, "--host", HOST]);
+    let rustdoc = sysroot
+        .join("bin")
+        .join(format!("rustdoc{}", std::env::consts::EXE_SUFFIX));
+    if rustdoc.is_file() {
+        command.arg("--rustdoc-path").arg(rustdoc);
+    }
+    if bless {
+        command.arg("--bless");
+    }
+    command
+        .args(&config.compiletest_args)
+        .args(filters)
+        .args(test_results::JSON_FORMAT_ARGS.split_whitespace());
+    process::run(&mut command)
+        .await
+        .into_diagnostic()
+        .wrap_err(format!(
+            "error trying to invoke compiletest on `{suite}` with {}",
+            filters.join(" ")
+        ))
+}
@@ -0,0 +1,205 @@
+//! Running bootstrap inside a Docker or Podman container, see `container_image` in the config, for
+//! a reproducible toolchain environment and to keep the build off the host.
+//!
+//! Each `x` invocation runs in a fresh container of the image, with the `rustc` repo (and any
+//! build directory or scratch directory outside of it) bind-mounted at the same path as on the
+//! host, so that paths mean the same inside and out. The image needs to provide `x` and whatever
+//! bootstrap needs on its `PATH`.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use miette::{bail, Context, IntoDiagnostic, Result};
+use tokio::process::Command;
+use tracing::*;
+
+use crate::config::Config;
+
+#[derive(Debug)]
+struct Container {
+    engine: String,
+    image: String,
+    args: Vec<String>,
+    /// Directories mounted on top of the one `x` runs in.
+    mounts: Vec<PathBuf>,
+    memory_limit_mib: Option<u64>,
+}
+
+static CONTAINER: Mutex<Option<Arc<Container>>> = Mutex::new(None);
+
+/// The jobserver's file descriptors can't be handed into a container.
+const HOST_ONLY_ENV: &[&str] = &["CARGO_MAKEFLAGS", "MAKEFLAGS", "MFLAGS"];
+
+/// Pick up the container image from `config`, if any.
+pub(super) fn configure(config: &Config) {
+    let container = config.container_image.as_ref().map(|image| Container {
+        engine: config.container_engine.clone(),
+        image: image.clone(),
+        args: config.container_args.clone(),
+        mounts: config
+            .build_dir
+            .iter()
+            .chain(&config.bootstrap_config)
+            .filter(|path| path.is_absolute())
+            .cloned()
+            .collect(),
+        memory_limit_mib: config.memory_limit_mib,
+    });
+    if let Some(container) = &container {
+        info!(
+            "running `x` in `{}` containers with `{}`",
+            container.image, container.engine
+        );
+    }
+    *CONTAINER.lock().unwrap() = container.map(Arc::new);
+}
+
+fn container() -> Option<Arc<Container>> {
+    CONTAINER.lock().unwrap().clone()
+}
+
+/// Make sure the container engine is available.
+pub(super) fn preflight(config: &Config) -> Result<()> {
+    let Some(image) = &config.container_image else {
+        return Ok(());
+    };
+    if config.ssh_host.is_some() {
+        bail!("`container_image` and `ssh_host` can't be used together");
+    }
+    if config.niceness.is_some() {
+        warn!(
+            "`niceness` only applies to `{}`, not to `x`",
+            config.container_engine
+        );
+    }
+    let engine = &config.container_engine;
+    let output = std::process::Command::new(engine)
+        .args(["image", "inspect", image])
+        .output()
+        .into_diagnostic()
+        .wrap_err(format!("failed to invoke `{engine}`, is it installed?"))?;
+    if !output.status.success() {
+        info!("image `{image}` is not available locally yet, `{engine}` will pull it");
+    }
+    Ok(())
+}
+
+/// `command` (an `x` invocation in the `rustc` repo) run in a container, or `command` itself if
+/// no container image is configured.
+pub(super) fn wrap(command: Command) -> Command {
+    match container() {
+        Some(container) => in_container(&container, &command),
+        None => command,
+    }
+}
+
+/// `container.engine` running `command` in a fresh container, with the directories it needs.
+fn in_container(container: &Container, command: &Command) -> Command {
+    let original = command.as_std();
+    let workdir = original
+        .get_current_dir()
+        .map(|dir| std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf()))
+        .unwrap_or_default();
+
+    let mut run = Command::new(&container.engine);
+    run.args(["run", "--rm", "--init"]);
+    if Path::new(&container.engine)
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().contains("podman"))
+    {
+        run.arg("--userns=keep-id");
+    } else {
+        #[cfg(unix)]
+        {
+            // SAFETY: `getuid` and `getgid` can't fail and have no side effects.
+            let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+            // Keep the build owned by the user rather than by root.
+            run.arg("--user").arg(format!("{uid}:{gid}"));
+        }
+    }
+    if let Some(mib) = container.memory_limit_mib {
+        run.arg("--memory").arg(format!("{mib}m"));
+    }
+
+    let mut mounts = vec![workdir.clone()];
+    mounts.extend(container.mounts.iter().cloned());
+    for (key, value) in original.get_envs() {
+        let key = key.to_string_lossy();
+        let Some(value) = value else {
+            continue;
+        };
+        if HOST_ONLY_ENV.contains(&&*key) {
+            continue;
+        }
+        if key == "TMPDIR" {
+            // Per-worker scratch directory.
+            mounts.push(PathBuf::from(value));
+        }
+        run.arg("--env")
+            .arg(format!("{key}={}", value.to_string_lossy()));
+    }
+    for mount in mounts {
+        let mount = mount.display();
+        run.arg("--volume").arg(format!("{mount}:{mount}"));
+    }
+    run.arg("--workdir")
+        .arg(&workdir)
+        .args(&container.args)
+        .arg(&container.image)
+        .arg(original.get_program())
+        .args(original.get_args());
+    run
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Windows paths would be made absolute to another drive.
+    #[cfg(unix)]
+    #[test]
+    fn runs_x_with_the_directories_it_needs_mounted() {
+        let container = Container {
+            engine: "/usr/bin/podman".to_string(),
+            image: "rust-ci:latest".to_string(),
+            args: vec!["--network=none".to_string()],
+            mounts: vec![PathBuf::from("/build")],
+            memory_limit_mib: Some(8192),
+        };
+        let mut command = Command::new("./x");
+        command
+            .current_dir("/rust")
+            .args(["test", "tests/ui/a.rs"])
+            .env("TMPDIR", "/scratch/worker-1")
+            .env("MAKEFLAGS", "-j --jobserver-auth=3,4");
+        let run = in_container(&container, &command);
+        let run = run.as_std();
+        assert_eq!(run.get_program(), "/usr/bin/podman");
+        assert_eq!(
+            run.get_args().collect::<Vec<_>>(),
+            [
+                "run",
+                "--rm",
+                "--init",
+                "--userns=keep-id",
+                "--memory",
+                "8192m",
+                "--env",
+                "TMPDIR=/scratch/worker-1",
+                "--volume",
+                "/rust:/rust",
+                "--volume",
+                "/build:/build",
+                "--volume",
+                "/scratch/worker-1:/scratch/worker-1",
+                "--workdir",
+                "/rust",
+                "--network=none",
+                "rust-ci:latest",
+                "./x",
+                "test",
+                "tests/ui/a.rs",
+            ]
+        );
+    }
+}
@@ -0,0 +1,101 @@
+//! Steering a run while it is in progress, as the TUI of `run --tui` does: following the output
+//! of `x` for each test, skipping a test, or aborting the whole run.
+//!
+//! Each test is processed within [`scope`], which tells the `x` invocations made for it (see
+//! [`super::process`]) which test their output belongs to, and which of them to stop when the
+//! test is skipped.
+
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+use tracing::*;
+
+use super::{process, INTERRUPTED};
+
+tokio::task_local! {
+    /// Repo-relative path of the test being processed by the current task.
+    static CURRENT_TEST: String;
+}
+
+/// A callback receiving each line of output of `x` (stdout and stderr alike) along with the
+/// repo-relative path of the test it was run for, possibly from several threads.
+pub type OutputListener = Arc<dyn Fn(&str, &str) + Send + Sync>;
+
+/// The output listeners of the current run, see [`configure`].
+static OUTPUT: Mutex<Vec<OutputListener>> = Mutex::new(Vec::new());
+
+/// Tests [`Controls::skip`] was called for.
+static SKIPPED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Woken by [`Controls::skip`].
+static SKIP: Notify = Notify::const_new();
+
+/// Set the output listeners of the run about to start.
+pub(super) fn configure(listeners: Vec<OutputListener>) {
+    *OUTPUT.lock().unwrap() = listeners;
+    SKIPPED.lock().unwrap().clear();
+}
+
+/// Run `f`, which processes the test at `rel_path`.
+pub(super) async fn scope<F: Future>(rel_path: String, f: F) -> F::Output {
+    CURRENT_TEST.scope(rel_path, f).await
+}
+
+/// The test processed by the current task, if any.
+pub(super) fn current_test() -> Option<String> {
+    CURRENT_TEST.try_with(Clone::clone).ok()
+}
+
+/// Pass a line of output of `x`, run for `test`, to the output listeners.
+pub(super) fn output(test: &str, line: &str) {
+    let listeners = OUTPUT.lock().unwrap().clone();
+    for listener in &listeners {
+        listener(test, line);
+    }
+}
+
+pub(super) fn skip_requested(rel_path: &str) -> bool {
+    SKIPPED.lock().unwrap().contains(rel_path)
+}
+
+/// Complete once the test at `rel_path` is skipped, never if `None`.
+pub(super) async fn skipped(rel_path: Option<&str>) {
+    let Some(rel_path) = rel_path else {
+        return std::future::pending().await;
+    };
+    loop {
+        let notified = SKIP.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        if skip_requested(rel_path) {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// Steers the runs of an [`Engine`](super::Engine), see [`Engine::controls`](super::Engine::controls).
+#[derive(Debug, Clone, Default)]
+pub struct Controls {
+    _private: (),
+}
+
+impl Controls {
+    /// Leave the test at the repo-relative `path` unmodified and flag it for manual review,
+    /// stopping the `x` invocation running it if any.
+    pub fn skip(&self, path: &str) {
+        info!("skipping `{path}`");
+        SKIPPED.lock().unwrap().insert(path.to_string());
+        SKIP.notify_waiters();
+    }
+
+    /// Stop the run after restoring the tests in flight, as Ctrl-C does.
+    pub fn abort(&self) {
+        warn!("aborting, stopping after restoring the tests in flight");
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        process::interrupt();
+    }
+}
@@ -0,0 +1,126 @@
+//! `--dedup`: tests with identical contents, such as generated families of tests, are verified
+//! once through a representative whose decision is then applied to the others.
+//!
+//! Tests are identical if they are in the same suite, their sources only differ in trailing
+//! whitespace and leading or trailing blank lines, and they have the same snapshot files with the
+//! same contents.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use miette::{Context, IntoDiagnostic, Result};
+use sha2::{Digest, Sha256};
+
+use super::directives::Syntax;
+use super::manifest::sha256_hex;
+use super::rules::{self, Rule};
+use super::{paths, snapshots, write_guard, RunOutcome, TestRecord};
+
+/// Tests of each family but the first, keyed by the first (the representative).
+pub(super) type Copies = BTreeMap<PathBuf, Vec<PathBuf>>;
+
+/// Split `targets` into representatives, which are to be processed, and their copies.
+pub(super) fn find_families(
+    rustc_repo_path: &Path,
+    targets: BTreeSet<PathBuf>,
+) -> Result<(BTreeSet<PathBuf>, Copies)> {
+    let mut families: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for target in targets {
+        let key = family_key(rustc_repo_path, &target)?;
+        families.entry(key).or_default().push(target);
+    }
+
+    let mut representatives = BTreeSet::new();
+    let mut copies = Copies::new();
+    for mut family in families.into_values() {
+        // Targets were sorted, so the representative is the first path of the family.
+        let rest = family.split_off(1);
+        let representative = family.pop().expect("families are not empty");
+        if !rest.is_empty() {
+            copies.insert(representative.clone(), rest);
+        }
+        representatives.insert(representative);
+    }
+    Ok((representatives, copies))
+}
+
+fn family_key(rustc_repo_path: &Path, target: &Path) -> Result<String> {
+    let src = std::fs::read_to_string(target)
+        .into_diagnostic()
+        .wrap_err(format!("failed to read `{}`", target.display()))?;
+    let rel_path = paths::repo_relative(rustc_repo_path, target);
+    let stem = target.file_stem().unwrap_or_default().to_string_lossy();
+
+    let mut hasher = Sha256::new();
+    hasher.update(paths::suite_of(&rel_path).as_bytes());
+    hasher.update(normalize(&src).as_bytes());
+    for companion in snapshots::companions(target) {
+        let contents = std::fs::read(&companion)
+            .into_diagnostic()
+            .wrap_err(format!("failed to read `{}`", companion.display()))?;
+        let name = companion.file_name().unwrap_or_default().to_string_lossy();
+        // `.stderr`, `.run.stdout`, ...: the part which is the same for every copy.
+        hasher.update(name.strip_prefix(&*stem).unwrap_or(&name).as_bytes());
+        hasher.update(sha256_hex(&contents).as_bytes());
+    }
+    Ok(sha256_hex(&hasher.finalize()))
+}
+
+fn normalize(src: &str) -> String {
+    let lines: Vec<&str> = src.lines().map(str::trim_end).collect();
+    let start = lines
+        .iter()
+        .position(|l| !l.is_empty())
+        .unwrap_or(lines.len());
+    let end = lines
+        .iter()
+        .rposition(|l| !l.is_empty())
+        .map_or(start, |i| i + 1);
+    lines[start..end].join("\n")
+}
+
+/// Apply the decision recorded for `representative` to `copy`, editing it on disk if the
+/// directive was removed or replaced.
+pub(super) fn apply(
+    rustc_repo_path: &Path,
+    copy: &Path,
+    representative: &Path,
+    record: &TestRecord,
+    rules: &[Rule],
+) -> Result<TestRecord> {
+    let representative = paths::repo_relative(rustc_repo_path, representative);
+    let inherited = TestRecord {
+        duration: Default::default(),
+        changes: Vec::new(),
+        snapshot_changes: Vec::new(),
+        duplicate_of: Some(representative.clone()),
+        ..record.clone()
+    };
+
+    let Some(rule) = rules::kept_rule(rules, record) else {
+        return Ok(inherited);
+    };
+    if !record.snapshot_changes.is_empty() {
+        return Ok(TestRecord {
+            outcome: RunOutcome::NeedsManualReview,
+            review_hint: Some(
+                "the snapshots of the identical test were rewritten by `--bless`, needs to be \
+                 verified on its own"
+                    .to_string(),
+            ),
+            ..inherited
+        });
+    }
+
+    let src = std::fs::read_to_string(copy)
+        .into_diagnostic()
+        .wrap_err(format!("failed to read `{}`", copy.display()))?;
+    let edit = rule.apply(&src, Syntax::of(copy));
+    write_guard::write(copy, &edit.src)
+        .into_diagnostic()
+        .wrap_err(format!("failed to write `{}`", copy.display()))?;
+    Ok(TestRecord {
+        changes: edit.changes,
+        ..inherited
+    })
+}
@@ -0,0 +1,55 @@
+//! `--notify`: a desktop notification with the outcome counts when a run completes, for the
+//! runs taking hours that nobody watches.
+
+use miette::{IntoDiagnostic, Result};
+
+use super::state::RunState;
+use super::{summary, RunOutcome};
+use crate::exit_status::ExitStatus;
+use crate::history::RunDir;
+
+pub(super) fn notify(run_dir: &RunDir, result: &Result<ExitStatus>) -> Result<()> {
+    let state = RunState::load(&run_dir.state_path()).ok();
+    let summary = match result {
+        Ok(ExitStatus::Success) => "Run finished".to_string(),
+        Ok(ExitStatus::NeedsManualReview) => "Run finished, some tests need review".to_string(),
+        Ok(ExitStatus::Regression) => "Run finished, some tests regressed".to_string(),
+        Ok(status) => format!("Run stopped ({status:?})"),
+        Err(_) => "Run failed".to_string(),
+    };
+    let body = match (&state, result) {
+        (_, Err(e)) => e.to_string(),
+        (Some(state), Ok(_)) => {
+            let count = |outcome| {
+                state
+                    .records
+                    .values()
+                    .filter(|r| r.outcome == outcome)
+                    .count()
+            };
+            let mut body = format!(
+                "{} removed, {} replaced, {} unmodified, {} to review",
+                count(RunOutcome::RemoveOk),
+                count(RunOutcome::ReplaceOk),
+                count(RunOutcome::UnmodifiedOk),
+                count(RunOutcome::NeedsManualReview),
+            );
+            if let Some(finished_at) = state.finished_at {
+                let took = (finished_at - state.started_at)
+                    .to_std()
+                    .unwrap_or_default();
+                body.push_str(&format!(" in {}", summary::format_duration(took)));
+            }
+            body
+        }
+        (None, Ok(_)) => String::new(),
+    };
+
+    notify_rust::Notification::new()
+        .appname("rustc-less-ignore-debug")
+        .summary(&summary)
+        .body(&body)
+        .show()
+        .into_diagnostic()?;
+    Ok(())
+}
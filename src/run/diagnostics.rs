@@ -0,0 +1,126 @@
+//! Warnings about the header of a test rendered with its source, highlighting the directive lines
+//! involved, like `rustc` does: headers contradicting themselves, tests left for manual review,
+//! and lines which look like a directive the rules are after without being parsed as one.
+
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use miette::{Diagnostic, LabeledSpan, NamedSource};
+use thiserror::Error;
+use tracing::*;
+
+use super::directives::{self, Syntax};
+use super::review::{Contradiction, Flag};
+use super::rules::Rule;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("{message}")]
+#[diagnostic(severity(Warning))]
+pub(super) struct DirectiveDiagnostic {
+    message: String,
+    #[source_code]
+    src: NamedSource<String>,
+    #[label(collection)]
+    labels: Vec<LabeledSpan>,
+    #[help]
+    help: Option<String>,
+}
+
+impl DirectiveDiagnostic {
+    /// A diagnostic about the test at `rel_path`, whose contents are `src`.
+    pub(super) fn new(message: impl Into<String>, rel_path: &str, src: &str) -> Self {
+        DirectiveDiagnostic {
+            message: message.into(),
+            src: NamedSource::new(rel_path, src.to_string()),
+            labels: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub(super) fn label(mut self, span: Range<usize>, label: impl Into<String>) -> Self {
+        self.labels
+            .push(LabeledSpan::new_with_span(Some(label.into()), span));
+        self
+    }
+
+    pub(super) fn help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Log the diagnostic as a warning.
+    pub(super) fn warn(self) {
+        warn!("{:?}", miette::Report::new(self));
+    }
+}
+
+/// Point at the directives of the test at `rel_path` which the `rules` are after, left in place
+/// for manual review because of `flag`.
+pub(super) fn flagged(rel_path: &str, src: &str, syntax: Syntax, rules: &[Rule], flag: &Flag) {
+    let mut diagnostic =
+        DirectiveDiagnostic::new(format!("`{rel_path}` needs manual review"), rel_path, src)
+            .help(flag.hint.clone());
+    if let Some((span, label)) = &flag.line {
+        diagnostic = diagnostic.label(span.clone(), *label);
+    }
+    for directive in directives::parse_with(src, syntax) {
+        if rules.iter().any(|rule| rule.matches(&directive)) {
+            diagnostic = diagnostic.label(directive.span, "left in place");
+        }
+    }
+    diagnostic.warn();
+}
+
+/// Point at both directives of each of the `contradictions` in the header of the test at
+/// `rel_path`.
+pub(super) fn contradictions(rel_path: &str, src: &str, contradictions: &[Contradiction]) {
+    for contradiction in contradictions {
+        let [a, b] = contradiction.spans.clone();
+        DirectiveDiagnostic::new(
+            format!("the header of `{rel_path}` contradicts itself about debug assertions"),
+            rel_path,
+            src,
+        )
+        .label(a, "this directive")
+        .label(b, "contradicts this one")
+        .help(contradiction.description.clone())
+        .warn();
+    }
+}
+
+/// Point at the lines of the header of the test at `rel_path` which mention the directive of one
+/// of the `rules` without being parsed as that directive, e.g. `// ignore-debug` among `//@`
+/// directives, which leave the test out of the run.
+pub(super) fn lookalikes(rel_path: &str, src: &str, syntax: Syntax, rules: &[Rule]) {
+    let names: BTreeSet<&str> = rules
+        .iter()
+        .map(|rule| rule.matcher.directive.as_str())
+        .collect();
+    for name in names {
+        for span in directives::lookalikes(src, syntax, name) {
+            let line = src[span.clone()].trim();
+            let help = if syntax.legacy_marker.is_some_and(|m| line.starts_with(m))
+                && !line.starts_with(syntax.marker)
+            {
+                format!(
+                    "the test uses `{}` directives, so `{line}` is a plain comment; write \
+                     `{} {name}` if it is meant as a directive",
+                    syntax.marker, syntax.marker
+                )
+            } else {
+                format!(
+                    "directives are written `{} {name}`, or `{}[<revisions>] {name}`",
+                    syntax.marker, syntax.marker
+                )
+            };
+            DirectiveDiagnostic::new(
+                format!("`{rel_path}` mentions `{name}` outside of a directive"),
+                rel_path,
+                src,
+            )
+            .label(span, "not parsed as a directive")
+            .help(help)
+            .warn();
+        }
+    }
+}
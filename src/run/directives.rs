@@ -0,0 +1,478 @@
+//! Parsing of the compiletest directives in the header of a test, following compiletest's own
+//! rules closely enough that a directive is found here exactly when compiletest would honour it.
+//!
+//! A directive is a line comment `//@ name`, `//@ name: value` or `//@[rev1,rev2] name: value`,
+//! with the legacy `// name` syntax accepted as well for trees predating `//@`. Legacy directives
+//! look like any other comment starting with a lowercase word, so callers should only act on the
+//! names they know. The syntax is detected per file: once a file has a `//@` directive, its `//`
+//! comments are only comments, as for compiletest, which keeps directives added after the last
+//! one in the file's own syntax.
+//!
+//! Other kinds of files found in the test tree write directives with their own comment marker,
+//! e.g. `# ignore-cross-compile` in a `Makefile` of `tests/run-make`, see [`Syntax`].
+//!
+//! Only the header of the test is parsed: the contiguous block of line comments and blank lines
+//! at the top of the file. compiletest itself reads on until the first line starting with `fn` or
+//! `mod`, but directives below the first line of code are practically nonexistent, while comments
+//! mentioning a directive in the code (e.g. explaining why a test is `ignore-debug`) are not.
+
+use std::ops::Range;
+use std::path::Path;
+
+/// The comment markers directives are written with in a kind of test file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Syntax {
+    /// Marker of directives, e.g. `//@`.
+    pub marker: &'static str,
+    /// Marker of plain comments, if different from `marker`. Directives written with it are
+    /// [`Directive::legacy`].
+    pub legacy_marker: Option<&'static str>,
+}
+
+impl Syntax {
+    /// Rust tests: `//@ name`, or `// name` in trees predating `//@`.
+    pub const RUST: Syntax = Syntax {
+        marker: "//@",
+        legacy_marker: Some("//"),
+    };
+    /// `Makefile`s: `# name`.
+    pub const MAKEFILE: Syntax = Syntax {
+        marker: "#",
+        legacy_marker: None,
+    };
+    /// Assembly and LLVM IR expectations: `; name`.
+    pub const ASSEMBLY: Syntax = Syntax {
+        marker: ";",
+        legacy_marker: None,
+    };
+
+    /// The syntax of the file at `path`, from its name. Anything but a `Makefile` or a `.s` or
+    /// `.ll` file is taken to be Rust.
+    pub fn of(path: &Path) -> Syntax {
+        if path.file_name().is_some_and(|n| n == "Makefile") {
+            return Syntax::MAKEFILE;
+        }
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("s" | "ll") => Syntax::ASSEMBLY,
+            _ => Syntax::RUST,
+        }
+    }
+
+    /// Marker of plain comments, e.g. the reasons kept above replaced directives.
+    pub fn comment(&self) -> &'static str {
+        self.legacy_marker.unwrap_or(self.marker)
+    }
+}
+
+/// A single directive of a test header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directive<'a> {
+    /// 1-based line number.
+    pub line: usize,
+    /// Byte range of the whole line in the source, without its line ending.
+    pub span: Range<usize>,
+    /// Everything before the name: indentation, the comment marker and the revisions, e.g.
+    /// `//@[a,b]`. Writing another directive after it keeps it on the same revisions.
+    pub prefix: &'a str,
+    /// Revisions the directive is restricted to, empty if it applies to all of them.
+    pub revisions: Vec<&'a str>,
+    /// Name of the directive, e.g. `ignore-debug` or `compile-flags`.
+    pub name: &'a str,
+    /// Whatever follows the name: the value after `:` for e.g. `compile-flags`, or the reason
+    /// given for e.g. `ignore-*`. `None` if empty.
+    pub value: Option<&'a str>,
+    /// Whether the directive uses the legacy `//` syntax rather than `//@`.
+    pub legacy: bool,
+    /// The comment marker the directive is written with, e.g. `//@`.
+    pub marker: &'static str,
+}
+
+/// Every directive in the header of the Rust test `src`, in file order, see the [module
+/// docs](self).
+pub fn parse(src: &str) -> Vec<Directive<'_>> {
+    parse_with(src, Syntax::RUST)
+}
+
+/// Every directive in the header of `src`, written with `syntax`, in file order.
+pub fn parse_with(src: &str, syntax: Syntax) -> Vec<Directive<'_>> {
+    let mut directives = Vec::new();
+    let mut offset = 0;
+    for (idx, line) in src.split_inclusive('\n').enumerate() {
+        let start = offset;
+        offset += line.len();
+        let line = line.trim_end_matches(['\r', '\n']);
+        let trimmed = line.trim_start();
+        if !trimmed.is_empty() && !trimmed.starts_with(syntax.comment()) {
+            break;
+        }
+        if let Some(mut directive) = parse_line(line, syntax) {
+            directive.line = idx + 1;
+            directive.span = start..start + line.len();
+            directives.push(directive);
+        }
+    }
+    if directives.iter().any(|d| !d.legacy) {
+        directives.retain(|d| !d.legacy);
+    }
+    directives
+}
+
+/// Lines of the header of `src` which look like they were meant as a `name` directive without
+/// being parsed as one, e.g. `// name` in a file using `//@`, or `//@[rev name` with an unclosed
+/// bracket, as byte ranges of the whole lines without their line endings.
+pub fn lookalikes(src: &str, syntax: Syntax, name: &str) -> Vec<Range<usize>> {
+    let directives = parse_with(src, syntax);
+    let mut found = Vec::new();
+    let mut offset = 0;
+    for line in src.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        let line = line.trim_end_matches(['\r', '\n']);
+        let trimmed = line.trim_start();
+        if !trimmed.is_empty() && !trimmed.starts_with(syntax.comment()) {
+            break;
+        }
+        let Some(rest) = trimmed
+            .strip_prefix(syntax.marker)
+            .or_else(|| trimmed.strip_prefix(syntax.legacy_marker?))
+        else {
+            continue;
+        };
+        let rest = match rest.trim_start().strip_prefix('[') {
+            Some(bracketed) => match bracketed.split_once(']') {
+                Some((_, rest)) => rest,
+                None => bracketed
+                    .split_once(char::is_whitespace)
+                    .map_or("", |(_, rest)| rest),
+            },
+            None => rest,
+        };
+        if rest.trim_start().starts_with(name) && !directives.iter().any(|d| d.span.start == start)
+        {
+            found.push(start..start + line.len());
+        }
+    }
+    found
+}
+
+/// The directive on `line`, if any, with a placeholder position.
+fn parse_line(line: &str, syntax: Syntax) -> Option<Directive<'_>> {
+    let trimmed = line.trim_start();
+    let (rest, marker, legacy) = match (trimmed.strip_prefix(syntax.marker), syntax.legacy_marker) {
+        (Some(rest), _) => (rest, syntax.marker, false),
+        (None, Some(legacy_marker)) => (trimmed.strip_prefix(legacy_marker)?, legacy_marker, true),
+        (None, None) => return None,
+    };
+
+    let (revisions, rest) = match rest.strip_prefix('[') {
+        Some(bracketed) => {
+            let (revisions, rest) = bracketed.split_once(']')?;
+            let revisions = revisions
+                .split(',')
+                .map(str::trim)
+                .filter(|r| !r.is_empty())
+                .collect();
+            (revisions, rest)
+        }
+        None => (Vec::new(), rest),
+    };
+    let prefix = &line[..line.len() - rest.len()];
+
+    let directive = rest.trim();
+    let name_len = directive
+        .find(|c: char| c == ':' || c.is_whitespace())
+        .unwrap_or(directive.len());
+    let name = &directive[..name_len];
+    if !is_directive_name(name) {
+        return None;
+    }
+    let value = directive[name_len..]
+        .strip_prefix(':')
+        .unwrap_or(&directive[name_len..])
+        .trim();
+
+    Some(Directive {
+        line: 0,
+        span: 0..0,
+        prefix,
+        revisions,
+        name,
+        value: (!value.is_empty()).then_some(value),
+        legacy,
+        marker,
+    })
+}
+
+/// Directive names are made of lowercase letters, digits, `-` and `_` (as in
+/// `ignore-x86_64-unknown-linux-gnu`), which keeps ordinary legacy `//` comments out.
+fn is_directive_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names<'a>(directives: &[Directive<'a>]) -> Vec<&'a str> {
+        directives.iter().map(|d| d.name).collect()
+    }
+
+    /// `tests/codegen/vec-shrink-panik.rs`, as of the switch to `//@`.
+    const CODEGEN: &str = "\
+//@ revisions: old new
+//@ compile-flags: -O
+//@[old]min-llvm-version: 17.0
+//@[new]min-llvm-version: 18
+//@ ignore-debug: plain old debug assertions
+//@ needs-unwind
+#![crate_type = \"lib\"]
+#![feature(shrink_to)]
+
+// Make sure that `Vec::shrink_to_fit` never emits panics via `RawVec::shrink_to_fit`,
+// \"Tried to shrink to a larger capacity\", because the length is *always* <= capacity.
+
+// CHECK-LABEL: @shrink_to_fit
+#[no_mangle]
+pub fn shrink_to_fit(vec: &mut Vec<u32>) {
+    // CHECK-NOT: panic
+    vec.shrink_to_fit();
+}
+";
+
+    /// `tests/ui/print_type_sizes/niche-filling.rs`, before the switch to `//@`.
+    const LEGACY_UI: &str = "\
+// compile-flags: -Z print-type-sizes --crate-type=lib
+// build-pass
+// ignore-pass
+// ^-- needed because `--pass check` does not emit the output needed.
+//     FIXME: consider using an attribute instead of side-effects.
+
+// This file illustrates how niche-filling enums are handled,
+// modelled after cases like `Option<&u32>`, `Option<bool>` and such.
+//
+// ignore-debug: debug assertions will print more types
+
+#![feature(rustc_attrs)]
+
+mod one {}
+// ignore-test
+";
+
+    #[test]
+    fn parses_names_values_and_revisions() {
+        let directives = parse(CODEGEN);
+        assert_eq!(
+            names(&directives),
+            [
+                "revisions",
+                "compile-flags",
+                "min-llvm-version",
+                "min-llvm-version",
+                "ignore-debug",
+                "needs-unwind",
+            ]
+        );
+        assert_eq!(directives[0].value, Some("old new"));
+        assert_eq!(directives[1].value, Some("-O"));
+        assert_eq!(directives[2].revisions, ["old"]);
+        assert_eq!(directives[2].prefix, "//@[old]");
+        assert_eq!(directives[2].value, Some("17.0"));
+        assert_eq!(
+            directives[4].value,
+            Some("plain old debug assertions"),
+            "the reason of an `ignore-*` directive"
+        );
+        assert_eq!(directives[5].value, None);
+        assert!(directives.iter().all(|d| !d.legacy));
+    }
+
+    #[test]
+    fn comments_after_the_header_are_not_directives() {
+        // `// CHECK-LABEL` and `// CHECK-NOT` are FileCheck lines and the prose comment has an
+        // uppercase first word.
+        assert_eq!(parse(CODEGEN).len(), 6);
+    }
+
+    #[test]
+    fn parses_legacy_syntax_in_the_header() {
+        let directives = parse(LEGACY_UI);
+        let ignore_debug = directives
+            .iter()
+            .find(|d| d.name == "ignore-debug")
+            .unwrap();
+        assert_eq!(ignore_debug.line, 10);
+        assert_eq!(ignore_debug.prefix, "//");
+        assert_eq!(
+            ignore_debug.value,
+            Some("debug assertions will print more types")
+        );
+        assert!(ignore_debug.legacy);
+        assert!(
+            !directives.iter().any(|d| d.name == "ignore-test"),
+            "directives after the code are not part of the header"
+        );
+        assert!(!directives.iter().any(|d| d.name.starts_with('^')));
+    }
+
+    #[test]
+    fn directives_below_code_are_comments() {
+        let src = "\
+//@ check-pass
+#![feature(rustc_attrs)]
+//@ ignore-debug
+";
+        assert_eq!(names(&parse(src)), ["check-pass"]);
+
+        let src = "\
+//@ run-pass
+
+pub struct S;
+
+impl S {
+    // ignore-debug: this used to be needed because of the overflow check below
+    pub fn get(&self) -> u8 {
+        255 + 1
+    }
+}
+";
+        assert_eq!(names(&parse(src)), ["run-pass"]);
+
+        let src = "\
+use std::hint::black_box;
+// ignore-debug
+fn main() {}
+";
+        assert_eq!(parse(src), []);
+    }
+
+    #[test]
+    fn block_comments_end_the_header() {
+        let src = "\
+//@ build-pass
+/* Regression test for #12345.
+// ignore-debug
+*/
+";
+        assert_eq!(names(&parse(src)), ["build-pass"]);
+    }
+
+    #[test]
+    fn header_spans_blank_lines_and_prose() {
+        let src = "\
+// Regression test for #12345: the overflow check must not fire.
+
+//@ ignore-debug
+//! Crate docs.
+//@ compile-flags: -O
+fn main() {}
+";
+        assert_eq!(names(&parse(src)), ["ignore-debug", "compile-flags"]);
+    }
+
+    #[test]
+    fn spans_cover_the_line_without_its_ending() {
+        let src = "//@ check-pass\r\n  //@[a, b] ignore-debug (slow)\nfn main() {}\n";
+        let directives = parse(src);
+        assert_eq!(directives.len(), 2);
+        assert_eq!(&src[directives[0].span.clone()], "//@ check-pass");
+        assert_eq!(directives[1].line, 2);
+        assert_eq!(
+            &src[directives[1].span.clone()],
+            "  //@[a, b] ignore-debug (slow)"
+        );
+        assert_eq!(directives[1].prefix, "  //@[a, b]");
+        assert_eq!(directives[1].revisions, ["a", "b"]);
+        assert_eq!(directives[1].value, Some("(slow)"));
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        for line in [
+            "//! Crate docs",
+            "/// Item docs",
+            "//@[unclosed ignore-debug",
+            "//@ Uppercase",
+            "// ",
+            "let x = 1; // ignore-debug",
+        ] {
+            assert_eq!(parse(line), [], "{line:?}");
+        }
+    }
+
+    #[test]
+    fn finds_lines_meant_as_directives() {
+        let src = "\
+//@ run-pass
+// ignore-debug
+//@[unclosed ignore-debug (slow)
+//@ ignore-debug-assertions
+// FIXME: drop ignore-debug
+fn main() {} // ignore-debug
+";
+        let lines: Vec<&str> = lookalikes(src, Syntax::RUST, "ignore-debug")
+            .into_iter()
+            .map(|span| &src[span])
+            .collect();
+        assert_eq!(
+            lines,
+            ["// ignore-debug", "//@[unclosed ignore-debug (slow)"]
+        );
+        assert!(lookalikes("//@ ignore-debug\n", Syntax::RUST, "ignore-debug").is_empty());
+    }
+
+    #[test]
+    fn comments_are_not_directives_in_files_using_the_new_syntax() {
+        let src = "\
+// check that the overflow is caught
+//@ run-pass
+//@ ignore-debug
+// needs-unwind is not needed
+fn main() {}
+";
+        let directives = parse(src);
+        assert_eq!(names(&directives), ["run-pass", "ignore-debug"]);
+        assert_eq!(directives.last().unwrap().marker, "//@");
+    }
+
+    #[test]
+    fn other_syntaxes() {
+        let makefile = "\
+# ignore-cross-compile
+# ignore-debug: the output differs
+include ../tools.mk
+
+# ignore-test
+all:
+";
+        assert_eq!(
+            Syntax::of(Path::new("tests/run-make/foo/Makefile")),
+            Syntax::MAKEFILE
+        );
+        let directives = parse_with(makefile, Syntax::MAKEFILE);
+        assert_eq!(names(&directives), ["ignore-cross-compile", "ignore-debug"]);
+        assert_eq!(directives[1].prefix, "#");
+        assert_eq!(directives[1].marker, "#");
+        assert!(!directives[1].legacy);
+        assert_eq!(parse(makefile), []);
+
+        let ll = "; ignore-debug\n; CHECK: foo\ndefine void @f() {\n";
+        assert_eq!(
+            Syntax::of(Path::new("tests/assembly/foo.ll")),
+            Syntax::ASSEMBLY
+        );
+        assert_eq!(names(&parse_with(ll, Syntax::ASSEMBLY)), ["ignore-debug"]);
+    }
+
+    #[test]
+    fn name_is_delimited_by_colon_or_whitespace() {
+        let directives = parse("//@ ignore-debug-assertions\n//@ ignore-debug:x\n");
+        assert_eq!(
+            names(&directives),
+            ["ignore-debug-assertions", "ignore-debug"]
+        );
+        assert_eq!(directives[1].value, Some("x"));
+    }
+}
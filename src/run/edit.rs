@@ -0,0 +1,245 @@
+//! Editing of `// ignore-debug` directives in test source.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::directives::{self, Directive, Syntax};
+use super::rules::{self, Rule};
+
+/// The result of editing a test file.
+#[derive(Debug, Clone)]
+pub struct Edit {
+    /// The new contents of the file.
+    pub src: String,
+    /// Each directive line that was touched, in file order.
+    pub changes: Vec<LineChange>,
+}
+
+/// A single directive line that was removed or replaced.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LineChange {
+    /// 1-based line number in the original file.
+    pub line: usize,
+    /// The original line, verbatim except for the line ending.
+    pub original: String,
+    /// The line(s) inserted in its place, if any.
+    pub replacement: Vec<String>,
+}
+
+/// Whether `directive` is an `ignore-debug` directive.
+fn is_ignore_debug(directive: &Directive<'_>) -> bool {
+    directive.name == "ignore-debug"
+}
+
+pub fn has_directive(src: &str) -> bool {
+    directives::parse(src).iter().any(is_ignore_debug)
+}
+
+/// 1-based line numbers and contents of every `// ignore-debug` line.
+pub fn find_directives(src: &str) -> Vec<(usize, &str)> {
+    directives::parse(src)
+        .into_iter()
+        .filter(is_ignore_debug)
+        .map(|d| (d.line, &src[d.span]))
+        .collect()
+}
+
+/// Drop every `// ignore-debug` line, as the built-in `remove` rule does.
+pub fn remove_directive(src: &str) -> Edit {
+    default_rule("remove").apply(src, Syntax::RUST)
+}
+
+/// Replace every `// ignore-debug` line with `// compile-flags: -Cdebug-assertions=no`, as the
+/// built-in `replace` rule does.
+pub fn replace_directive(src: &str) -> Edit {
+    default_rule("replace").apply(src, Syntax::RUST)
+}
+
+fn default_rule(name: &str) -> Rule {
+    rules::default_rules()
+        .into_iter()
+        .find(|rule| rule.name == name)
+        .unwrap()
+}
+
+/// Substitute the lines of `src` with the lines they map to in `replacements`, by 1-based line
+/// number. The line ending of the original line is reused for the inserted lines.
+pub(super) fn edit_lines(src: &str, replacements: &BTreeMap<usize, Vec<String>>) -> Edit {
+    let mut out = String::with_capacity(src.len());
+    let mut changes = Vec::new();
+    for (idx, line) in src.split_inclusive('\n').enumerate() {
+        let content = line.trim_end_matches(['\r', '\n']);
+        let Some(replacement) = replacements.get(&(idx + 1)) else {
+            out.push_str(line);
+            continue;
+        };
+        let ending = &line[content.len()..];
+        for new_line in replacement {
+            out.push_str(new_line);
+            out.push_str(if ending.is_empty() { "\n" } else { ending });
+        }
+        if !replacement.is_empty() && ending.is_empty() {
+            // Don't introduce a trailing newline the file didn't have.
+            out.pop();
+        }
+        changes.push(LineChange {
+            line: idx + 1,
+            original: content.to_string(),
+            replacement: replacement.clone(),
+        });
+    }
+    Edit { src: out, changes }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Header lines of a test in one syntax: every directive is written with `marker`. Files
+    /// mixing `//@` and legacy `//` directives are left out, as removing their last `//@`
+    /// directive turns their `//` comments back into directives.
+    fn header_line(marker: &'static str) -> impl Strategy<Value = String> {
+        prop_oneof![
+            Just(format!("{marker} ignore-debug")),
+            Just(format!("{marker} ignore-debug: slow in debug")),
+            Just(format!("{marker}[a] ignore-debug")),
+            Just(format!("  {marker} ignore-debug")),
+            Just(format!("{marker} compile-flags: -O")),
+            Just(format!("{marker}[a,b] compile-flags: -Copt-level=3")),
+            Just(format!("{marker} revisions: a b")),
+            Just(format!("{marker} run-pass")),
+            Just("// A comment about ignore-debug.".to_string()),
+            Just(String::new()),
+        ]
+    }
+
+    fn body_line() -> impl Strategy<Value = String> {
+        prop_oneof![
+            Just("fn main() {}".to_string()),
+            Just("// ignore-debug".to_string()),
+            Just("//@ ignore-debug".to_string()),
+            Just("#![feature(rustc_attrs)]".to_string()),
+            "[^\r\n]*",
+        ]
+    }
+
+    /// A test source: a header, a body starting with code, a line ending and whether the last
+    /// line has one.
+    fn test_source() -> impl Strategy<Value = String> {
+        let header = prop_oneof![Just("//@"), Just("//")]
+            .prop_flat_map(|marker| prop::collection::vec(header_line(marker), 0..8));
+        let body = (
+            Just("fn main() {}".to_string()),
+            prop::collection::vec(body_line(), 0..6),
+        )
+            .prop_map(|(first, mut rest)| {
+                rest.insert(0, first);
+                rest
+            });
+        (
+            header,
+            body,
+            prop_oneof![Just("\n"), Just("\r\n")],
+            any::<bool>(),
+        )
+            .prop_map(|(header, body, ending, trailing)| {
+                let mut src = header
+                    .into_iter()
+                    .chain(body)
+                    .collect::<Vec<_>>()
+                    .join(ending);
+                if trailing {
+                    src.push_str(ending);
+                }
+                src
+            })
+    }
+
+    /// Number of lines in the header of `src`, see [`directives`].
+    fn header_len(src: &str) -> usize {
+        src.split_inclusive('\n')
+            .take_while(|line| {
+                let line = line.trim();
+                line.is_empty() || line.starts_with("//")
+            })
+            .count()
+    }
+
+    fn lines(src: &str) -> Vec<&str> {
+        src.split_inclusive('\n').collect()
+    }
+
+    proptest! {
+        #[test]
+        fn removal_only_drops_header_lines(src in test_source()) {
+            let edit = remove_directive(&src);
+            let header_len = header_len(&src);
+            let removed: Vec<usize> = edit.changes.iter().map(|c| c.line).collect();
+            for change in &edit.changes {
+                prop_assert!(change.line <= header_len);
+                prop_assert!(change.replacement.is_empty());
+                prop_assert_eq!(
+                    lines(&src)[change.line - 1].trim_end_matches(['\r', '\n']),
+                    change.original.as_str()
+                );
+            }
+            let kept: Vec<&str> = lines(&src)
+                .into_iter()
+                .enumerate()
+                .filter(|(idx, _)| !removed.contains(&(idx + 1)))
+                .map(|(_, line)| line)
+                .collect();
+            prop_assert_eq!(lines(&edit.src), kept);
+        }
+
+        #[test]
+        fn removal_is_idempotent(src in test_source()) {
+            let once = remove_directive(&src);
+            prop_assert!(!has_directive(&once.src));
+            let twice = remove_directive(&once.src);
+            prop_assert!(twice.changes.is_empty());
+            prop_assert_eq!(twice.src, once.src);
+        }
+
+        #[test]
+        fn replacement_is_idempotent(src in test_source()) {
+            let once = replace_directive(&src);
+            prop_assert!(!has_directive(&once.src));
+            let twice = replace_directive(&once.src);
+            prop_assert!(twice.changes.is_empty());
+            prop_assert_eq!(twice.src, once.src);
+        }
+
+        #[test]
+        fn replacement_only_rewrites_directive_lines(src in test_source()) {
+            let edit = replace_directive(&src);
+            let header_len = header_len(&src);
+            // `ignore-debug` lines, or the `compile-flags` lines the flag is merged into.
+            let directives: Vec<usize> = directives::parse(&src).iter().map(|d| d.line).collect();
+            for change in &edit.changes {
+                prop_assert!(directives.contains(&change.line));
+            }
+            // Every other line is kept as is, line ending included.
+            let mut edited = lines(&edit.src).into_iter();
+            for (idx, line) in lines(&src).into_iter().enumerate() {
+                match edit.changes.iter().find(|c| c.line == idx + 1) {
+                    Some(change) => {
+                        prop_assert!(change.line <= header_len);
+                        for replacement in &change.replacement {
+                            let edited_line = edited.next().unwrap();
+                            prop_assert_eq!(
+                                edited_line.trim_end_matches(['\r', '\n']),
+                                replacement.as_str()
+                            );
+                        }
+                    }
+                    None => prop_assert_eq!(edited.next(), Some(line)),
+                }
+            }
+            prop_assert_eq!(edited.next(), None);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! The `estimate` command: how long a run would take and how many times it would invoke `x`,
+//! going by the durations measured by previous runs.
+
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::time::Duration;
+
+use miette::Result;
+
+use super::summary::format_duration;
+use super::{incremental, paths, rules, scan, worktrees};
+use crate::config::Config;
+use crate::history;
+
+/// What a run would amount to, see [`estimate`].
+#[derive(Debug)]
+pub struct Estimate {
+    /// Tests the run would process.
+    pub candidates: usize,
+    /// Candidates with a duration measured by a previous run.
+    pub measured: usize,
+    /// Fewest and most `x` invocations the run would make, depending on which rule each test
+    /// passes with.
+    pub invocations: (usize, usize),
+    /// Expected wall-clock duration, if any previous run measured a candidate.
+    pub duration: Option<Duration>,
+    pub jobs: NonZeroUsize,
+}
+
+/// Estimate a run of `config` on `rustc_repo_path` with `jobs` (in `batch`es or not), going by the
+/// runs under `runs_root`. Tests already migrated are left out like the run would, but outcomes
+/// the cache would reuse are not: the estimate errs on the long side.
+pub fn estimate(
+    config: &Config,
+    rustc_repo_path: &Path,
+    runs_root: &Path,
+    jobs: NonZeroUsize,
+    batch: bool,
+) -> Result<Estimate> {
+    let mut candidates = scan::scan(config, rustc_repo_path).candidates;
+    let migrated = incremental::already_migrated(runs_root, rustc_repo_path, &candidates)?;
+    candidates.retain(|f| !migrated.contains_key(&paths::repo_relative(rustc_repo_path, f)));
+
+    let rules = rules::resolve(config).len();
+    let prebuilds = if config.prebuild && !candidates.is_empty() {
+        1 + worktrees::resolve(config, rustc_repo_path).len()
+    } else {
+        0
+    };
+    let invocations = if candidates.is_empty() {
+        (0, 0)
+    } else if batch {
+        // One pass per rule, each over the tests left.
+        (prebuilds + 1, prebuilds + rules)
+    } else {
+        // The sanity check of each test, then its rules until one passes.
+        (
+            prebuilds + candidates.len() * 2,
+            prebuilds + candidates.len() * (1 + rules),
+        )
+    };
+
+    let durations = history::last_durations(runs_root)?;
+    let estimates: Vec<Option<Duration>> = candidates
+        .iter()
+        .map(|f| {
+            durations
+                .get(&paths::repo_relative(rustc_repo_path, f))
+                .copied()
+        })
+        .collect();
+    let known: Vec<Duration> = estimates.iter().flatten().copied().collect();
+    let duration = (!known.is_empty()).then(|| {
+        let average = known.iter().sum::<Duration>() / known.len() as u32;
+        let total: Duration = estimates.iter().map(|e| e.unwrap_or(average)).sum();
+        if batch {
+            total
+        } else {
+            total / jobs.get() as u32
+        }
+    });
+
+    Ok(Estimate {
+        candidates: candidates.len(),
+        measured: known.len(),
+        invocations,
+        duration,
+        jobs,
+    })
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} candidate test(s), {} measured by a previous run",
+            self.candidates, self.measured
+        )?;
+        let (fewest, most) = self.invocations;
+        if fewest == most {
+            writeln!(f, "`x` invocations: {fewest}")?;
+        } else {
+            writeln!(f, "`x` invocations: {fewest} to {most}")?;
+        }
+        match self.duration {
+            Some(duration) => writeln!(
+                f,
+                "Expected duration: {} with {} job(s)",
+                format_duration(duration),
+                self.jobs
+            ),
+            None => writeln!(
+                f,
+                "Expected duration: unknown, no previous run measured any of the tests"
+            ),
+        }
+    }
+}
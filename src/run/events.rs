@@ -0,0 +1,131 @@
+//! A stable, versioned stream of events for orchestrators building on top of a run, delivered to
+//! the callbacks registered with [`Engine::on_event`](super::Engine::on_event) and, as JSON lines,
+//! to `--events-fd`.
+//!
+//! Unlike the progress events of `--progress-fd`, which are meant for progress bars and may
+//! change, the shape of these events only changes along with [`VERSION`]. New variants and fields
+//! may be added within a version, so consumers should ignore what they don't know.
+
+use std::fs::File;
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use miette::Result;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use super::{LineChange, RunOutcome, TestRecord};
+
+/// Version of the event schema, written along with every event as `"version"`.
+pub const VERSION: u32 = 1;
+
+/// Something that happened during a run. Paths of tests are relative to the `rustc` repo.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Event {
+    /// The target directories are about to be walked for candidate tests.
+    ScanStarted { target_directories: Vec<PathBuf> },
+    /// A test is about to be processed. In batch mode, all the tests start at once.
+    TestStarted { path: String },
+    /// An edit of the directive(s) of a test was written, and the edited test is about to be
+    /// run. It is reverted unless the test passes.
+    EditApplied {
+        path: String,
+        changes: Vec<LineChange>,
+    },
+    /// The final outcome of a test.
+    OutcomeDecided {
+        path: String,
+        outcome: RunOutcome,
+        duration_secs: f64,
+        source: OutcomeSource,
+    },
+    /// The report, or one of the commit message and PR description drafts next to it, was
+    /// written to `path`.
+    ReportWritten { path: PathBuf },
+}
+
+/// How the outcome of a test was decided.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum OutcomeSource {
+    /// The test was run.
+    Run,
+    /// The outcome of a previous run was reused from the cache.
+    Cache,
+    /// The test is identical to another one which was run, see `--dedup`.
+    Dedup,
+}
+
+/// An [`Event`] along with the schema [`VERSION`], as written to `--events-fd`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub version: u32,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl Envelope {
+    /// Parse a line written to `--events-fd`.
+    pub fn from_json_line(line: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(line)
+    }
+}
+
+impl Event {
+    /// The event as a single line of JSON (without the newline), tagged with [`VERSION`].
+    pub fn to_json_line(&self) -> String {
+        serde_json::to_string(&Envelope {
+            version: VERSION,
+            event: self.clone(),
+        })
+        .expect("events are serializable")
+    }
+
+    pub(super) fn outcome_decided(path: &str, record: &TestRecord, source: OutcomeSource) -> Self {
+        Event::OutcomeDecided {
+            path: path.to_string(),
+            outcome: record.outcome,
+            duration_secs: record.duration.as_secs_f64(),
+            source,
+        }
+    }
+}
+
+/// A callback receiving the events of a run, possibly from several threads.
+pub type Listener = Arc<dyn Fn(&Event) + Send + Sync>;
+
+/// The listeners of the current run, see [`configure`].
+static LISTENERS: Mutex<Vec<Listener>> = Mutex::new(Vec::new());
+
+/// Set the listeners receiving the events of the run about to start.
+pub(super) fn configure(listeners: Vec<Listener>) {
+    *LISTENERS.lock().unwrap() = listeners;
+}
+
+pub(super) fn emit(event: Event) {
+    let listeners = LISTENERS.lock().unwrap().clone();
+    for listener in &listeners {
+        listener(&event);
+    }
+}
+
+/// A listener writing each event as a JSON line to the already-open file descriptor `fd`.
+pub(super) fn write_to_fd(fd: i32) -> Result<Listener> {
+    let out: Mutex<Option<File>> = Mutex::new(Some(super::progress::open_fd(fd)?));
+    Ok(Arc::new(move |event: &Event| {
+        let mut out = out.lock().unwrap();
+        let Some(file) = out.as_mut() else {
+            return;
+        };
+        let mut line = event.to_json_line();
+        line.push('\n');
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            warn!("failed to write event, no further events will be emitted: {e}");
+            *out = None;
+        }
+    }))
+}
@@ -0,0 +1,203 @@
+//! The `explain` command: what a run did with a test, why it settled on its outcome, and what to do
+//! about it, for triaging the tests left for manual review.
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use miette::{bail, Result};
+
+use super::rules::{Rule, Ruleset};
+use super::state::RunState;
+use super::{paths, test_logs, RunOutcome, TestRecord};
+use crate::history;
+
+/// Explain what the most recent run under `runs_root` which came across `test` did with it, or the
+/// run with id `run`. `test` is a path to the test, or a path relative to `rustc_repo_path`.
+pub fn explain(
+    rustc_repo_path: &Path,
+    runs_root: &Path,
+    test: &Path,
+    run: Option<&str>,
+) -> Result<String> {
+    let rel_path = match (test.canonicalize(), rustc_repo_path.canonicalize()) {
+        (Ok(test), Ok(rustc_repo_path)) if test.starts_with(&rustc_repo_path) => {
+            paths::repo_relative(&rustc_repo_path, &test)
+        }
+        _ => paths::repo_relative(rustc_repo_path, test),
+    };
+    let runs = history::list_runs(runs_root)?;
+    let found = runs.into_iter().rev().find_map(|(run_dir, state)| {
+        let state = state?;
+        let matches = match run {
+            Some(id) => run_dir.id == id,
+            None => {
+                state.records.contains_key(&rel_path)
+                    || state.skipped.contains_key(&rel_path)
+                    || state.pending.contains(&rel_path)
+            }
+        };
+        matches.then_some(state)
+    });
+    let Some(state) = found else {
+        match run {
+            Some(id) => bail!("no run `{id}` under `{}`", runs_root.display()),
+            None => bail!(
+                help = "run the tool on the directory of the test first",
+                "no run under `{}` came across `{rel_path}`",
+                runs_root.display()
+            ),
+        }
+    };
+    Ok(format_explanation(&rel_path, &state))
+}
+
+/// What the run of `state` did with the test at `rel_path`, and what to do next.
+pub(super) fn format_explanation(rel_path: &str, state: &RunState) -> String {
+    let mut out = String::new();
+    let mut next_steps = Vec::new();
+    if let Some(record) = state.records.get(rel_path) {
+        let _ = writeln!(
+            out,
+            "`{rel_path}`: {} (run {})\n",
+            record.outcome.description(),
+            state.id
+        );
+        explain_record(&mut out, &mut next_steps, record, &rules_of(state));
+        if let Some(logs) = &state.logs {
+            let log = test_logs::path_of(logs, rel_path);
+            if log.is_file() {
+                let _ = writeln!(out, "\nThe output of `x` is in `{}`.", log.display());
+            }
+        }
+    } else if let Some(reason) = state.skipped.get(rel_path) {
+        let _ = writeln!(out, "`{rel_path}`: skipped (run {})\n", state.id);
+        let _ = writeln!(out, "The test was not attempted: {reason}.");
+        next_steps.push("nothing, unless the test was expected to have a directive to rewrite");
+    } else if state.pending.contains(rel_path) {
+        let _ = writeln!(out, "`{rel_path}`: not processed (run {})\n", state.id);
+        let _ = writeln!(
+            out,
+            "The run stopped before getting to the test, e.g. on reaching its `--deadline`."
+        );
+        next_steps.push("run again with `--incremental` to pick up where it stopped");
+    } else {
+        let _ = writeln!(out, "Run {} did not come across `{rel_path}`.", state.id);
+        return out;
+    }
+
+    if let Some(contradictions) = state.contradictions.get(rel_path) {
+        let _ = writeln!(out, "\nIts header already contradicts itself:");
+        for contradiction in contradictions {
+            let _ = writeln!(out, "  - {contradiction}");
+        }
+        next_steps.push("fix the contradicting directives of its header");
+    }
+
+    let _ = writeln!(out, "\nNext steps:");
+    for step in next_steps {
+        let _ = writeln!(out, "  - {step}");
+    }
+    out
+}
+
+fn explain_record(
+    out: &mut String,
+    next_steps: &mut Vec<&'static str>,
+    record: &TestRecord,
+    rules: &[Rule],
+) {
+    if let Some(original) = &record.duplicate_of {
+        let _ = writeln!(
+            out,
+            "The test is identical to `{original}`, whose outcome was applied to it (`--dedup`)."
+        );
+    }
+    let tried = rules
+        .iter()
+        .map(|rule| format!("`{}`", rule.name))
+        .collect::<Vec<_>>()
+        .join(", then ");
+    match record.outcome {
+        RunOutcome::RemoveOk | RunOutcome::ReplaceOk => {
+            let rule = record.rule.as_deref().unwrap_or("?");
+            let _ = writeln!(
+                out,
+                "Rules are tried in order ({tried}). The test passed with `{rule}`, so its edit \
+                 was kept:"
+            );
+            for change in &record.changes {
+                let _ = writeln!(out, "  line {}: -{}", change.line, change.original);
+                for line in &change.replacement {
+                    let _ = writeln!(out, "  line {}: +{line}", change.line);
+                }
+            }
+            for snapshot in &record.snapshot_changes {
+                let _ = writeln!(
+                    out,
+                    "  {} blessed (+{} -{})",
+                    snapshot.path, snapshot.added_lines, snapshot.removed_lines
+                );
+            }
+            next_steps.push("review the change and commit it, or open a PR with `submit`");
+        }
+        RunOutcome::UnmodifiedOk => {
+            let _ = writeln!(
+                out,
+                "Tried {tried}. The test failed with each of them, so it was put back as it was: \
+                 it most likely does depend on debug assertions being off."
+            );
+            next_steps.push(
+                "to see why it failed, read its log under `logs/` in the run directory, or run it \
+                 again with `--show-output`",
+            );
+        }
+        RunOutcome::Ignored => {
+            let _ = writeln!(
+                out,
+                "Bootstrap reported the test as ignored even without its directive, so nothing \
+                 could be learned from running it."
+            );
+            next_steps.push(
+                "check its other `ignore-*` and `needs-*` directives, or run it on a host where \
+                 it is not ignored",
+            );
+        }
+        RunOutcome::NeedsManualReview => {
+            let hint = record
+                .review_hint
+                .as_deref()
+                .unwrap_or("no reason was recorded");
+            let _ = writeln!(
+                out,
+                "Automation declined to decide and left the test unmodified: {hint}."
+            );
+            next_steps.push(if hint.contains("FileCheck") {
+                "inspect its `CHECK` lines, which may match code that differs under debug \
+                 assertions, or set `try_filecheck_tests` to let the tool try it"
+            } else if hint.contains("snapshot") {
+                "inspect the snapshot diff, or raise `snapshot_outlier_lines` if it is expected"
+            } else if hint.contains("debug assertions explicitly") {
+                "decide by hand whether the test needs `ignore-debug` given its `compile-flags`"
+            } else if hint.contains("skipped") {
+                "run it again, without skipping it"
+            } else {
+                "edit the test by hand"
+            });
+        }
+    }
+    for (worktree, result) in &record.worktrees {
+        let _ = writeln!(out, "In worktree `{worktree}`: {result:?}.");
+    }
+}
+
+/// The rules the run of `state` tried, in order, according to its manifest.
+pub(super) fn rules_of(state: &RunState) -> Vec<Rule> {
+    let config = &state.manifest.config;
+    let rules: Vec<Rule> = serde_json::from_value(config["rules"].clone()).unwrap_or_default();
+    if !rules.is_empty() {
+        return rules;
+    }
+    serde_json::from_value::<Ruleset>(config["ruleset"].clone())
+        .unwrap_or_default()
+        .rules()
+}
@@ -0,0 +1,238 @@
+//! `pre_test` and `post_test` hooks: commands from the config run around each test which is run,
+//! for custom logging, metrics or gating without modifying the tool. The `classifier` hook
+//! likewise encodes custom heuristics for ambiguous results, e.g. rejecting edits whose failure
+//! mentions `SIGSEGV`.
+//!
+//! Hooks are run with the shell from the root of the `rustc` repo. They get the test and (after
+//! it ran) its outcome both as `LESS_IGNORE_DEBUG_*` environment variables and as a JSON object on
+//! stdin.
+
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use miette::{Context, IntoDiagnostic, Result};
+use serde::Serialize;
+use tokio::io::AsyncWriteExt as _;
+use tokio::process::Command;
+use tracing::*;
+
+use super::{paths, process, Decision, RunOutcome};
+use crate::config::Config;
+
+#[derive(Debug, Serialize)]
+struct HookInput<'a> {
+    hook: &'static str,
+    /// Repo-relative path of the test.
+    path: &'a str,
+    /// Name of the rewrite rule which was kept (by default `remove` or `replace`) or `none`, once
+    /// the test ran.
+    strategy: Option<&'a str>,
+    outcome: Option<RunOutcome>,
+    duration_secs: Option<f64>,
+    /// How the edited test fared, for the `classifier` hook: `failed`, `timed_out` or `passed`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<&'a str>,
+    /// Unified diff of the test and its snapshots, for the `classifier` hook.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stdout: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stderr: Option<&'a str>,
+}
+
+/// What the `classifier` hook made of an ambiguous result, see [`classify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Classification {
+    Accept,
+    Reject,
+    /// With the reason given by the hook, if any.
+    Manual(String),
+}
+
+/// Run the `pre_test` hook for `target`, if any. Returns why the test should be left alone if the
+/// hook exited unsuccessfully.
+pub(super) async fn pre_test(
+    config: &Config,
+    rustc_repo_path: &Path,
+    target: &Path,
+) -> Result<Option<String>> {
+    let Some(command) = &config.pre_test else {
+        return Ok(None);
+    };
+    let path = paths::repo_relative(rustc_repo_path, target);
+    let input = HookInput {
+        hook: "pre_test",
+        path: &path,
+        strategy: None,
+        outcome: None,
+        duration_secs: None,
+        result: None,
+        diff: None,
+        stdout: None,
+        stderr: None,
+    };
+    let output = run_hook(command, rustc_repo_path, &input).await?;
+    if output.status.success() {
+        return Ok(None);
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    debug!("`pre_test` hook rejected `{path}`: {}", stderr.trim());
+    let mut hint = format!("the `pre_test` hook failed ({})", output.status);
+    if let Some(line) = stderr.lines().rev().find(|l| !l.trim().is_empty()) {
+        hint.push_str(&format!(": {}", line.trim()));
+    }
+    Ok(Some(hint))
+}
+
+/// Run the `post_test` hook for `target`, if any, once its outcome is decided. Failures of the
+/// hook are only reported.
+pub(super) async fn post_test(
+    config: &Config,
+    rustc_repo_path: &Path,
+    target: &Path,
+    decision: &Decision,
+    duration: Duration,
+) {
+    let Some(command) = &config.post_test else {
+        return;
+    };
+    let path = paths::repo_relative(rustc_repo_path, target);
+    let input = HookInput {
+        hook: "post_test",
+        path: &path,
+        strategy: Some(decision.rule.as_deref().unwrap_or("none")),
+        outcome: Some(decision.outcome),
+        duration_secs: Some(duration.as_secs_f64()),
+        result: None,
+        diff: None,
+        stdout: None,
+        stderr: None,
+    };
+    match run_hook(command, rustc_repo_path, &input).await {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => warn!(
+            "`post_test` hook failed for `{path}` with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => warn!("{e:?}"),
+    }
+}
+
+/// Run the `classifier` hook, if any, on the `result` of the edited `target`, given its `diff`
+/// and the `output` of `x`. `None` if there is no hook, or it failed or gave no classification.
+pub(super) async fn classify(
+    config: &Config,
+    rustc_repo_path: &Path,
+    target: &Path,
+    result: &str,
+    diff: &str,
+    output: &process::Captured,
+) -> Option<Classification> {
+    let command = config.classifier.as_ref()?;
+    let path = paths::repo_relative(rustc_repo_path, target);
+    let input = HookInput {
+        hook: "classifier",
+        path: &path,
+        strategy: None,
+        outcome: None,
+        duration_secs: None,
+        result: Some(result),
+        diff: Some(diff),
+        stdout: Some(&output.stdout),
+        stderr: Some(&output.stderr),
+    };
+    let output = match run_hook(command, rustc_repo_path, &input).await {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            warn!(
+                "`classifier` hook failed for `{path}` with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return None;
+        }
+        Err(e) => {
+            warn!("{e:?}");
+            return None;
+        }
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+    let (verdict, reason) = line
+        .trim()
+        .split_once(char::is_whitespace)
+        .unwrap_or((line.trim(), ""));
+    let classification = match verdict.to_lowercase().as_str() {
+        "accept" => Classification::Accept,
+        "reject" => Classification::Reject,
+        "manual" => Classification::Manual(reason.trim().to_string()),
+        _ => {
+            warn!(
+                "`classifier` hook gave no classification for `{path}`: `{}`",
+                line.trim()
+            );
+            return None;
+        }
+    };
+    info!("`classifier` hook: {result} `{path}`: {}", line.trim());
+    Some(classification)
+}
+
+async fn run_hook(
+    command: &str,
+    rustc_repo_path: &Path,
+    input: &HookInput<'_>,
+) -> Result<std::process::Output> {
+    let mut cmd = shell(command);
+    cmd.current_dir(rustc_repo_path)
+        .env("LESS_IGNORE_DEBUG_HOOK", input.hook)
+        .env("LESS_IGNORE_DEBUG_TEST", input.path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(strategy) = input.strategy {
+        cmd.env("LESS_IGNORE_DEBUG_STRATEGY", strategy);
+    }
+    if let Some(outcome) = input.outcome {
+        cmd.env("LESS_IGNORE_DEBUG_OUTCOME", format!("{outcome:?}"));
+    }
+    if let Some(result) = input.result {
+        cmd.env("LESS_IGNORE_DEBUG_RESULT", result);
+    }
+    let mut child = cmd.spawn().into_diagnostic().wrap_err(format!(
+        "failed to run the `{}` hook `{command}`",
+        input.hook
+    ))?;
+    let mut stdin = child.stdin.take().unwrap();
+    let json = serde_json::to_string(input).into_diagnostic()?;
+    // Hooks are free to ignore their input, and to exit before reading it.
+    let _ = stdin.write_all(json.as_bytes()).await;
+    drop(stdin);
+    let output = child.wait_with_output().await.into_diagnostic()?;
+    if !output.stdout.is_empty() {
+        debug!(
+            "`{}` hook: {}",
+            input.hook,
+            String::from_utf8_lossy(&output.stdout).trim()
+        );
+    }
+    Ok(output)
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
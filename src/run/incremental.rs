@@ -0,0 +1,173 @@
+//! `--incremental`: skip tests which were left with their directive by a previous run and have
+//! not been touched since, going by `git diff` between the commit that run was performed at and
+//! the working tree.
+//!
+//! Tests whose directive a previous run removed or replaced are always re-verified by
+//! `--incremental`, as the edit may not have been committed. Changes to the toolchain are not
+//! considered; use a full run (or the outcome cache, which does consider them) after compiler
+//! changes which may matter.
+//!
+//! Regardless of `--incremental`, tests whose contents are exactly what a previous run changed
+//! them to are skipped as already migrated, so that re-running after applying a run is a no-op.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use miette::Result;
+use tracing::*;
+
+use super::manifest::sha256_hex;
+use super::{paths, TestRecord};
+use crate::git;
+use crate::history;
+
+/// Candidates which need not be processed again, by repo-relative path, mapped to the id of the
+/// run which last verified them.
+pub(super) fn unchanged_since_verified(
+    runs_root: &Path,
+    rustc_repo_path: &Path,
+    candidates: &BTreeSet<PathBuf>,
+) -> Result<BTreeMap<String, String>> {
+    let candidates: BTreeSet<String> = candidates
+        .iter()
+        .map(|c| paths::repo_relative(rustc_repo_path, c))
+        .collect();
+
+    // The most recent run which processed each candidate, with the commit it was performed at.
+    let mut last_verified: BTreeMap<&str, (String, String)> = BTreeMap::new();
+    for (run_dir, state) in history::list_runs(runs_root)?.into_iter().rev() {
+        let Some(state) = state else {
+            continue;
+        };
+        let Some(commit) = state.manifest.rustc_commit else {
+            continue;
+        };
+        for (rel_path, record) in state.records {
+            let Some(candidate) = candidates.get(&rel_path) else {
+                continue;
+            };
+            if last_verified.contains_key(candidate.as_str()) {
+                continue;
+            }
+            if record.outcome.keeps_directive() {
+                last_verified.insert(candidate, (run_dir.id.clone(), commit.clone()));
+            } else {
+                // Mark as seen without making it skippable, so older runs don't count either.
+                last_verified.insert(candidate, (String::new(), String::new()));
+            }
+        }
+    }
+
+    let mut changed_since: BTreeMap<String, Option<BTreeSet<String>>> = BTreeMap::new();
+    let mut unchanged = BTreeMap::new();
+    for (rel_path, (run_id, commit)) in last_verified {
+        if commit.is_empty() {
+            continue;
+        }
+        let changed = changed_since
+            .entry(commit)
+            .or_insert_with_key(|commit| {
+                let changed = git::changed_files_since(rustc_repo_path, commit);
+                if changed.is_none() {
+                    warn!("could not diff against `{commit}`, re-verifying tests last run there");
+                }
+                changed
+            })
+            .as_ref();
+        let Some(changed) = changed else {
+            continue;
+        };
+        if !touched(rel_path, changed) {
+            unchanged.insert(rel_path.to_string(), run_id);
+        }
+    }
+    Ok(unchanged)
+}
+
+/// The SHA-256 of the contents of each test of `records` whose directive was removed or replaced,
+/// by repo-relative path, as kept on disk. To be called before the edits are reverted for
+/// `--emit`.
+pub(super) fn migrated_hashes(
+    rustc_repo_path: &Path,
+    records: &BTreeMap<String, TestRecord>,
+) -> BTreeMap<String, String> {
+    records
+        .iter()
+        .filter(|(_, record)| !record.outcome.keeps_directive())
+        .filter_map(|(rel_path, _)| {
+            let contents = std::fs::read(rustc_repo_path.join(rel_path)).ok()?;
+            Some((rel_path.clone(), sha256_hex(&contents)))
+        })
+        .collect()
+}
+
+/// Candidates whose contents are those a previous run migrated them to, by repo-relative path,
+/// mapped to the id of that run.
+pub(super) fn already_migrated(
+    runs_root: &Path,
+    rustc_repo_path: &Path,
+    candidates: &BTreeSet<PathBuf>,
+) -> Result<BTreeMap<String, String>> {
+    let mut migrated: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    for (run_dir, state) in history::list_runs(runs_root)? {
+        let Some(state) = state else {
+            continue;
+        };
+        for (rel_path, hash) in state.migrated {
+            migrated
+                .entry(rel_path)
+                .or_default()
+                .insert(hash, run_dir.id.clone());
+        }
+    }
+    if migrated.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+
+    let mut already = BTreeMap::new();
+    for candidate in candidates {
+        let rel_path = paths::repo_relative(rustc_repo_path, candidate);
+        let Some(hashes) = migrated.get(&rel_path) else {
+            continue;
+        };
+        let Ok(contents) = std::fs::read(candidate) else {
+            continue;
+        };
+        if let Some(run_id) = hashes.get(&sha256_hex(&contents)) {
+            already.insert(rel_path, run_id.clone());
+        }
+    }
+    Ok(already)
+}
+
+/// Whether the test or any of its companion snapshots is among the `changed` files.
+fn touched(rel_path: &str, changed: &BTreeSet<String>) -> bool {
+    let stem = rel_path.strip_suffix(".rs").unwrap_or(rel_path);
+    let companion_prefix = format!("{stem}.");
+    changed
+        .range(stem.to_string()..)
+        .take_while(|p| p.starts_with(stem))
+        .any(|p| p == rel_path || p.starts_with(&companion_prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tests_are_touched_by_changes_to_them_or_their_snapshots() {
+        let changed: BTreeSet<String> = [
+            "tests/ui/a.stderr",
+            "tests/ui/b.rs",
+            "tests/ui/c2.rs",
+            "tests/ui/d/e.rs",
+        ]
+        .map(String::from)
+        .into();
+        assert!(touched("tests/ui/a.rs", &changed));
+        assert!(touched("tests/ui/b.rs", &changed));
+        assert!(!touched("tests/ui/c.rs", &changed));
+        assert!(!touched("tests/ui/d.rs", &changed));
+        assert!(!touched("tests/ui/e.rs", &changed));
+    }
+}
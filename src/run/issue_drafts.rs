@@ -0,0 +1,198 @@
+//! The `issue-drafts` command: one issue draft per test a run left with its directive as
+//! `UnmodifiedOk` or `NeedsManualReview`, with the edit that was tried and an excerpt of why it
+//! failed, so that the residual work can be handed out to contributors.
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use miette::{bail, Result};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use super::directives::Syntax;
+use super::explain::rules_of;
+use super::rules::Rule;
+use super::state::RunState;
+use super::{test_logs, RunOutcome};
+use crate::github::GitHub;
+use crate::history;
+
+/// Lines of the failure output kept in a draft.
+const EXCERPT_LINES: usize = 40;
+
+/// A follow-up issue for a test, see [`issue_drafts`].
+#[derive(Debug, Serialize)]
+pub struct IssueDraft {
+    /// Repo-relative path of the test.
+    pub path: String,
+    pub outcome: RunOutcome,
+    pub title: String,
+    /// Markdown.
+    pub body: String,
+}
+
+/// The issue drafts for the tests the latest run under `runs_root` (or the run with id `run`) left
+/// with their directive, along with the id of that run. The edits are computed against the tests
+/// as they are now in `rustc_repo_path`.
+pub fn issue_drafts(
+    rustc_repo_path: &Path,
+    runs_root: &Path,
+    run: Option<&str>,
+) -> Result<(String, Vec<IssueDraft>)> {
+    let found = history::list_runs(runs_root)?
+        .into_iter()
+        .rev()
+        .filter(|(run_dir, _)| run.is_none_or(|id| run_dir.id == id))
+        .find_map(|(_, state)| state);
+    let Some(state) = found else {
+        match run {
+            Some(id) => bail!("no run `{id}` with a state under `{}`", runs_root.display()),
+            None => bail!("no run with a state under `{}`", runs_root.display()),
+        }
+    };
+    Ok((state.id.clone(), drafts_of(rustc_repo_path, &state)))
+}
+
+pub(super) fn drafts_of(rustc_repo_path: &Path, state: &RunState) -> Vec<IssueDraft> {
+    let rules = rules_of(state);
+    let directive = rules
+        .first()
+        .map_or("ignore-debug", |rule| rule.matcher.directive.as_str());
+    state
+        .records
+        .iter()
+        .filter(|(_, record)| {
+            matches!(
+                record.outcome,
+                RunOutcome::UnmodifiedOk | RunOutcome::NeedsManualReview
+            )
+        })
+        .map(|(rel_path, record)| {
+            let title = match record.outcome {
+                RunOutcome::UnmodifiedOk => {
+                    format!("`{rel_path}` still fails without `{directive}`")
+                }
+                _ => format!("Decide whether `{rel_path}` still needs `{directive}`"),
+            };
+
+            let mut body = format!(
+                "`{rel_path}` was left with its `{directive}` directive by run `{}`",
+                state.id
+            );
+            if let Some(commit) = &state.manifest.rustc_commit {
+                let _ = write!(body, " (at `{commit}`)");
+            }
+            let _ = writeln!(body, ": {}.", record.outcome.description().to_lowercase());
+            if let Some(hint) = &record.review_hint {
+                let mut chars = hint.chars();
+                let hint: String = chars
+                    .next()
+                    .map(|c| c.to_uppercase().chain(chars).collect())
+                    .unwrap_or_default();
+                let _ = writeln!(body, "\n{hint}.");
+            }
+            if record.outcome == RunOutcome::UnmodifiedOk {
+                let tried = rules
+                    .iter()
+                    .map(|rule| format!("`{}`", rule.name))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let _ = writeln!(
+                    body,
+                    "\nThe test failed with each of the rules tried ({tried}), so it most likely \
+                     depends on debug assertions being off."
+                );
+            }
+            if let Some(bisection) = state.bisections.get(rel_path) {
+                let _ = writeln!(body, "\nBisected over nightlies, the test {bisection}.");
+            }
+            if let Some(diff) = tried_diff(rustc_repo_path, rel_path, &rules) {
+                let _ = writeln!(body, "\n### Edit\n\n```diff\n{diff}```");
+            }
+            let excerpt = state
+                .logs
+                .as_deref()
+                .and_then(|logs| failure_excerpt(&test_logs::path_of(logs, rel_path)));
+            if let Some(excerpt) = excerpt {
+                let _ = writeln!(body, "\n### Failure\n\n```text\n{excerpt}\n```");
+            }
+            IssueDraft {
+                path: rel_path.clone(),
+                outcome: record.outcome,
+                title,
+                body,
+            }
+        })
+        .collect()
+}
+
+/// `src`, the test at `rel_path`, as edited by the first of `rules` changing it.
+pub(super) fn tried_edit(src: &str, rel_path: &str, rules: &[Rule]) -> Option<String> {
+    let syntax = Syntax::of(Path::new(rel_path));
+    rules
+        .iter()
+        .map(|rule| rule.apply(src, syntax))
+        .find(|edit| !edit.changes.is_empty())
+        .map(|edit| edit.src)
+}
+
+/// The unified diff of the first of `rules` changing the test at `rel_path`, as it is now.
+fn tried_diff(rustc_repo_path: &Path, rel_path: &str, rules: &[Rule]) -> Option<String> {
+    let src = std::fs::read_to_string(rustc_repo_path.join(rel_path)).ok()?;
+    let edited = tried_edit(&src, rel_path, rules)?;
+    let diff = similar::TextDiff::from_lines(&src, &edited);
+    Some(
+        diff.unified_diff()
+            .header(&format!("a/{rel_path}"), &format!("b/{rel_path}"))
+            .to_string(),
+    )
+}
+
+/// The end of the output of the last invocation in the log at `log`: what libtest captured for the
+/// failed test if it reported any, and otherwise the stderr of `x`, e.g. for a build failure.
+fn failure_excerpt(log: &Path) -> Option<String> {
+    let log = std::fs::read_to_string(log).ok()?;
+    let last = &log[log.rfind("==== ").unwrap_or(0)..];
+    let (stdout, stderr) = last.split_once("---- stderr ----\n").unwrap_or((last, ""));
+    let captured = stdout.lines().rev().find_map(|line| {
+        let event: Value = serde_json::from_str(line).ok()?;
+        if event["event"] != "failed" {
+            return None;
+        }
+        event["stdout"].as_str().map(str::to_string)
+    });
+    let output = captured.unwrap_or_else(|| stderr.to_string());
+    let lines: Vec<&str> = output.trim_end().lines().collect();
+    if lines.is_empty() {
+        return None;
+    }
+    Some(lines[lines.len().saturating_sub(EXCERPT_LINES)..].join("\n"))
+}
+
+/// The drafts as a Markdown document, one section per issue.
+pub fn format_issue_drafts(run: &str, drafts: &[IssueDraft]) -> String {
+    let mut out = format!("# Follow-up issues from run `{run}`\n");
+    for draft in drafts {
+        let _ = write!(out, "\n---\n\n## {}\n\n{}", draft.title, draft.body);
+    }
+    out
+}
+
+/// File each draft as an issue in `repo` (`<owner>/<repo>`) with `labels`, returning their URLs.
+pub fn file_issues(repo: &str, drafts: &[IssueDraft], labels: &[String]) -> Result<Vec<String>> {
+    let github = GitHub::new()?;
+    let mut urls = Vec::with_capacity(drafts.len());
+    for draft in drafts {
+        let issue = github.request(
+            "POST",
+            &format!("repos/{repo}/issues"),
+            Some(&json!({
+                "title": draft.title,
+                "body": draft.body,
+                "labels": labels,
+            })),
+        )?;
+        urls.push(issue["html_url"].as_str().unwrap_or_default().to_string());
+    }
+    Ok(urls)
+}
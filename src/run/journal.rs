@@ -0,0 +1,258 @@
+//! Write-ahead journal of the edits of a run, so that the tests a run was trying when it died
+//! (power loss, OOM kill, `kill -9`) can be put back, see [`Engine::roll_back`].
+//!
+//! Before a test is edited, its original contents and those of its snapshots are backed up under
+//! `runs/journal/`, and an entry (the test, the hashes of its original and edited contents, the
+//! backups and the lines changed) is appended to `runs/journal/journal.jsonl`, both synced to
+//! disk. Once the edit is kept or reverted, another entry closes it. The journal is removed when a
+//! run finishes, so a journal with open entries means the run which wrote it did not.
+//!
+//! [`Engine::roll_back`]: super::Engine::roll_back
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use miette::{Context, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use super::edit::LineChange;
+use super::manifest::sha256_hex;
+use super::snapshots::{self, Snapshots};
+use super::write_guard;
+
+const JOURNAL: &str = "journal.jsonl";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "entry", rename_all = "kebab-case")]
+enum Entry {
+    /// A test is about to be edited.
+    Edit {
+        id: u64,
+        path: PathBuf,
+        original_sha256: String,
+        edited_sha256: String,
+        /// Backup of the original contents of the test.
+        backup: PathBuf,
+        /// Backups of the snapshots of the test, by path.
+        snapshots: BTreeMap<PathBuf, PathBuf>,
+        changes: Vec<LineChange>,
+    },
+    /// The edit was kept or reverted.
+    Settled { id: u64 },
+}
+
+struct Journal {
+    dir: PathBuf,
+    file: File,
+    next_id: u64,
+}
+
+/// The journal of the current run, `None` outside of runs.
+static CURRENT: Mutex<Option<Journal>> = Mutex::new(None);
+
+/// Start the journal of a run in `runs_root`. [`interrupted`] must have come back empty.
+pub(super) fn configure(runs_root: &Path) -> Result<()> {
+    let dir = runs_root.join("journal");
+    std::fs::create_dir_all(&dir)
+        .into_diagnostic()
+        .wrap_err(format!("failed to create `{}`", dir.display()))?;
+    let file = File::create(dir.join(JOURNAL))
+        .into_diagnostic()
+        .wrap_err("failed to create the journal")?;
+    *CURRENT.lock().unwrap() = Some(Journal {
+        dir,
+        file,
+        next_id: 0,
+    });
+    Ok(())
+}
+
+/// Record that `path` is about to be changed from `original` to `edited`, with its `snapshots`
+/// as captured beforehand. Returns the id to [`settle`] the entry with, if journaling.
+pub(super) fn begin(
+    path: &Path,
+    original: &str,
+    edited: &str,
+    snapshots: &Snapshots,
+    changes: &[LineChange],
+) -> std::io::Result<Option<u64>> {
+    let mut current = CURRENT.lock().unwrap();
+    let Some(journal) = current.as_mut() else {
+        return Ok(None);
+    };
+    let id = journal.next_id;
+    journal.next_id += 1;
+
+    let backup = journal.dir.join(format!("{id}.orig"));
+    write_synced(&backup, original.as_bytes())?;
+    let mut snapshot_backups = BTreeMap::new();
+    for (n, (snapshot, contents)) in snapshots.files().enumerate() {
+        let snapshot_backup = journal.dir.join(format!("{id}.{n}.snapshot"));
+        write_synced(&snapshot_backup, contents)?;
+        snapshot_backups.insert(snapshot.to_path_buf(), snapshot_backup);
+    }
+    journal.append(&Entry::Edit {
+        id,
+        path: path.to_path_buf(),
+        original_sha256: sha256_hex(original.as_bytes()),
+        edited_sha256: sha256_hex(edited.as_bytes()),
+        backup,
+        snapshots: snapshot_backups,
+        changes: changes.to_vec(),
+    })?;
+    Ok(Some(id))
+}
+
+/// Close the entry `id` returned by [`begin`], once its edit was kept or reverted.
+pub(super) fn settle(id: Option<u64>) -> std::io::Result<()> {
+    let mut current = CURRENT.lock().unwrap();
+    let (Some(id), Some(journal)) = (id, current.as_mut()) else {
+        return Ok(());
+    };
+    journal.append(&Entry::Settled { id })?;
+    let _ = std::fs::remove_file(journal.dir.join(format!("{id}.orig")));
+    for n in 0.. {
+        if std::fs::remove_file(journal.dir.join(format!("{id}.{n}.snapshot"))).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Remove the journal of the run which just finished, unless edits were left open (e.g. because
+/// reverting one of them failed).
+pub(super) fn finish() {
+    let Some(journal) = CURRENT.lock().unwrap().take() else {
+        return;
+    };
+    drop(journal.file);
+    match open_entries(&journal.dir) {
+        Ok(open) if open.is_empty() => {
+            let _ = std::fs::remove_dir_all(&journal.dir);
+        }
+        Ok(open) => warn!(
+            "{} edit(s) were left open in `{}`, run again to roll them back",
+            open.len(),
+            journal.dir.display()
+        ),
+        Err(e) => warn!("failed to read the journal: {e:?}"),
+    }
+}
+
+impl Journal {
+    fn append(&mut self, entry: &Entry) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()
+    }
+}
+
+fn write_synced(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(contents)?;
+    file.sync_data()
+}
+
+/// Tests a previous run in `runs_root` was editing when it died, see [`roll_back`].
+pub(super) fn interrupted(runs_root: &Path) -> Result<Vec<PathBuf>> {
+    let open = open_entries(&runs_root.join("journal"))?;
+    Ok(open
+        .into_iter()
+        .filter_map(|entry| match entry {
+            Entry::Edit { path, .. } => Some(path),
+            Entry::Settled { .. } => None,
+        })
+        .collect())
+}
+
+/// The entries of the journal in `dir` which were never settled, in order.
+fn open_entries(dir: &Path) -> Result<Vec<Entry>> {
+    let path = dir.join(JOURNAL);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e)
+                .into_diagnostic()
+                .wrap_err(format!("failed to read `{}`", path.display()))
+        }
+    };
+    let mut edits = Vec::new();
+    let mut settled = BTreeSet::new();
+    for line in BufReader::new(file).lines() {
+        let line = line
+            .into_diagnostic()
+            .wrap_err(format!("failed to read `{}`", path.display()))?;
+        // The last line may have been cut short by whatever stopped the run.
+        match serde_json::from_str(&line) {
+            Ok(Entry::Settled { id }) => {
+                settled.insert(id);
+            }
+            Ok(edit) => edits.push(edit),
+            Err(_) => debug!("skipping a torn journal entry: {line}"),
+        }
+    }
+    edits.retain(|edit| !matches!(edit, Entry::Edit { id, .. } if settled.contains(id)));
+    Ok(edits)
+}
+
+/// Put back the tests (and their snapshots) of the open entries of the journal in `runs_root`,
+/// then remove it. Tests which changed since the run edited them are left alone. Returns the
+/// tests rolled back.
+pub(super) fn roll_back(runs_root: &Path) -> Result<Vec<PathBuf>> {
+    let dir = runs_root.join("journal");
+    let mut rolled_back = Vec::new();
+    for entry in open_entries(&dir)? {
+        let Entry::Edit {
+            path,
+            original_sha256,
+            edited_sha256,
+            backup,
+            snapshots: snapshot_backups,
+            ..
+        } = entry
+        else {
+            continue;
+        };
+        let current = match std::fs::read(&path) {
+            Ok(current) => sha256_hex(&current),
+            Err(e) => {
+                warn!("failed to read `{}`, leaving it alone: {e}", path.display());
+                continue;
+            }
+        };
+        if current != original_sha256 && current != edited_sha256 {
+            warn!(
+                "`{}` changed since the interrupted run edited it, leaving it alone",
+                path.display()
+            );
+            continue;
+        }
+        let restore = || -> std::io::Result<()> {
+            write_guard::restore(&path, std::fs::read(&backup)?)?;
+            for companion in snapshots::companions(&path) {
+                if !snapshot_backups.contains_key(&companion) {
+                    write_guard::remove_file(&companion)?;
+                }
+            }
+            for (snapshot, snapshot_backup) in &snapshot_backups {
+                write_guard::restore(snapshot, std::fs::read(snapshot_backup)?)?;
+            }
+            Ok(())
+        };
+        restore()
+            .into_diagnostic()
+            .wrap_err(format!("failed to roll back `{}`", path.display()))?;
+        info!("rolled back `{}`", path.display());
+        rolled_back.push(path);
+    }
+    std::fs::remove_dir_all(&dir)
+        .into_diagnostic()
+        .wrap_err(format!("failed to remove `{}`", dir.display()))?;
+    Ok(rolled_back)
+}
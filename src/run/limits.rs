@@ -0,0 +1,139 @@
+//! Resource limits for `x` and everything it spawns, so that an overnight run leaves the machine
+//! usable.
+
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+
+use tokio::process::Command;
+use tracing::*;
+
+use crate::config::Config;
+
+#[derive(Debug, Default)]
+struct Limits {
+    niceness: Option<i32>,
+    memory: Option<MemoryLimit>,
+    link_jobs: Option<u32>,
+    test_threads: Option<usize>,
+}
+
+#[derive(Debug)]
+enum MemoryLimit {
+    /// Run `x` in a transient systemd scope, i.e. a cgroup covering every process it spawns.
+    Scope { mib: u64 },
+    /// Limit the address space of each process.
+    Rlimit { bytes: u64 },
+}
+
+static LIMITS: Mutex<Option<Arc<Limits>>> = Mutex::new(None);
+
+/// Pick up the limits from `config`, checking how memory can be limited on this machine.
+pub(super) fn configure(config: &Config) {
+    // Containers get their own memory limit, see `super::container`.
+    let memory = config
+        .memory_limit_mib
+        .filter(|_| config.container_image.is_none());
+    let memory = memory.map(|mib| {
+        if systemd_scopes_available() {
+            info!("limiting `x` to {mib} MiB with a systemd scope");
+            MemoryLimit::Scope { mib }
+        } else {
+            info!("limiting the address space of each process spawned by `x` to {mib} MiB");
+            MemoryLimit::Rlimit {
+                bytes: mib.saturating_mul(1024 * 1024),
+            }
+        }
+    });
+    let limits = Limits {
+        niceness: config.niceness,
+        memory,
+        link_jobs: config.link_jobs,
+        test_threads: config.test_threads,
+    };
+    #[cfg(not(unix))]
+    if limits.niceness.is_some() || matches!(limits.memory, Some(MemoryLimit::Rlimit { .. })) {
+        warn!("`niceness` and `memory_limit_mib` are only supported on unix platforms");
+    }
+    *LIMITS.lock().unwrap() = Some(Arc::new(limits));
+}
+
+fn systemd_scopes_available() -> bool {
+    std::process::Command::new("systemd-run")
+        .args(["--user", "--scope", "--quiet", "true"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// `command` with the configured limits applied, wrapped in `systemd-run` if need be.
+pub(super) fn apply(command: &Command) -> Command {
+    let limits = LIMITS.lock().unwrap().clone().unwrap_or_default();
+    let original = command.as_std();
+
+    let mut limited = match limits.memory {
+        Some(MemoryLimit::Scope { mib }) => {
+            let mut wrapper = Command::new("systemd-run");
+            wrapper
+                .args(["--user", "--scope", "--quiet", "--collect"])
+                .arg(format!("--property=MemoryMax={mib}M"))
+                .arg("--")
+                .arg(original.get_program());
+            wrapper
+        }
+        _ => Command::new(original.get_program()),
+    };
+    limited.args(original.get_args());
+    if let Some(dir) = original.get_current_dir() {
+        limited.current_dir(dir);
+    }
+    for (key, value) in original.get_envs() {
+        match value {
+            Some(value) => limited.env(key, value),
+            None => limited.env_remove(key),
+        };
+    }
+
+    if let Some(link_jobs) = limits.link_jobs {
+        limited
+            .arg("--set")
+            .arg(format!("llvm.link-jobs={link_jobs}"));
+    }
+    if let Some(test_threads) = limits.test_threads {
+        limited.env("RUST_TEST_THREADS", test_threads.to_string());
+    }
+
+    #[cfg(unix)]
+    {
+        let niceness = limits.niceness;
+        let address_space = match limits.memory {
+            Some(MemoryLimit::Rlimit { bytes }) => Some(bytes),
+            _ => None,
+        };
+        if niceness.is_some() || address_space.is_some() {
+            // SAFETY: the closure only makes async-signal-safe system calls and doesn't allocate.
+            unsafe {
+                limited.pre_exec(move || {
+                    if let Some(niceness) = niceness {
+                        if libc::setpriority(libc::PRIO_PROCESS, 0, niceness) != 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    }
+                    if let Some(bytes) = address_space {
+                        let limit = libc::rlimit {
+                            rlim_cur: bytes as libc::rlim_t,
+                            rlim_max: bytes as libc::rlim_t,
+                        };
+                        if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+
+    limited
+}
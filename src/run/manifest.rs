@@ -0,0 +1,97 @@
+//! Everything needed to tell whether two runs performed the same experiment.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::*;
+
+use crate::config::Config;
+use crate::git;
+
+/// Bootstrap config files looked for at the root of the `rustc` repo, in order of precedence.
+const BOOTSTRAP_CONFIGS: [&str; 2] = ["bootstrap.toml", "config.toml"];
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Version of this tool.
+    pub tool_version: String,
+    /// The config the run was performed with, after defaults were applied.
+    pub config: serde_json::Value,
+    /// HEAD commit of the `rustc` repo when the run started, if known.
+    pub rustc_commit: Option<String>,
+    /// Repo-relative path of the bootstrap config, if any.
+    pub bootstrap_config: Option<String>,
+    /// SHA-256 of the bootstrap config.
+    pub bootstrap_config_sha256: Option<String>,
+    /// Number of candidate test files.
+    pub target_file_count: usize,
+    /// SHA-256 of the newline-separated, sorted, repo-relative candidate paths.
+    pub target_files_sha256: String,
+}
+
+impl Manifest {
+    /// `target_files` must be repo-relative and sorted.
+    pub fn collect<'a>(
+        config: &Config,
+        rustc_repo_path: &Path,
+        target_files: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        let (bootstrap_config, bootstrap_config_sha256) =
+            match bootstrap_config_in_use(config, rustc_repo_path) {
+                Some((name, path)) => match std::fs::read(&path) {
+                    Ok(contents) => (Some(name), Some(sha256_hex(&contents))),
+                    Err(e) => {
+                        warn!("failed to read bootstrap config `{}`: {e}", path.display());
+                        (Some(name.to_string()), None)
+                    }
+                },
+                None => (None, None),
+            };
+
+        let mut hasher = Sha256::new();
+        let mut target_file_count = 0;
+        for path in target_files {
+            hasher.update(path.as_bytes());
+            hasher.update(b"\n");
+            target_file_count += 1;
+        }
+
+        Manifest {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            config: serde_json::to_value(config).unwrap_or_default(),
+            rustc_commit: git::head_commit(rustc_repo_path),
+            bootstrap_config,
+            bootstrap_config_sha256,
+            target_file_count,
+            target_files_sha256: hex(&hasher.finalize()),
+        }
+    }
+}
+
+/// The bootstrap config `x` is run with: the `bootstrap_config` of `config` if set, or else the
+/// one found in the repo, along with how to refer to it.
+pub(super) fn bootstrap_config_in_use(
+    config: &Config,
+    rustc_repo_path: &Path,
+) -> Option<(String, PathBuf)> {
+    match &config.bootstrap_config {
+        Some(path) => Some((path.display().to_string(), rustc_repo_path.join(path))),
+        None => find_bootstrap_config(rustc_repo_path).map(|(name, path)| (name.to_string(), path)),
+    }
+}
+
+pub(super) fn find_bootstrap_config(rustc_repo_path: &Path) -> Option<(&'static str, PathBuf)> {
+    BOOTSTRAP_CONFIGS
+        .into_iter()
+        .map(|name| (name, rustc_repo_path.join(name)))
+        .find(|(_, path)| path.is_file())
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,127 @@
+//! The `metrics_file` of the config: one line of JSON per run, appended, for charting the
+//! throughput of the migration over time without parsing reports.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use miette::{Context, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+
+use super::state::RunState;
+use super::RunOutcome;
+use crate::exit_status::ExitStatus;
+
+/// Bumped on changes to [`RunMetrics`] other than new fields.
+const VERSION: u32 = 1;
+
+/// A line of the metrics file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunMetrics {
+    pub version: u32,
+    pub run: String,
+    pub started_at: DateTime<Local>,
+    pub finished_at: DateTime<Local>,
+    pub status: Option<ExitStatus>,
+    pub rustc_commit: Option<String>,
+    pub candidates: usize,
+    pub removed: usize,
+    pub replaced: usize,
+    pub unmodified: usize,
+    pub ignored: usize,
+    pub needs_manual_review: usize,
+    pub skipped: usize,
+    /// Candidates not processed because the run stopped early.
+    pub pending: usize,
+    /// Wall-clock duration of the run.
+    pub duration_secs: f64,
+    /// Time spent on the tests run (not reused from the cache), summed.
+    pub test_secs: f64,
+    /// Tests run rather than reused from the cache.
+    pub tests_run: usize,
+    pub cache_hits: usize,
+    /// Candidates looked up in the cache, zero without one.
+    pub cache_lookups: usize,
+    /// Tests run whose outcome differs from the last one measured for them by a previous run,
+    /// a proxy for flakiness (or for the tests having changed in between).
+    pub outcome_changes: usize,
+}
+
+/// Append the metrics of the run of `state` to the file at `path`. `previous` are the outcomes
+/// last measured by previous runs, see [`crate::history::last_outcomes`].
+pub(super) fn append(
+    path: &Path,
+    state: &RunState,
+    (cache_hits, cache_lookups): (usize, usize),
+    previous: &BTreeMap<String, (String, RunOutcome)>,
+) -> Result<()> {
+    let count = |outcome| {
+        state
+            .records
+            .values()
+            .filter(|r| r.outcome == outcome)
+            .count()
+    };
+    // Outcomes reused from the cache are recorded without a duration.
+    let run: Vec<_> = state
+        .records
+        .iter()
+        .filter(|(_, r)| !r.duration.is_zero())
+        .collect();
+    let finished_at = state.finished_at.unwrap_or_else(Local::now);
+    let metrics = RunMetrics {
+        version: VERSION,
+        run: state.id.clone(),
+        started_at: state.started_at,
+        finished_at,
+        status: state.status,
+        rustc_commit: state.manifest.rustc_commit.clone(),
+        candidates: state.manifest.target_file_count,
+        removed: count(RunOutcome::RemoveOk),
+        replaced: count(RunOutcome::ReplaceOk),
+        unmodified: count(RunOutcome::UnmodifiedOk),
+        ignored: count(RunOutcome::Ignored),
+        needs_manual_review: count(RunOutcome::NeedsManualReview),
+        skipped: state.skipped.len(),
+        pending: state.pending.len(),
+        duration_secs: (finished_at - state.started_at)
+            .to_std()
+            .unwrap_or_default()
+            .as_secs_f64(),
+        test_secs: run
+            .iter()
+            .map(|(_, r)| r.duration)
+            .sum::<Duration>()
+            .as_secs_f64(),
+        tests_run: run.len(),
+        cache_hits,
+        cache_lookups,
+        outcome_changes: run
+            .iter()
+            .filter(|(rel_path, r)| {
+                previous
+                    .get(*rel_path)
+                    .is_some_and(|(_, outcome)| *outcome != r.outcome)
+            })
+            .count(),
+    };
+
+    let append = || -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut line = serde_json::to_string(&metrics)?;
+        line.push('\n');
+        File::options()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(line.as_bytes())
+    };
+    append()
+        .into_diagnostic()
+        .wrap_err(format!("failed to append to `{}`", path.display()))
+}
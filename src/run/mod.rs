@@ -1,38 +1,358 @@
+mod annotations;
+mod backup;
+mod batch;
+mod bisect;
+mod bootstrap_config;
+mod build_lock;
+mod cache;
+mod checkpoint;
+mod compiletest;
+mod container;
+pub mod control;
+mod dedup;
+mod desktop_notification;
+mod diagnostics;
+pub mod directives;
+mod edit;
+mod estimate;
+pub mod events;
+mod explain;
+mod hooks;
+mod incremental;
+mod issue_drafts;
+mod journal;
+mod limits;
+mod manifest;
+mod metrics;
+mod patches;
+mod paths;
+mod platform;
+mod pr_draft;
+mod process;
+mod progress;
+mod remote;
+mod remote_cache;
+mod report;
+mod review;
+pub mod rules;
+mod runner;
+mod scan;
+mod snapshots;
+pub mod state;
+pub mod suites;
+mod summary;
+mod test_logs;
+mod test_results;
+#[cfg(test)]
+pub(crate) mod tests;
+mod tidy;
+mod timings;
+pub mod tokens;
+mod tracking_issue;
+mod undo;
+mod verify;
+mod webhook;
+mod workers;
+mod worktrees;
+mod write_guard;
+mod zulip;
+
 use std::collections::{BTreeMap, BTreeSet};
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
-use miette::{bail, Context, Diagnostic, IntoDiagnostic, Result, Severity};
+use miette::{bail, miette, Context, Diagnostic, IntoDiagnostic, Result, Severity};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tokio::process::Command;
 use tracing::*;
 
 use crate::config::Config;
+use crate::exit_status::ExitStatus;
+use crate::git;
+use crate::history::{self, RunDir};
+pub use bisect::{bisect, BisectOptions, Bisection};
+use directives::Syntax;
+pub use edit::{find_directives, has_directive, remove_directive, replace_directive};
+pub use edit::{Edit, LineChange};
+pub use estimate::{estimate, Estimate};
+pub use explain::explain;
+pub use issue_drafts::{file_issues, format_issue_drafts, issue_drafts, IssueDraft};
+pub use manifest::Manifest;
+pub use metrics::RunMetrics;
+pub use patches::Emit;
+pub use progress::suspend_progress_bar;
+pub use report::format_report;
+use rules::Rule;
+use runner::TestRunner;
+pub use scan::{discover_target_directories, Scan, SkipReason};
+pub use snapshots::SnapshotChange;
+use snapshots::Snapshots;
+use state::RunState;
+use test_results::TestStatus;
+pub use undo::undo_last_run;
+pub use verify::{verify, Verification, VerifyResult};
+pub use worktrees::WorktreeResult;
+
+/// Set by the Ctrl-C handler installed by [`run`], see also [`process::interrupt`].
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Set once tests are left out because of the `--deadline` budget.
+static DEADLINE_REACHED: AtomicBool = AtomicBool::new(false);
+/// Set while a run is in progress in this process, see [`Engine`].
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Marks a run as in progress until dropped, so that no other run starts meanwhile.
+struct ActiveRun;
 
-/// Run the reduction steps.
+impl ActiveRun {
+    fn acquire() -> Result<ActiveRun> {
+        if ACTIVE.swap(true, Ordering::SeqCst) {
+            bail!("another run is already in progress in this process");
+        }
+        Ok(ActiveRun)
+    }
+}
+
+impl Drop for ActiveRun {
+    fn drop(&mut self) {
+        ACTIVE.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Options controlling a run which are given on the command line rather than in the config.
+#[derive(Debug)]
+pub struct RunOptions {
+    /// Directory under which each run gets its own `<timestamp>-<short-id>` directory holding
+    /// the report, state and other artifacts.
+    pub runs_root: PathBuf,
+    /// Additional path to write the report to, on top of the copy in the run directory.
+    pub report_path: Option<PathBuf>,
+    /// Emit GitHub Actions workflow commands for the tests which still need attention.
+    pub github_annotations: bool,
+    /// File descriptor to write JSON-lines progress events to.
+    pub progress_fd: Option<i32>,
+    /// Draw a progress bar with the current tests and the ETA on stderr. Other output to stderr
+    /// should go through [`suspend_progress_bar`] meanwhile.
+    pub progress_bar: bool,
+    /// File descriptor to write the versioned [`events`] to as JSON lines, see `--events-fd`.
+    pub events_fd: Option<i32>,
+    /// Edit all candidates of a suite at once and run them in a single `x` invocation, see
+    /// `--batch`.
+    pub batch: bool,
+    /// Number of tests to process concurrently, see `--jobs`.
+    pub jobs: NonZeroUsize,
+    /// Jobserver budget for `x` invocations when not running under `make`, see [`tokens`].
+    pub job_budget: Option<NonZeroUsize>,
+    /// Where to cache outcomes across runs. `None` disables the cache.
+    pub cache_path: Option<PathBuf>,
+    /// File to append the metrics of the run to, see [`Config::metrics_file`].
+    pub metrics_path: Option<PathBuf>,
+    /// Skip tests untouched since a previous run left them with their directive, see
+    /// `--incremental`.
+    pub incremental: bool,
+    /// Echo the output of `x` to stderr as it is produced.
+    pub show_output: bool,
+    /// Write the full output of every `x` invocation to `x-logs/` in the run directory.
+    pub log_output: bool,
+    /// Directory to record every `x test` invocation to, see `--record`.
+    pub record: Option<PathBuf>,
+    /// Directory of a recording to answer `x test` invocations from instead of running `x`, see
+    /// `--replay`.
+    pub replay: Option<PathBuf>,
+    /// Run compiletest directly against the already built stage 1 toolchain instead of `x test`,
+    /// see `--direct-compiletest`.
+    pub direct_compiletest: bool,
+    /// When the wall-clock budget of the run runs out, see `--deadline`.
+    pub deadline: Option<Instant>,
+    /// Only run one test of each family of identical tests, see `--dedup`.
+    pub dedup: bool,
+    /// Branch of the `rustc` repo to work on, created from HEAD if needed, see `--branch` and
+    /// `--commit-branch`.
+    pub branch: Option<String>,
+    /// Commit each accepted change individually to [`RunOptions::branch`].
+    pub commit_changes: bool,
+    /// Run even if HEAD is detached.
+    pub allow_detached: bool,
+    /// Whether to leave the changes in the working tree or write them as patches.
+    pub emit: patches::Emit,
+    /// Show a desktop notification when the run completes.
+    pub notify: bool,
+    /// Also write the state of the run as `<report>.json` next to each report.
+    pub json_report: bool,
+    /// Fail on problems which are otherwise only warned about, see `--strict`.
+    pub strict: bool,
+    /// Print how long each phase of processing the tests took at the end, see `--timings`.
+    pub timings: bool,
+}
+
+/// Runs the reduction over the candidate tests of a `rustc` checkout, as the `run` command does.
 ///
-/// For each of the tests in the specified directories / suites:
-/// - Run the unmodified test as a sanity check
-/// - (CASE remove-directives) Remove `// ignore-debug`, try to run the test and see if it passes
-///   (assuming it is no longer ignored). If it passes, then we can keep the changes. Otherwise,
-///   restore the original test.
-/// - (CASE replace-directives) Try to specify the compile flags directive
-///   `// compile-flags: -Cdebug-assertions=no`, try to run the test and see it passes. If it
-///   passes, keep the changes, otherwise, revert.
-/// At the end of the run, generate a summary / report detailing, for each changed test, what
-/// specifically has been done (either remove directive entirely or replace directive).
-pub fn run(
-    config: &Config,
-    current_exe_path: &Path,
-    rustc_repo_path: &Path,
-    report_path: Option<&Path>,
-) -> Result<()> {
-    debug!(
-        ?config,
-        ?rustc_repo_path,
-        ?report_path,
-        "run command invoked"
-    );
+/// ```no_run
+/// # async fn example(
+/// #     config: rustc_less_ignore_debug::config::Config,
+/// #     options: rustc_less_ignore_debug::RunOptions,
+/// # ) -> miette::Result<()> {
+/// use rustc_less_ignore_debug::Engine;
+///
+/// let engine = Engine::new(config, "../rust".into(), options);
+/// let run_dir = engine.preflight()?;
+/// let status = engine.run(&run_dir).await?;
+/// println!("{status:?}, report in {}", run_dir.report_path().display());
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The state of a run in progress (Ctrl-C and the deadline, skipped tests, event and output
+/// listeners, the journal of edits, ...) is process-wide, so only one run may be in progress per
+/// process: [`Engine::run`] fails while another one is, whichever engine it belongs to. Engines
+/// may otherwise coexist and run one after the other.
+pub struct Engine {
+    config: Config,
+    rustc_repo_path: PathBuf,
+    options: RunOptions,
+    listeners: Vec<events::Listener>,
+    output_listeners: Vec<control::OutputListener>,
+    /// The listener writing to `events_fd`, which is duplicated once for all the runs.
+    events_out: std::sync::Mutex<Option<events::Listener>>,
+}
+
+impl std::fmt::Debug for Engine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Engine")
+            .field("config", &self.config)
+            .field("rustc_repo_path", &self.rustc_repo_path)
+            .field("options", &self.options)
+            .field("listeners", &self.listeners.len())
+            .field("output_listeners", &self.output_listeners.len())
+            .finish()
+    }
+}
+
+impl Engine {
+    pub fn new(config: Config, rustc_repo_path: PathBuf, options: RunOptions) -> Self {
+        Engine {
+            config,
+            rustc_repo_path,
+            options,
+            listeners: Vec::new(),
+            output_listeners: Vec::new(),
+            events_out: Default::default(),
+        }
+    }
+
+    /// Call `listener` with every [`events::Event`] of the runs of this engine. Events may come
+    /// from several threads, so forwarding them to a channel is the easiest way to consume them:
+    ///
+    /// ```no_run
+    /// # fn example(engine: &mut rustc_less_ignore_debug::Engine) {
+    /// let (tx, rx) = std::sync::mpsc::channel();
+    /// engine.on_event(move |event| drop(tx.send(event.clone())));
+    /// # }
+    /// ```
+    pub fn on_event(&mut self, listener: impl Fn(&events::Event) + Send + Sync + 'static) {
+        self.listeners.push(std::sync::Arc::new(listener));
+    }
+
+    /// Call `listener` with every line of output of `x` and the test it was run for, as with
+    /// [`Engine::on_event`]. Not called for the tests run by `--batch`, which share their `x`
+    /// invocations.
+    pub fn on_output(&mut self, listener: impl Fn(&str, &str) + Send + Sync + 'static) {
+        self.output_listeners.push(std::sync::Arc::new(listener));
+    }
+
+    /// Steer the runs of this engine from another thread or task, e.g. to skip a test. As there
+    /// is only one run in progress per process (see [`Engine`]), the controls act on it whichever
+    /// engine they were obtained from.
+    pub fn controls(&self) -> control::Controls {
+        control::Controls::default()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn options(&self) -> &RunOptions {
+        &self.options
+    }
+
+    /// Whether the `rustc` repo has no bootstrap config, which [`Engine::setup`] can create. Always
+    /// `false` with `ssh_host`, where `x` runs in a checkout with a config of its own.
+    pub fn needs_setup(&self) -> bool {
+        self.config.ssh_host.is_none()
+            && self.config.bootstrap_config.is_none()
+            && bootstrap_config::is_missing(&self.rustc_repo_path)
+    }
+
+    /// Tests a previous run left edited because it died before putting them back (e.g. on power
+    /// loss or an OOM kill), according to its journal. [`Engine::preflight`] fails until they are
+    /// rolled back with [`Engine::roll_back`].
+    pub fn interrupted_edits(&self) -> Result<Vec<PathBuf>> {
+        journal::interrupted(&self.options.runs_root)
+    }
+
+    /// Put back the tests listed by [`Engine::interrupted_edits`] and their snapshots, except for
+    /// those changed since. Returns the tests rolled back.
+    pub fn roll_back(&self) -> Result<Vec<PathBuf>> {
+        journal::roll_back(&self.options.runs_root)
+    }
 
+    /// Create a bootstrap config suitable for the runs with `x setup`, using a profile which
+    /// builds the compiler with debug assertions. `x setup` may prompt on the terminal.
+    pub fn setup(&self) -> Result<()> {
+        bootstrap_config::setup(&self.rustc_repo_path)
+    }
+
+    /// Checks performed before any test is touched, so that problems with the environment are
+    /// reported early rather than hours into a run. Returns the directory created for this run.
+    pub fn preflight(&self) -> Result<RunDir> {
+        preflight(&self.config, &self.rustc_repo_path, &self.options)
+    }
+
+    /// Find the candidate tests under the configured target directories without touching them.
+    pub fn scan(&self) -> Scan {
+        scan::scan(&self.config, &self.rustc_repo_path)
+    }
+
+    /// Run the reduction steps. [`Engine::preflight`] must have succeeded first.
+    ///
+    /// For each of the tests in the specified directories / suites:
+    /// - Run the unmodified test as a sanity check
+    /// - (CASE remove-directives) Remove `// ignore-debug`, try to run the test and see if it
+    ///   passes (assuming it is no longer ignored). If it passes, then we can keep the changes.
+    ///   Otherwise, restore the original test.
+    /// - (CASE replace-directives) Try to specify the compile flags directive
+    ///   `// compile-flags: -Cdebug-assertions=no`, try to run the test and see it passes. If it
+    ///   passes, keep the changes, otherwise, revert.
+    ///
+    /// At the end of the run, generate a summary / report detailing, for each changed test, what
+    /// specifically has been done (either remove directive entirely or replace directive).
+    ///
+    /// If the run is interrupted with Ctrl-C, the `x` invocations in flight are stopped, their
+    /// tests restored and a partial report is written for the tests completed so far.
+    ///
+    /// Fails without touching anything if another run is in progress in this process, see
+    /// [`Engine`].
+    pub async fn run(&self, run_dir: &RunDir) -> Result<ExitStatus> {
+        let _active = ActiveRun::acquire()?;
+        let mut listeners = self.listeners.clone();
+        if let Some(fd) = self.options.events_fd {
+            let mut events_out = self.events_out.lock().unwrap();
+            let listener = match &*events_out {
+                Some(listener) => listener.clone(),
+                None => events_out.insert(events::write_to_fd(fd)?).clone(),
+            };
+            listeners.push(listener);
+        }
+        events::configure(listeners);
+        control::configure(self.output_listeners.clone());
+        run(&self.config, &self.rustc_repo_path, &self.options, run_dir).await
+    }
+}
+
+/// See [`Engine::preflight`].
+fn preflight(config: &Config, rustc_repo_path: &Path, options: &RunOptions) -> Result<RunDir> {
     if !rustc_repo_path.exists() {
         bail!(
             "`{}` does not exist, please check your path to rustc repo",
@@ -40,6 +360,26 @@ pub fn run(
         );
     }
 
+    check_layout(rustc_repo_path)?;
+    bootstrap_config::check(config, rustc_repo_path)?;
+
+    if let Some(fd) = options
+        .events_fd
+        .filter(|&fd| options.progress_fd == Some(fd))
+    {
+        bail!("`--events-fd` and `--progress-fd` cannot both write to fd {fd}");
+    }
+
+    let interrupted = journal::interrupted(&options.runs_root)?;
+    if !interrupted.is_empty() {
+        bail!(
+            help = "roll them back with `--roll-back`",
+            "a previous run died while {} test(s) were edited, e.g. `{}`",
+            interrupted.len(),
+            interrupted[0].display()
+        );
+    }
+
     if config.target_directories.is_empty() {
         warn!("no target directories specified in config");
         warn!("maybe you forgot to edit the config?");
@@ -49,20 +389,72 @@ pub fn run(
         );
     }
 
-    // Let's check if bootstrap `x` is available and executable.
-    {
-        match Command::new("x").output() {
-            Ok(_) => {
+    for (name, worktree) in worktrees::resolve(config, rustc_repo_path) {
+        if !worktree.exists() {
+            bail!("worktree `{name}` does not exist");
+        }
+        check_layout(&worktree)?;
+    }
+    if options.batch && !config.worktrees.is_empty() {
+        bail!("`worktrees` are not supported with `--batch`");
+    }
+    if options.batch && config.tidy {
+        bail!("`tidy` is not supported with `--batch`");
+    }
+    if let Some(replay) = &options.replay {
+        if !replay.is_dir() {
+            bail!("recording `{}` does not exist", replay.display());
+        }
+        if config.tidy {
+            bail!("`tidy` is not supported with `--replay`");
+        }
+    }
+
+    if options.batch && options.jobs.get() > 1 {
+        warn!("`--jobs` has no effect in batch mode");
+    }
+
+    if let Some(report_path) = &options.report_path {
+        info!("report will also be written to `{}`", report_path.display());
+    }
+
+    if options.branch.is_some() && git::head_commit(rustc_repo_path).is_none() {
+        bail!(
+            "`--branch` and `--commit-branch` need `{}` to be a git checkout",
+            rustc_repo_path.display()
+        );
+    }
+    if !options.allow_detached && git::is_detached(rustc_repo_path) == Some(true) {
+        bail!(
+            "HEAD is detached in `{}`, check out a branch or pass `--allow-detached`",
+            rustc_repo_path.display()
+        );
+    }
+
+    remote::preflight(config, rustc_repo_path)?;
+    container::preflight(config)?;
+
+    // Let's check if bootstrap `x` is available and executable, unless it runs elsewhere.
+    if config.ssh_host.is_none() && config.container_image.is_none() {
+        match platform::launcher() {
+            Some(platform::Launcher::X) => {
                 info!("detected bootstrap script `x`");
             }
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Some(launcher) => {
+                let (program, args) = launcher.program();
+                info!(
+                    "`x` is not on the `PATH`, starting bootstrap with `{program} {}`",
+                    args.join(" ")
+                );
+            }
+            None => {
                 error!(
                     "could not detect bootstrap `x`, did you provide a correct rustc repo path?"
                 );
+                if options.strict {
+                    bail!("bootstrap `x` is not available");
+                }
             }
-            Err(e) => Err(e)
-                .into_diagnostic()
-                .wrap_err("error while trying to detect bootstrap script `x`")?,
         }
     }
 
@@ -74,59 +466,768 @@ pub fn run(
         }
     }
 
-    let mut target_files = BTreeSet::new();
+    // Create the run directory last so that failed preflights don't litter the history.
+    let run_dir = RunDir::create(&options.runs_root)?;
+    info!(
+        "run artifacts will be written to `{}`",
+        run_dir.path.display()
+    );
 
-    trace!("iter through target directories");
-    for p in &config.target_directories {
-        let dir = rustc_repo_path.join(p);
-        trace!(?dir);
-
-        let iter = walkdir::WalkDir::new(dir)
-            .sort_by_file_name()
-            .into_iter()
-            .filter_map(Result::ok)
-            .filter(|e| {
-                !e.file_type().is_dir()
-                    && e.path()
-                        .extension()
-                        .map(|s| s == "rs" || s == "fixed")
-                        .unwrap_or(false)
+    Ok(run_dir)
+}
+
+/// Make sure `rustc_repo_path` looks like a checkout of rust-lang/rust, rather than failing with
+/// confusing `x` errors later on.
+fn check_layout(rustc_repo_path: &Path) -> Result<()> {
+    let missing: Vec<&str> = ["x.py", "src/bootstrap", "tests"]
+        .into_iter()
+        .filter(|p| !rustc_repo_path.join(p).exists())
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let missing = missing
+        .iter()
+        .map(|p| format!("`{p}`"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Err(miette!(
+        help = "pass the root of a rust-lang/rust checkout",
+        "`{}` does not look like a `rustc` repo, it has no {missing}",
+        rustc_repo_path.display()
+    ))
+}
+
+/// See [`Engine::run`].
+async fn run(
+    config: &Config,
+    rustc_repo_path: &Path,
+    options: &RunOptions,
+    run_dir: &RunDir,
+) -> Result<ExitStatus> {
+    let started_at = chrono::Local::now();
+    let mut result = run_tests(config, rustc_repo_path, options, run_dir).await;
+    let mut delivered = true;
+    if options.notify {
+        if let Err(e) = desktop_notification::notify(run_dir, &result) {
+            warn!("failed to show a desktop notification: {e:?}");
+        }
+    }
+    if let Some(url) = &config.webhook_url {
+        match webhook::notify(url, run_dir, started_at, &result) {
+            Ok(()) => info!("notified `{url}`"),
+            Err(e) => {
+                warn!("failed to notify `{url}`: {e:?}");
+                delivered = false;
+            }
+        }
+    }
+    if let Err(e) = zulip::post_report(config, run_dir, &result) {
+        warn!("failed to post the report to Zulip: {e:?}");
+        delivered = false;
+    }
+    if options.strict && !delivered && result.is_ok() {
+        result = Err(miette!("failed to post the results of the run"));
+    }
+    result
+}
+
+/// [`run`], short of the notifications.
+async fn run_tests(
+    config: &Config,
+    rustc_repo_path: &Path,
+    options: &RunOptions,
+    run_dir: &RunDir,
+) -> Result<ExitStatus> {
+    debug!(?config, ?rustc_repo_path, ?options, "run command invoked");
+    let started_at = chrono::Local::now();
+
+    // State left over from a previous run of the same process, see `Engine::run`.
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    DEADLINE_REACHED.store(false, Ordering::SeqCst);
+    // Dropped, and so stopped, when the run ends.
+    let mut watchers = tokio::task::JoinSet::new();
+    watchers.spawn(async {
+        match tokio::signal::ctrl_c().await {
+            Ok(()) => {
+                warn!("interrupted, stopping after restoring the tests in flight");
+                INTERRUPTED.store(true, Ordering::SeqCst);
+                process::interrupt();
+            }
+            Err(e) => warn!("failed to listen for Ctrl-C: {e}"),
+        }
+    });
+    if let Some(deadline) = options.deadline {
+        watchers.spawn(async move {
+            tokio::time::sleep_until(deadline.into()).await;
+            warn!("deadline reached, stopping after restoring the tests in flight");
+            DEADLINE_REACHED.store(true, Ordering::SeqCst);
+            INTERRUPTED.store(true, Ordering::SeqCst);
+            process::interrupt();
+        });
+    }
+
+    let log_dir = options.log_output.then(|| run_dir.path.join("x-logs"));
+    if let Some(log_dir) = &log_dir {
+        std::fs::create_dir_all(log_dir)
+            .into_diagnostic()
+            .wrap_err(format!("failed to create `{}`", log_dir.display()))?;
+    }
+    test_logs::configure(run_dir.logs_path());
+    timings::reset();
+    process::configure(process::OutputOptions {
+        show_output: options.show_output,
+        log_dir,
+        timeout: config.timeout_secs.map(Duration::from_secs),
+    });
+
+    if let Some(branch) = &options.branch {
+        git::switch_branch(rustc_repo_path, branch)?;
+        info!("working on branch `{branch}`");
+    }
+
+    tokens::configure(options.job_budget)?;
+    limits::configure(config);
+    remote::configure(config);
+    container::configure(config);
+    write_guard::configure(config, rustc_repo_path)?;
+    journal::configure(&options.runs_root)?;
+
+    let backend: Box<dyn TestRunner> = if options.direct_compiletest {
+        compiletest::check(config, rustc_repo_path)?;
+        info!("running compiletest directly against the stage 1 toolchain");
+        Box::new(runner::Compiletest)
+    } else {
+        Box::new(runner::Bootstrap)
+    };
+    let runner: Box<dyn TestRunner> = match (&options.record, &options.replay) {
+        (_, Some(replay)) => {
+            info!("replaying `x` from `{}`", replay.display());
+            Box::new(runner::Replayer {
+                dir: replay.clone(),
+            })
+        }
+        (Some(record), None) => {
+            std::fs::create_dir_all(record)
+                .into_diagnostic()
+                .wrap_err(format!("failed to create `{}`", record.display()))?;
+            info!("recording `x` to `{}`", record.display());
+            Box::new(runner::Recorder {
+                dir: record.clone(),
+                inner: backend,
             })
-            .map(|e| e.into_path());
-        target_files.extend(iter);
+        }
+        (None, None) => backend,
+    };
+
+    let mut progress = progress::Progress::new(options.progress_fd, options.progress_bar)?;
+    events::emit(events::Event::ScanStarted {
+        target_directories: config.target_directories.iter().cloned().collect(),
+    });
+
+    let scan::Scan {
+        candidates: mut target_files,
+        mut skipped,
+        contradictions,
+    } = {
+        let _timer = timings::start(timings::Phase::Scan);
+        scan::scan(config, rustc_repo_path)
+    };
+    if !contradictions.is_empty() {
+        warn!(
+            "{} test(s) have headers contradicting themselves about debug assertions, see the \
+             report",
+            contradictions.len()
+        );
+    }
+    if options.incremental {
+        let unchanged = incremental::unchanged_since_verified(
+            &options.runs_root,
+            rustc_repo_path,
+            &target_files,
+        )?;
+        info!("{} test(s) unchanged since last verified", unchanged.len());
+        target_files.retain(|f| !unchanged.contains_key(&paths::repo_relative(rustc_repo_path, f)));
+        skipped.extend(
+            unchanged
+                .into_iter()
+                .map(|(rel_path, run)| (rel_path, scan::SkipReason::Unchanged(run))),
+        );
+    }
+    let migrated =
+        incremental::already_migrated(&options.runs_root, rustc_repo_path, &target_files)?;
+    if !migrated.is_empty() {
+        info!(
+            "{} test(s) already migrated by a previous run",
+            migrated.len()
+        );
+        target_files.retain(|f| !migrated.contains_key(&paths::repo_relative(rustc_repo_path, f)));
+        skipped.extend(
+            migrated
+                .into_iter()
+                .map(|(rel_path, run)| (rel_path, scan::SkipReason::AlreadyMigrated(run))),
+        );
     }
+    info!("skipped {} files", skipped.len());
 
     info!(
         "there are {} target test files to be processed",
         target_files.len()
     );
+    progress.scanned(target_files.len() + skipped.len(), target_files.len());
+
+    let rel_target_files: BTreeSet<String> = target_files
+        .iter()
+        .map(|f| paths::repo_relative(rustc_repo_path, f))
+        .collect();
+    let manifest = Manifest::collect(
+        config,
+        rustc_repo_path,
+        rel_target_files.iter().map(String::as_str),
+    );
+    debug!(?manifest);
+
+    // Keyed by repo-relative path, see `paths::repo_relative`.
+    let mut records: BTreeMap<String, TestRecord> = BTreeMap::new();
+
+    let backup = if target_files.is_empty() {
+        None
+    } else {
+        Some(backup::archive(
+            rustc_repo_path,
+            &run_dir.path,
+            &target_files,
+        )?)
+    };
+
+    // Before the checkpoint makes this run one of the previous ones.
+    let previous = history::last_outcomes(&options.runs_root)?;
+    let mut checkpoint = checkpoint::Checkpoint::new(
+        config,
+        RunState {
+            id: run_dir.id.clone(),
+            started_at,
+            finished_at: None,
+            rustc_repo_path: rustc_repo_path.to_path_buf(),
+            manifest: manifest.clone(),
+            status: None,
+            records: BTreeMap::new(),
+            skipped: skipped.clone(),
+            pending: rel_target_files.clone(),
+            migrated: BTreeMap::new(),
+            contradictions: contradictions.clone(),
+            backup: backup.clone(),
+            logs: Some(run_dir.logs_path()),
+            regressions: BTreeMap::new(),
+            bisections: BTreeMap::new(),
+        },
+        run_dir.state_path(),
+        run_dir.report_path(),
+    );
+
+    let originals = match options.emit {
+        patches::Emit::InPlace => None,
+        _ => Some(patches::Originals::capture(rustc_repo_path, &target_files)?),
+    };
+
+    let mut cache = match &options.cache_path {
+        Some(cache_path) => {
+            let remote = config
+                .remote_cache
+                .as_deref()
+                .map(remote_cache::RemoteCache::new);
+            cache::Cache::load(cache_path, config, rustc_repo_path, remote)?
+        }
+        None => None,
+    };
+    let rewrite_rules = rules::resolve(config);
+    let mut cache_keys = BTreeMap::new();
+    // Tests whose unmodified version is known to pass, and those found to pass in this run.
+    let mut verified_baselines = BTreeSet::new();
+    let mut passed_baselines = BTreeSet::new();
+    let (mut cache_hits, mut cache_lookups) = (0, 0);
+    let target_files = match &mut cache {
+        Some(cache) => {
+            let mut uncached = BTreeSet::new();
+            for target_file in target_files {
+                let baseline_key = cache::Cache::baseline_key(&target_file)?;
+                let key = cache::Cache::key(config, &baseline_key);
+                match cache.reuse(rustc_repo_path, &target_file, &key, &rewrite_rules)? {
+                    Some(record) => {
+                        let rel_path = paths::repo_relative(rustc_repo_path, &target_file);
+                        debug!("`{rel_path}`: {:?} (cached)", record.outcome);
+                        progress.test_finished(&rel_path, record.outcome, record.duration);
+                        events::emit(events::Event::outcome_decided(
+                            &rel_path,
+                            &record,
+                            events::OutcomeSource::Cache,
+                        ));
+                        checkpoint.record(&rel_path, &record);
+                        records.insert(rel_path, record);
+                    }
+                    None => {
+                        if cache.baseline_passed(&baseline_key) {
+                            verified_baselines.insert(target_file.clone());
+                        }
+                        cache_keys.insert(target_file.clone(), (baseline_key, key));
+                        uncached.insert(target_file);
+                    }
+                }
+            }
+            info!("reused {} outcome(s) from the cache", records.len());
+            cache_hits = records.len();
+            cache_lookups = records.len() + uncached.len();
+            uncached
+        }
+        None => target_files,
+    };
+
+    let (target_files, copies) = if options.dedup {
+        let (representatives, copies) = dedup::find_families(rustc_repo_path, target_files)?;
+        info!(
+            "{} test(s) are identical to another one and will not be run",
+            copies.values().map(Vec::len).sum::<usize>()
+        );
+        (representatives, copies)
+    } else {
+        (target_files, dedup::Copies::new())
+    };
+
+    if config.prebuild && options.replay.is_none() && !target_files.is_empty() {
+        prebuild(config, rustc_repo_path).await?;
+        for (_, worktree) in worktrees::resolve(config, rustc_repo_path) {
+            prebuild(config, &worktree).await?;
+        }
+    }
 
-    let mut report: BTreeMap<PathBuf, RunOutcome> = BTreeMap::new();
+    if options.batch {
+        trace!("processing files in batches");
+        for target_file in &target_files {
+            events::emit(events::Event::TestStarted {
+                path: paths::repo_relative(rustc_repo_path, target_file),
+            });
+        }
+        for (target_file, (decision, duration)) in
+            batch::run_batch(config, rustc_repo_path, &*runner, &target_files).await?
+        {
+            hooks::post_test(config, rustc_repo_path, &target_file, &decision, duration).await;
+            let rel_path = paths::repo_relative(rustc_repo_path, &target_file);
+            progress.test_finished(&rel_path, decision.outcome, duration);
+            info!(
+                "`{rel_path}`: {:?} ({})",
+                decision.outcome,
+                progress.position()
+            );
+            let record = decision.into_record(duration);
+            events::emit(events::Event::outcome_decided(
+                &rel_path,
+                &record,
+                events::OutcomeSource::Run,
+            ));
+            checkpoint.record(&rel_path, &record);
+            records.insert(rel_path, record);
+        }
+    } else {
+        trace!("processing files with {} job(s)", options.jobs);
+        let estimates = history::last_durations(&options.runs_root)?;
+        progress.plan(
+            target_files
+                .iter()
+                .map(|f| paths::repo_relative(rustc_repo_path, f)),
+            &estimates,
+            options.jobs.get(),
+        );
+        if let Some(eta) = progress.eta() {
+            info!(
+                "estimated to take {} going by previous runs",
+                summary::format_duration(eta)
+            );
+        }
+        workers::run_parallel(
+            config,
+            rustc_repo_path,
+            workers::Work {
+                targets: &target_files,
+                verified_baselines: &verified_baselines,
+                estimates: &estimates,
+                deadline: options.deadline,
+                runner: &*runner,
+            },
+            options.jobs,
+            &run_dir.path.join("workers"),
+            |event| match event {
+                workers::WorkerEvent::Started(target_file, queued) => {
+                    let rel_path = paths::repo_relative(rustc_repo_path, &target_file);
+                    progress.queued(queued);
+                    progress.test_started(&rel_path);
+                    events::emit(events::Event::TestStarted { path: rel_path });
+                }
+                workers::WorkerEvent::Finished(target_file, decision, duration) => {
+                    let rel_path = paths::repo_relative(rustc_repo_path, &target_file);
+                    if decision.baseline_passed {
+                        passed_baselines.insert(target_file);
+                    }
+                    progress.test_finished(&rel_path, decision.outcome, duration);
+                    info_span!(
+                        "test",
+                        path = %rel_path,
+                        strategy = decision.rule.as_deref().unwrap_or("none"),
+                        outcome = ?decision.outcome,
+                        duration_secs = duration.as_secs_f64(),
+                    )
+                    .in_scope(|| {
+                        info!(
+                            "`{rel_path}`: {:?} ({})",
+                            decision.outcome,
+                            progress.position()
+                        )
+                    });
+                    let record = decision.into_record(duration);
+                    events::emit(events::Event::outcome_decided(
+                        &rel_path,
+                        &record,
+                        events::OutcomeSource::Run,
+                    ));
+                    checkpoint.record(&rel_path, &record);
+                    records.insert(rel_path, record);
+                }
+            },
+        )
+        .await?;
+    }
 
-    trace!("processing each file");
-    for target_file in &target_files {
-        trace!(?target_file);
-        let outcome = try_run(target_file)?;
-        report.insert(target_file.to_path_buf(), outcome);
+    for (representative, copies) in &copies {
+        let rep_path = paths::repo_relative(rustc_repo_path, representative);
+        let Some(record) = records.get(&rep_path).cloned() else {
+            continue;
+        };
+        for copy in copies {
+            let rel_path = paths::repo_relative(rustc_repo_path, copy);
+            let record = dedup::apply(
+                rustc_repo_path,
+                copy,
+                representative,
+                &record,
+                &rewrite_rules,
+            )?;
+            progress.test_finished(&rel_path, record.outcome, record.duration);
+            info!(
+                "`{rel_path}`: {:?} (same as `{rep_path}`, {})",
+                record.outcome,
+                progress.position()
+            );
+            events::emit(events::Event::outcome_decided(
+                &rel_path,
+                &record,
+                events::OutcomeSource::Dedup,
+            ));
+            checkpoint.record(&rel_path, &record);
+            records.insert(rel_path, record);
+        }
     }
 
-    let report = format_report(&report);
+    progress.finished();
+    journal::finish();
 
-    let report_path = current_exe_path.join("report.md");
-    std::fs::write(&report_path, report)
-        .into_diagnostic()
-        .wrap_err(format!(
-            "failed to write report to {}",
-            report_path.display()
-        ))?;
+    let pending: BTreeSet<String> = target_files
+        .iter()
+        .chain(copies.values().flatten())
+        .map(|f| paths::repo_relative(rustc_repo_path, f))
+        .filter(|rel_path| !records.contains_key(rel_path))
+        .collect();
+    if !pending.is_empty() {
+        warn!("{} test(s) were not processed", pending.len());
+    }
+
+    if let Some(cache) = &mut cache {
+        for (target_file, (baseline_key, key)) in cache_keys {
+            if passed_baselines.contains(&target_file) {
+                cache.record_baseline(baseline_key);
+            }
+            let rel_path = paths::repo_relative(rustc_repo_path, &target_file);
+            if let Some(record) = records.get(&rel_path) {
+                cache.insert(rustc_repo_path, key, record);
+            }
+        }
+        cache.save()?;
+    }
+
+    summary::print_summary(&records);
+    let regressions = regressions(&records, &previous);
+    for (rel_path, run) in &regressions {
+        warn!(
+            "regression: `{rel_path}` fails without its directive, which run {run} found it not \
+             to need"
+        );
+    }
+    if options.timings {
+        timings::print_timings(
+            (chrono::Local::now() - started_at)
+                .to_std()
+                .unwrap_or_default(),
+        );
+    }
+
+    if options.github_annotations {
+        annotations::emit(config, rustc_repo_path, &records);
+    }
+
+    let status = if DEADLINE_REACHED.load(Ordering::SeqCst) {
+        ExitStatus::DeadlineReached
+    } else if INTERRUPTED.load(Ordering::SeqCst) {
+        ExitStatus::Interrupted
+    } else if !regressions.is_empty() {
+        ExitStatus::Regression
+    } else if records.values().any(|r| r.outcome.keeps_directive()) {
+        ExitStatus::NeedsManualReview
+    } else {
+        ExitStatus::Success
+    };
+
+    let migrated = incremental::migrated_hashes(rustc_repo_path, &records);
+    if options.commit_changes {
+        commit_changes(config, rustc_repo_path, &records)?;
+    }
+    let patches = match &originals {
+        Some(originals) => Some(patches::emit(
+            config,
+            rustc_repo_path,
+            options.emit,
+            &records,
+            originals,
+            &run_dir.path,
+        )?),
+        None => None,
+    };
+
+    let state = RunState {
+        id: run_dir.id.clone(),
+        started_at,
+        finished_at: Some(chrono::Local::now()),
+        rustc_repo_path: rustc_repo_path.to_path_buf(),
+        manifest,
+        status: Some(status),
+        records,
+        skipped,
+        pending,
+        migrated,
+        contradictions,
+        backup,
+        logs: Some(run_dir.logs_path()),
+        regressions,
+        bisections: BTreeMap::new(),
+    };
+    if let Some(path) = &options.metrics_path {
+        metrics::append(path, &state, (cache_hits, cache_lookups), &previous)?;
+    }
+    state.save(&run_dir.state_path())?;
+
+    write_reports(config, &state, &run_dir.report_path(), options.json_report)?;
+    if let Some(report_path) = &options.report_path {
+        write_reports(config, &state, report_path, options.json_report)?;
+    }
+    if let Some(issue) = &config.tracking_issue {
+        match tracking_issue::post_summary(issue, &state) {
+            Ok(url) => info!("posted the summary to {url}"),
+            Err(e) if options.strict => {
+                return Err(e.wrap_err(format!("failed to post the summary to `{issue}`")))
+            }
+            Err(e) => warn!("failed to post the summary to `{issue}`: {e:?}"),
+        }
+    }
+    if let Some(branch) = &options.branch {
+        eprintln!("\nChanges are on branch `{branch}`.");
+    }
+    if let Some(patches) = &patches {
+        eprintln!("\nChanges were written to `{}`.", patches.display());
+    }
+
+    Ok(status)
+}
+
+/// Commit each removed or replaced directive, along with its snapshots, in path order.
+fn commit_changes(
+    config: &Config,
+    rustc_repo_path: &Path,
+    records: &BTreeMap<String, TestRecord>,
+) -> Result<()> {
+    let mut committed = 0;
+    for (rel_path, record) in records {
+        if record.outcome.keeps_directive() {
+            continue;
+        }
+        let mut paths = vec![rel_path.as_str()];
+        paths.extend(record.snapshot_changes.iter().map(|c| c.path.as_str()));
+        let message =
+            pr_draft::format_test_commit_message(&config.commit_message_template, rel_path, record);
+        if git::commit_paths(rustc_repo_path, &paths, &message)? {
+            committed += 1;
+        } else {
+            debug!("nothing to commit for `{rel_path}`");
+        }
+    }
+    info!("made {committed} commit(s)");
+    Ok(())
+}
+
+/// The tests of `records` which failed with every rule although their `previous` outcome (see
+/// [`history::last_outcomes`]) was to remove or replace the directive, with the id of the run
+/// which found so.
+fn regressions(
+    records: &BTreeMap<String, TestRecord>,
+    previous: &BTreeMap<String, (String, RunOutcome)>,
+) -> BTreeMap<String, String> {
+    records
+        .iter()
+        .filter(|(_, r)| r.outcome == RunOutcome::UnmodifiedOk)
+        .filter_map(|(rel_path, _)| match previous.get(rel_path) {
+            Some((run, RunOutcome::RemoveOk | RunOutcome::ReplaceOk)) => {
+                Some((rel_path.clone(), run.clone()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Write the report, and the commit message and PR description drafts next to it, along with the
+/// state of the run as JSON if `json` is set.
+fn write_reports(config: &Config, state: &RunState, report_path: &Path, json: bool) -> Result<()> {
+    let report = report::format_report(config, state);
+    write_output(report_path, &report)?;
+    info!("report written to `{}`", report_path.display());
+    events::emit(events::Event::ReportWritten {
+        path: report_path.to_path_buf(),
+    });
+
+    let commit_message_path = report_path.with_extension("commit.txt");
+    write_output(
+        &commit_message_path,
+        &pr_draft::format_commit_message(&state.records),
+    )?;
+    let pr_body_path = report_path.with_extension("pr.md");
+    write_output(&pr_body_path, &pr_draft::format_pr_body(&state.records))?;
+    for path in [commit_message_path.clone(), pr_body_path.clone()] {
+        events::emit(events::Event::ReportWritten { path });
+    }
+    if json {
+        let json_path = report_path.with_extension("json");
+        state.save(&json_path)?;
+        info!("JSON report written to `{}`", json_path.display());
+        events::emit(events::Event::ReportWritten { path: json_path });
+    }
+    info!(
+        "commit message and PR description drafts written to `{}` and `{}`",
+        commit_message_path.display(),
+        pr_body_path.display()
+    );
     Ok(())
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+/// Write `contents` to `path`, creating parent directories as needed.
+fn write_output(path: &Path, contents: &str) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)
+            .into_diagnostic()
+            .wrap_err(format!("failed to create directory `{}`", parent.display()))?;
+    }
+    checkpoint::write_atomically(path, contents.as_bytes())
+        .into_diagnostic()
+        .wrap_err(format!("failed to write to {}", path.display()))
+}
+
+/// What happened to a single test file during the run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TestRecord {
+    pub outcome: RunOutcome,
+    /// Wall-clock time spent on the test across all attempted strategies.
+    pub duration: Duration,
+    /// The directive lines that were removed or replaced in the kept version of the test. Empty
+    /// if the test was left unmodified.
+    pub changes: Vec<LineChange>,
+    /// Snapshot files rewritten by `--bless` in the kept version of the test.
+    #[serde(default)]
+    pub snapshot_changes: Vec<SnapshotChange>,
+    /// Why automation declined to decide, for [`RunOutcome::NeedsManualReview`].
+    #[serde(default)]
+    pub review_hint: Option<String>,
+    /// Repo-relative path of the identical test whose decision was applied to this one, see
+    /// `--dedup`.
+    #[serde(default)]
+    pub duplicate_of: Option<String>,
+    /// How the last edit tried fared in each of the `worktrees` of the config, if it passed in
+    /// the main checkout.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub worktrees: BTreeMap<String, WorktreeResult>,
+    /// Name of the [`rules::Rule`] the kept edit was made with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+}
+
+/// The outcome decided for a test, along with the changes kept on disk to get there.
+#[derive(Debug, Default)]
+struct Decision {
+    outcome: RunOutcome,
+    changes: Vec<LineChange>,
+    snapshot_changes: Vec<SnapshotChange>,
+    review_hint: Option<String>,
+    /// The unmodified test passed its sanity check, in this run or a previous one.
+    baseline_passed: bool,
+    worktrees: worktrees::WorktreeResults,
+    rule: Option<String>,
+}
 
-enum RunOutcome {
+impl Decision {
+    fn unmodified(outcome: RunOutcome) -> Self {
+        Decision {
+            outcome,
+            ..Default::default()
+        }
+    }
+
+    /// The decision for a test skipped with [`control::Controls::skip`], left unmodified.
+    fn skipped() -> Self {
+        Decision::needs_review("skipped during the run".to_string())
+    }
+
+    /// The decision for an edit made with `rule`, once settled.
+    fn kept_with(self, rule: &Rule) -> Self {
+        match self.outcome {
+            RunOutcome::RemoveOk => Decision {
+                outcome: rule.outcome(),
+                rule: Some(rule.name.clone()),
+                ..self
+            },
+            _ => self,
+        }
+    }
+
+    fn needs_review(hint: String) -> Self {
+        Decision {
+            outcome: RunOutcome::NeedsManualReview,
+            review_hint: Some(hint),
+            ..Default::default()
+        }
+    }
+
+    fn into_record(self, duration: Duration) -> TestRecord {
+        TestRecord {
+            outcome: self.outcome,
+            duration,
+            changes: self.changes,
+            snapshot_changes: self.snapshot_changes,
+            review_hint: self.review_hint,
+            duplicate_of: None,
+            worktrees: self.worktrees,
+            rule: self.rule,
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RunOutcome {
     /// The test needs to remain unmodified because removal or replacement of `// ignore-debug`
     /// both cause errors.
+    #[default]
     UnmodifiedOk,
     /// The test has its `// ignore-debug` directive removed and still passes.
     RemoveOk,
@@ -135,74 +1236,621 @@ enum RunOutcome {
     ReplaceOk,
     /// The test is ignored.
     Ignored,
+    /// Automation declined to decide, e.g. because of conflicting flags, huge snapshot diffs or
+    /// FileCheck sensitivity. The test is left unmodified.
+    NeedsManualReview,
 }
 
-fn try_run(target: &Path) -> miette::Result<RunOutcome> {
-    sanity_check(target)?;
+impl RunOutcome {
+    /// All outcomes, in the order they are presented in reports.
+    pub const ALL: [RunOutcome; 5] = [
+        RunOutcome::RemoveOk,
+        RunOutcome::ReplaceOk,
+        RunOutcome::NeedsManualReview,
+        RunOutcome::UnmodifiedOk,
+        RunOutcome::Ignored,
+    ];
 
-    match try_remove(target) {
-        Ok(RunOutcome::Ignored) => return Ok(RunOutcome::Ignored),
-        Ok(_) => {}
-        Err(e) if matches!(e, RunError::TestFailure) => {
-            return Ok(RunOutcome::UnmodifiedOk);
+    /// Whether the test still carries `// ignore-debug` after the run.
+    pub fn keeps_directive(self) -> bool {
+        !matches!(self, RunOutcome::RemoveOk | RunOutcome::ReplaceOk)
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            RunOutcome::RemoveOk => "Directive removed",
+            RunOutcome::ReplaceOk => "Directive replaced",
+            RunOutcome::UnmodifiedOk => "Unmodified",
+            RunOutcome::Ignored => "Ignored",
+            RunOutcome::NeedsManualReview => "Needs manual review",
         }
-        Err(e) => Err(e)?,
+    }
+}
+
+/// A test read and edited ahead of its turn, so that its `x` invocations can start as soon as a
+/// worker is free, see [`workers`].
+#[derive(Debug)]
+struct Prepared {
+    target: PathBuf,
+    /// When the test was read, to detect changes made since (e.g. by `--bless` on a test of the
+    /// same group).
+    stamp: Option<(SystemTime, u64)>,
+    original: String,
+    review_hint: Option<review::Flag>,
+    /// The edit of each rule which applies to the test, in the order they are tried.
+    attempts: Vec<(Rule, Edit)>,
+}
+
+impl Prepared {
+    fn new(target: PathBuf, rules: &[Rule]) -> Result<Self> {
+        let _timer = timings::start(timings::Phase::Edit);
+        let stamp = stamp(&target);
+        let original = std::fs::read_to_string(&target)
+            .into_diagnostic()
+            .wrap_err(format!("failed to read `{}`", target.display()))?;
+        Ok(Prepared {
+            review_hint: review::conflicting_flags(&original),
+            attempts: rules
+                .iter()
+                .map(|rule| (rule.clone(), rule.apply(&original, Syntax::of(&target))))
+                .filter(|(_, edit)| !edit.changes.is_empty())
+                .collect(),
+            target,
+            stamp,
+            original,
+        })
     }
 
-    match try_replace(target) {
-        Ok(RunOutcome::Ignored) => Ok(RunOutcome::Ignored),
-        Ok(_) => Ok(RunOutcome::ReplaceOk),
-        Err(e) if matches!(e, RunError::TestFailure) => Ok(RunOutcome::RemoveOk),
-        Err(e) => Err(e)?,
+    /// Prepare the test again if it changed since it was prepared.
+    fn refresh(self, rules: &[Rule]) -> Result<Self> {
+        if self.stamp.is_some() && stamp(&self.target) == self.stamp {
+            return Ok(self);
+        }
+        debug!("`{}` changed since it was prepared", self.target.display());
+        Prepared::new(self.target, rules)
     }
 }
 
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Process a single test. The sanity check is skipped if the unmodified test is already known to
+/// pass (`baseline_verified`).
+async fn try_run(
+    config: &Config,
+    rustc_repo_path: &Path,
+    runner: &dyn TestRunner,
+    prepared: Prepared,
+    baseline_verified: bool,
+) -> miette::Result<Decision> {
+    if let Err(e) = write_guard::check(&prepared.target) {
+        warn!("{e}");
+        return Ok(Decision::needs_review(e.to_string()));
+    }
+    let rules = rules::resolve(config);
+    let prepared = prepared.refresh(&rules)?;
+    let rel_path = paths::repo_relative(rustc_repo_path, &prepared.target);
+    let flag = prepared
+        .review_hint
+        .clone()
+        .or_else(|| review::filecheck_sensitive(config, &rel_path, &prepared.original));
+    if let Some(flag) = flag {
+        let syntax = Syntax::of(&prepared.target);
+        diagnostics::flagged(&rel_path, &prepared.original, syntax, &rules, &flag);
+        return Ok(Decision::needs_review(flag.hint));
+    }
+    if let Some(hint) = hooks::pre_test(config, rustc_repo_path, &prepared.target).await? {
+        return Ok(Decision::needs_review(hint));
+    }
+
+    if control::skip_requested(&rel_path) {
+        return Ok(Decision::skipped());
+    }
+    if baseline_verified {
+        debug!("skipping sanity check of `{}`", prepared.target.display());
+    } else {
+        let sanity = sanity_check(config, rustc_repo_path, runner, &prepared.target).await;
+        if control::skip_requested(&rel_path) {
+            return Ok(Decision::skipped());
+        }
+        // A broken or flaky test is left for a human rather than ending the run, unlike `x`
+        // failing to run at all.
+        let why = match sanity {
+            Ok(_) => None,
+            Err(RunError::TestFailure) => Some("the test failed".to_string()),
+            Err(e @ RunError::NoResult(_)) => Some(e.to_string()),
+            Err(e @ RunError::Other(_)) => Err(e)?,
+        };
+        if let Some(why) = why {
+            warn!("`{rel_path}` fails without any edit: {why}");
+            return Ok(Decision::needs_review(format!(
+                "`{rel_path}` fails without any edit: {why}"
+            )));
+        }
+    }
+
+    let mut worktrees = worktrees::WorktreeResults::new();
+    let mut decision = Decision::unmodified(RunOutcome::UnmodifiedOk);
+    for (rule, edit) in &prepared.attempts {
+        match try_rule(
+            config,
+            rustc_repo_path,
+            runner,
+            &prepared,
+            rule,
+            edit,
+            &mut worktrees,
+        )
+        .await
+        {
+            Ok(result) => {
+                decision = result;
+                break;
+            }
+            Err(RunError::TestFailure) if control::skip_requested(&rel_path) => {
+                return Ok(Decision::skipped());
+            }
+            Err(RunError::TestFailure) => continue,
+            Err(e) => Err(e)?,
+        }
+    }
+    Ok(Decision {
+        baseline_passed: true,
+        worktrees,
+        ..decision
+    })
+}
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("run error")]
 enum RunError {
-    /// We successfully invoked `./x test <path-to-test-file> --stage 1`, but the test failed.
+    /// We successfully invoked `./x test <path-to-test-file>`, but the test failed.
     #[error("test failed")]
     TestFailure,
+    /// `./x test <path-to-test-file>` succeeded without reporting a result for the test (the
+    /// repo-relative path).
+    #[error("`x test` reported no result for `{0}`")]
+    NoResult(String),
     /// Some other unexpected kind of error.
-    #[error("unexpected error")]
+    #[error("{}", error_chain(.0))]
     Other(miette::Error),
 }
 
-// `./x test <path-to-test-file> --stage 1 --bless`
-fn invoke_x(rustc_repo_path: &Path, target: &Path) -> miette::Result<Output> {
-    Command::new("x")
-        .current_dir(rustc_repo_path)
-        .arg("test")
-        .arg(target)
-        .arg("--stage")
-        .arg("1")
-        .arg("--bless")
-        .output()
-        .into_diagnostic()
-        .wrap_err(format!(
-            "error trying to invoke `x test {} --stage 1`",
-            target.display()
-        ))
+/// `error` followed by its causes, e.g. `failed to restore `a.rs`: <why>`.
+fn error_chain(error: &miette::Error) -> String {
+    error
+        .chain()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+/// `./x build --stage 1`, so that the `x test` invocations which follow can skip rebuild checks
+/// via [`Config::keep_stage_args`].
+async fn prebuild(config: &Config, rustc_repo_path: &Path) -> Result<()> {
+    info!("building the stage 1 compiler");
+    build_lock::wait_until_free(config, rustc_repo_path).await;
+    let start = Instant::now();
+    let output = process::run(
+        x_command(config, rustc_repo_path, "build")
+            .arg("--stage")
+            .arg("1"),
+    )
+    .await
+    .into_diagnostic()
+    .wrap_err("error trying to invoke `x build --stage 1`")?;
+    if !output.status.success() {
+        bail!("`x build --stage 1` failed:\n{}", output.stderr);
+    }
+    info!(
+        "built the stage 1 compiler in {}",
+        summary::format_duration(start.elapsed())
+    );
+    Ok(())
+}
+
+/// `x <subcommand>` in the `rustc` repo, pointed at [`Config::bootstrap_config`] and
+/// [`Config::build_dir`] if set.
+fn x_command(config: &Config, rustc_repo_path: &Path, subcommand: &str) -> Command {
+    // Preflight reports a missing `x`, which then fails to spawn.
+    let launcher = platform::launcher().unwrap_or(platform::Launcher::X);
+    let mut command = Command::from(platform::x_command(launcher, rustc_repo_path, subcommand));
+    // `x` runs in the repo, which relative paths are relative to.
+    if let Some(bootstrap_config) = &config.bootstrap_config {
+        command.arg("--config").arg(bootstrap_config);
+    }
+    if let Some(build_dir) = &config.build_dir {
+        command.arg("--build-dir").arg(build_dir);
+    }
+    command
+}
+
+/// The build directory used by `x`, see [`Config::build_dir`].
+fn build_dir(config: &Config, rustc_repo_path: &Path) -> PathBuf {
+    rustc_repo_path.join(config.build_dir.as_deref().unwrap_or(Path::new("build")))
+}
+
+/// `x test <paths>... --stage <stage>`, filtered with `filters`, blessing snapshots if `bless` is
+/// set.
+async fn x_test(
+    config: &Config,
+    rustc_repo_path: &Path,
+    paths: &[&Path],
+    filters: &[String],
+    stage: u32,
+    bless: bool,
+) -> miette::Result<process::Captured> {
+    let mut command = x_command(config, rustc_repo_path, "test");
+    command.args(paths).arg("--stage").arg(stage.to_string());
+    if bless {
+        command.arg("--bless");
+    }
+    command
+        .args(&config.keep_stage_args)
+        .arg("--test-args")
+        .arg(test_results::JSON_FORMAT_ARGS);
+    for filter in filters {
+        command.arg("--test-args").arg(filter);
+    }
+    workers::scope(&mut command);
+    loop {
+        build_lock::wait_until_free(config, rustc_repo_path).await;
+        let output = process::run(&mut command)
+            .await
+            .into_diagnostic()
+            .wrap_err(format!(
+                "error trying to invoke `x test {} --stage {stage}`",
+                paths
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .chain(filters.iter().map(|f| format!("--test-args {f}")))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ))?;
+        if output.timed_out && build_lock::waited_for_lock(&output.stderr) {
+            warn!("`x` timed out waiting for the build directory lock, trying again");
+            continue;
+        }
+        return Ok(output);
+    }
+}
+
+/// Invoke `x` on the test in its current state (`what`, for its log) and classify the result.
+async fn check(
+    config: &Config,
+    rustc_repo_path: &Path,
+    runner: &dyn TestRunner,
+    target: &Path,
+    what: &str,
+) -> Result<RunOutcome, RunError> {
+    check_captured(config, rustc_repo_path, runner, target, what)
+        .await
+        .0
+}
+
+/// [`check`], along with the output of `x`, or `None` if it could not be invoked.
+async fn check_captured(
+    config: &Config,
+    rustc_repo_path: &Path,
+    runner: &dyn TestRunner,
+    target: &Path,
+    what: &str,
+) -> (Result<RunOutcome, RunError>, Option<process::Captured>) {
+    let output = match runner.run(config, rustc_repo_path, &[target]).await {
+        Ok(output) => output,
+        Err(e) => return (Err(RunError::Other(e)), None),
+    };
+    if output.timed_out {
+        warn!("`{}` timed out", target.display());
+    }
+    let rel_path = paths::repo_relative(rustc_repo_path, target);
+    debug!("`x test {rel_path}` finished ({})", output.status);
+    test_logs::append(&rel_path, what, &output);
+    let results = {
+        let _timer = timings::start(timings::Phase::Parse);
+        test_results::parse_events(&output.stdout)
+    };
+    let outcome = match results.get(paths::test_name(&rel_path)) {
+        Some(result) => match result.status {
+            TestStatus::Passed => Ok(RunOutcome::RemoveOk),
+            TestStatus::Ignored => Ok(RunOutcome::Ignored),
+            TestStatus::Failed => Err(RunError::TestFailure),
+        },
+        // The build broke before any test ran.
+        None if !output.status.success() => Err(RunError::TestFailure),
+        None => {
+            debug!("no result for `{rel_path}`, stderr:\n{}", output.stderr);
+            Err(RunError::NoResult(rel_path))
+        }
+    };
+    (outcome, Some(output))
 }
 
 /// Run the unmodified test as a sanity check
-fn sanity_check(_target: &Path) -> miette::Result<RunOutcome, RunError> {
-    todo!()
+async fn sanity_check(
+    config: &Config,
+    rustc_repo_path: &Path,
+    runner: &dyn TestRunner,
+    target: &Path,
+) -> miette::Result<RunOutcome, RunError> {
+    check(
+        config,
+        rustc_repo_path,
+        runner,
+        target,
+        "the unmodified test",
+    )
+    .await
+}
+
+/// Apply the `edit` of `rule` (e.g. remove `// ignore-debug`), try to run the test and see if it
+/// passes (assuming it is no longer ignored). If it passes, then we can keep the changes.
+/// Otherwise, restore the original test.
+async fn try_rule(
+    config: &Config,
+    rustc_repo_path: &Path,
+    runner: &dyn TestRunner,
+    prepared: &Prepared,
+    rule: &Rule,
+    edit: &Edit,
+    worktrees: &mut worktrees::WorktreeResults,
+) -> miette::Result<Decision, RunError> {
+    let decision = try_edit(config, rustc_repo_path, runner, prepared, edit, worktrees)
+        .instrument(info_span!("rule", strategy = %rule.name))
+        .await?;
+    Ok(decision.kept_with(rule))
+}
+
+/// Apply `edit` to the test file and run it, restoring the original contents (including any
+/// snapshots rewritten by `--bless`) unless the test passes (in the `worktrees` too) and nothing
+/// calls for manual review. The results in the worktrees are stored in `worktrees`.
+async fn try_edit(
+    config: &Config,
+    rustc_repo_path: &Path,
+    runner: &dyn TestRunner,
+    prepared: &Prepared,
+    edit: &Edit,
+    worktrees: &mut worktrees::WorktreeResults,
+) -> miette::Result<Decision, RunError> {
+    let target = prepared.target.as_path();
+    let mut applied = AppliedEdit::write(target, prepared.original.clone(), edit.clone())?;
+    applied.announce(rustc_repo_path);
+    let (mut outcome, output) =
+        check_captured(config, rustc_repo_path, runner, target, "the edited test").await;
+    if let Some(output) = &output {
+        if let Some(decision) =
+            classify(config, rustc_repo_path, &mut applied, &mut outcome, output).await?
+        {
+            return Ok(decision);
+        }
+    }
+    if matches!(outcome, Ok(RunOutcome::RemoveOk)) && !config.worktrees.is_empty() {
+        match worktrees::check(config, rustc_repo_path, runner, target, &edit.src).await {
+            Ok(results) => {
+                if results.values().any(|r| *r == WorktreeResult::Failed) {
+                    outcome = Err(RunError::TestFailure);
+                }
+                *worktrees = results;
+            }
+            Err(e) => outcome = Err(e),
+        }
+    }
+    if matches!(outcome, Ok(RunOutcome::RemoveOk)) && config.tidy {
+        match tidy::errors_in(config, rustc_repo_path, target).await {
+            Ok(errors) if errors.is_empty() => {}
+            Ok(errors) => {
+                warn!(
+                    "`{}` passes but fails tidy: {}",
+                    paths::repo_relative(rustc_repo_path, target),
+                    errors.join("; ")
+                );
+                outcome = Err(RunError::TestFailure);
+            }
+            Err(e) => outcome = Err(RunError::Other(e)),
+        }
+    }
+    applied.settle(config, rustc_repo_path, outcome)
 }
 
-/// Remove `// ignore-debug`, try to run the test and see if it passes (assuming it is no longer
-/// ignored). If it passes, then we can keep the changes. Otherwise, restore the original test.
-fn try_remove(_target: &Path) -> miette::Result<RunOutcome, RunError> {
-    todo!()
+/// Let the `classifier` hook settle an ambiguous `outcome` of the edited test: a failure, or a
+/// pass whose snapshot changes call for manual review. Returns the decision if the hook asks for
+/// manual review, after reverting the edit; otherwise `outcome` is updated to what the hook made
+/// of it, if anything.
+async fn classify(
+    config: &Config,
+    rustc_repo_path: &Path,
+    applied: &mut AppliedEdit<'_>,
+    outcome: &mut Result<RunOutcome, RunError>,
+    output: &process::Captured,
+) -> Result<Option<Decision>, RunError> {
+    if config.classifier.is_none() {
+        return Ok(None);
+    }
+    let result = match outcome {
+        Err(RunError::TestFailure) if output.timed_out => "timed_out",
+        Err(RunError::TestFailure) => "failed",
+        Ok(RunOutcome::RemoveOk) => {
+            let snapshot_changes = applied
+                .snapshots
+                .changes(rustc_repo_path)
+                .into_diagnostic()
+                .map_err(RunError::Other)?;
+            if review::huge_snapshot_diff(&snapshot_changes, config.snapshot_outlier_lines)
+                .is_none()
+            {
+                return Ok(None);
+            }
+            "passed"
+        }
+        _ => return Ok(None),
+    };
+    let diff = applied.diff(rustc_repo_path)?;
+    match hooks::classify(
+        config,
+        rustc_repo_path,
+        applied.target,
+        result,
+        &diff,
+        output,
+    )
+    .await
+    {
+        None => {}
+        Some(hooks::Classification::Accept) => {
+            applied.accepted = true;
+            *outcome = Ok(RunOutcome::RemoveOk);
+        }
+        Some(hooks::Classification::Reject) => *outcome = Err(RunError::TestFailure),
+        Some(hooks::Classification::Manual(reason)) => {
+            applied.revert()?;
+            let mut hint = "the `classifier` hook asked for manual review".to_string();
+            if !reason.is_empty() {
+                hint.push_str(&format!(": {reason}"));
+            }
+            return Ok(Some(Decision::needs_review(hint)));
+        }
+    }
+    Ok(None)
 }
 
-/// Try to replace `// ignore-debug` by the compile flags directive
-/// `// compile-flags: -Cdebug-assertions=no`, try to run the test and see it passes. If it
-/// passes, keep the changes, otherwise, revert.
-fn try_replace(_target: &Path) -> miette::Result<RunOutcome, RunError> {
-    todo!()
+/// An edit which has been written to disk, and which is either kept or reverted once the edited
+/// test has been run.
+struct AppliedEdit<'a> {
+    target: &'a Path,
+    original: String,
+    snapshots: Snapshots,
+    changes: Vec<LineChange>,
+    /// See [`journal::begin`].
+    journal_id: Option<u64>,
+    /// Whether the `classifier` hook accepted the result, overriding the snapshot checks.
+    accepted: bool,
 }
 
-fn format_report(_report: &BTreeMap<PathBuf, RunOutcome>) -> String {
-    todo!()
+impl<'a> AppliedEdit<'a> {
+    fn apply(target: &'a Path, edit: impl Fn(&str) -> Edit) -> Result<Self, RunError> {
+        let original = std::fs::read_to_string(target)
+            .into_diagnostic()
+            .wrap_err(format!("failed to read `{}`", target.display()))
+            .map_err(RunError::Other)?;
+        let edit = edit(&original);
+        AppliedEdit::write(target, original, edit)
+    }
+
+    /// Write `edit`, computed ahead of time from the `original` contents of `target`.
+    fn write(target: &'a Path, original: String, edit: Edit) -> Result<Self, RunError> {
+        let _timer = timings::start(timings::Phase::Edit);
+        let snapshots = Snapshots::capture(target)
+            .into_diagnostic()
+            .wrap_err(format!(
+                "failed to read snapshots of `{}`",
+                target.display()
+            ))
+            .map_err(RunError::Other)?;
+        let journal_id = journal::begin(target, &original, &edit.src, &snapshots, &edit.changes)
+            .into_diagnostic()
+            .wrap_err("failed to write to the journal")
+            .map_err(RunError::Other)?;
+        write_guard::write(target, &edit.src)
+            .into_diagnostic()
+            .wrap_err(format!("failed to write `{}`", target.display()))
+            .map_err(RunError::Other)?;
+        Ok(AppliedEdit {
+            target,
+            original,
+            snapshots,
+            changes: edit.changes,
+            journal_id,
+            accepted: false,
+        })
+    }
+
+    /// The unified diff of the test and its snapshots since [`AppliedEdit::write`].
+    fn diff(&self, rustc_repo_path: &Path) -> Result<String, RunError> {
+        let rel_path = paths::repo_relative(rustc_repo_path, self.target);
+        let current = std::fs::read_to_string(self.target)
+            .into_diagnostic()
+            .wrap_err(format!("failed to read `{}`", self.target.display()))
+            .map_err(RunError::Other)?;
+        let mut diff = similar::TextDiff::from_lines(&self.original, &current)
+            .unified_diff()
+            .header(&format!("a/{rel_path}"), &format!("b/{rel_path}"))
+            .to_string();
+        diff.push_str(
+            &self
+                .snapshots
+                .diff(rustc_repo_path)
+                .into_diagnostic()
+                .wrap_err(format!(
+                    "failed to read snapshots of `{}`",
+                    self.target.display()
+                ))
+                .map_err(RunError::Other)?,
+        );
+        Ok(diff)
+    }
+
+    /// Emit [`events::Event::EditApplied`] for this edit.
+    fn announce(&self, rustc_repo_path: &Path) {
+        events::emit(events::Event::EditApplied {
+            path: paths::repo_relative(rustc_repo_path, self.target),
+            changes: self.changes.clone(),
+        });
+    }
+
+    /// Restore the test and its snapshots to how they were before [`AppliedEdit::apply`].
+    fn revert(&self) -> Result<(), RunError> {
+        let _timer = timings::start(timings::Phase::Revert);
+        write_guard::restore(self.target, &self.original)
+            .and_then(|()| self.snapshots.restore())
+            .and_then(|()| journal::settle(self.journal_id))
+            .into_diagnostic()
+            .wrap_err(format!("failed to restore `{}`", self.target.display()))
+            .map_err(RunError::Other)
+    }
+
+    /// Keep or revert the edit given the `outcome` of running the edited test. A kept edit is
+    /// reported as [`RunOutcome::RemoveOk`], which callers map to the strategy they applied.
+    fn settle(
+        self,
+        config: &Config,
+        rustc_repo_path: &Path,
+        outcome: Result<RunOutcome, RunError>,
+    ) -> Result<Decision, RunError> {
+        let outcome = match outcome {
+            Ok(RunOutcome::RemoveOk) => RunOutcome::RemoveOk,
+            other => {
+                self.revert()?;
+                return other.map(Decision::unmodified);
+            }
+        };
+
+        let snapshot_changes = self
+            .snapshots
+            .changes(rustc_repo_path)
+            .into_diagnostic()
+            .wrap_err(format!(
+                "failed to read snapshots of `{}`",
+                self.target.display()
+            ))
+            .map_err(RunError::Other)?;
+        let flagged = review::huge_snapshot_diff(&snapshot_changes, config.snapshot_outlier_lines)
+            .filter(|_| !self.accepted);
+        if let Some(hint) = flagged {
+            self.revert()?;
+            return Ok(Decision::needs_review(hint));
+        }
+
+        journal::settle(self.journal_id)
+            .into_diagnostic()
+            .wrap_err("failed to write to the journal")
+            .map_err(RunError::Other)?;
+        Ok(Decision {
+            outcome,
+            changes: self.changes,
+            snapshot_changes,
+            ..Default::default()
+        })
+    }
 }
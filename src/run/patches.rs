@@ -0,0 +1,287 @@
+//! `--emit patch`: writing the accepted changes as `git format-patch`-style files under the run
+//! directory instead of leaving them in the working tree.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use miette::{Context, IntoDiagnostic, Result};
+use tracing::*;
+
+use super::{paths, pr_draft, snapshots, write_guard, write_output, TestRecord};
+use crate::config::Config;
+use crate::git;
+
+/// Where the changes of a run end up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Emit {
+    /// Leave the changes in the working tree.
+    #[default]
+    InPlace,
+    /// One patch per test under `patches/` in the run directory.
+    Patch,
+    /// A single `changes.patch` in the run directory.
+    CombinedPatch,
+}
+
+/// Used when no git identity is configured in the `rustc` repo.
+const DEFAULT_AUTHOR: &str = "rustc-less-ignore-debug <rustc-less-ignore-debug@localhost>";
+
+/// Contents of the candidate tests and their snapshots before the run touched them, keyed by
+/// repo-relative path. They are put back when this is dropped, so that the working tree is left
+/// as it was however the run ends, e.g. on an error or a panic after some tests were edited.
+#[derive(Debug)]
+pub(super) struct Originals {
+    rustc_repo_path: PathBuf,
+    targets: Vec<PathBuf>,
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl Originals {
+    pub(super) fn capture<'a>(
+        rustc_repo_path: &Path,
+        targets: impl IntoIterator<Item = &'a PathBuf>,
+    ) -> Result<Self> {
+        let targets: Vec<PathBuf> = targets.into_iter().cloned().collect();
+        let mut files = BTreeMap::new();
+        for target in &targets {
+            for path in std::iter::once(target.clone()).chain(snapshots::companions(target)) {
+                let contents = std::fs::read(&path)
+                    .into_diagnostic()
+                    .wrap_err(format!("failed to read `{}`", path.display()))?;
+                files.insert(paths::repo_relative(rustc_repo_path, &path), contents);
+            }
+        }
+        Ok(Originals {
+            rustc_repo_path: rustc_repo_path.to_path_buf(),
+            targets,
+            files,
+        })
+    }
+
+    /// Put back the captured files which changed since, and remove the snapshots created since.
+    pub(super) fn restore(&self) -> Result<()> {
+        let mut current = BTreeMap::new();
+        for target in &self.targets {
+            for path in std::iter::once(target.clone()).chain(snapshots::companions(target)) {
+                current.insert(paths::repo_relative(&self.rustc_repo_path, &path), path);
+            }
+        }
+        for (rel_path, contents) in &self.files {
+            let path = self.rustc_repo_path.join(rel_path);
+            if std::fs::read(&path).ok().as_ref() != Some(contents) {
+                write_guard::restore(&path, contents)
+                    .into_diagnostic()
+                    .wrap_err(format!("failed to restore `{}`", path.display()))?;
+            }
+        }
+        for (rel_path, path) in current {
+            if !self.files.contains_key(&rel_path) {
+                write_guard::remove_file(&path)
+                    .into_diagnostic()
+                    .wrap_err(format!("failed to remove `{}`", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Originals {
+    fn drop(&mut self) {
+        if let Err(e) = self.restore() {
+            error!("failed to put back the tests edited by the run: {e:?}");
+        }
+    }
+}
+
+/// A file touched by a change, before and after. `None` if it didn't exist.
+struct FileChange {
+    path: String,
+    before: Option<Vec<u8>>,
+    after: Option<Vec<u8>>,
+}
+
+/// Write the accepted changes of `records` as patches under `run_dir`, then restore the files
+/// they touch from `originals`, which also happens when dropping `originals` on failure. Returns the path of the patch file or directory.
+pub(super) fn emit(
+    config: &Config,
+    rustc_repo_path: &Path,
+    emit: Emit,
+    records: &BTreeMap<String, TestRecord>,
+    originals: &Originals,
+    run_dir: &Path,
+) -> Result<PathBuf> {
+    let mut changes = Vec::new();
+    for (rel_path, record) in records {
+        if record.outcome.keeps_directive() {
+            continue;
+        }
+        let mut files = Vec::new();
+        let touched =
+            std::iter::once(rel_path).chain(record.snapshot_changes.iter().map(|c| &c.path));
+        for path in touched {
+            let after = match std::fs::read(rustc_repo_path.join(path)) {
+                Ok(contents) => Some(contents),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    return Err(e)
+                        .into_diagnostic()
+                        .wrap_err(format!("failed to read `{path}`"))
+                }
+            };
+            let before = originals.files.get(path).cloned();
+            if before != after {
+                files.push(FileChange {
+                    path: path.clone(),
+                    before,
+                    after,
+                });
+            }
+        }
+        if !files.is_empty() {
+            changes.push((rel_path, record, files));
+        }
+    }
+
+    let author = git::author(rustc_repo_path).unwrap_or_else(|| DEFAULT_AUTHOR.to_string());
+    let date = chrono::Local::now().to_rfc2822();
+    let output = match emit {
+        Emit::InPlace => unreachable!("nothing to emit for in-place changes"),
+        Emit::Patch => {
+            let dir = run_dir.join("patches");
+            let total = changes.len();
+            for (idx, (rel_path, record, files)) in changes.iter().enumerate() {
+                let message = pr_draft::format_test_commit_message(
+                    &config.commit_message_template,
+                    rel_path,
+                    record,
+                );
+                let (subject, _) = message.split_once('\n').unwrap_or((&message, ""));
+                let name = format!("{:04}-{}.patch", idx + 1, slug(subject));
+                let patch = format_patch(
+                    &author,
+                    &date,
+                    &format!("[PATCH {}/{total}]", idx + 1),
+                    &message,
+                    files,
+                );
+                write_output(&dir.join(name), &patch)?;
+            }
+            dir
+        }
+        Emit::CombinedPatch => {
+            let path = run_dir.join("changes.patch");
+            let message = pr_draft::format_commit_message(records);
+            let files: Vec<_> = changes.iter().flat_map(|(_, _, files)| files).collect();
+            let patch = format_patch(&author, &date, "[PATCH]", &message, files);
+            write_output(&path, &patch)?;
+            path
+        }
+    };
+    info!(
+        "wrote {} change(s) as patches to `{}`",
+        changes.len(),
+        output.display()
+    );
+
+    originals.restore()?;
+    Ok(output)
+}
+
+/// A single patch in mbox format, as produced by `git format-patch`.
+fn format_patch<'a>(
+    author: &str,
+    date: &str,
+    prefix: &str,
+    message: &str,
+    files: impl IntoIterator<Item = &'a FileChange>,
+) -> String {
+    let (subject, body) = message.split_once('\n').unwrap_or((message, ""));
+    let mut out = String::new();
+    writeln!(
+        out,
+        "From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001"
+    )
+    .unwrap();
+    writeln!(out, "From: {author}").unwrap();
+    writeln!(out, "Date: {date}").unwrap();
+    writeln!(out, "Subject: {prefix} {}", subject.trim()).unwrap();
+    writeln!(out).unwrap();
+    let body = body.trim();
+    if !body.is_empty() {
+        writeln!(out, "{body}").unwrap();
+    }
+    writeln!(out, "---").unwrap();
+    for file in files {
+        out.push_str(&format_diff(file));
+    }
+    writeln!(out, "-- ").unwrap();
+    writeln!(out, "rustc-less-ignore-debug").unwrap();
+    writeln!(out).unwrap();
+    out
+}
+
+/// `git diff`-style unified diff of a single file.
+fn format_diff(file: &FileChange) -> String {
+    let path = &file.path;
+    let before = String::from_utf8_lossy(file.before.as_deref().unwrap_or_default());
+    let after = String::from_utf8_lossy(file.after.as_deref().unwrap_or_default());
+    let old = match file.before {
+        Some(_) => format!("a/{path}"),
+        None => "/dev/null".to_string(),
+    };
+    let new = match file.after {
+        Some(_) => format!("b/{path}"),
+        None => "/dev/null".to_string(),
+    };
+
+    let mut out = format!("diff --git a/{path} b/{path}\n");
+    match (&file.before, &file.after) {
+        (None, _) => out.push_str("new file mode 100644\n"),
+        (_, None) => out.push_str("deleted file mode 100644\n"),
+        _ => {}
+    }
+    let diff = similar::TextDiff::from_lines(&before, &after);
+    write!(out, "{}", diff.unified_diff().header(&old, &new)).unwrap();
+    out
+}
+
+/// File name component derived from a commit subject, like `git format-patch` does.
+fn slug(subject: &str) -> String {
+    let mut slug = String::new();
+    for c in subject.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(52);
+    slug.trim_end_matches(['-', '.']).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Originals;
+    use crate::run::tests::Repo;
+
+    #[test]
+    fn puts_back_tests_and_snapshots_when_dropped() {
+        let repo = Repo::new("originals");
+        let test = repo.add("tests/ui/a.rs", "//@ ignore-debug\n");
+        let stderr = repo.add("tests/ui/a.stderr", "error\n");
+
+        let originals = Originals::capture(&repo.0, [&test]).unwrap();
+        std::fs::write(&test, "").unwrap();
+        std::fs::remove_file(&stderr).unwrap();
+        repo.add("tests/ui/a.stdout", "blessed\n");
+        drop(originals);
+
+        assert_eq!(
+            std::fs::read_to_string(&test).unwrap(),
+            "//@ ignore-debug\n"
+        );
+        assert_eq!(std::fs::read_to_string(&stderr).unwrap(), "error\n");
+        assert!(!repo.0.join("tests/ui/a.stdout").exists());
+    }
+}
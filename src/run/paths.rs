@@ -0,0 +1,119 @@
+//! Normalization of paths for output.
+
+use std::path::{Component, Path};
+
+use super::platform;
+
+/// The suite a repo-relative test path belongs to, e.g. `tests/ui` for `tests/ui/foo/bar.rs`.
+pub(super) fn suite_of(rel_path: &str) -> &str {
+    let mut slashes = rel_path.match_indices('/').map(|(idx, _)| idx);
+    match (slashes.next(), slashes.next()) {
+        (Some(_), Some(second)) => &rel_path[..second],
+        _ => rel_path.rsplit_once('/').map_or("", |(dir, _)| dir),
+    }
+}
+
+/// Suites whose tests are directories, which compiletest names after the directory rather than
+/// after a file in it.
+const DIRECTORY_SUITES: [&str; 2] = ["tests/run-make", "tests/run-make-cargo"];
+
+/// The name compiletest reports the test of the file at `rel_path` under (without the `[suite] `
+/// prefix), which also selects it for `x test`: the path of the file itself, or of its directory
+/// in a suite of [`DIRECTORY_SUITES`], e.g. `tests/run-make/foo` for
+/// `tests/run-make/foo/rmake.rs`.
+pub(super) fn test_name(rel_path: &str) -> &str {
+    let suite = suite_of(rel_path);
+    if !DIRECTORY_SUITES.contains(&suite) {
+        return rel_path;
+    }
+    let in_suite = &rel_path[suite.len() + 1..];
+    match in_suite.split_once('/') {
+        Some((dir, _)) => &rel_path[..suite.len() + 1 + dir.len()],
+        None => rel_path,
+    }
+}
+
+/// The `--test-args` filter selecting the test of the file at `rel_path` in `suite`: the path of
+/// its [`test_name`] within the suite.
+pub(super) fn suite_filter(rel_path: &str, suite: &str) -> String {
+    let rel_path = test_name(rel_path);
+    rel_path
+        .strip_prefix(suite)
+        .map_or(rel_path, |p| p.trim_start_matches('/'))
+        .to_string()
+}
+
+/// Render `path` relative to the root of the `rustc` repo, with `/` as the separator regardless of
+/// platform, so that output generated on different machines can be diffed against each other.
+///
+/// Paths outside of the repo are rendered as-is (modulo separators).
+pub(super) fn repo_relative(rustc_repo_path: &Path, path: &Path) -> String {
+    let rel = path.strip_prefix(rustc_repo_path).unwrap_or(path);
+    let mut rendered = String::new();
+    for component in rel.components() {
+        match component {
+            Component::CurDir => {}
+            Component::Prefix(prefix) => rendered.push_str(&platform::display_prefix(
+                &prefix.as_os_str().to_string_lossy(),
+            )),
+            Component::RootDir => rendered.push('/'),
+            c => {
+                if !rendered.is_empty() && !rendered.ends_with('/') {
+                    rendered.push('/');
+                }
+                rendered.push_str(&c.as_os_str().to_string_lossy());
+            }
+        }
+    }
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_suite_of_a_test() {
+        assert_eq!(suite_of("tests/ui/foo/bar.rs"), "tests/ui");
+        assert_eq!(suite_of("tests/ui/a.rs"), "tests/ui");
+        assert_eq!(suite_of("tests/a.rs"), "tests");
+        assert_eq!(suite_of("a.rs"), "");
+    }
+
+    #[test]
+    fn names_run_make_tests_after_their_directory() {
+        assert_eq!(test_name("tests/ui/foo/bar.rs"), "tests/ui/foo/bar.rs");
+        assert_eq!(
+            test_name("tests/run-make/foo/rmake.rs"),
+            "tests/run-make/foo"
+        );
+        assert_eq!(
+            test_name("tests/run-make/foo/Makefile"),
+            "tests/run-make/foo"
+        );
+        assert_eq!(
+            test_name("tests/run-make/README.md"),
+            "tests/run-make/README.md"
+        );
+        assert_eq!(
+            suite_filter("tests/run-make/foo/rmake.rs", "tests/run-make"),
+            "foo"
+        );
+    }
+
+    #[test]
+    fn renders_paths_relative_to_the_repo() {
+        assert_eq!(
+            repo_relative(Path::new("/rust"), Path::new("/rust/tests/ui/a.rs")),
+            "tests/ui/a.rs"
+        );
+        assert_eq!(
+            repo_relative(Path::new("/rust"), Path::new("/elsewhere/a.rs")),
+            "/elsewhere/a.rs"
+        );
+        assert_eq!(
+            repo_relative(Path::new("rust"), Path::new("./rust/tests/./a.rs")),
+            "rust/tests/a.rs"
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! What differs between platforms when running bootstrap: how it is started, how a hung `x` is
+//! killed along with its children, and how files locked by another process are written.
+//!
+//! The decisions are made by plain functions taking the platform as a parameter, so that they are
+//! covered by the tests on any host.
+
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// How bootstrap is started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Launcher {
+    /// The `x` binary of `src/tools/x` on the `PATH`, which finds the repo from the current
+    /// directory.
+    X,
+    /// `x.ps1` at the root of the repo, through Windows PowerShell (or PowerShell 7 as `pwsh`).
+    PowerShell(&'static str),
+    /// `x.py` at the root of the repo, through the `py` launcher of Python for Windows.
+    Py,
+}
+
+impl Launcher {
+    /// The program to spawn and the arguments preceding the subcommand, run from the root of the
+    /// repo.
+    pub(super) fn program(self) -> (&'static str, &'static [&'static str]) {
+        match self {
+            Launcher::X => ("x", &[]),
+            Launcher::PowerShell(shell) => (
+                shell,
+                &["-NoProfile", "-ExecutionPolicy", "Bypass", "-File", "x.ps1"],
+            ),
+            Launcher::Py => ("py", &["-3", "x.py"]),
+        }
+    }
+}
+
+/// How to start bootstrap on this host, `None` if it can't be. Looked up once.
+pub(super) fn launcher() -> Option<Launcher> {
+    static LAUNCHER: OnceLock<Option<Launcher>> = OnceLock::new();
+    *LAUNCHER.get_or_init(|| {
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        let exts = std::env::var_os("PATHEXT").unwrap_or_default();
+        choose_launcher(cfg!(windows), |program| {
+            find_on_path(program, &path, &exts, cfg!(windows)).is_some()
+        })
+    })
+}
+
+/// `x` if it is on the `PATH` (`on_path`), and otherwise on Windows, where it often isn't
+/// installed, `x.ps1` through PowerShell, then `x.py` through `py`. Elsewhere, `x` is the only way
+/// bootstrap is started.
+fn choose_launcher(windows: bool, on_path: impl Fn(&str) -> bool) -> Option<Launcher> {
+    if on_path("x") {
+        return Some(Launcher::X);
+    }
+    if !windows {
+        return None;
+    }
+    for shell in ["pwsh", "powershell"] {
+        if on_path(shell) {
+            return Some(Launcher::PowerShell(shell));
+        }
+    }
+    on_path("py").then_some(Launcher::Py)
+}
+
+/// The executable `program` would resolve to in the directories of `path` (a `PATH` value). On
+/// Windows, `program` may also have any of the extensions of `exts` (a `PATHEXT` value).
+fn find_on_path(program: &str, path: &OsStr, exts: &OsStr, windows: bool) -> Option<PathBuf> {
+    let names: Vec<OsString> = if windows {
+        let exts = exts.to_string_lossy();
+        let exts = if exts.is_empty() {
+            ".COM;.EXE;.BAT;.CMD"
+        } else {
+            &exts
+        };
+        exts.split(';')
+            .filter(|ext| !ext.is_empty())
+            .map(|ext| format!("{program}{}", ext.to_ascii_lowercase()).into())
+            .collect()
+    } else {
+        vec![program.into()]
+    };
+    let dirs: Vec<PathBuf> = if windows {
+        // `split_paths` follows the conventions of the host.
+        path.to_string_lossy()
+            .split(';')
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| PathBuf::from(dir.trim_matches('"')))
+            .collect()
+    } else {
+        std::env::split_paths(path).collect()
+    };
+    dirs.iter()
+        .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+        .find(|candidate| candidate.is_file())
+}
+
+/// `x <subcommand>` started by `launcher` in `rustc_repo_path`.
+pub(super) fn x_command(
+    launcher: Launcher,
+    rustc_repo_path: &Path,
+    subcommand: &str,
+) -> std::process::Command {
+    let (program, args) = launcher.program();
+    let mut command = std::process::Command::new(program);
+    command
+        .current_dir(rustc_repo_path)
+        .args(args)
+        .arg(subcommand);
+    command
+}
+
+/// Whether `error` means that the file is open in another process without sharing, which on
+/// Windows (`ERROR_SHARING_VIOLATION`, `ERROR_LOCK_VIOLATION`) happens while an antivirus or an
+/// indexer has a look at a file just written, and goes away shortly.
+fn is_sharing_violation(error: &io::Error, windows: bool) -> bool {
+    windows && matches!(error.raw_os_error(), Some(32 | 33))
+}
+
+/// Run `op` until it succeeds or fails otherwise than with a sharing violation, for up to about a
+/// second.
+pub(super) fn retry_if_locked<T>(mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut attempts = 0;
+    loop {
+        match op() {
+            Err(e) if is_sharing_violation(&e, cfg!(windows)) && attempts < 10 => {
+                attempts += 1;
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The prefix of a Windows path (e.g. `C:`) as shown to users: without the `\\?\` of the
+/// verbatim paths returned by `canonicalize`.
+pub(super) fn display_prefix(prefix: &str) -> String {
+    if let Some(unc) = prefix.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{unc}")
+    } else {
+        prefix.strip_prefix(r"\\?\").unwrap_or(prefix).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run::tests::Repo;
+
+    #[test]
+    fn starts_bootstrap_through_powershell_or_py_on_windows_without_x() {
+        assert_eq!(choose_launcher(false, |p| p == "x"), Some(Launcher::X));
+        assert_eq!(choose_launcher(false, |p| p == "py"), None);
+        assert_eq!(choose_launcher(true, |p| p == "x"), Some(Launcher::X));
+        assert_eq!(
+            choose_launcher(true, |p| p == "powershell" || p == "py"),
+            Some(Launcher::PowerShell("powershell"))
+        );
+        assert_eq!(choose_launcher(true, |p| p == "py"), Some(Launcher::Py));
+        assert_eq!(choose_launcher(true, |_| false), None);
+
+        let command = x_command(Launcher::PowerShell("pwsh"), Path::new("rust"), "test");
+        assert_eq!(command.get_program(), "pwsh");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "-NoProfile",
+                "-ExecutionPolicy",
+                "Bypass",
+                "-File",
+                "x.ps1",
+                "test"
+            ]
+        );
+        assert_eq!(command.get_current_dir(), Some(Path::new("rust")));
+    }
+
+    #[test]
+    fn finds_programs_on_the_path_with_windows_extensions() {
+        let repo = Repo::new("path");
+        let bin = repo.add("bin/x.exe", "").parent().unwrap().to_path_buf();
+        let path = format!("{};\"{}\"", repo.0.join("missing").display(), bin.display());
+
+        assert_eq!(
+            find_on_path("x", path.as_ref(), ".COM;.EXE".as_ref(), true),
+            Some(bin.join("x.exe"))
+        );
+        assert_eq!(
+            find_on_path("x", path.as_ref(), ".COM".as_ref(), true),
+            None
+        );
+        assert_eq!(
+            find_on_path("x.exe", bin.as_os_str(), "".as_ref(), false),
+            Some(bin.join("x.exe"))
+        );
+    }
+
+    #[test]
+    fn retries_writes_only_on_windows_sharing_violations() {
+        let sharing_violation = std::io::Error::from_raw_os_error(32);
+        assert!(is_sharing_violation(&sharing_violation, true));
+        assert!(!is_sharing_violation(&sharing_violation, false));
+        let not_found = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert!(!is_sharing_violation(&not_found, true));
+    }
+
+    #[test]
+    fn renders_windows_path_prefixes_without_verbatim_markers() {
+        assert_eq!(display_prefix(r"\\?\C:"), "C:");
+        assert_eq!(display_prefix(r"\\?\UNC\server\share"), r"\\server\share");
+        assert_eq!(display_prefix("C:"), "C:");
+    }
+}
@@ -0,0 +1,120 @@
+//! Drafts of the commit message and PR description for submitting the changes upstream.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+use super::{RunOutcome, TestRecord};
+
+const JUSTIFICATION: &str = "\
+Tests marked with `ignore-debug` are skipped entirely whenever the compiler and standard library \
+are built with debug assertions, which is the case on most contributors' machines and on several \
+CI jobs. Where a test passes with debug assertions enabled, the directive is removed so the test \
+runs everywhere. Where a test only depends on debug assertions being disabled in the test itself \
+(and not in the compiler), the directive is replaced by `-Cdebug-assertions=no` so that it still \
+runs instead of being ignored.";
+
+/// Per-directory counts of removed and replaced directives.
+fn by_directory(records: &BTreeMap<String, TestRecord>) -> BTreeMap<&str, (usize, usize)> {
+    let mut dirs: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for (path, record) in records {
+        let dir = path.rsplit_once('/').map_or(".", |(dir, _)| dir);
+        match record.outcome {
+            RunOutcome::RemoveOk => dirs.entry(dir).or_default().0 += 1,
+            RunOutcome::ReplaceOk => dirs.entry(dir).or_default().1 += 1,
+            RunOutcome::UnmodifiedOk | RunOutcome::Ignored | RunOutcome::NeedsManualReview => {}
+        }
+    }
+    dirs
+}
+
+fn count(records: &BTreeMap<String, TestRecord>, outcome: RunOutcome) -> usize {
+    records.values().filter(|r| r.outcome == outcome).count()
+}
+
+/// Message of the commit for a single test, see `commit_message_template` in the config.
+pub(super) fn format_test_commit_message(
+    template: &str,
+    path: &str,
+    record: &TestRecord,
+) -> String {
+    let action = match record.outcome {
+        RunOutcome::RemoveOk => "Remove `ignore-debug` from",
+        RunOutcome::ReplaceOk => "Replace `ignore-debug` with `-Cdebug-assertions=no` in",
+        RunOutcome::UnmodifiedOk | RunOutcome::Ignored | RunOutcome::NeedsManualReview => {
+            "Keep `ignore-debug` in"
+        }
+    };
+    template
+        .replace("{action}", action)
+        .replace("{path}", path)
+        .replace("{outcome}", record.outcome.description())
+}
+
+pub(super) fn format_commit_message(records: &BTreeMap<String, TestRecord>) -> String {
+    let removed = count(records, RunOutcome::RemoveOk);
+    let replaced = count(records, RunOutcome::ReplaceOk);
+
+    let mut out = String::new();
+    writeln!(out, "Reduce `ignore-debug` usage in tests\n").unwrap();
+    writeln!(out, "{}\n", wrap(JUSTIFICATION, 72)).unwrap();
+    writeln!(out, "- Removed `ignore-debug` from {removed} test(s).").unwrap();
+    writeln!(
+        out,
+        "- Replaced `ignore-debug` with `-Cdebug-assertions=no` in {replaced} test(s)."
+    )
+    .unwrap();
+    let dirs = by_directory(records);
+    if !dirs.is_empty() {
+        writeln!(out, "\nBy directory:\n").unwrap();
+        for (dir, (dir_removed, dir_replaced)) in dirs {
+            writeln!(
+                out,
+                "- {dir}: {dir_removed} removed, {dir_replaced} replaced"
+            )
+            .unwrap();
+        }
+    }
+    out
+}
+
+pub(super) fn format_pr_body(records: &BTreeMap<String, TestRecord>) -> String {
+    let removed = count(records, RunOutcome::RemoveOk);
+    let replaced = count(records, RunOutcome::ReplaceOk);
+    let unmodified = count(records, RunOutcome::UnmodifiedOk);
+
+    let mut out = String::new();
+    writeln!(out, "{JUSTIFICATION}\n").unwrap();
+    writeln!(out, "| Directory | Removed | Replaced |").unwrap();
+    writeln!(out, "|---|---:|---:|").unwrap();
+    for (dir, (dir_removed, dir_replaced)) in by_directory(records) {
+        writeln!(out, "| `{dir}` | {dir_removed} | {dir_replaced} |").unwrap();
+    }
+    writeln!(out, "| **Total** | **{removed}** | **{replaced}** |\n").unwrap();
+    if unmodified > 0 {
+        writeln!(
+            out,
+            "{unmodified} test(s) still fail with debug assertions enabled even with \
+             `-Cdebug-assertions=no` and keep their `ignore-debug`."
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Greedy word wrap, as commit message bodies are conventionally wrapped at 72 columns.
+fn wrap(text: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut line_len = 0;
+    for word in text.split_whitespace() {
+        if line_len > 0 && line_len + 1 + word.len() > width {
+            out.push('\n');
+            line_len = 0;
+        } else if line_len > 0 {
+            out.push(' ');
+            line_len += 1;
+        }
+        out.push_str(word);
+        line_len += word.len();
+    }
+    out
+}
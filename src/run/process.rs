@@ -0,0 +1,275 @@
+//! Running `x` with its output streamed rather than buffered in full, since compiletest can print
+//! an enormous amount for a large suite. Only the lines reporting test results and the tail of
+//! each stream are kept in memory; the full output can be logged to files and tailed live.
+//!
+//! Children are spawned in their own process group, so that a timeout or an interruption takes
+//! down everything `x` spawned (bootstrap, cargo, compiletest, rustc) rather than just `x`.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::Notify;
+use tracing::*;
+
+use super::timings::{self, Phase};
+use super::{container, control, limits, remote, test_results, tokens, INTERRUPTED};
+
+/// Number of trailing lines of each stream kept in memory.
+const TAIL_LINES: usize = 200;
+
+/// How the output of child processes is surfaced, set once per run with [`configure`].
+#[derive(Debug, Default)]
+pub(super) struct OutputOptions {
+    /// Echo every line to stderr as it is produced.
+    pub(super) show_output: bool,
+    /// Directory to write the full output of every invocation to, one file per invocation.
+    pub(super) log_dir: Option<PathBuf>,
+    /// Kill invocations running for longer than this.
+    pub(super) timeout: Option<Duration>,
+}
+
+static OPTIONS: Mutex<Option<Arc<OutputOptions>>> = Mutex::new(None);
+
+/// Numbers the log files of the invocations of the current run.
+static INVOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Woken by [`interrupt`] to stop the children in flight.
+static INTERRUPT: Notify = Notify::const_new();
+
+pub(super) fn configure(options: OutputOptions) {
+    *OPTIONS.lock().unwrap() = Some(Arc::new(options));
+    INVOCATIONS.store(0, Ordering::SeqCst);
+}
+
+/// Stop every child in flight. [`INTERRUPTED`] must have been set first, so that no new child is
+/// spawned afterwards.
+pub(super) fn interrupt() {
+    INTERRUPT.notify_waiters();
+}
+
+/// What was kept of the output of a finished child process.
+#[derive(Debug)]
+pub(super) struct Captured {
+    pub(super) status: ExitStatus,
+    /// The child was killed because it ran for longer than the configured timeout.
+    pub(super) timed_out: bool,
+    /// Every line of the libtest event stream (see [`test_results`]), followed by the last
+    /// [`TAIL_LINES`] other lines.
+    pub(super) stdout: String,
+    /// The last [`TAIL_LINES`] lines.
+    pub(super) stderr: String,
+}
+
+/// Spawn `command` and wait for it, streaming its stdout and stderr.
+pub(super) async fn run(command: &mut Command) -> std::io::Result<Captured> {
+    let options = OPTIONS.lock().unwrap().clone().unwrap_or_default();
+    let log = match &options.log_dir {
+        Some(log_dir) => {
+            let n = INVOCATIONS.fetch_add(1, Ordering::SeqCst);
+            let mut file = File::create(log_dir.join(format!("{n:05}.log")))?;
+            writeln!(file, "$ {:?}", command.as_std())?;
+            Some(Mutex::new(file))
+        }
+        None => None,
+    };
+
+    let interrupted = INTERRUPT.notified();
+    tokio::pin!(interrupted);
+    interrupted.as_mut().enable();
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Interrupted,
+            "the run was interrupted",
+        ));
+    }
+
+    let _token = tokens::acquire().await?;
+    let _timer = timings::start(Phase::Invocation);
+    let mut command = limits::apply(command);
+    tokens::hand_down(&mut command);
+    let mut command = container::wrap(remote::wrap(command));
+    #[cfg(unix)]
+    command.process_group(0);
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+
+    let test = control::current_test();
+    let mut timed_out = false;
+    let wait = async {
+        let deadline = async {
+            match options.timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            status = child.wait() => status,
+            () = deadline => {
+                warn!("killing `x` after {:?}", options.timeout.unwrap_or_default());
+                timed_out = true;
+                kill(&mut child, Signal::Kill).await
+            }
+            () = &mut interrupted => kill(&mut child, Signal::Interrupt).await,
+            () = control::skipped(test.as_deref()) => kill(&mut child, Signal::Interrupt).await,
+        }
+    };
+    let log = log.as_ref();
+    let (status, stdout, stderr) = tokio::join!(
+        wait,
+        stream(stdout, true, options.show_output, log, test.as_deref()),
+        stream(stderr, false, options.show_output, log, test.as_deref()),
+    );
+
+    Ok(Captured {
+        status: status?,
+        timed_out,
+        stdout: stdout?,
+        stderr: stderr?,
+    })
+}
+
+enum Signal {
+    Interrupt,
+    Kill,
+}
+
+/// Signal the process group of `child` and reap it.
+async fn kill(child: &mut Child, signal: Signal) -> std::io::Result<ExitStatus> {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        let signal = match signal {
+            Signal::Interrupt => libc::SIGINT,
+            Signal::Kill => libc::SIGKILL,
+        };
+        // SAFETY: `killpg` has no memory safety preconditions. The group was created for `child`,
+        // which has not been reaped yet, so its id can't have been reused.
+        if unsafe { libc::killpg(pid as libc::pid_t, signal) } == 0 {
+            return child.wait().await;
+        }
+    }
+    #[cfg(windows)]
+    if let Some(pid) = child.id() {
+        // There are no signals, and killing `x` alone would leave the test it runs running, so
+        // take down the whole tree (`/T`).
+        let _ = signal;
+        let status = std::process::Command::new("taskkill")
+            .args(["/T", "/F", "/PID"])
+            .arg(pid.to_string())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status();
+        if status.is_ok_and(|s| s.success()) {
+            return child.wait().await;
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    let _ = signal;
+    child.kill().await?;
+    child.wait().await
+}
+
+async fn stream(
+    out: impl AsyncRead + Unpin,
+    keep_events: bool,
+    show_output: bool,
+    log: Option<&Mutex<File>>,
+    test: Option<&str>,
+) -> std::io::Result<String> {
+    let mut kept = String::new();
+    let mut tail = VecDeque::with_capacity(TAIL_LINES);
+    let mut lines = BufReader::new(out).split(b'\n');
+    while let Some(line) = lines.next_segment().await? {
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches('\r');
+        if show_output {
+            if !keep_events {
+                eprintln!("{line}");
+            } else if let Some(line) = test_results::render(line) {
+                eprintln!("{line}");
+            }
+        }
+        if let Some(log) = log {
+            writeln!(log.lock().unwrap(), "{line}")?;
+        }
+        if let Some(test) = test {
+            control::output(test, line);
+        }
+        if keep_events && test_results::is_event(line) {
+            kept.push_str(line);
+            kept.push('\n');
+            continue;
+        }
+        if tail.len() == TAIL_LINES {
+            tail.pop_front();
+        }
+        tail.push_back(line.to_string());
+    }
+    for line in tail {
+        kept.push_str(&line);
+        kept.push('\n');
+    }
+    Ok(kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn keeps_every_event_and_the_tail_of_the_rest() {
+        let event = r#"{ "type": "test", "event": "ok", "name": "[ui] tests/ui/a.rs" }"#;
+        let mut out = format!("{event}\n");
+        for n in 0..TAIL_LINES + 10 {
+            out.push_str(&format!("line {n}\r\n"));
+        }
+        let kept = stream(out.as_bytes(), true, false, None, None)
+            .await
+            .unwrap();
+        let kept: Vec<&str> = kept.lines().collect();
+        assert_eq!(kept.len(), 1 + TAIL_LINES);
+        assert_eq!(kept[0], event);
+        assert_eq!(kept[1], "line 10");
+        assert_eq!(kept[TAIL_LINES], format!("line {}", TAIL_LINES + 9));
+
+        // Events are only picked out of stdout.
+        let kept = stream(format!("{event}\n").as_bytes(), false, false, None, None)
+            .await
+            .unwrap();
+        assert_eq!(kept, format!("{event}\n"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn kills_children_running_past_the_timeout() {
+        configure(OutputOptions {
+            timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        let captured = run(Command::new("sh").args(["-c", "echo started; sleep 30"]))
+            .await
+            .unwrap();
+        configure(OutputOptions::default());
+
+        assert!(captured.timed_out);
+        assert!(!captured.status.success());
+        assert_eq!(captured.stdout, "started\n");
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,327 @@
+//! Machine-readable progress events for wrappers (IDE plugins, web frontends, ...), written as
+//! JSON lines to a file descriptor given via `--progress-fd`, and a progress bar for humans
+//! watching the terminal.
+//!
+//! While the bar is shown, anything else written to stderr should go through
+//! [`suspend_progress_bar`] so that it is printed above the bar rather than over it.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Write as _;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use miette::{Context, IntoDiagnostic, Result};
+use serde::Serialize;
+use tracing::*;
+
+use super::RunOutcome;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(super) enum ProgressEvent<'a> {
+    /// The target directories have been walked.
+    Scanned {
+        files_scanned: usize,
+        candidates: usize,
+    },
+    /// A test is about to be processed.
+    TestStarted {
+        path: &'a str,
+        /// 0-based index of the test among the candidates.
+        index: usize,
+        total: usize,
+        percent_complete: f64,
+        /// Estimated seconds until the run completes, see [`Progress::eta`].
+        eta_secs: Option<u64>,
+        /// Groups of tests prepared ahead of time and waiting for a worker.
+        queued: usize,
+    },
+    /// A test has been processed.
+    TestFinished {
+        path: &'a str,
+        outcome: RunOutcome,
+        duration_secs: f64,
+    },
+    /// All tests have been processed (or the run was interrupted).
+    Finished { completed: usize, total: usize },
+}
+
+/// The progress bar of the current run, if shown.
+static BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Run `f`, which writes to stderr, with the progress bar (if any) hidden meanwhile.
+pub fn suspend_progress_bar<R>(f: impl FnOnce() -> R) -> R {
+    let bar = BAR.lock().unwrap().clone();
+    match bar {
+        Some(bar) => bar.suspend(f),
+        None => f(),
+    }
+}
+
+/// Where progress events go. Events are silently dropped if no progress fd was requested.
+#[derive(Debug, Default)]
+pub(super) struct Progress {
+    out: Option<File>,
+    bar: Option<ProgressBar>,
+    /// Tests being processed, shown next to the bar.
+    running: BTreeSet<String>,
+    total: usize,
+    completed: usize,
+    /// Time spent on the tests completed so far, and how many of them were actually run rather
+    /// than reused from the cache.
+    elapsed: Duration,
+    measured: usize,
+    /// Tests left to process, with their duration in a previous run if known, see
+    /// [`Progress::plan`].
+    pending: BTreeMap<String, Option<Duration>>,
+    /// Number of tests processed concurrently.
+    jobs: usize,
+    /// Groups of tests prepared ahead of time and waiting for a worker, see [`super::workers`].
+    queued: usize,
+}
+
+impl Progress {
+    /// Events go to `progress_fd`, if any, and a progress bar is drawn on stderr if `show_bar`.
+    pub(super) fn new(progress_fd: Option<i32>, show_bar: bool) -> Result<Self> {
+        let out = progress_fd.map(open_fd).transpose()?;
+        let bar = show_bar.then(|| {
+            let bar = ProgressBar::with_draw_target(Some(0), ProgressDrawTarget::stderr());
+            bar.set_style(
+                ProgressStyle::with_template("{elapsed_precise} [{bar:30}] {pos}/{len} {wide_msg}")
+                    .expect("the progress bar template is valid")
+                    .progress_chars("=> "),
+            );
+            bar.enable_steady_tick(Duration::from_secs(1));
+            *BAR.lock().unwrap() = Some(bar.clone());
+            bar
+        });
+        let mut progress = Progress::default();
+        progress.out = out;
+        progress.bar = bar;
+        Ok(progress)
+    }
+
+    pub(super) fn scanned(&mut self, files_scanned: usize, candidates: usize) {
+        self.total = candidates;
+        if let Some(bar) = &self.bar {
+            bar.set_length(candidates as u64);
+        }
+        self.emit(&ProgressEvent::Scanned {
+            files_scanned,
+            candidates,
+        });
+    }
+
+    /// Announce the tests about to be processed by `jobs` concurrent workers, along with their
+    /// durations in previous runs where known.
+    pub(super) fn plan(
+        &mut self,
+        tests: impl IntoIterator<Item = String>,
+        estimates: &BTreeMap<String, Duration>,
+        jobs: usize,
+    ) {
+        self.pending = tests
+            .into_iter()
+            .map(|test| {
+                let estimate = estimates.get(&test).copied();
+                (test, estimate)
+            })
+            .collect();
+        self.jobs = jobs;
+    }
+
+    /// Estimated time until the run completes: the previous durations of the pending tests (or
+    /// the average duration, for tests without one) spread over the concurrent jobs.
+    pub(super) fn eta(&self) -> Option<Duration> {
+        let jobs = self.jobs.max(1) as u32;
+        let average = self.average_duration();
+        if self.pending.is_empty() {
+            let remaining = self.total.saturating_sub(self.completed) as u32;
+            return average.map(|average| average * remaining / jobs);
+        }
+        let mut remaining = Duration::ZERO;
+        for estimate in self.pending.values() {
+            remaining += estimate.or(average)?;
+        }
+        Some(remaining / jobs)
+    }
+
+    /// `<completed>/<total>`, with the ETA and the queue depth if there are any.
+    pub(super) fn position(&self) -> String {
+        let mut position = format!("{}/{}", self.completed, self.total);
+        if let Some(eta) = self.eta() {
+            position.push_str(&format!(", ETA {}", super::summary::format_duration(eta)));
+        }
+        if self.queued > 0 {
+            position.push_str(&format!(", {} queued", self.queued));
+        }
+        position
+    }
+
+    pub(super) fn queued(&mut self, queued: usize) {
+        self.queued = queued;
+    }
+
+    /// Show the position, the ETA and the tests being processed on the bar.
+    fn update_bar(&self) {
+        let Some(bar) = &self.bar else {
+            return;
+        };
+        bar.set_position(self.completed as u64);
+        let mut message = match self.eta() {
+            Some(eta) => format!("ETA {}", super::summary::format_duration(eta)),
+            None => "ETA unknown".to_string(),
+        };
+        let mut running = self.running.iter();
+        if let Some(first) = running.next() {
+            message.push_str(&format!(" | {first}"));
+            let others = running.len();
+            if others > 0 {
+                message.push_str(&format!(" (+{others} more)"));
+            }
+        }
+        bar.set_message(message);
+    }
+
+    /// Average duration of the tests run so far, or of the pending tests in previous runs.
+    fn average_duration(&self) -> Option<Duration> {
+        if self.measured > 0 {
+            return Some(self.elapsed / self.measured as u32);
+        }
+        let known: Vec<Duration> = self.pending.values().flatten().copied().collect();
+        (!known.is_empty()).then(|| known.iter().sum::<Duration>() / known.len() as u32)
+    }
+
+    pub(super) fn test_started(&mut self, path: &str) {
+        self.running.insert(path.to_string());
+        self.update_bar();
+        let eta_secs = self.eta().map(|eta| eta.as_secs());
+        let percent_complete = if self.total == 0 {
+            100.0
+        } else {
+            self.completed as f64 * 100.0 / self.total as f64
+        };
+        self.emit(&ProgressEvent::TestStarted {
+            path,
+            index: self.completed,
+            total: self.total,
+            percent_complete,
+            eta_secs,
+            queued: self.queued,
+        });
+    }
+
+    pub(super) fn test_finished(&mut self, path: &str, outcome: RunOutcome, duration: Duration) {
+        self.completed += 1;
+        self.pending.remove(path);
+        self.running.remove(path);
+        if !duration.is_zero() {
+            self.elapsed += duration;
+            self.measured += 1;
+        }
+        self.update_bar();
+        self.emit(&ProgressEvent::TestFinished {
+            path,
+            outcome,
+            duration_secs: duration.as_secs_f64(),
+        });
+    }
+
+    pub(super) fn finished(&mut self) {
+        self.clear_bar();
+        self.emit(&ProgressEvent::Finished {
+            completed: self.completed,
+            total: self.total,
+        });
+    }
+
+    fn clear_bar(&mut self) {
+        if let Some(bar) = self.bar.take() {
+            bar.finish_and_clear();
+            *BAR.lock().unwrap() = None;
+        }
+    }
+
+    fn emit(&mut self, event: &ProgressEvent<'_>) {
+        let Some(out) = &mut self.out else {
+            return;
+        };
+        let mut line = serde_json::to_string(event).expect("progress events are serializable");
+        line.push('\n');
+        if let Err(e) = out.write_all(line.as_bytes()) {
+            warn!("failed to write progress event, no further events will be emitted: {e}");
+            self.out = None;
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        // Don't leave the bar behind when the run fails.
+        self.clear_bar();
+    }
+}
+
+/// A duplicate of the already-open file descriptor `fd`, leaving `fd` itself to its owner.
+#[cfg(unix)]
+pub(super) fn open_fd(fd: i32) -> Result<File> {
+    use std::os::fd::{AsRawFd, BorrowedFd};
+
+    if (0..=2).contains(&fd) {
+        miette::bail!("fd {fd} is stdin, stdout or stderr, which cannot receive events");
+    }
+    // SAFETY: `F_GETFD` only reads the flags of `fd`, failing with `EBADF` if it is not open.
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        miette::bail!("fd {fd} is not open");
+    }
+    // SAFETY: `fd` is open, and is only borrowed for as long as it takes to duplicate it, so
+    // whoever owns it may still use and close it.
+    let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+    let owned = fd
+        .try_clone_to_owned()
+        .into_diagnostic()
+        .wrap_err(format!("failed to duplicate fd {}", fd.as_raw_fd()))?;
+    Ok(File::from(owned))
+}
+
+#[cfg(not(unix))]
+pub(super) fn open_fd(_fd: i32) -> Result<File> {
+    miette::bail!("writing events to a file descriptor is only supported on unix platforms")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::fd::{AsRawFd, IntoRawFd};
+
+    use super::open_fd;
+
+    #[test]
+    fn rejects_the_standard_streams_and_closed_fds() {
+        for fd in 0..=2 {
+            assert!(open_fd(fd).is_err(), "fd {fd} was accepted");
+        }
+        let (_reader, writer) = std::io::pipe().unwrap();
+        let fd = writer.into_raw_fd();
+        // SAFETY: `fd` was just released by `writer` and nothing else uses it.
+        unsafe { libc::close(fd) };
+        assert!(open_fd(fd).is_err());
+    }
+
+    #[test]
+    fn writes_to_a_duplicate_leaving_the_fd_open() {
+        let (mut reader, mut writer) = std::io::pipe().unwrap();
+        open_fd(writer.as_raw_fd())
+            .unwrap()
+            .write_all(b"a")
+            .unwrap();
+        // The duplicate is closed by now, the original must still work.
+        writer.write_all(b"b").unwrap();
+        drop(writer);
+        let mut written = String::new();
+        reader.read_to_string(&mut written).unwrap();
+        assert_eq!(written, "ab");
+    }
+}
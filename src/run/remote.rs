@@ -0,0 +1,280 @@
+//! Running the `x` invocations on a remote build machine over SSH, see `ssh_host` in the config,
+//! so that a laptop can drive a run executed on a powerful server.
+//!
+//! The remote machine needs its own checkout of rust-lang/rust at `ssh_repo_path`, ideally at the
+//! same commit as the local one. Before each `x test`, the tests about to be run are copied there
+//! along with their snapshots with `rsync`, and the snapshots blessed by `x` are copied back once
+//! it is done. Everything else (editing, reporting, committing, ...) happens locally, and the
+//! remote checkout is only scratch space: it is left with whatever edit was tried last.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use miette::{bail, Context, IntoDiagnostic, Result};
+use tokio::process::Command;
+use tracing::*;
+
+use super::{paths, snapshots};
+use crate::config::Config;
+use crate::git;
+
+#[derive(Debug)]
+struct Remote {
+    host: String,
+    repo_path: String,
+}
+
+static REMOTE: Mutex<Option<Arc<Remote>>> = Mutex::new(None);
+
+/// Environment variables which only make sense on this machine: per-worker scratch directories
+/// and the jobserver, whose file descriptors can't be handed over SSH.
+const LOCAL_ONLY_ENV: &[&str] = &["TMPDIR", "CARGO_MAKEFLAGS", "MAKEFLAGS", "MFLAGS"];
+
+/// Pick up the remote machine from `config`, if any.
+pub(super) fn configure(config: &Config) {
+    let remote = match (&config.ssh_host, &config.ssh_repo_path) {
+        (Some(host), Some(repo_path)) => Some(Remote {
+            host: host.clone(),
+            repo_path: repo_path.clone(),
+        }),
+        _ => None,
+    };
+    if let Some(remote) = &remote {
+        info!("running `x` on `{}` in `{}`", remote.host, remote.repo_path);
+    }
+    *REMOTE.lock().unwrap() = remote.map(Arc::new);
+}
+
+fn remote() -> Option<Arc<Remote>> {
+    REMOTE.lock().unwrap().clone()
+}
+
+/// Make sure the remote machine is reachable and has a checkout at `ssh_repo_path`.
+pub(super) fn preflight(config: &Config, rustc_repo_path: &Path) -> Result<()> {
+    let Some(host) = &config.ssh_host else {
+        return Ok(());
+    };
+    let Some(repo_path) = &config.ssh_repo_path else {
+        bail!("`ssh_host` needs `ssh_repo_path` to be set too");
+    };
+    if !config.worktrees.is_empty() {
+        bail!("`worktrees` are not supported with `ssh_host`");
+    }
+    if config.memory_limit_mib.is_some() {
+        bail!("`memory_limit_mib` is not supported with `ssh_host`");
+    }
+    if config.niceness.is_some() {
+        warn!("`niceness` only applies to `ssh` on this machine, not to `x` on `{host}`");
+    }
+    if std::process::Command::new("rsync")
+        .arg("--version")
+        .output()
+        .is_err()
+    {
+        bail!("`rsync` is needed to copy tests to `{host}`");
+    }
+
+    let output = std::process::Command::new("ssh")
+        .args(["-o", "BatchMode=yes", "--", host])
+        .arg(format!(
+            "test -e {} && git -C {} rev-parse HEAD",
+            quote(&format!("{repo_path}/x.py")),
+            quote(repo_path)
+        ))
+        .output()
+        .into_diagnostic()
+        .wrap_err("failed to invoke `ssh`")?;
+    if !output.status.success() {
+        bail!(
+            "`{repo_path}` on `{host}` is not reachable or not a `rustc` repo: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let remote_commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if let Some(local_commit) = git::head_commit(rustc_repo_path) {
+        if remote_commit != local_commit {
+            warn!(
+                "`{repo_path}` on `{host}` is at `{remote_commit}`, not at `{local_commit}` like \
+                 the local checkout"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// `command` (an `x` invocation in the `rustc` repo) run over SSH in the remote checkout, or
+/// `command` itself if no remote machine is configured.
+pub(super) fn wrap(command: Command) -> Command {
+    match remote() {
+        Some(remote) => over_ssh(&remote, &command),
+        None => command,
+    }
+}
+
+/// `ssh` running `command` in the checkout of `remote`, with the environment it sets.
+fn over_ssh(remote: &Remote, command: &Command) -> Command {
+    let original = command.as_std();
+    let mut words = vec![
+        "cd".to_string(),
+        quote(&remote.repo_path),
+        "&&".to_string(),
+        "exec".to_string(),
+        "env".to_string(),
+    ];
+    for (key, value) in original.get_envs() {
+        let key = key.to_string_lossy();
+        let Some(value) = value else {
+            continue;
+        };
+        if LOCAL_ONLY_ENV.contains(&&*key) {
+            continue;
+        }
+        words.push(quote(&format!("{key}={}", value.to_string_lossy())));
+    }
+    words.push(quote(&original.get_program().to_string_lossy()));
+    words.extend(original.get_args().map(|arg| quote(&arg.to_string_lossy())));
+
+    let mut ssh = Command::new("ssh");
+    ssh.args(["-o", "BatchMode=yes", "--", &remote.host])
+        .arg(words.join(" "));
+    ssh
+}
+
+/// Copy `targets` and their snapshots to the remote checkout, removing snapshots which don't
+/// exist locally.
+pub(super) async fn push(rustc_repo_path: &Path, targets: &[&Path]) -> Result<()> {
+    sync(rustc_repo_path, targets, Direction::Push).await
+}
+
+/// Copy the snapshots of `targets` blessed on the remote machine back.
+pub(super) async fn pull(rustc_repo_path: &Path, targets: &[&Path]) -> Result<()> {
+    sync(rustc_repo_path, targets, Direction::Pull).await
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Direction {
+    Push,
+    Pull,
+}
+
+/// Mirror the files of `targets` sharing their name up to the first `.` (the test and its
+/// snapshots), and the other companions of `tests/mir-opt` tests (see
+/// [`super::snapshots::prefixes`]), one `rsync` per directory.
+async fn sync(rustc_repo_path: &Path, targets: &[&Path], direction: Direction) -> Result<()> {
+    let Some(remote) = remote() else {
+        return Ok(());
+    };
+    for (dir, prefixes) in sync_prefixes(rustc_repo_path, targets) {
+        let local = format!("{}/", rustc_repo_path.join(&dir).display());
+        let remote_dir = format!("{}:{}/{dir}/", remote.host, remote.repo_path);
+        let (from, to) = match direction {
+            Direction::Push => (&local, &remote_dir),
+            Direction::Pull => (&remote_dir, &local),
+        };
+        let mut rsync = Command::new("rsync");
+        rsync.args(["--archive", "--delete", "--protect-args"]);
+        for prefix in &prefixes {
+            rsync.arg(format!("--include={prefix}*"));
+        }
+        let output = rsync
+            .arg("--exclude=*")
+            .arg(from)
+            .arg(to)
+            .output()
+            .await
+            .into_diagnostic()
+            .wrap_err("failed to invoke `rsync`")?;
+        if !output.status.success() {
+            bail!(
+                "failed to copy `{dir}` from `{from}` to `{to}`: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The prefixes of the file names to mirror for `targets`, by repo-relative directory.
+fn sync_prefixes(rustc_repo_path: &Path, targets: &[&Path]) -> BTreeMap<String, BTreeSet<String>> {
+    let mut prefixes: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for target in targets {
+        let rel_path = paths::repo_relative(rustc_repo_path, target);
+        let (dir, file_name) = rel_path.rsplit_once('/').unwrap_or(("", &rel_path));
+        let stem = file_name.split('.').next().unwrap_or_default();
+        let dir_prefixes = prefixes.entry(dir.to_string()).or_default();
+        dir_prefixes.insert(format!("{stem}."));
+        dir_prefixes.extend(snapshots::prefixes(target));
+    }
+    prefixes
+}
+
+/// `word` quoted for a POSIX shell.
+fn quote(word: &str) -> String {
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:@,+".contains(c))
+    {
+        return word.to_string();
+    }
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_x_in_the_remote_checkout_with_its_environment() {
+        let remote = Remote {
+            host: "builder".to_string(),
+            repo_path: "/home/me/rust".to_string(),
+        };
+        let mut command = Command::new("./x");
+        command
+            .args(["test", "tests/ui/it's.rs"])
+            .env("RUSTC_LOG", "debug")
+            .env("TMPDIR", "/tmp/worker-1");
+        let ssh = over_ssh(&remote, &command);
+        let ssh = ssh.as_std();
+        assert_eq!(ssh.get_program(), "ssh");
+        assert_eq!(
+            ssh.get_args().collect::<Vec<_>>(),
+            [
+                "-o",
+                "BatchMode=yes",
+                "--",
+                "builder",
+                r"cd /home/me/rust && exec env RUSTC_LOG=debug ./x test 'tests/ui/it'\''s.rs'",
+            ]
+        );
+        assert_eq!(quote(""), "''");
+    }
+
+    #[test]
+    fn mirrors_tests_with_their_snapshots() {
+        let repo = Path::new("/rust");
+        let prefixes = sync_prefixes(
+            repo,
+            &[
+                Path::new("/rust/tests/ui/a.rs"),
+                Path::new("/rust/tests/ui/b.rs"),
+                Path::new("/rust/tests/codegen/c.rs"),
+            ],
+        );
+        assert_eq!(
+            prefixes,
+            BTreeMap::from([
+                (
+                    "tests/codegen".to_string(),
+                    BTreeSet::from(["c.".to_string()])
+                ),
+                (
+                    "tests/ui".to_string(),
+                    BTreeSet::from(["a.".to_string(), "b.".to_string()])
+                ),
+            ])
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! Cache backends shared between machines, see `remote_cache` in the config.
+//!
+//! Objects are stored under flat names, either as files in a directory (e.g. on a network
+//! filesystem) or behind an HTTP server accepting `GET` and `PUT` on `<url>/<name>`, like the
+//! HTTP caches of other build tools.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use miette::{Context, IntoDiagnostic, Result};
+
+#[derive(Debug)]
+pub(super) enum RemoteCache {
+    Dir(PathBuf),
+    Http {
+        base_url: String,
+        agent: ureq::Agent,
+    },
+}
+
+impl RemoteCache {
+    /// `location` is an `http://` or `https://` URL, or a directory path.
+    pub(super) fn new(location: &str) -> Self {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            let agent = ureq::Agent::config_builder()
+                .timeout_global(Some(Duration::from_secs(30)))
+                .build()
+                .into();
+            RemoteCache::Http {
+                base_url: location.trim_end_matches('/').to_string(),
+                agent,
+            }
+        } else {
+            RemoteCache::Dir(PathBuf::from(location))
+        }
+    }
+
+    /// The object called `name`, or `None` if there is no such object.
+    pub(super) fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        match self {
+            RemoteCache::Dir(dir) => {
+                let path = dir.join(name);
+                match std::fs::read(&path) {
+                    Ok(contents) => Ok(Some(contents)),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(e)
+                        .into_diagnostic()
+                        .wrap_err(format!("failed to read `{}`", path.display())),
+                }
+            }
+            RemoteCache::Http { base_url, agent } => {
+                let url = format!("{base_url}/{name}");
+                match agent.get(&url).call() {
+                    Ok(mut response) => response
+                        .body_mut()
+                        .read_to_vec()
+                        .map(Some)
+                        .into_diagnostic()
+                        .wrap_err(format!("failed to download `{url}`")),
+                    Err(ureq::Error::StatusCode(404)) => Ok(None),
+                    Err(e) => Err(e)
+                        .into_diagnostic()
+                        .wrap_err(format!("failed to download `{url}`")),
+                }
+            }
+        }
+    }
+
+    /// Store `contents` as the object called `name`, replacing any previous version.
+    pub(super) fn put(&self, name: &str, contents: &[u8]) -> Result<()> {
+        match self {
+            RemoteCache::Dir(dir) => {
+                let path = dir.join(name);
+                // Write under a unique name first so that concurrent readers never see a partial
+                // object.
+                let tmp = dir.join(format!(".{name}.{}.tmp", std::process::id()));
+                std::fs::create_dir_all(dir)
+                    .and_then(|()| std::fs::write(&tmp, contents))
+                    .and_then(|()| std::fs::rename(&tmp, &path))
+                    .into_diagnostic()
+                    .wrap_err(format!("failed to write `{}`", path.display()))
+            }
+            RemoteCache::Http { base_url, agent } => {
+                let url = format!("{base_url}/{name}");
+                agent
+                    .put(&url)
+                    .send(contents)
+                    .map(drop)
+                    .into_diagnostic()
+                    .wrap_err(format!("failed to upload `{url}`"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run::tests::Repo;
+
+    #[test]
+    fn directories_store_objects_under_their_name() {
+        let repo = Repo::new("remote-cache");
+        let dir = repo.0.join("cache");
+        let cache = RemoteCache::new(dir.to_str().unwrap());
+        assert!(matches!(&cache, RemoteCache::Dir(path) if *path == dir));
+
+        assert_eq!(cache.get("outcome-a.json").unwrap(), None);
+        cache.put("outcome-a.json", b"first").unwrap();
+        cache.put("outcome-a.json", b"second").unwrap();
+        assert_eq!(
+            cache.get("outcome-a.json").unwrap().as_deref(),
+            Some(&b"second"[..])
+        );
+        assert_eq!(cache.get("outcome-b.json").unwrap(), None);
+        // No temporary file is left behind.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn urls_are_http_caches() {
+        let cache = RemoteCache::new("https://cache.example.com/rustc/");
+        assert!(matches!(
+            cache,
+            RemoteCache::Http { base_url, .. } if base_url == "https://cache.example.com/rustc"
+        ));
+    }
+}
@@ -0,0 +1,431 @@
+//! Markdown report written at the end of a run.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::path::Path;
+
+use super::directives::Syntax;
+use super::manifest::Manifest;
+use super::scan::SkipReason;
+use super::snapshots::SnapshotChange;
+use super::state::RunState;
+use super::{directives, rules, test_logs};
+use super::{LineChange, RunOutcome, TestRecord, WorktreeResult};
+use crate::config::Config;
+
+pub fn format_report(config: &Config, state: &RunState) -> String {
+    let records = &state.records;
+    let links = SourceLinks::new(config, state);
+    let rules = rules::resolve(config);
+
+    let mut out = String::new();
+    writeln!(out, "# `ignore-debug` reduction summary\n").unwrap();
+    if let Some(commit) = &state.manifest.rustc_commit {
+        writeln!(out, "Run against `rustc` repo commit `{commit}`.\n").unwrap();
+    }
+    for outcome in RunOutcome::ALL {
+        let count = records.values().filter(|r| r.outcome == outcome).count();
+        writeln!(out, "- {outcome:?}: {count}").unwrap();
+    }
+    format_regressions(&mut out, &links, &state.regressions);
+    for outcome in RunOutcome::ALL {
+        writeln!(out, "\n## {}\n", outcome.description()).unwrap();
+        for (path, record) in records.iter().filter(|(_, r)| r.outcome == outcome) {
+            let link = links.render(path, record.changes.first().map(|c| c.line));
+            let mut note = match &record.duplicate_of {
+                Some(representative) => format!(" (identical to `{representative}`)"),
+                None => String::new(),
+            };
+            match rules::count_directives(&rules, &record.changes, Syntax::of(Path::new(path))) {
+                (0 | 1, _) => {}
+                (count, 0) => note.push_str(&format!(" ({count} directives)")),
+                (count, duplicates) => {
+                    note.push_str(&format!(" ({count} directives, {duplicates} duplicate(s))"))
+                }
+            }
+            match &record.review_hint {
+                Some(hint) => writeln!(out, "- {link}{note}: {hint}").unwrap(),
+                None => writeln!(out, "- {link}{note}").unwrap(),
+            }
+            format_reasons(&mut out, &record.changes, Syntax::of(Path::new(path)));
+            format_changes(&mut out, &record.changes);
+            if let Some(logs) = &state.logs {
+                let log = test_logs::path_of(logs, path);
+                if log.is_file() {
+                    writeln!(out, "  - log: `{}`", log.display()).unwrap();
+                }
+            }
+            if let Some(bisection) = state.bisections.get(path) {
+                writeln!(out, "  - bisection: {bisection}").unwrap();
+            }
+        }
+    }
+    format_pending(&mut out, &state.pending);
+    format_contradictions(&mut out, &links, &state.contradictions);
+    format_worktrees(&mut out, config, records);
+    format_snapshot_changes(&mut out, records, config.snapshot_outlier_lines);
+    format_skipped(&mut out, &state.skipped);
+    format_manifest(&mut out, &state.manifest);
+    out
+}
+
+/// Short Markdown summary of a run for the tracking issue, see [`super::tracking_issue`].
+pub(super) fn format_status_comment(state: &RunState) -> String {
+    let records = &state.records;
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "### `ignore-debug` reduction status
+"
+    )
+    .unwrap();
+    let status = state
+        .status
+        .map_or_else(|| "unfinished".to_string(), |s| format!("{s:?}"));
+    write!(out, "Run `{}` ({status})", state.id).unwrap();
+    if let Some(commit) = &state.manifest.rustc_commit {
+        write!(out, " against `{commit}`").unwrap();
+    }
+    if let Some(finished_at) = state.finished_at {
+        write!(
+            out,
+            ", took {}",
+            super::summary::format_duration(
+                (finished_at - state.started_at)
+                    .to_std()
+                    .unwrap_or_default()
+            )
+        )
+        .unwrap();
+    }
+    writeln!(out, ".\n").unwrap();
+    writeln!(out, "| Outcome | Tests |").unwrap();
+    writeln!(out, "|---|---:|").unwrap();
+    for outcome in RunOutcome::ALL {
+        let count = records.values().filter(|r| r.outcome == outcome).count();
+        writeln!(out, "| {} | {count} |", outcome.description()).unwrap();
+    }
+    writeln!(out, "| **Total** | **{}** |", records.len()).unwrap();
+    if !state.pending.is_empty() {
+        writeln!(
+            out,
+            "\n{} test(s) were not processed and are left for the next run.",
+            state.pending.len()
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Inputs of the run, so that two people can verify they ran the same experiment.
+fn format_manifest(out: &mut String, manifest: &Manifest) {
+    let or_unknown = |v: &Option<String>| v.clone().unwrap_or_else(|| "unknown".to_string());
+    writeln!(out, "\n## Manifest\n").unwrap();
+    writeln!(out, "| | |").unwrap();
+    writeln!(out, "|---|---|").unwrap();
+    writeln!(out, "| Tool version | `{}` |", manifest.tool_version).unwrap();
+    writeln!(
+        out,
+        "| `rustc` commit | `{}` |",
+        or_unknown(&manifest.rustc_commit)
+    )
+    .unwrap();
+    match &manifest.bootstrap_config {
+        Some(path) => writeln!(
+            out,
+            "| Bootstrap config | `{path}` (sha256 `{}`) |",
+            or_unknown(&manifest.bootstrap_config_sha256)
+        ),
+        None => writeln!(out, "| Bootstrap config | none |"),
+    }
+    .unwrap();
+    writeln!(
+        out,
+        "| Target files | {} (sha256 `{}`) |",
+        manifest.target_file_count, manifest.target_files_sha256
+    )
+    .unwrap();
+    writeln!(out, "\n<details>\n<summary>Effective config</summary>\n").unwrap();
+    writeln!(out, "```json").unwrap();
+    writeln!(
+        out,
+        "{}",
+        serde_json::to_string_pretty(&manifest.config).unwrap_or_default()
+    )
+    .unwrap();
+    writeln!(out, "```\n\n</details>").unwrap();
+}
+
+/// Links to test sources on the forge, at the commit the run was performed against.
+struct SourceLinks {
+    /// `<repo url>/blob/<commit>`, or `None` if links are disabled or the commit is unknown.
+    base: Option<String>,
+}
+
+impl SourceLinks {
+    fn new(config: &Config, state: &RunState) -> Self {
+        let repo_url = config.source_link_base.trim_end_matches('/');
+        let base = match &state.manifest.rustc_commit {
+            Some(commit) if !repo_url.is_empty() => Some(format!("{repo_url}/blob/{commit}")),
+            _ => None,
+        };
+        SourceLinks { base }
+    }
+
+    /// The repo-relative `path` as inline code, linked to its source (at `line`, if given) when
+    /// possible.
+    fn render(&self, path: &str, line: Option<usize>) -> String {
+        match &self.base {
+            Some(base) => {
+                let anchor = line.map(|l| format!("#L{l}")).unwrap_or_default();
+                format!("[`{path}`]({base}/{path}{anchor})")
+            }
+            None => format!("`{path}`"),
+        }
+    }
+}
+
+/// The reasons the removed or replaced directives gave (e.g. `ignore-debug: <reason>`), so they
+/// are not lost on reviewers.
+fn format_reasons(out: &mut String, changes: &[LineChange], syntax: Syntax) {
+    let reasons: Vec<String> = changes
+        .iter()
+        .flat_map(|change| directives::parse_with(&change.original, syntax))
+        .filter_map(|directive| rules::directive_reason(&directive).map(str::to_string))
+        .collect();
+    if !reasons.is_empty() {
+        writeln!(out, "\n  Reason given: {}", reasons.join("; ")).unwrap();
+    }
+}
+
+/// Quote the directive lines that were touched as a diff, so the change can be audited without
+/// opening the test.
+fn format_changes(out: &mut String, changes: &[LineChange]) {
+    if changes.is_empty() {
+        return;
+    }
+    writeln!(out, "\n  ```diff").unwrap();
+    for change in changes {
+        writeln!(out, "  @@ line {} @@", change.line).unwrap();
+        writeln!(out, "  -{}", change.original).unwrap();
+        for line in &change.replacement {
+            writeln!(out, "  +{line}").unwrap();
+        }
+    }
+    writeln!(out, "  ```").unwrap();
+}
+
+/// Per blessed test, how much its snapshots changed, largest first, so reviewers can prioritize
+/// the tests whose expected output shifted significantly.
+fn format_snapshot_changes(
+    out: &mut String,
+    records: &BTreeMap<String, TestRecord>,
+    outlier_lines: usize,
+) {
+    let mut blessed: Vec<(&String, &TestRecord, usize)> = records
+        .iter()
+        .filter(|(_, r)| !r.snapshot_changes.is_empty())
+        .map(|(path, r)| {
+            let total = r.snapshot_changes.iter().map(|c| c.changed_lines()).sum();
+            (path, r, total)
+        })
+        .collect();
+    blessed.sort_by(|(pa, _, a), (pb, _, b)| b.cmp(a).then_with(|| pa.cmp(pb)));
+
+    writeln!(out, "\n## Snapshot changes\n").unwrap();
+    if blessed.is_empty() {
+        writeln!(out, "No snapshots were changed.").unwrap();
+        return;
+    }
+    let outliers = blessed
+        .iter()
+        .filter(|(_, _, t)| *t > outlier_lines)
+        .count();
+    writeln!(
+        out,
+        "{} blessed test(s), {outliers} outlier(s) with more than {outlier_lines} changed \
+         line(s).\n",
+        blessed.len()
+    )
+    .unwrap();
+    let mir_dumps: Vec<&SnapshotChange> = blessed
+        .iter()
+        .flat_map(|(_, r, _)| &r.snapshot_changes)
+        .filter(|c| c.is_mir_dump())
+        .collect();
+    if !mir_dumps.is_empty() {
+        let added: usize = mir_dumps.iter().map(|c| c.added_lines).sum();
+        let removed: usize = mir_dumps.iter().map(|c| c.removed_lines).sum();
+        writeln!(
+            out,
+            "Of these snapshots, {} are MIR dumps of `tests/mir-opt`, with +{added} -{removed} \
+             line(s) in total.\n",
+            mir_dumps.len()
+        )
+        .unwrap();
+    }
+    writeln!(out, "| Test | Snapshot | Added | Removed |").unwrap();
+    writeln!(out, "|---|---|---:|---:|").unwrap();
+    for (path, record, total) in blessed {
+        let marker = if total > outlier_lines {
+            " ⚠️ outlier"
+        } else {
+            ""
+        };
+        for change in &record.snapshot_changes {
+            writeln!(
+                out,
+                "| `{path}`{marker} | `{}` | +{} | -{} |",
+                change.path, change.added_lines, change.removed_lines
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// Tests whose header already contradicts itself about debug assertions, whether or not anything
+/// was attempted on them, as they need fixing by hand either way.
+fn format_contradictions(
+    out: &mut String,
+    links: &SourceLinks,
+    contradictions: &BTreeMap<String, Vec<String>>,
+) {
+    if contradictions.is_empty() {
+        return;
+    }
+    writeln!(out, "\n## Contradictory headers\n").unwrap();
+    writeln!(
+        out,
+        "These tests' headers are inconsistent with respect to debug assertions.\n"
+    )
+    .unwrap();
+    for (path, found) in contradictions {
+        writeln!(out, "- {}: {}", links.render(path, None), found.join("; ")).unwrap();
+    }
+}
+
+/// Candidates left out because the run was interrupted or ran out of time.
+fn format_regressions(
+    out: &mut String,
+    links: &SourceLinks,
+    regressions: &BTreeMap<String, String>,
+) {
+    if regressions.is_empty() {
+        return;
+    }
+    writeln!(out, "\n## Regressions\n").unwrap();
+    writeln!(
+        out,
+        "These tests fail without their directive, although a previous run found them not to need \
+         it: changes since (e.g. a rebase) likely made them depend on debug assertions again.\n"
+    )
+    .unwrap();
+    for (path, run) in regressions {
+        writeln!(
+            out,
+            "- {} (verified by run `{run}`)",
+            links.render(path, None)
+        )
+        .unwrap();
+    }
+}
+
+fn format_pending(out: &mut String, pending: &BTreeSet<String>) {
+    if pending.is_empty() {
+        return;
+    }
+    writeln!(out, "\n## Not processed\n").unwrap();
+    writeln!(
+        out,
+        "The run stopped before these tests were processed. Running again resumes with them, \
+         reusing the outcomes above from the cache.\n"
+    )
+    .unwrap();
+    for path in pending {
+        writeln!(out, "- `{path}`").unwrap();
+    }
+}
+
+/// Matrix of how each test fared in the main checkout and in each of the `worktrees`, for the last
+/// edit tried. Worktrees are only tried once the edit passes in the main checkout.
+fn format_worktrees(out: &mut String, config: &Config, records: &BTreeMap<String, TestRecord>) {
+    if config.worktrees.is_empty() {
+        return;
+    }
+    writeln!(
+        out,
+        "
+## Worktrees
+"
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "Changes are only kept if they pass in every worktree. \"—\" means the test was not run \
+         in the worktree, e.g. because the edit already failed in the main checkout.\n"
+    )
+    .unwrap();
+    let names: Vec<String> = config
+        .worktrees
+        .iter()
+        .map(|w| w.display().to_string())
+        .collect();
+    write!(out, "| Test | Outcome |").unwrap();
+    for name in &names {
+        write!(out, " `{name}` |").unwrap();
+    }
+    writeln!(out, "\n|---|---|{}", "---|".repeat(names.len())).unwrap();
+    for (path, record) in records {
+        write!(out, "| `{path}` | {:?} |", record.outcome).unwrap();
+        for name in &names {
+            let cell = match record.worktrees.get(name) {
+                Some(WorktreeResult::Passed) => "✅",
+                Some(WorktreeResult::Failed) => "❌",
+                Some(WorktreeResult::Ignored) => "ignored",
+                Some(WorktreeResult::Missing) => "missing",
+                None => "—",
+            };
+            write!(out, " {cell} |").unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+}
+
+/// Appendix listing every file that was not processed, grouped by reason. Each group is
+/// collapsed since e.g. snapshot files vastly outnumber the actual candidates.
+fn format_skipped(out: &mut String, skipped: &BTreeMap<String, SkipReason>) {
+    let mut by_reason: BTreeMap<&'static str, Vec<(&String, &SkipReason)>> = BTreeMap::new();
+    for (path, reason) in skipped {
+        by_reason
+            .entry(reason.heading())
+            .or_default()
+            .push((path, reason));
+    }
+
+    writeln!(out, "\n## Skipped\n").unwrap();
+    if by_reason.is_empty() {
+        writeln!(out, "No files were skipped.").unwrap();
+        return;
+    }
+    for (heading, files) in by_reason {
+        writeln!(
+            out,
+            "<details>\n<summary>{heading} ({})</summary>\n",
+            files.len()
+        )
+        .unwrap();
+        for (path, reason) in files {
+            match reason {
+                SkipReason::Unreadable(e) => writeln!(out, "- `{}`: {e}", path),
+                SkipReason::Unchanged(run) | SkipReason::AlreadyMigrated(run) => {
+                    writeln!(out, "- `{}`: run `{run}`", path)
+                }
+                SkipReason::SameFileAs(kept) => writeln!(out, "- `{}`: `{kept}`", path),
+                _ => writeln!(out, "- `{}`", path),
+            }
+            .unwrap();
+        }
+        writeln!(out, "\n</details>\n").unwrap();
+    }
+}
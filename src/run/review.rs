@@ -0,0 +1,181 @@
+//! Heuristics for when automation should decline to decide and leave a test for a human, along
+//! with a short hint explaining why.
+
+use std::ops::Range;
+
+use super::directives::{Directive, Syntax};
+use super::snapshots::SnapshotChange;
+use super::{directives, paths, suites};
+use crate::config::Config;
+
+/// Why a test is left for a human.
+#[derive(Debug, Clone)]
+pub(super) struct Flag {
+    pub(super) hint: String,
+    /// The line of the test prompting it, if it comes down to one, with a label for it.
+    pub(super) line: Option<(Range<usize>, &'static str)>,
+}
+
+impl Flag {
+    fn new(hint: String) -> Self {
+        Flag { hint, line: None }
+    }
+}
+
+/// The test already sets `-Cdebug-assertions` itself, so removing or replacing `ignore-debug`
+/// would interact with an explicit choice of the test author.
+pub(super) fn conflicting_flags(src: &str) -> Option<Flag> {
+    let directive = directives::parse(src).into_iter().find(|d| {
+        d.name == "compile-flags"
+            && d.value.is_some_and(|flags| {
+                flags.contains("-Cdebug-assertions") || flags.contains("-C debug-assertions")
+            })
+    })?;
+    Some(Flag {
+        hint: format!(
+            "the test already sets debug assertions explicitly (`{}`), which conflicts with \
+             `ignore-debug`",
+            src[directive.span.clone()].trim()
+        ),
+        line: Some((directive.span, "sets debug assertions explicitly")),
+    })
+}
+
+/// Directives which exclude each other: under `ignore-debug`, a test never runs where
+/// `needs-debug-assertions` would let it.
+const CONTRADICTORY: [(&str, &str); 1] = [("ignore-debug", "needs-debug-assertions")];
+
+/// Two directives of a test header which contradict each other, see [`contradictions`].
+#[derive(Debug, Clone)]
+pub(super) struct Contradiction {
+    /// What is wrong, quoting both directives.
+    pub(super) description: String,
+    /// The lines of both directives.
+    pub(super) spans: [Range<usize>; 2],
+}
+
+/// Ways the header of a test is already inconsistent with respect to debug assertions, found
+/// regardless of whether anything is attempted on it, e.g. `ignore-debug` along with
+/// `-Cdebug-assertions=yes`, or both `-Cdebug-assertions=yes` and `=no` on the same revisions.
+pub(super) fn contradictions(src: &str, syntax: Syntax) -> Vec<Contradiction> {
+    let directives = directives::parse_with(src, syntax);
+    let overlapping = |a: &Directive<'_>, b: &Directive<'_>| {
+        a.revisions.is_empty()
+            || b.revisions.is_empty()
+            || a.revisions.iter().any(|r| b.revisions.contains(r))
+    };
+    let mut found = Vec::new();
+    for (i, a) in directives.iter().enumerate() {
+        for b in &directives[i + 1..] {
+            if !overlapping(a, b) {
+                continue;
+            }
+            let (a_line, b_line) = (src[a.span.clone()].trim(), src[b.span.clone()].trim());
+            let mut push = |description| {
+                found.push(Contradiction {
+                    description,
+                    spans: [a.span.clone(), b.span.clone()],
+                })
+            };
+            if CONTRADICTORY
+                .iter()
+                .any(|&(x, y)| (a.name, b.name) == (x, y) || (a.name, b.name) == (y, x))
+            {
+                push(format!("`{a_line}` contradicts `{b_line}`"));
+                continue;
+            }
+            match (debug_assertions_flag(a), debug_assertions_flag(b)) {
+                (Some(x), Some(y)) if x != y => push(format!("`{a_line}` contradicts `{b_line}`")),
+                (Some(true), None) if b.name == "ignore-debug" => {
+                    push(format!("`{b_line}` along with `{a_line}`"));
+                }
+                (None, Some(true)) if a.name == "ignore-debug" => {
+                    push(format!("`{a_line}` along with `{b_line}`"));
+                }
+                _ => {}
+            }
+        }
+    }
+    found
+}
+
+/// Whether the `compile-flags` of `directive` enable or disable debug assertions, the last flag
+/// winning.
+fn debug_assertions_flag(directive: &Directive<'_>) -> Option<bool> {
+    if directive.name != "compile-flags" {
+        return None;
+    }
+    let flags: Vec<&str> = directive.value?.split_whitespace().collect();
+    let mut enabled = None;
+    for (i, flag) in flags.iter().enumerate() {
+        let setting = match flag.strip_prefix("-C") {
+            Some("") => flags.get(i + 1).copied().unwrap_or_default(),
+            Some(setting) => setting,
+            None => continue,
+        };
+        let Some(value) = setting.strip_prefix("debug-assertions") else {
+            continue;
+        };
+        enabled = match value.strip_prefix('=') {
+            None if value.is_empty() => Some(true),
+            Some("yes" | "y" | "on" | "true") => Some(true),
+            Some("no" | "n" | "off" | "false") => Some(false),
+            _ => enabled,
+        };
+    }
+    enabled
+}
+
+/// The test passed, but `--bless` rewrote its snapshots substantially, so the new expected
+/// output needs to be looked at.
+pub(super) fn huge_snapshot_diff(changes: &[SnapshotChange], threshold: usize) -> Option<String> {
+    let total: usize = changes.iter().map(SnapshotChange::changed_lines).sum();
+    (total > threshold).then(|| {
+        format!(
+            "the test passes but `--bless` changed {total} snapshot line(s) (more than \
+             {threshold}), the new expected output needs review"
+        )
+    })
+}
+
+/// The test uses FileCheck, where a pass after removing `ignore-debug` may be vacuous (e.g.
+/// `CHECK-NOT` lines matching nothing) or the `CHECK` lines may need adjusting for debug
+/// assertions. Such tests are left for a human without being run, unless `try_filecheck_tests` is
+/// set in the config.
+pub(super) fn filecheck_sensitive(config: &Config, rel_path: &str, src: &str) -> Option<Flag> {
+    if config.try_filecheck_tests {
+        return None;
+    }
+    let mut offset = 0;
+    let mut check_lines = Vec::new();
+    for line in src.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        let line = line.trim_end_matches(['\r', '\n']);
+        let is_check = line
+            .trim_start()
+            .strip_prefix("//")
+            .map(str::trim_start)
+            .is_some_and(|l| l.starts_with("CHECK"));
+        if is_check {
+            check_lines.push(start..start + line.len());
+        }
+    }
+    if let Some(first) = check_lines.first() {
+        return Some(Flag {
+            hint: format!(
+                "the test has {} FileCheck `CHECK` line(s) which may need adjusting for debug \
+                 assertions, set `try_filecheck_tests` to try it anyway",
+                check_lines.len()
+            ),
+            line: Some((first.clone(), "checked with FileCheck")),
+        });
+    }
+    let suite = paths::suite_of(rel_path);
+    suites::profile(config, suite).filecheck.then(|| {
+        Flag::new(format!(
+            "`{suite}` tests are checked with FileCheck, whose patterns may need adjusting for \
+             debug assertions, set `try_filecheck_tests` to try it anyway"
+        ))
+    })
+}
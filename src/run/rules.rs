@@ -0,0 +1,408 @@
+//! Rewrite rules: which directives to rewrite and how, see `rules` in the config.
+//!
+//! The rules are tried on each candidate in order, every rule as one edit of the test, and the
+//! first one under which the test passes is kept. Without any rules in the config, the built-in
+//! ones of the selected [`Ruleset`] are used, by default removing `ignore-debug`, then replacing it
+//! with `compile-flags: -Cdebug-assertions=no`.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::directives::{self, Directive, Syntax};
+use super::edit::{self, Edit, LineChange};
+use super::{RunOutcome, TestRecord};
+use crate::config::Config;
+
+/// A rewrite rule of the config.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Name recorded for the tests the rule was kept for, e.g. `remove`.
+    pub name: String,
+    /// The directives the rule rewrites.
+    #[serde(rename = "match")]
+    pub matcher: Match,
+    /// What to do with each matching directive.
+    pub action: Action,
+    /// Keep the reason given by a replaced directive (e.g. `ignore-debug: <reason>`) as a comment
+    /// above what replaces it, see `keep_directive_reasons` in the config.
+    #[serde(default)]
+    pub keep_reason: bool,
+}
+
+/// The directives a [`Rule`] applies to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Match {
+    /// Name of the directive, e.g. `ignore-debug`.
+    pub directive: String,
+    /// Pattern the value of the directive (e.g. the reason of an `ignore-*` directive) has to
+    /// match, where `*` matches anything. Any value matches if unset.
+    #[serde(default)]
+    pub value: Option<String>,
+}
+
+/// What a [`Rule`] does with the directives it matches.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// Drop the directive.
+    Remove,
+    /// Replace the directive by another one on the same revisions, e.g.
+    /// `compile-flags: -Cdebug-assertions=no`.
+    ReplaceWith(String),
+    /// Drop the directive and add a flag to the `compile-flags` of the same revisions, adding a
+    /// `compile-flags` directive in its place if there is none.
+    AppendFlag(String),
+}
+
+/// A built-in set of rules for a directive cleanup, see `--ruleset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Ruleset {
+    /// Remove `ignore-debug`, or replace it with `compile-flags: -Cdebug-assertions=no`.
+    #[default]
+    IgnoreDebug,
+    /// Audit `ignore-test`: remove it from the tests which pass again.
+    IgnoreTest,
+    /// Replace `ignore-<target>` directives (`wasm32`, `wasm`, `emscripten`) whose reason names a
+    /// missing capability with the matching `needs-*` directive (`needs-threads`,
+    /// `needs-subprocess`, `needs-unwind`).
+    IgnoreTarget,
+}
+
+impl Ruleset {
+    /// The rules of the set, in the order they are tried.
+    pub fn rules(self) -> Vec<Rule> {
+        match self {
+            Ruleset::IgnoreDebug => default_rules(),
+            Ruleset::IgnoreTest => vec![Rule {
+                name: "remove".to_string(),
+                matcher: Match {
+                    directive: "ignore-test".to_string(),
+                    value: None,
+                },
+                action: Action::Remove,
+                keep_reason: false,
+            }],
+            Ruleset::IgnoreTarget => {
+                const CAPABILITIES: [(&str, &str); 6] = [
+                    ("*thread*", "needs-threads"),
+                    ("*process*", "needs-subprocess"),
+                    ("*spawn*", "needs-subprocess"),
+                    ("*unwind*", "needs-unwind"),
+                    ("*panic*", "needs-unwind"),
+                    ("*abort*", "needs-unwind"),
+                ];
+                let mut rules = Vec::new();
+                for target in ["wasm32", "wasm", "emscripten"] {
+                    for (reason, needs) in CAPABILITIES {
+                        rules.push(Rule {
+                            name: format!("{target}-to-{needs}"),
+                            matcher: Match {
+                                directive: format!("ignore-{target}"),
+                                value: Some(reason.to_string()),
+                            },
+                            action: Action::ReplaceWith(needs.to_string()),
+                            keep_reason: false,
+                        });
+                    }
+                }
+                rules
+            }
+        }
+    }
+}
+
+/// The rules applied when the config has none and selects no other [`Ruleset`].
+pub fn default_rules() -> Vec<Rule> {
+    let ignore_debug = Match {
+        directive: "ignore-debug".to_string(),
+        value: None,
+    };
+    vec![
+        Rule {
+            name: "remove".to_string(),
+            matcher: ignore_debug.clone(),
+            action: Action::Remove,
+            keep_reason: false,
+        },
+        Rule {
+            name: "replace".to_string(),
+            matcher: ignore_debug,
+            action: Action::ReplaceWith("compile-flags: -Cdebug-assertions=no".to_string()),
+            keep_reason: false,
+        },
+    ]
+}
+
+/// The rules of `config`, or the ones of its `ruleset`.
+pub(super) fn resolve(config: &Config) -> Vec<Rule> {
+    let mut rules = if config.rules.is_empty() {
+        config.ruleset.rules()
+    } else {
+        config.rules.clone()
+    };
+    if config.keep_directive_reasons {
+        for rule in &mut rules {
+            rule.keep_reason = true;
+        }
+    }
+    rules
+}
+
+/// Whether any of `rules` applies to the test `src`.
+pub(super) fn applies(rules: &[Rule], src: &str, syntax: Syntax) -> bool {
+    let directives = directives::parse_with(src, syntax);
+    rules
+        .iter()
+        .any(|rule| directives.iter().any(|d| rule.matches(d)))
+}
+
+/// The rule `record` was kept with, to redo its edit. Records predating rules map to the default
+/// rules through their outcome.
+pub(super) fn kept_rule<'a>(rules: &'a [Rule], record: &TestRecord) -> Option<&'a Rule> {
+    match &record.rule {
+        Some(name) => rules.iter().find(|rule| rule.name == *name),
+        None => {
+            let name = match record.outcome {
+                RunOutcome::RemoveOk => "remove",
+                RunOutcome::ReplaceOk => "replace",
+                _ => return None,
+            };
+            rules.iter().find(|rule| rule.name == name)
+        }
+    }
+}
+
+impl Rule {
+    /// Whether the rule rewrites `directive`.
+    pub fn matches(&self, directive: &Directive<'_>) -> bool {
+        directive.name == self.matcher.directive
+            && self
+                .matcher
+                .value
+                .as_deref()
+                .is_none_or(|pattern| wildcard_match(pattern, directive.value.unwrap_or_default()))
+    }
+
+    /// The outcome of a test kept with this rule: whether the directive is gone or replaced.
+    pub fn outcome(&self) -> RunOutcome {
+        match self.action {
+            Action::Remove => RunOutcome::RemoveOk,
+            Action::ReplaceWith(_) | Action::AppendFlag(_) => RunOutcome::ReplaceOk,
+        }
+    }
+
+    /// Rewrite every matching directive of `src`, written with `syntax`. The edit has no changes
+    /// if none matches.
+    ///
+    /// Directives added by the rule go after the last directive of the header, in the style of
+    /// that directive, rather than where the matching directive was.
+    pub fn apply(&self, src: &str, syntax: Syntax) -> Edit {
+        let directives = directives::parse_with(src, syntax);
+        let matching: Vec<&Directive<'_>> = directives.iter().filter(|d| self.matches(d)).collect();
+        let mut replacements: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+        for d in &matching {
+            replacements.insert(d.line, Vec::new());
+        }
+        // Reason comments and directives to add after the last directive, with the directive they
+        // stand in for.
+        let mut added: Vec<(&Directive<'_>, Option<String>, Option<String>)> = Vec::new();
+        // Revisions done, as replacing a duplicate again would duplicate the replacement.
+        let mut done: Vec<&[&str]> = Vec::new();
+        for d in &matching {
+            let duplicate = done.contains(&d.revisions.as_slice());
+            if !duplicate {
+                done.push(&d.revisions);
+            }
+            match &self.action {
+                Action::Remove => {}
+                Action::ReplaceWith(directive) => {
+                    added.push((
+                        d,
+                        self.reason_comment(d, syntax),
+                        (!duplicate).then(|| directive.clone()),
+                    ));
+                }
+                Action::AppendFlag(flag) => {
+                    let comment = self.reason_comment(d, syntax);
+                    let flags = directives.iter().find(|f| {
+                        f.name == "compile-flags"
+                            && f.revisions == d.revisions
+                            && !matching.iter().any(|m| m.line == f.line)
+                    });
+                    match flags {
+                        Some(f) => {
+                            let value = f.value.unwrap_or_default().trim();
+                            let lines = replacements
+                                .entry(f.line)
+                                .or_insert_with(|| vec![src[f.span.clone()].to_string()]);
+                            if let Some(comment) = comment {
+                                let comment = format!("{}{comment}", indentation(f.prefix));
+                                lines.insert(lines.len() - 1, comment);
+                            }
+                            if !duplicate && !value.split_whitespace().any(|v| v == flag) {
+                                let separator = if value.is_empty() { "" } else { " " };
+                                *lines.last_mut().unwrap() =
+                                    format!("{} compile-flags: {value}{separator}{flag}", f.prefix);
+                            }
+                        }
+                        None => {
+                            added.push((
+                                d,
+                                comment,
+                                (!duplicate).then(|| format!("compile-flags: {flag}")),
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(last) = directives.last() {
+            let mut lines = Vec::new();
+            for (d, comment, directive) in added {
+                if let Some(comment) = comment {
+                    lines.push(format!("{}{comment}", indentation(last.prefix)));
+                }
+                if let Some(directive) = directive {
+                    lines.push(format!("{} {directive}", insertion_prefix(last, d)));
+                }
+            }
+            if !lines.is_empty() {
+                replacements
+                    .entry(last.line)
+                    .or_insert_with(|| vec![src[last.span.clone()].to_string()])
+                    .extend(lines);
+            }
+        }
+        edit::edit_lines(src, &replacements)
+    }
+
+    /// The comment keeping the reason given by `directive`, if any and if asked to, without
+    /// indentation.
+    fn reason_comment(&self, directive: &Directive<'_>, syntax: Syntax) -> Option<String> {
+        if !self.keep_reason {
+            return None;
+        }
+        let reason = directive_reason(directive)?;
+        let comment = syntax.comment();
+        Some(match directive.name {
+            "ignore-debug" => format!("{comment} (previously ignored in debug: {reason})"),
+            name => format!("{comment} (previously `{name}`: {reason})"),
+        })
+    }
+}
+
+/// The prefix of a directive added in place of `replaced`, after the `last` directive of the
+/// header: the indentation and comment marker of `last`, with the revisions of `replaced`.
+fn insertion_prefix(last: &Directive<'_>, replaced: &Directive<'_>) -> String {
+    let marker = last.marker;
+    match replaced.revisions.as_slice() {
+        [] => format!("{}{marker}", indentation(last.prefix)),
+        revisions => format!(
+            "{}{marker}[{}]",
+            indentation(last.prefix),
+            revisions.join(",")
+        ),
+    }
+}
+
+/// The leading whitespace of `line`.
+fn indentation(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// The reason given by a directive such as `ignore-debug: <reason>` or
+/// `ignore-debug (<reason>)`, if any. Only `ignore-*`, `only-*` and `needs-*` directives give
+/// reasons, the value of other directives means something else.
+pub(super) fn directive_reason<'a>(directive: &Directive<'a>) -> Option<&'a str> {
+    if !["ignore-", "only-", "needs-"]
+        .iter()
+        .any(|kind| directive.name.starts_with(kind))
+    {
+        return None;
+    }
+    let value = directive.value?;
+    Some(
+        value
+            .strip_prefix('(')
+            .and_then(|v| v.strip_suffix(')'))
+            .unwrap_or(value)
+            .trim(),
+    )
+    .filter(|reason| !reason.is_empty())
+}
+
+/// How many directives of the touched lines `changes` any of `rules` matches, and how many of
+/// those repeat a directive on the same revisions, e.g. by mistake.
+pub(super) fn count_directives(
+    rules: &[Rule],
+    changes: &[LineChange],
+    syntax: Syntax,
+) -> (usize, usize) {
+    let mut seen: Vec<(&str, Vec<&str>)> = Vec::new();
+    let mut duplicates = 0;
+    for change in changes {
+        for d in directives::parse_with(&change.original, syntax) {
+            if !rules.iter().any(|rule| rule.matches(&d)) {
+                continue;
+            }
+            let key = (d.name, d.revisions);
+            if seen.contains(&key) {
+                duplicates += 1;
+            } else {
+                seen.push(key);
+            }
+        }
+    }
+    (seen.len() + duplicates, duplicates)
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any (possibly empty) substring.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appends_flags_to_empty_compile_flags_without_a_double_space() {
+        let rule = Rule {
+            name: "append".to_string(),
+            matcher: Match {
+                directive: "ignore-debug".to_string(),
+                value: None,
+            },
+            action: Action::AppendFlag("-Cdebug-assertions=no".to_string()),
+            keep_reason: false,
+        };
+        let edit = rule.apply(
+            "//@ compile-flags:\n//@ ignore-debug\n\nfn main() {}\n",
+            Syntax::RUST,
+        );
+        assert_eq!(
+            edit.src,
+            "//@ compile-flags: -Cdebug-assertions=no\n\nfn main() {}\n"
+        );
+    }
+}
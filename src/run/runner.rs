@@ -0,0 +1,379 @@
+//! How edited tests are run: through bootstrap, or by a fake in unit tests, which can then cover
+//! the strategy pipeline, reverts and reports without a `rustc` checkout.
+//!
+//! Bootstrap invocations can also be recorded (see `--record`) and later replayed from the
+//! recording instead of running `x` (see `--replay`). Each invocation is stored as
+//! `<key>.json`, keyed by its arguments and the contents of the tests it runs, along with the
+//! snapshots of those tests as left by `--bless`.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+use futures_util::future::BoxFuture;
+use miette::{miette, Context, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::*;
+
+use super::manifest::sha256_hex;
+use super::{compiletest, paths, process, remote, snapshots, suites, write_guard, x_test};
+use crate::config::Config;
+
+/// Runs tests with `--bless`, reporting their results as a libtest JSON event stream (see
+/// [`super::test_results`]) in the captured stdout.
+pub(super) trait TestRunner: Sync {
+    /// Run `targets` (paths under `rustc_repo_path`).
+    fn run<'a>(
+        &'a self,
+        config: &'a Config,
+        rustc_repo_path: &'a Path,
+        targets: &'a [&'a Path],
+    ) -> BoxFuture<'a, Result<process::Captured>>;
+
+    /// Run `targets`, all in `suite`, at once.
+    fn run_suite<'a>(
+        &'a self,
+        config: &'a Config,
+        rustc_repo_path: &'a Path,
+        suite: &'a str,
+        targets: &'a [&'a Path],
+    ) -> BoxFuture<'a, Result<process::Captured>>;
+}
+
+/// The real thing: `x test`, on the machine configured with `ssh_host` if any.
+pub(super) struct Bootstrap;
+
+impl TestRunner for Bootstrap {
+    // `./x test <path-to-test>... --stage <stage> [--bless] <keep-stage-args>...`, as set by
+    // the profile of the suite of the tests.
+    fn run<'a>(
+        &'a self,
+        config: &'a Config,
+        rustc_repo_path: &'a Path,
+        targets: &'a [&'a Path],
+    ) -> BoxFuture<'a, Result<process::Captured>> {
+        Box::pin(async move {
+            remote::push(rustc_repo_path, targets).await?;
+            let rel_paths: Vec<String> = targets
+                .iter()
+                .map(|t| paths::repo_relative(rustc_repo_path, t))
+                .collect();
+            let mut names: Vec<&Path> = rel_paths
+                .iter()
+                .map(|p| Path::new(paths::test_name(p)))
+                .collect();
+            // Several files of a run-make test are one test.
+            names.dedup();
+            let suite = rel_paths
+                .first()
+                .map(|p| paths::suite_of(p).to_string())
+                .unwrap_or_default();
+            let profile = suites::profile(config, &suite);
+            let output = x_test(
+                config,
+                rustc_repo_path,
+                &names,
+                &[],
+                profile.stage,
+                profile.bless,
+            )
+            .await;
+            remote::pull(rustc_repo_path, targets).await?;
+            output
+        })
+    }
+
+    // `./x test <suite> --stage <stage> [--bless] <keep-stage-args>... --test-args <path-in-suite>...`
+    //
+    // One compiletest invocation for the whole suite, rather than one per path as bootstrap may
+    // do for `x test <path>...`. Filters match test names by substring, so tests which merely
+    // share a name suffix with a target are run too; callers only look at the results of their
+    // targets.
+    fn run_suite<'a>(
+        &'a self,
+        config: &'a Config,
+        rustc_repo_path: &'a Path,
+        suite: &'a str,
+        targets: &'a [&'a Path],
+    ) -> BoxFuture<'a, Result<process::Captured>> {
+        Box::pin(async move {
+            let filters: Vec<String> = targets
+                .iter()
+                .map(|t| paths::suite_filter(&paths::repo_relative(rustc_repo_path, t), suite))
+                .collect();
+            remote::push(rustc_repo_path, targets).await?;
+            let profile = suites::profile(config, suite);
+            let output = x_test(
+                config,
+                rustc_repo_path,
+                &[Path::new(suite)],
+                &filters,
+                profile.stage,
+                profile.bless,
+            )
+            .await;
+            remote::pull(rustc_repo_path, targets).await?;
+            output
+        })
+    }
+}
+
+/// compiletest run directly against the stage 1 toolchain, see `--direct-compiletest`.
+pub(super) struct Compiletest;
+
+impl TestRunner for Compiletest {
+    // Unlike `x test <path>...`, compiletest takes one suite at a time, which all targets are in
+    // as far as callers are concerned.
+    fn run<'a>(
+        &'a self,
+        config: &'a Config,
+        rustc_repo_path: &'a Path,
+        targets: &'a [&'a Path],
+    ) -> BoxFuture<'a, Result<process::Captured>> {
+        Box::pin(async move {
+            let suite = targets
+                .first()
+                .map(|t| paths::suite_of(&paths::repo_relative(rustc_repo_path, t)).to_string())
+                .unwrap_or_default();
+            self.run_suite(config, rustc_repo_path, &suite, targets)
+                .await
+        })
+    }
+
+    fn run_suite<'a>(
+        &'a self,
+        config: &'a Config,
+        rustc_repo_path: &'a Path,
+        suite: &'a str,
+        targets: &'a [&'a Path],
+    ) -> BoxFuture<'a, Result<process::Captured>> {
+        Box::pin(async move {
+            let filters: Vec<String> = targets
+                .iter()
+                .map(|t| paths::suite_filter(&paths::repo_relative(rustc_repo_path, t), suite))
+                .collect();
+            let bless = suites::profile(config, suite).bless;
+            compiletest::run(config, rustc_repo_path, suite, &filters, bless).await
+        })
+    }
+}
+
+/// `inner` (e.g. [`Bootstrap`]), saving every invocation to `dir`, see `--record`.
+pub(super) struct Recorder {
+    pub(super) dir: PathBuf,
+    pub(super) inner: Box<dyn TestRunner>,
+}
+
+impl TestRunner for Recorder {
+    fn run<'a>(
+        &'a self,
+        config: &'a Config,
+        rustc_repo_path: &'a Path,
+        targets: &'a [&'a Path],
+    ) -> BoxFuture<'a, Result<process::Captured>> {
+        Box::pin(async move {
+            let invocation = Invocation::new(rustc_repo_path, None, targets)?;
+            let output = self.inner.run(config, rustc_repo_path, targets).await?;
+            invocation.save(&self.dir, rustc_repo_path, targets, &output)?;
+            Ok(output)
+        })
+    }
+
+    fn run_suite<'a>(
+        &'a self,
+        config: &'a Config,
+        rustc_repo_path: &'a Path,
+        suite: &'a str,
+        targets: &'a [&'a Path],
+    ) -> BoxFuture<'a, Result<process::Captured>> {
+        Box::pin(async move {
+            let invocation = Invocation::new(rustc_repo_path, Some(suite), targets)?;
+            let output = self
+                .inner
+                .run_suite(config, rustc_repo_path, suite, targets)
+                .await?;
+            invocation.save(&self.dir, rustc_repo_path, targets, &output)?;
+            Ok(output)
+        })
+    }
+}
+
+/// Answers invocations from the recording in `dir` without running `x`, see `--replay`.
+/// Invocations missing from the recording are errors.
+pub(super) struct Replayer {
+    pub(super) dir: PathBuf,
+}
+
+impl TestRunner for Replayer {
+    fn run<'a>(
+        &'a self,
+        _config: &'a Config,
+        rustc_repo_path: &'a Path,
+        targets: &'a [&'a Path],
+    ) -> BoxFuture<'a, Result<process::Captured>> {
+        Box::pin(async move {
+            Invocation::new(rustc_repo_path, None, targets)?.replay(
+                &self.dir,
+                rustc_repo_path,
+                targets,
+            )
+        })
+    }
+
+    fn run_suite<'a>(
+        &'a self,
+        _config: &'a Config,
+        rustc_repo_path: &'a Path,
+        suite: &'a str,
+        targets: &'a [&'a Path],
+    ) -> BoxFuture<'a, Result<process::Captured>> {
+        Box::pin(async move {
+            Invocation::new(rustc_repo_path, Some(suite), targets)?.replay(
+                &self.dir,
+                rustc_repo_path,
+                targets,
+            )
+        })
+    }
+}
+
+/// An `x test` invocation, identified by its arguments and the contents of its targets.
+struct Invocation {
+    args: Vec<String>,
+    key: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Recorded {
+    args: Vec<String>,
+    success: bool,
+    timed_out: bool,
+    stdout: String,
+    stderr: String,
+    /// Contents of the snapshots of the targets after the invocation, keyed by repo-relative
+    /// path.
+    snapshots: BTreeMap<String, String>,
+}
+
+impl Invocation {
+    fn new(rustc_repo_path: &Path, suite: Option<&str>, targets: &[&Path]) -> Result<Self> {
+        let rel_paths = targets
+            .iter()
+            .map(|t| paths::repo_relative(rustc_repo_path, t));
+        let args: Vec<String> = match suite {
+            Some(suite) => ["test", suite, "--test-args"]
+                .into_iter()
+                .map(String::from)
+                .chain(rel_paths)
+                .collect(),
+            None => std::iter::once("test".to_string())
+                .chain(rel_paths)
+                .collect(),
+        };
+        let mut hasher = Sha256::new();
+        for arg in &args {
+            hasher.update(arg.as_bytes());
+            hasher.update([0]);
+        }
+        for target in targets {
+            let contents = std::fs::read(target)
+                .into_diagnostic()
+                .wrap_err(format!("failed to read `{}`", target.display()))?;
+            hasher.update(sha256_hex(&contents).as_bytes());
+        }
+        Ok(Invocation {
+            args,
+            key: sha256_hex(&hasher.finalize()),
+        })
+    }
+
+    fn path(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{}.json", self.key))
+    }
+
+    fn save(
+        &self,
+        dir: &Path,
+        rustc_repo_path: &Path,
+        targets: &[&Path],
+        output: &process::Captured,
+    ) -> Result<()> {
+        let mut snapshots = BTreeMap::new();
+        for snapshot in targets.iter().flat_map(|t| snapshots::companions(t)) {
+            // Non-UTF-8 snapshots are left out, and so not restored on replay.
+            if let Ok(contents) = std::fs::read_to_string(&snapshot) {
+                snapshots.insert(paths::repo_relative(rustc_repo_path, &snapshot), contents);
+            }
+        }
+        let recorded = Recorded {
+            args: self.args.clone(),
+            success: output.status.success(),
+            timed_out: output.timed_out,
+            stdout: output.stdout.clone(),
+            stderr: output.stderr.clone(),
+            snapshots,
+        };
+        let path = self.path(dir);
+        std::fs::write(&path, serde_json::to_string(&recorded).into_diagnostic()?)
+            .into_diagnostic()
+            .wrap_err(format!("failed to record `{}`", path.display()))
+    }
+
+    /// The recorded output, with the snapshots of `targets` put back the way the invocation left
+    /// them.
+    fn replay(
+        &self,
+        dir: &Path,
+        rustc_repo_path: &Path,
+        targets: &[&Path],
+    ) -> Result<process::Captured> {
+        let path = self.path(dir);
+        let contents = std::fs::read_to_string(&path).map_err(|_| {
+            miette!(
+                help = "record again with `--record` after changing the tests or the config",
+                "`x {}` with the tests as they are now was not recorded in `{}`",
+                self.args.join(" "),
+                dir.display()
+            )
+        })?;
+        let recorded: Recorded = serde_json::from_str(&contents)
+            .into_diagnostic()
+            .wrap_err(format!("failed to parse recording `{}`", path.display()))?;
+        debug!("replaying `x {}`", recorded.args.join(" "));
+
+        for snapshot in targets.iter().flat_map(|t| snapshots::companions(t)) {
+            let rel_path = paths::repo_relative(rustc_repo_path, &snapshot);
+            if !recorded.snapshots.contains_key(&rel_path) {
+                write_guard::remove_file(&snapshot)
+                    .into_diagnostic()
+                    .wrap_err(format!("failed to remove `{}`", snapshot.display()))?;
+            }
+        }
+        for (rel_path, contents) in &recorded.snapshots {
+            let snapshot = rustc_repo_path.join(rel_path);
+            write_guard::write(&snapshot, contents)
+                .into_diagnostic()
+                .wrap_err(format!("failed to write `{}`", snapshot.display()))?;
+        }
+
+        Ok(process::Captured {
+            status: exit_status(recorded.success),
+            timed_out: recorded.timed_out,
+            stdout: recorded.stdout,
+            stderr: recorded.stderr,
+        })
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(success: bool) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw(if success { 0 } else { 1 << 8 })
+}
+
+#[cfg(windows)]
+fn exit_status(success: bool) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(if success { 0 } else { 1 })
+}
@@ -0,0 +1,257 @@
+//! Discovery of candidate test files under the configured target directories.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use ignore::WalkState;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use super::directives::Syntax;
+use super::rules::{self, Rule};
+use super::{diagnostics, paths, review};
+use crate::config::Config;
+
+/// Result of walking the target directories.
+#[derive(Debug, Default)]
+pub struct Scan {
+    /// Test files with a directive one of the rewrite rules applies to, by default
+    /// `// ignore-debug`.
+    pub candidates: BTreeSet<PathBuf>,
+    /// Every other file encountered (by repo-relative path), with the reason it was not
+    /// processed.
+    pub skipped: BTreeMap<String, SkipReason>,
+    /// Test files whose header is already inconsistent with respect to debug assertions (by
+    /// repo-relative path), candidates or not, with what is wrong.
+    pub contradictions: BTreeMap<String, Vec<String>>,
+}
+
+/// Why a file under the target directories was not processed.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum SkipReason {
+    /// Not a file directives are written in (a `.rs` / `.fixed` file, a `Makefile`, or a `.s` /
+    /// `.ll` file), e.g. a `.stderr` snapshot.
+    Filtered,
+    /// Matched an entry of the `exclude` config.
+    Excluded,
+    /// The file could not be decoded as UTF-8.
+    NonUtf8,
+    /// The file could not be read.
+    Unreadable(String),
+    /// The file has no directive any rewrite rule applies to.
+    NoDirective,
+    /// `--incremental`: the test kept its directive in the given run and was not touched since.
+    Unchanged(String),
+    /// The test has the contents the given run changed it to.
+    AlreadyMigrated(String),
+    /// A symlink to a file outside of the `rustc` repo, see `follow_external_symlinks` in the
+    /// config.
+    ExternalSymlink,
+    /// The same file as the given candidate, reached through a symlink.
+    SameFileAs(String),
+}
+
+impl SkipReason {
+    /// Short heading used to group files by reason in reports.
+    pub fn heading(&self) -> &'static str {
+        match self {
+            SkipReason::Filtered => "Not a test file",
+            SkipReason::Excluded => "Excluded by config",
+            SkipReason::NonUtf8 => "Not valid UTF-8",
+            SkipReason::Unreadable(_) => "Unreadable",
+            SkipReason::NoDirective => "No directive to rewrite",
+            SkipReason::Unchanged(_) => "Unchanged since last verified",
+            SkipReason::AlreadyMigrated(_) => "Already migrated by a previous run",
+            SkipReason::ExternalSymlink => "Symlink leaving the `rustc` repo",
+            SkipReason::SameFileAs(_) => "Same file as another candidate",
+        }
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Unreadable(e) => write!(f, "{}: {e}", self.heading()),
+            SkipReason::Unchanged(run) | SkipReason::AlreadyMigrated(run) => {
+                write!(f, "{} in run `{run}`", self.heading())
+            }
+            SkipReason::SameFileAs(path) => write!(f, "{}: `{path}`", self.heading()),
+            _ => f.write_str(self.heading()),
+        }
+    }
+}
+
+/// Walk the target directories in parallel, skipping files ignored by git (`.gitignore` and
+/// friends). Files are read by the walker threads, which dominates the scan of large suites.
+///
+/// Symlinks are followed, and each file is only a candidate once however many paths lead to it.
+pub(super) fn scan(config: &Config, rustc_repo_path: &Path) -> Scan {
+    scan_dirs(config, rustc_repo_path, &config.target_directories)
+}
+
+/// The directories of the test suites under `tests/` with candidates, relative to the root of the
+/// `rustc` repo (e.g. `tests/ui`), with their number of candidates, for `--auto-discover`.
+pub fn discover_target_directories(
+    config: &Config,
+    rustc_repo_path: &Path,
+) -> BTreeMap<PathBuf, usize> {
+    let scan = scan_dirs(
+        config,
+        rustc_repo_path,
+        &BTreeSet::from([PathBuf::from("tests")]),
+    );
+    let mut suites = BTreeMap::new();
+    for candidate in &scan.candidates {
+        let rel_path = paths::repo_relative(rustc_repo_path, candidate);
+        *suites
+            .entry(PathBuf::from(paths::suite_of(&rel_path)))
+            .or_default() += 1;
+    }
+    suites
+}
+
+/// [`scan`] of `target_directories` (relative to the root of the `rustc` repo) rather than those
+/// of `config`.
+fn scan_dirs(
+    config: &Config,
+    rustc_repo_path: &Path,
+    target_directories: &BTreeSet<PathBuf>,
+) -> Scan {
+    let excluded: Vec<PathBuf> = config
+        .exclude
+        .iter()
+        .map(|p| rustc_repo_path.join(p))
+        .collect();
+
+    let rules = rules::resolve(config);
+    let dirs: Vec<PathBuf> = target_directories
+        .iter()
+        .map(|p| rustc_repo_path.join(p))
+        .collect();
+    trace!(?dirs, "walking target directories");
+    let Some((first, rest)) = dirs.split_first() else {
+        return Scan::default();
+    };
+    let mut builder = ignore::WalkBuilder::new(first);
+    for dir in rest {
+        builder.add(dir);
+    }
+    builder.hidden(false).follow_links(true);
+    let repo_root = rustc_repo_path
+        .canonicalize()
+        .unwrap_or_else(|_| rustc_repo_path.to_path_buf());
+
+    let scan = Mutex::new(Scan::default());
+    builder.build_parallel().run(|| {
+        Box::new(|entry| {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    debug!("skipping unreadable entry: {e}");
+                    return WalkState::Continue;
+                }
+            };
+            if entry.file_type().is_none_or(|t| t.is_dir()) {
+                return WalkState::Continue;
+            }
+            let path = entry.into_path();
+            let rel_path = paths::repo_relative(rustc_repo_path, &path);
+            let (reason, contradictions) = if !config.follow_external_symlinks
+                && path
+                    .canonicalize()
+                    .is_ok_and(|p| !p.starts_with(&repo_root))
+            {
+                (Some(SkipReason::ExternalSymlink), Vec::new())
+            } else {
+                classify(&path, &rel_path, &excluded, &rules)
+            };
+            let mut scan = scan.lock().unwrap();
+            if !contradictions.is_empty() {
+                scan.contradictions.insert(rel_path.clone(), contradictions);
+            }
+            match reason {
+                Some(reason) => {
+                    trace!(%rel_path, %reason, "skipped");
+                    scan.skipped.insert(rel_path, reason);
+                }
+                None => {
+                    scan.candidates.insert(path);
+                }
+            }
+            WalkState::Continue
+        })
+    });
+
+    let mut scan = scan.into_inner().unwrap();
+    dedup_aliases(&mut scan, rustc_repo_path, &repo_root);
+    scan
+}
+
+/// Keep a single path to each candidate reached through several (through symlinks), preferring
+/// the one without symlinks, and otherwise the first one.
+fn dedup_aliases(scan: &mut Scan, rustc_repo_path: &Path, repo_root: &Path) {
+    let mut by_file: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for candidate in &scan.candidates {
+        let resolved = candidate
+            .canonicalize()
+            .unwrap_or_else(|_| candidate.clone());
+        by_file.entry(resolved).or_default().push(candidate.clone());
+    }
+    for (resolved, aliases) in by_file {
+        if aliases.len() < 2 {
+            continue;
+        }
+        let kept = aliases
+            .iter()
+            .find(|alias| repo_root.join(paths::repo_relative(rustc_repo_path, alias)) == resolved)
+            .unwrap_or(&aliases[0]);
+        let kept_rel_path = paths::repo_relative(rustc_repo_path, kept);
+        for alias in aliases.iter().filter(|alias| *alias != kept) {
+            scan.candidates.remove(alias);
+            let rel_path = paths::repo_relative(rustc_repo_path, alias);
+            debug!("`{rel_path}` is the same file as `{kept_rel_path}`");
+            scan.contradictions.remove(&rel_path);
+            scan.skipped
+                .insert(rel_path, SkipReason::SameFileAs(kept_rel_path.clone()));
+        }
+    }
+}
+
+/// Returns why `path` should be skipped, or `None` if it is a candidate, along with the
+/// contradictions in its header, see [`review::contradictions`].
+fn classify(
+    path: &Path,
+    rel_path: &str,
+    excluded: &[PathBuf],
+    rules: &[Rule],
+) -> (Option<SkipReason>, Vec<String>) {
+    let syntax = Syntax::of(path);
+    let is_test_file =
+        syntax != Syntax::RUST || path.extension().is_some_and(|s| s == "rs" || s == "fixed");
+    if !is_test_file {
+        return (Some(SkipReason::Filtered), Vec::new());
+    }
+    if excluded.iter().any(|e| path.starts_with(e)) {
+        return (Some(SkipReason::Excluded), Vec::new());
+    }
+    let src = match std::fs::read(path) {
+        Ok(bytes) => match String::from_utf8(bytes) {
+            Ok(src) => src,
+            Err(_) => return (Some(SkipReason::NonUtf8), Vec::new()),
+        },
+        Err(e) => {
+            warn!("failed to read `{rel_path}`, skipping: {e}");
+            return (Some(SkipReason::Unreadable(e.to_string())), Vec::new());
+        }
+    };
+    let contradictions = review::contradictions(&src, syntax);
+    diagnostics::contradictions(rel_path, &src, &contradictions);
+    let contradictions = contradictions.into_iter().map(|c| c.description).collect();
+    if !rules::applies(rules, &src, syntax) {
+        diagnostics::lookalikes(rel_path, &src, syntax, rules);
+        return (Some(SkipReason::NoDirective), contradictions);
+    }
+    (None, contradictions)
+}
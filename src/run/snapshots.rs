@@ -0,0 +1,219 @@
+//! Tracking of the snapshot files (`.stderr`, `.stdout`, ...) that `--bless` may rewrite next to
+//! a test, so that changes to them can be measured and reverted along with the test.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::{paths, write_guard};
+
+/// How a single snapshot file changed while running a test.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotChange {
+    /// Repo-relative path of the snapshot.
+    pub path: String,
+    pub added_lines: usize,
+    pub removed_lines: usize,
+}
+
+impl SnapshotChange {
+    pub fn changed_lines(&self) -> usize {
+        self.added_lines + self.removed_lines
+    }
+
+    /// Whether the snapshot is a MIR dump of a `tests/mir-opt` test.
+    pub fn is_mir_dump(&self) -> bool {
+        self.path.starts_with("tests/mir-opt/")
+            && (self.path.ends_with(".mir") || self.path.ends_with(".diff"))
+    }
+}
+
+/// The snapshot files of a test as they were before running it.
+#[derive(Debug)]
+pub(super) struct Snapshots {
+    test: PathBuf,
+    before: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+/// Files next to `test` named `<test stem>.*`, e.g. `foo.stderr`, `foo.32bit.stderr` or
+/// `foo.run.stdout` for `foo.rs`, and for `tests/mir-opt` the MIR dumps, see [`prefixes`]. Other
+/// tests (`foo.bar.rs`) are not companions.
+pub(super) fn companions(test: &Path) -> Vec<PathBuf> {
+    let Some(dir) = test.parent() else {
+        return Vec::new();
+    };
+    let prefixes = prefixes(test);
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut companions: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| {
+            p != test
+                && p.is_file()
+                && p.extension().is_none_or(|ext| ext != "rs")
+                && p.file_name().is_some_and(|n| {
+                    let n = n.to_string_lossy();
+                    prefixes.iter().any(|prefix| n.starts_with(prefix.as_str()))
+                })
+        })
+        .collect();
+    companions.sort();
+    companions
+}
+
+/// File name prefixes of the companions of `test`. Besides `<test stem>.`, the MIR dumps of a
+/// `tests/mir-opt` test are named after its crate name (`issue_1234.` for `issue-1234.rs`), or
+/// given verbatim by its `// EMIT_MIR` lines, e.g. for items of `core`. These may be suffixed
+/// with `.32bit`, `.panic-unwind` and the like before their extension.
+pub(super) fn prefixes(test: &Path) -> Vec<String> {
+    let Some(stem) = test.file_stem() else {
+        return Vec::new();
+    };
+    let stem = stem.to_string_lossy();
+    let mut prefixes = vec![format!("{stem}.")];
+    if !is_mir_opt(test) {
+        return prefixes;
+    }
+    let crate_name = format!("{}.", stem.replace('-', "_"));
+    if crate_name != prefixes[0] {
+        prefixes.push(crate_name);
+    }
+    let src = std::fs::read_to_string(test).unwrap_or_default();
+    for line in src.lines() {
+        let Some(dump) = line.trim_start().strip_prefix("// EMIT_MIR ") else {
+            continue;
+        };
+        let dump = dump.trim();
+        let base = dump
+            .strip_suffix(".mir")
+            .or_else(|| dump.strip_suffix(".diff"))
+            .unwrap_or(dump);
+        let prefix = format!("{base}.");
+        if !prefixes.iter().any(|p| prefix.starts_with(p.as_str())) {
+            prefixes.push(prefix);
+        }
+    }
+    prefixes
+}
+
+/// Whether `test` is in `tests/mir-opt`, whose tests are checked against MIR dumps.
+fn is_mir_opt(test: &Path) -> bool {
+    test.components().any(|c| c.as_os_str() == "mir-opt")
+}
+
+impl Snapshots {
+    pub(super) fn capture(test: &Path) -> std::io::Result<Self> {
+        let mut before = BTreeMap::new();
+        for path in companions(test) {
+            let contents = std::fs::read(&path)?;
+            before.insert(path, contents);
+        }
+        Ok(Snapshots {
+            test: test.to_path_buf(),
+            before,
+        })
+    }
+
+    /// The snapshot files as captured, with their contents.
+    pub(super) fn files(&self) -> impl Iterator<Item = (&Path, &[u8])> {
+        self.before
+            .iter()
+            .map(|(path, contents)| (path.as_path(), contents.as_slice()))
+    }
+
+    /// Snapshots which were created, modified or deleted since [`Snapshots::capture`].
+    pub(super) fn changes(&self, rustc_repo_path: &Path) -> std::io::Result<Vec<SnapshotChange>> {
+        Ok(self
+            .changed()?
+            .into_iter()
+            .map(|(path, before, after)| {
+                let (added_lines, removed_lines) = line_diff(&before, &after);
+                SnapshotChange {
+                    path: paths::repo_relative(rustc_repo_path, &path),
+                    added_lines,
+                    removed_lines,
+                }
+            })
+            .collect())
+    }
+
+    /// The unified diff of every snapshot changed since [`Snapshots::capture`].
+    pub(super) fn diff(&self, rustc_repo_path: &Path) -> std::io::Result<String> {
+        let mut out = String::new();
+        for (path, before, after) in self.changed()? {
+            let rel_path = paths::repo_relative(rustc_repo_path, &path);
+            let diff = similar::TextDiff::from_lines(&before, &after);
+            out.push_str(
+                &diff
+                    .unified_diff()
+                    .header(&format!("a/{rel_path}"), &format!("b/{rel_path}"))
+                    .to_string(),
+            );
+        }
+        Ok(out)
+    }
+
+    /// The snapshots changed since [`Snapshots::capture`], with their contents before and after,
+    /// empty if missing.
+    fn changed(&self) -> std::io::Result<Vec<(PathBuf, String, String)>> {
+        let mut all: BTreeMap<PathBuf, Option<&[u8]>> = self
+            .before
+            .iter()
+            .map(|(path, contents)| (path.clone(), Some(contents.as_slice())))
+            .collect();
+        for path in companions(&self.test) {
+            all.entry(path).or_insert(None);
+        }
+
+        let mut changes = Vec::new();
+        for (path, before) in &all {
+            let after = match std::fs::read(path) {
+                Ok(contents) => Some(contents),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            };
+            if *before == after.as_deref() {
+                continue;
+            }
+            changes.push((
+                path.clone(),
+                String::from_utf8_lossy(before.unwrap_or_default()).into_owned(),
+                String::from_utf8_lossy(after.as_deref().unwrap_or_default()).into_owned(),
+            ));
+        }
+        Ok(changes)
+    }
+
+    /// Put every snapshot back the way it was, deleting ones which did not exist before.
+    pub(super) fn restore(&self) -> std::io::Result<()> {
+        for path in companions(&self.test) {
+            if !self.before.contains_key(&path) {
+                write_guard::remove_file(&path)?;
+            }
+        }
+        for (path, contents) in &self.before {
+            write_guard::restore(path, contents)?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of added and removed lines between `before` and `after`.
+fn line_diff(before: &str, after: &str) -> (usize, usize) {
+    use similar::{ChangeTag, TextDiff};
+
+    let diff = TextDiff::from_lines(before, after);
+    let mut added = 0;
+    let mut removed = 0;
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Insert => added += 1,
+            ChangeTag::Delete => removed += 1,
+            ChangeTag::Equal => {}
+        }
+    }
+    (added, removed)
+}
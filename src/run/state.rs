@@ -0,0 +1,79 @@
+//! Persistent state of a run, stored as `state.json` in the run directory.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use miette::{Context, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+
+use super::bisect::Bisection;
+use super::checkpoint::write_atomically;
+use super::manifest::Manifest;
+use super::scan::SkipReason;
+use super::TestRecord;
+use crate::exit_status::ExitStatus;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunState {
+    /// Name of the run directory, `<timestamp>-<short-id>`.
+    pub id: String,
+    pub started_at: DateTime<Local>,
+    /// `None` if the run did not finish (yet).
+    pub finished_at: Option<DateTime<Local>>,
+    pub rustc_repo_path: PathBuf,
+    /// Inputs of the run, for reproducibility.
+    #[serde(default)]
+    pub manifest: Manifest,
+    /// `None` if the run did not finish (yet).
+    pub status: Option<ExitStatus>,
+    /// Keyed by repo-relative path.
+    pub records: BTreeMap<String, TestRecord>,
+    /// Keyed by repo-relative path.
+    pub skipped: BTreeMap<String, SkipReason>,
+    /// Repo-relative paths of the candidates which were not processed because the run stopped
+    /// early.
+    #[serde(default)]
+    pub pending: BTreeSet<String>,
+    /// SHA-256 of the contents of each test whose directive was removed or replaced, as edited,
+    /// keyed by repo-relative path. Tests found with these contents again are already migrated.
+    #[serde(default)]
+    pub migrated: BTreeMap<String, String>,
+    /// Tests whose header is already inconsistent with respect to debug assertions, with what is
+    /// wrong, keyed by repo-relative path.
+    #[serde(default)]
+    pub contradictions: BTreeMap<String, Vec<String>>,
+    /// Archive of the candidate tests and their snapshots as they were before the run, extracted
+    /// with `tar -xzf <backup> -C <rustc repo>`.
+    #[serde(default)]
+    pub backup: Option<PathBuf>,
+    /// Directory of the output of `x` for each test run, as `<repo-relative path>.log`.
+    #[serde(default)]
+    pub logs: Option<PathBuf>,
+    /// Tests which fail without their directive although a previous run had removed or replaced
+    /// it, keyed by repo-relative path, with the id of that run.
+    #[serde(default)]
+    pub regressions: BTreeMap<String, String>,
+    /// What `bisect` found out about tests left with their directive, keyed by repo-relative
+    /// path.
+    #[serde(default)]
+    pub bisections: BTreeMap<String, Bisection>,
+}
+
+impl RunState {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .into_diagnostic()
+            .wrap_err(format!("failed to read run state `{}`", path.display()))?;
+        serde_json::from_str(&contents)
+            .into_diagnostic()
+            .wrap_err(format!("failed to parse run state `{}`", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self).into_diagnostic()?;
+        write_atomically(path, contents.as_bytes())
+            .into_diagnostic()
+            .wrap_err(format!("failed to write run state `{}`", path.display()))
+    }
+}
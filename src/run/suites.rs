@@ -0,0 +1,114 @@
+//! How the tests of each suite are run: which stage, whether `--bless` means anything, and whether
+//! the whole suite is checked with FileCheck. Built-in defaults cover the differences between e.g.
+//! `tests/ui`, `tests/codegen`, `tests/mir-opt` and `tests/run-make`, and `suite_profiles` in the
+//! config overrides them.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+/// An entry of `suite_profiles` in the config. Unset fields keep the built-in default of the suite.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SuiteProfile {
+    /// Directory of the suite relative to the root of the `rustc` repo, e.g. `tests/ui`.
+    pub suite: PathBuf,
+    /// Stage to test with (`--stage`).
+    #[serde(default)]
+    pub stage: Option<u32>,
+    /// Whether to pass `--bless`, for suites whose tests have snapshots.
+    #[serde(default)]
+    pub bless: Option<bool>,
+    /// Whether every test of the suite is checked with FileCheck, and so left for manual review
+    /// unless `try_filecheck_tests` is set. Tests with `CHECK` lines are in any suite.
+    #[serde(default)]
+    pub filecheck: Option<bool>,
+}
+
+/// How the tests of a suite are run, see [`profile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Profile {
+    pub(super) stage: u32,
+    pub(super) bless: bool,
+    pub(super) filecheck: bool,
+}
+
+/// The profile of `suite` (e.g. `tests/codegen`): its built-in defaults, overridden by the
+/// `suite_profiles` of `config`.
+pub(super) fn profile(config: &Config, suite: &str) -> Profile {
+    let mut profile = match suite {
+        // Checked against FileCheck patterns rather than snapshots.
+        "tests/codegen" | "tests/codegen-llvm" | "tests/assembly" | "tests/assembly-llvm" => {
+            Profile {
+                stage: 1,
+                bless: false,
+                filecheck: true,
+            }
+        }
+        // `rmake.rs` recipes check their own output, there is nothing to bless.
+        "tests/run-make" | "tests/run-make-cargo" => Profile {
+            stage: 1,
+            bless: false,
+            filecheck: false,
+        },
+        // Snapshots (`.stderr`, ...), and for `tests/mir-opt` MIR dumps, with `CHECK` lines only
+        // in some tests.
+        _ => Profile {
+            stage: 1,
+            bless: true,
+            filecheck: false,
+        },
+    };
+    for over in config
+        .suite_profiles
+        .iter()
+        .filter(|p| p.suite == Path::new(suite))
+    {
+        profile.stage = over.stage.unwrap_or(profile.stage);
+        profile.bless = over.bless.unwrap_or(profile.bless);
+        profile.filecheck = over.filecheck.unwrap_or(profile.filecheck);
+    }
+    profile
+}
+
+#[cfg(test)]
+mod tests {
+    use confique::Config as _;
+
+    use super::*;
+    use crate::run::review;
+
+    #[test]
+    fn runs_each_suite_according_to_its_profile() {
+        let mut config = Config::builder().load().unwrap();
+        let ui = profile(&config, "tests/ui");
+        assert!(ui.bless && !ui.filecheck);
+        let codegen = profile(&config, "tests/codegen");
+        assert!(!codegen.bless && codegen.filecheck);
+        assert!(!profile(&config, "tests/run-make").bless);
+        assert!(
+            review::filecheck_sensitive(&config, "tests/codegen/a.rs", "fn main() {}\n").is_some()
+        );
+
+        config.suite_profiles = vec![SuiteProfile {
+            suite: PathBuf::from("tests/codegen/"),
+            stage: Some(2),
+            bless: None,
+            filecheck: Some(false),
+        }];
+        let codegen = profile(&config, "tests/codegen");
+        assert_eq!(
+            (codegen.stage, codegen.bless, codegen.filecheck),
+            (2, false, false)
+        );
+        assert!(
+            review::filecheck_sensitive(&config, "tests/codegen/a.rs", "fn main() {}\n").is_none()
+        );
+        // `CHECK` lines are flagged whatever the suite.
+        assert!(
+            review::filecheck_sensitive(&config, "tests/codegen/a.rs", "// CHECK: foo\n").is_some()
+        );
+    }
+}
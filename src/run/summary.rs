@@ -0,0 +1,89 @@
+//! Compact summary printed to the terminal at the end of a run.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use console::{style, Style};
+
+use super::{RunOutcome, TestRecord};
+
+/// How many of the slowest tests to list.
+const SLOWEST_COUNT: usize = 5;
+
+/// Print outcome counts and the slowest tests to stderr, alongside the logs.
+pub(super) fn print_summary(records: &BTreeMap<String, TestRecord>) {
+    eprint!("{}", format_summary(records));
+}
+
+fn format_summary(records: &BTreeMap<String, TestRecord>) -> String {
+    let mut out = String::new();
+
+    let label_width = RunOutcome::ALL
+        .iter()
+        .map(|o| o.description().len())
+        .max()
+        .unwrap_or(0);
+    let total: Duration = records.values().map(|r| r.duration).sum();
+
+    out.push('\n');
+    out.push_str(&format!(
+        "{}\n",
+        style("Run summary").for_stderr().bold().underlined()
+    ));
+    for outcome in RunOutcome::ALL {
+        let count = records.values().filter(|r| r.outcome == outcome).count();
+        let label = format!("{:<label_width$}", outcome.description());
+        out.push_str(&format!(
+            "  {}  {:>6}\n",
+            outcome_style(outcome).apply_to(label),
+            count
+        ));
+    }
+    out.push_str(&format!(
+        "  {}  {:>6}  ({})\n",
+        style(format!("{:<label_width$}", "Total"))
+            .for_stderr()
+            .bold(),
+        records.len(),
+        format_duration(total)
+    ));
+
+    let mut slowest: Vec<_> = records.iter().collect();
+    slowest.sort_by(|(pa, a), (pb, b)| b.duration.cmp(&a.duration).then_with(|| pa.cmp(pb)));
+    slowest.truncate(SLOWEST_COUNT);
+    if !slowest.is_empty() {
+        out.push('\n');
+        out.push_str(&format!(
+            "{}\n",
+            style("Slowest tests").for_stderr().bold().underlined()
+        ));
+        for (path, record) in slowest {
+            out.push_str(&format!(
+                "  {:>9}  {}\n",
+                style(format_duration(record.duration)).for_stderr().dim(),
+                path
+            ));
+        }
+    }
+    out.push('\n');
+    out
+}
+
+fn outcome_style(outcome: RunOutcome) -> Style {
+    match outcome {
+        RunOutcome::RemoveOk | RunOutcome::ReplaceOk => Style::new().for_stderr().green(),
+        RunOutcome::UnmodifiedOk => Style::new().for_stderr().yellow(),
+        RunOutcome::NeedsManualReview => Style::new().for_stderr().magenta(),
+        RunOutcome::Ignored => Style::new().for_stderr().dim(),
+    }
+}
+
+/// `1h02m03s`, `2m03s` or `3.4s`.
+pub(super) fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..=59 => format!("{:.1}s", d.as_secs_f64()),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60),
+    }
+}
@@ -0,0 +1,52 @@
+//! The full output of the `x` invocations of each test, kept as `logs/<repo-relative path>.log` in
+//! the run directory and referenced from the report, so that failures can be investigated without
+//! running anything again.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use tracing::*;
+
+use super::process::Captured;
+
+/// The logs directory of the current run, `None` outside of runs.
+static DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Keep the logs of the tests of a run in `dir`.
+pub(super) fn configure(dir: PathBuf) {
+    *DIR.lock().unwrap() = Some(dir);
+}
+
+/// The log of the test at `rel_path` in the logs directory `dir`.
+pub(super) fn path_of(dir: &Path, rel_path: &str) -> PathBuf {
+    dir.join(format!("{rel_path}.log"))
+}
+
+/// Append the output of an invocation which ran the test at `rel_path` (`what` being e.g. the
+/// unmodified test) to its log. Failures are only warned about: the logs are a convenience.
+pub(super) fn append(rel_path: &str, what: &str, output: &Captured) {
+    let Some(dir) = DIR.lock().unwrap().clone() else {
+        return;
+    };
+    let path = path_of(&dir, rel_path);
+    let append = || -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = File::options().create(true).append(true).open(&path)?;
+        writeln!(
+            file,
+            "==== `x test` on {what} ({}{}, {}) ====",
+            output.status,
+            if output.timed_out { ", timed out" } else { "" },
+            chrono::Local::now().to_rfc3339()
+        )?;
+        writeln!(file, "---- stdout ----\n{}", output.stdout)?;
+        writeln!(file, "---- stderr ----\n{}", output.stderr)
+    };
+    if let Err(e) = append() {
+        warn!("failed to write the log of `{rel_path}`: {e}");
+    }
+}
@@ -0,0 +1,243 @@
+//! Per-test results from the libtest JSON event stream, which compiletest emits when passed
+//! `-Zunstable-options --format=json` via `--test-args`.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// Arguments to pass to compiletest via `--test-args` to get the event stream.
+pub(super) const JSON_FORMAT_ARGS: &str = "-Zunstable-options --format=json";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) enum TestStatus {
+    // Ordered from weakest to strongest, see `parse_events`.
+    Ignored,
+    Passed,
+    Failed,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(super) struct TestResult {
+    pub(super) status: TestStatus,
+    /// As measured by libtest, summed over revisions. Not reported by every libtest version.
+    pub(super) exec_time: Option<Duration>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Event {
+    Test {
+        event: String,
+        name: String,
+        #[serde(default)]
+        exec_time: Option<f64>,
+    },
+    Suite {
+        event: String,
+        #[serde(default)]
+        passed: usize,
+        #[serde(default)]
+        failed: usize,
+        #[serde(default)]
+        ignored: usize,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// Whether `line` looks like an event of the stream rather than other output.
+pub(super) fn is_event(line: &str) -> bool {
+    line.trim_start().starts_with('{')
+}
+
+/// Collect the results of the `{ "type": "test", "name": "[ui] tests/ui/foo.rs#rev", ... }`
+/// events, keyed by the forward-slash test path without the revision. A test with several
+/// revisions failed if any revision failed, and was ignored only if every revision was ignored.
+pub(super) fn parse_events(stdout: &str) -> BTreeMap<String, TestResult> {
+    let mut results: BTreeMap<String, TestResult> = BTreeMap::new();
+    for line in stdout.lines().filter(|line| is_event(line)) {
+        let Ok(Event::Test {
+            event,
+            name,
+            exec_time,
+        }) = serde_json::from_str(line)
+        else {
+            continue;
+        };
+        let status = match event.as_str() {
+            "ok" => TestStatus::Passed,
+            "failed" => TestStatus::Failed,
+            "ignored" => TestStatus::Ignored,
+            // `started`, and `timeout` which is only a warning followed by the actual result.
+            _ => continue,
+        };
+        // Strip the `[suite] ` prefix.
+        let name = name.rsplit("] ").next().unwrap_or(&name).trim();
+        let path = name.split_once('#').map_or(name, |(path, _)| path);
+        let exec_time = exec_time
+            .filter(|secs| secs.is_finite() && *secs >= 0.0)
+            .map(Duration::from_secs_f64);
+        let entry = results
+            .entry(path.replace('\\', "/"))
+            .or_insert(TestResult {
+                status: TestStatus::Ignored,
+                exec_time: None,
+            });
+        entry.status = entry.status.max(status);
+        if let Some(exec_time) = exec_time {
+            *entry.exec_time.get_or_insert(Duration::ZERO) += exec_time;
+        }
+    }
+    results
+}
+
+/// The event on `line` in the format of libtest's human-readable output, for `--show-output`.
+/// Lines which aren't events are returned as is, and events without a textual equivalent (such as
+/// `started`) are dropped.
+pub(super) fn render(line: &str) -> Option<String> {
+    if !is_event(line) {
+        return Some(line.to_string());
+    }
+    match serde_json::from_str(line) {
+        Ok(Event::Test { event, name, .. }) => match event.as_str() {
+            "ok" | "failed" | "ignored" => {
+                let status = if event == "failed" { "FAILED" } else { &event };
+                Some(format!("test {name} ... {status}"))
+            }
+            _ => None,
+        },
+        Ok(Event::Suite {
+            event,
+            passed,
+            failed,
+            ignored,
+        }) if event != "started" => Some(format!(
+            "test result: {}. {passed} passed; {failed} failed; {ignored} ignored",
+            if event == "ok" { "ok" } else { "FAILED" }
+        )),
+        Ok(_) => None,
+        Err(_) => Some(line.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The name of a case, the lines of stdout and the expected status of each test.
+    type Case = (
+        &'static str,
+        Vec<String>,
+        &'static [(&'static str, TestStatus)],
+    );
+
+    fn event(event: &str, name: &str) -> String {
+        format!(r#"{{ "type": "test", "event": "{event}", "name": "{name}" }}"#)
+    }
+
+    #[test]
+    fn parses_events() {
+        use TestStatus::*;
+
+        let cases: &[Case] = &[
+            ("nothing", vec![], &[]),
+            (
+                "each status",
+                vec![
+                    event("ok", "[ui] tests/ui/a.rs"),
+                    event("failed", "[ui] tests/ui/b.rs"),
+                    event("ignored", "[ui] tests/ui/c.rs"),
+                ],
+                &[
+                    ("tests/ui/a.rs", Passed),
+                    ("tests/ui/b.rs", Failed),
+                    ("tests/ui/c.rs", Ignored),
+                ],
+            ),
+            (
+                "a failed revision fails the test",
+                vec![
+                    event("ok", "[ui] tests/ui/a.rs#x"),
+                    event("failed", "[ui] tests/ui/a.rs#y"),
+                    event("ok", "[ui] tests/ui/a.rs#z"),
+                ],
+                &[("tests/ui/a.rs", Failed)],
+            ),
+            (
+                "an ignored revision doesn't hide a passing one",
+                vec![
+                    event("ignored", "[ui] tests/ui/a.rs#x"),
+                    event("ok", "[ui] tests/ui/a.rs#y"),
+                ],
+                &[("tests/ui/a.rs", Passed)],
+            ),
+            (
+                "every revision ignored",
+                vec![
+                    event("ignored", "[ui] tests/ui/a.rs#x"),
+                    event("ignored", "[ui] tests/ui/a.rs#y"),
+                ],
+                &[("tests/ui/a.rs", Ignored)],
+            ),
+            (
+                "started and timeout are not results",
+                vec![
+                    event("started", "[ui] tests/ui/a.rs"),
+                    event("timeout", "[ui] tests/ui/a.rs"),
+                    event("started", "[ui] tests/ui/b.rs"),
+                    event("ok", "[ui] tests/ui/a.rs"),
+                ],
+                &[("tests/ui/a.rs", Passed)],
+            ),
+            (
+                "other output, suite events and broken lines",
+                vec![
+                    "Building stage1 compiler".to_string(),
+                    r#"{ "type": "suite", "event": "started", "test_count": 1 }"#.to_string(),
+                    "{ not json".to_string(),
+                    event("ok", "[ui] tests/ui/a.rs"),
+                    r#"{ "type": "suite", "event": "ok", "passed": 1 }"#.to_string(),
+                ],
+                &[("tests/ui/a.rs", Passed)],
+            ),
+            (
+                "windows paths and no suite prefix",
+                vec![
+                    event("ok", r"[ui] tests\\ui\\a.rs"),
+                    event("failed", "tests/ui/b.rs"),
+                ],
+                &[("tests/ui/a.rs", Passed), ("tests/ui/b.rs", Failed)],
+            ),
+        ];
+        for (case, lines, expected) in cases {
+            let statuses: Vec<(String, TestStatus)> = parse_events(&lines.join("\n"))
+                .into_iter()
+                .map(|(path, result)| (path, result.status))
+                .collect();
+            let expected: Vec<(String, TestStatus)> = expected
+                .iter()
+                .map(|(path, status)| (path.to_string(), *status))
+                .collect();
+            assert_eq!(statuses, expected, "{case}");
+        }
+    }
+
+    #[test]
+    fn sums_the_exec_time_of_revisions() {
+        let stdout = [
+            r#"{ "type": "test", "event": "ok", "name": "[ui] tests/ui/a.rs#x", "exec_time": 1.5 }"#,
+            r#"{ "type": "test", "event": "ok", "name": "[ui] tests/ui/a.rs#y", "exec_time": 0.5 }"#,
+            r#"{ "type": "test", "event": "ok", "name": "[ui] tests/ui/b.rs", "exec_time": -1 }"#,
+            r#"{ "type": "test", "event": "ok", "name": "[ui] tests/ui/c.rs" }"#,
+        ]
+        .join("\n");
+        let results = parse_events(&stdout);
+        assert_eq!(
+            results["tests/ui/a.rs"].exec_time,
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(results["tests/ui/b.rs"].exec_time, None);
+        assert_eq!(results["tests/ui/c.rs"].exec_time, None);
+    }
+}
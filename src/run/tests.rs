@@ -0,0 +1,916 @@
+//! Tests of the strategy pipeline, reverts and reports, with tests run by [`FakeRunner`] rather
+//! than bootstrap.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use confique::Config as _;
+use futures_util::future::BoxFuture;
+use miette::Result;
+use proptest::prelude::*;
+
+use super::runner::TestRunner;
+use super::state::RunState;
+use super::test_results::TestStatus;
+use super::*;
+
+/// Reports tests which still have `ignore-debug` as ignored, like compiletest in a build with
+/// debug assertions, and the others according to `verdict`, except that those under `fails_under`
+/// fail. With `bless`, rewrites the `.stderr` snapshot of every test which is not ignored.
+struct FakeRunner {
+    verdict: fn(&str) -> TestStatus,
+    fails_under: Option<PathBuf>,
+    bless: bool,
+    runs: AtomicUsize,
+}
+
+impl FakeRunner {
+    fn new(verdict: fn(&str) -> TestStatus) -> Self {
+        FakeRunner {
+            verdict,
+            fails_under: None,
+            bless: false,
+            runs: AtomicUsize::new(0),
+        }
+    }
+
+    fn events(&self, rustc_repo_path: &Path, targets: &[&Path]) -> Result<process::Captured> {
+        self.runs.fetch_add(1, Ordering::SeqCst);
+        let mut stdout = String::new();
+        for target in targets {
+            let src = std::fs::read_to_string(target).unwrap();
+            let status = if src.contains("ignore-debug") {
+                TestStatus::Ignored
+            } else if self
+                .fails_under
+                .as_ref()
+                .is_some_and(|dir| target.starts_with(dir))
+            {
+                TestStatus::Failed
+            } else {
+                (self.verdict)(&src)
+            };
+            if self.bless && status != TestStatus::Ignored {
+                std::fs::write(target.with_extension("stderr"), "blessed\n").unwrap();
+            }
+            let event = match status {
+                TestStatus::Passed => "ok",
+                TestStatus::Failed => "failed",
+                TestStatus::Ignored => "ignored",
+            };
+            // compiletest names run-make tests after their directory.
+            let rel_path = paths::repo_relative(rustc_repo_path, target);
+            let name = match rel_path.strip_prefix("tests/run-make/") {
+                Some(in_suite) => {
+                    let dir = in_suite.split('/').next().unwrap();
+                    format!("[run-make] tests/run-make/{dir}")
+                }
+                None => format!("[ui] {rel_path}"),
+            };
+            stdout.push_str(
+                &serde_json::json!({ "type": "test", "event": event, "name": name }).to_string(),
+            );
+            stdout.push('\n');
+        }
+        Ok(process::Captured {
+            status: Default::default(),
+            timed_out: false,
+            stdout,
+            stderr: String::new(),
+        })
+    }
+}
+
+impl TestRunner for FakeRunner {
+    fn run<'a>(
+        &'a self,
+        _config: &'a Config,
+        rustc_repo_path: &'a Path,
+        targets: &'a [&'a Path],
+    ) -> BoxFuture<'a, Result<process::Captured>> {
+        Box::pin(async move { self.events(rustc_repo_path, targets) })
+    }
+
+    fn run_suite<'a>(
+        &'a self,
+        _config: &'a Config,
+        rustc_repo_path: &'a Path,
+        _suite: &'a str,
+        targets: &'a [&'a Path],
+    ) -> BoxFuture<'a, Result<process::Captured>> {
+        Box::pin(async move { self.events(rustc_repo_path, targets) })
+    }
+}
+
+/// A fake `rustc` repo in a temporary directory, removed on drop.
+pub(crate) struct Repo(pub(crate) PathBuf);
+
+impl Repo {
+    pub(crate) fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!(
+            "rustc-less-ignore-debug-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("tests/ui")).unwrap();
+        Repo(root)
+    }
+
+    /// Write `contents` to `rel_path`, creating its parent directories.
+    pub(crate) fn add(&self, rel_path: &str, contents: &str) -> PathBuf {
+        let path = self.0.join(rel_path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for Repo {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn config() -> Config {
+    Config::builder().load().unwrap()
+}
+
+const TEST: &str = "//@ ignore-debug\n//@ check-pass\n\nfn main() {}\n";
+
+/// The record of a test decided in a second, without any change.
+fn record(outcome: RunOutcome) -> TestRecord {
+    TestRecord {
+        outcome,
+        duration: Duration::from_secs(1),
+        changes: Vec::new(),
+        snapshot_changes: Vec::new(),
+        review_hint: None,
+        duplicate_of: None,
+        worktrees: BTreeMap::new(),
+        rule: None,
+    }
+}
+
+/// The state of a run over the repo at `rustc_repo_path` which decided `records` and nothing
+/// else, for tests to fill in further.
+fn run_state<'a>(
+    rustc_repo_path: &Path,
+    records: impl IntoIterator<Item = (&'a str, TestRecord)>,
+) -> RunState {
+    RunState {
+        id: "test".to_string(),
+        started_at: chrono::Local::now(),
+        finished_at: None,
+        rustc_repo_path: rustc_repo_path.to_path_buf(),
+        manifest: Default::default(),
+        status: None,
+        records: records
+            .into_iter()
+            .map(|(rel_path, record)| (rel_path.to_string(), record))
+            .collect(),
+        skipped: Default::default(),
+        pending: Default::default(),
+        migrated: Default::default(),
+        contradictions: Default::default(),
+        backup: None,
+        logs: None,
+        regressions: Default::default(),
+        bisections: Default::default(),
+    }
+}
+
+async fn decide(repo: &Repo, runner: &FakeRunner, target: PathBuf) -> Decision {
+    let config = config();
+    let prepared = Prepared::new(target, &rules::resolve(&config)).unwrap();
+    try_run(&config, &repo.0, runner, prepared, false)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn removes_the_directive_if_the_test_passes_without_it() {
+    let repo = Repo::new("remove");
+    let target = repo.add("tests/ui/a.rs", TEST);
+    let runner = FakeRunner::new(|_| TestStatus::Passed);
+
+    let decision = decide(&repo, &runner, target.clone()).await;
+    assert_eq!(decision.outcome, RunOutcome::RemoveOk);
+    assert!(decision.baseline_passed);
+    assert_eq!(
+        std::fs::read_to_string(&target).unwrap(),
+        "//@ check-pass\n\nfn main() {}\n"
+    );
+    // The sanity check, then the removal.
+    assert_eq!(runner.runs.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn finds_the_results_of_run_make_tests_under_their_directory() {
+    let repo = Repo::new("run-make");
+    let target = repo.add("tests/run-make/foo/rmake.rs", TEST);
+    let runner = FakeRunner::new(|_| TestStatus::Passed);
+
+    let decision = decide(&repo, &runner, target.clone()).await;
+    assert_eq!(decision.outcome, RunOutcome::RemoveOk);
+    assert_eq!(
+        std::fs::read_to_string(&target).unwrap(),
+        "//@ check-pass\n\nfn main() {}\n"
+    );
+}
+
+#[tokio::test]
+async fn scans_and_removes_directives_of_makefiles() {
+    let repo = Repo::new("makefile");
+    let target = repo.add(
+        "tests/run-make/foo/Makefile",
+        "# ignore-debug\n\nall:\n\t$(RUSTC) foo.rs\n",
+    );
+    let mut config = config();
+    config.target_directories = BTreeSet::from([PathBuf::from("tests/run-make")]);
+
+    let scan = scan::scan(&config, &repo.0);
+    assert_eq!(scan.candidates, BTreeSet::from([target.clone()]));
+
+    let runner = FakeRunner::new(|_| TestStatus::Passed);
+    let decision = decide(&repo, &runner, target.clone()).await;
+    assert_eq!(decision.outcome, RunOutcome::RemoveOk);
+    assert_eq!(
+        std::fs::read_to_string(&target).unwrap(),
+        "\nall:\n\t$(RUSTC) foo.rs\n"
+    );
+}
+
+#[tokio::test]
+async fn replaces_the_directive_if_only_the_replacement_passes() {
+    let repo = Repo::new("replace");
+    let target = repo.add("tests/ui/a.rs", TEST);
+    let runner = FakeRunner::new(|src| {
+        if src.contains("-Cdebug-assertions=no") {
+            TestStatus::Passed
+        } else {
+            TestStatus::Failed
+        }
+    });
+
+    let decision = decide(&repo, &runner, target.clone()).await;
+    assert_eq!(decision.outcome, RunOutcome::ReplaceOk);
+    let src = std::fs::read_to_string(&target).unwrap();
+    assert!(!src.contains("ignore-debug"));
+    assert!(src.contains("//@ compile-flags: -Cdebug-assertions=no"));
+}
+
+#[tokio::test]
+async fn restores_tests_and_snapshots_if_every_rule_fails() {
+    let repo = Repo::new("unmodified");
+    let target = repo.add("tests/ui/a.rs", TEST);
+    let snapshot = repo.add("tests/ui/a.stderr", "original\n");
+    let runner = FakeRunner {
+        bless: true,
+        ..FakeRunner::new(|_| TestStatus::Failed)
+    };
+
+    let decision = decide(&repo, &runner, target.clone()).await;
+    assert_eq!(decision.outcome, RunOutcome::UnmodifiedOk);
+    assert!(decision.changes.is_empty());
+    assert_eq!(std::fs::read_to_string(&target).unwrap(), TEST);
+    assert_eq!(std::fs::read_to_string(&snapshot).unwrap(), "original\n");
+    // Sanity check, removal, replacement.
+    assert_eq!(runner.runs.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn reuses_cached_edits_until_their_inputs_change() {
+    let repo = Repo::new("cache");
+    let target = repo.add("tests/ui/a.rs", TEST);
+    let snapshot = repo.add("tests/ui/a.stderr", "original\n");
+    let runner = FakeRunner {
+        bless: true,
+        ..FakeRunner::new(|_| TestStatus::Passed)
+    };
+    let config = config();
+    let baseline_key = cache::Cache::baseline_key(&target).unwrap();
+    let key = cache::Cache::key(&config, &baseline_key);
+
+    let decision = decide(&repo, &runner, target.clone()).await;
+    assert_eq!(decision.outcome, RunOutcome::RemoveOk);
+    let edited = std::fs::read_to_string(&target).unwrap();
+    let mut cache = cache::Cache::default();
+    cache.insert(
+        &repo.0,
+        key.clone(),
+        &TestRecord {
+            changes: decision.changes,
+            snapshot_changes: decision.snapshot_changes,
+            rule: decision.rule,
+            ..record(decision.outcome)
+        },
+    );
+
+    // Back to the inputs the outcome was cached for, e.g. after a rebase.
+    std::fs::write(&target, TEST).unwrap();
+    std::fs::write(&snapshot, "original\n").unwrap();
+    assert_eq!(cache::Cache::baseline_key(&target).unwrap(), baseline_key);
+    let reused = cache
+        .reuse(&repo.0, &target, &key, &rules::resolve(&config))
+        .unwrap()
+        .unwrap();
+    assert_eq!(reused.outcome, RunOutcome::RemoveOk);
+    assert_eq!(std::fs::read_to_string(&target).unwrap(), edited);
+    assert_eq!(std::fs::read_to_string(&snapshot).unwrap(), "blessed\n");
+
+    // Any other snapshot or config makes for another key.
+    std::fs::write(&target, TEST).unwrap();
+    assert_ne!(cache::Cache::baseline_key(&target).unwrap(), baseline_key);
+    let other = Config {
+        snapshot_outlier_lines: config.snapshot_outlier_lines + 1,
+        ..self::config()
+    };
+    let other_key = cache::Cache::key(&other, &baseline_key);
+    assert_ne!(other_key, key);
+    assert!(cache
+        .reuse(&repo.0, &target, &other_key, &rules::resolve(&other))
+        .unwrap()
+        .is_none());
+    assert_eq!(std::fs::read_to_string(&target).unwrap(), TEST);
+}
+
+#[tokio::test]
+async fn keeps_edits_only_if_they_pass_in_every_worktree() {
+    let repo = Repo::new("worktrees");
+    for worktree in ["passing", "failing"] {
+        std::fs::create_dir_all(repo.0.join(worktree).join("tests/ui")).unwrap();
+        repo.add(&format!("{worktree}/tests/ui/a.rs"), TEST);
+    }
+    let a = repo.add("tests/ui/a.rs", TEST);
+    // Missing from both worktrees.
+    let b = repo.add("tests/ui/b.rs", TEST);
+    let runner = FakeRunner {
+        fails_under: Some(repo.0.join("failing")),
+        ..FakeRunner::new(|_| TestStatus::Passed)
+    };
+    let config = Config {
+        worktrees: vec![PathBuf::from("passing"), PathBuf::from("failing")],
+        ..config()
+    };
+    let rules = rules::resolve(&config);
+
+    let prepared = Prepared::new(a.clone(), &rules).unwrap();
+    let decision = try_run(&config, &repo.0, &runner, prepared, false)
+        .await
+        .unwrap();
+    assert!(decision.outcome.keeps_directive());
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), TEST);
+    assert_eq!(
+        decision.worktrees,
+        BTreeMap::from([
+            ("failing".to_string(), WorktreeResult::Failed),
+            ("passing".to_string(), WorktreeResult::Passed),
+        ])
+    );
+    for worktree in ["passing", "failing"] {
+        let path = repo.0.join(worktree).join("tests/ui/a.rs");
+        assert_eq!(std::fs::read_to_string(path).unwrap(), TEST);
+    }
+
+    let prepared = Prepared::new(b.clone(), &rules).unwrap();
+    let decision = try_run(&config, &repo.0, &runner, prepared, false)
+        .await
+        .unwrap();
+    assert_eq!(decision.outcome, RunOutcome::RemoveOk);
+    assert!(decision
+        .worktrees
+        .values()
+        .all(|&result| result == WorktreeResult::Missing));
+}
+
+#[tokio::test]
+async fn lets_the_classifier_settle_ambiguous_results() {
+    let repo = Repo::new("classifier");
+    let runner = FakeRunner::new(|_| TestStatus::Failed);
+    let decide_with = |classifier: &str| {
+        let mut config = config();
+        config.classifier = Some(classifier.to_string());
+        let target = repo.add("tests/ui/a.rs", TEST);
+        let runner = &runner;
+        let repo = &repo;
+        async move {
+            let prepared = Prepared::new(target, &rules::resolve(&config)).unwrap();
+            try_run(&config, &repo.0, runner, prepared, false)
+                .await
+                .unwrap()
+        }
+    };
+
+    // The hook gets the result and the diff of the edit.
+    let decision = decide_with(
+        "grep -q '\"result\":\"failed\",\"diff\":\"--- a/tests/ui/a.rs' && echo accept",
+    )
+    .await;
+    assert_eq!(decision.outcome, RunOutcome::RemoveOk);
+    assert_eq!(
+        std::fs::read_to_string(repo.0.join("tests/ui/a.rs")).unwrap(),
+        "//@ check-pass\n\nfn main() {}\n"
+    );
+
+    let decision = decide_with("echo 'manual stderr mentions SIGSEGV'").await;
+    assert_eq!(decision.outcome, RunOutcome::NeedsManualReview);
+    assert_eq!(
+        decision.review_hint.as_deref(),
+        Some("the `classifier` hook asked for manual review: stderr mentions SIGSEGV")
+    );
+    assert_eq!(
+        std::fs::read_to_string(repo.0.join("tests/ui/a.rs")).unwrap(),
+        TEST
+    );
+
+    // Anything else leaves the failures to the tool.
+    let decision = decide_with("echo maybe").await;
+    assert_eq!(decision.outcome, RunOutcome::UnmodifiedOk);
+}
+
+#[tokio::test]
+async fn batches_keep_each_test_under_the_first_rule_it_passes_with() {
+    let repo = Repo::new("batch");
+    let removable = repo.add("tests/ui/removable.rs", TEST);
+    let replaceable = repo.add(
+        "tests/ui/replaceable.rs",
+        &format!("{TEST}// needs the replacement\n"),
+    );
+    let broken = repo.add("tests/ui/broken.rs", &format!("{TEST}// broken\n"));
+    let runner = FakeRunner::new(|src| {
+        if src.contains("broken")
+            || (src.contains("needs the replacement") && !src.contains("debug-assertions=no"))
+        {
+            TestStatus::Failed
+        } else {
+            TestStatus::Passed
+        }
+    });
+
+    let results = batch::run_batch(
+        &config(),
+        &repo.0,
+        &runner,
+        [&removable, &replaceable, &broken],
+    )
+    .await
+    .unwrap();
+    let outcomes: BTreeMap<_, _> = results
+        .into_iter()
+        .map(|(target, (decision, _))| (target, decision.outcome))
+        .collect();
+    assert_eq!(outcomes[&removable], RunOutcome::RemoveOk);
+    assert_eq!(outcomes[&replaceable], RunOutcome::ReplaceOk);
+    assert_eq!(outcomes[&broken], RunOutcome::UnmodifiedOk);
+    assert_eq!(
+        std::fs::read_to_string(&broken).unwrap(),
+        format!("{TEST}// broken\n")
+    );
+    // One pass per rule.
+    assert_eq!(runner.runs.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn workers_process_every_test_once_with_several_jobs() {
+    let repo = Repo::new("workers");
+    let targets: BTreeSet<PathBuf> = ["a", "a.b", "c", "d"]
+        .map(|name| repo.add(&format!("tests/ui/{name}.rs"), TEST))
+        .into();
+    let runner = FakeRunner::new(|_| TestStatus::Passed);
+    let scratch_root = repo.0.join("scratch");
+    let work = workers::Work {
+        targets: &targets,
+        verified_baselines: &BTreeSet::new(),
+        estimates: &BTreeMap::new(),
+        deadline: None,
+        runner: &runner,
+    };
+
+    let mut finished = BTreeMap::new();
+    workers::run_parallel(
+        &config(),
+        &repo.0,
+        work,
+        NonZeroUsize::new(2).unwrap(),
+        &scratch_root,
+        |event| {
+            if let workers::WorkerEvent::Finished(target, decision, _) = event {
+                assert!(finished.insert(target, decision.outcome).is_none());
+            }
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        finished.keys().collect::<BTreeSet<_>>(),
+        targets.iter().collect()
+    );
+    assert!(finished
+        .values()
+        .all(|&outcome| outcome == RunOutcome::RemoveOk));
+    assert!(!scratch_root.exists());
+}
+
+/// Fails every invocation without reporting any result, like `x` failing to build the compiler.
+struct BrokenRunner;
+
+impl TestRunner for BrokenRunner {
+    fn run<'a>(
+        &'a self,
+        config: &'a Config,
+        rustc_repo_path: &'a Path,
+        targets: &'a [&'a Path],
+    ) -> BoxFuture<'a, Result<process::Captured>> {
+        self.run_suite(config, rustc_repo_path, "", targets)
+    }
+
+    fn run_suite<'a>(
+        &'a self,
+        _config: &'a Config,
+        _rustc_repo_path: &'a Path,
+        _suite: &'a str,
+        _targets: &'a [&'a Path],
+    ) -> BoxFuture<'a, Result<process::Captured>> {
+        #[cfg(unix)]
+        let status = std::os::unix::process::ExitStatusExt::from_raw(1 << 8);
+        #[cfg(windows)]
+        let status = std::os::windows::process::ExitStatusExt::from_raw(1);
+        Box::pin(async move {
+            Ok(process::Captured {
+                status,
+                timed_out: false,
+                stdout: String::new(),
+                stderr: "error: failed to build the compiler\n".to_string(),
+            })
+        })
+    }
+}
+
+#[tokio::test]
+async fn batches_put_every_test_back_if_x_fails_without_results() {
+    let repo = Repo::new("batch-broken");
+    let a = repo.add("tests/ui/a.rs", TEST);
+    let b = repo.add("tests/ui/b.rs", TEST);
+
+    let error = batch::run_batch(&config(), &repo.0, &BrokenRunner, [&a, &b])
+        .await
+        .unwrap_err();
+    assert!(
+        format!("{error:?}").contains("failed to build the compiler"),
+        "{error:?}"
+    );
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), TEST);
+    assert_eq!(std::fs::read_to_string(&b).unwrap(), TEST);
+}
+
+#[tokio::test]
+async fn workers_leave_tests_failing_without_any_edit_for_review() {
+    let repo = Repo::new("broken-baseline");
+    let targets: BTreeSet<PathBuf> = ["a", "b"]
+        .map(|name| repo.add(&format!("tests/ui/{name}.rs"), TEST))
+        .into();
+    let work = workers::Work {
+        targets: &targets,
+        verified_baselines: &BTreeSet::new(),
+        estimates: &BTreeMap::new(),
+        deadline: None,
+        runner: &BrokenRunner,
+    };
+
+    let mut finished = BTreeMap::new();
+    workers::run_parallel(
+        &config(),
+        &repo.0,
+        work,
+        NonZeroUsize::MIN,
+        &repo.0.join("scratch"),
+        |event| {
+            if let workers::WorkerEvent::Finished(target, decision, _) = event {
+                finished.insert(target, decision);
+            }
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(finished.len(), 2);
+    for (target, decision) in &finished {
+        assert_eq!(decision.outcome, RunOutcome::NeedsManualReview);
+        let rel_path = paths::repo_relative(&repo.0, target);
+        assert_eq!(
+            decision.review_hint.as_deref(),
+            Some(&*format!(
+                "`{rel_path}` fails without any edit: the test failed"
+            ))
+        );
+        assert_eq!(std::fs::read_to_string(target).unwrap(), TEST);
+    }
+}
+
+/// Run `git <args>` in `repo`, which must succeed.
+pub(crate) fn git(repo: &Path, args: &[&str]) -> String {
+    let output = std::process::Command::new("git")
+        .current_dir(repo)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "`git {}` failed", args.join(" "));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn commits_each_kept_edit_with_its_snapshots() {
+    let repo = Repo::new("commit");
+    repo.add("tests/ui/a.rs", TEST);
+    repo.add("tests/ui/a.stderr", "original\n");
+    repo.add("tests/ui/b.rs", TEST);
+    git(&repo.0, &["init", "-q"]);
+    git(&repo.0, &["config", "user.name", "test"]);
+    git(&repo.0, &["config", "user.email", "test@example.com"]);
+    git(&repo.0, &["add", "-A"]);
+    git(&repo.0, &["commit", "-q", "-m", "init"]);
+
+    repo.add("tests/ui/a.rs", "//@ check-pass\n\nfn main() {}\n");
+    repo.add("tests/ui/a.stderr", "blessed\n");
+    // Neither kept by the run nor to be committed with its changes.
+    repo.add("tests/ui/b.rs", "// edited by hand\n");
+    let records = BTreeMap::from([
+        (
+            "tests/ui/a.rs".to_string(),
+            TestRecord {
+                snapshot_changes: vec![snapshots::SnapshotChange {
+                    path: "tests/ui/a.stderr".to_string(),
+                    added_lines: 1,
+                    removed_lines: 1,
+                }],
+                ..record(RunOutcome::RemoveOk)
+            },
+        ),
+        (
+            "tests/ui/b.rs".to_string(),
+            record(RunOutcome::UnmodifiedOk),
+        ),
+    ]);
+    commit_changes(&config(), &repo.0, &records).unwrap();
+
+    assert_eq!(
+        git(&repo.0, &["log", "--format=%s"]),
+        "Remove `ignore-debug` from `tests/ui/a.rs`\ninit\n"
+    );
+    assert_eq!(
+        git(&repo.0, &["show", "--name-only", "--format=", "HEAD"]),
+        "tests/ui/a.rs\ntests/ui/a.stderr\n"
+    );
+    assert_eq!(
+        git(&repo.0, &["status", "--porcelain"]),
+        " M tests/ui/b.rs\n"
+    );
+
+    // Nothing is left to commit the second time around.
+    commit_changes(&config(), &repo.0, &records).unwrap();
+    assert_eq!(git(&repo.0, &["rev-list", "--count", "HEAD"]), "2\n");
+}
+
+#[test]
+fn reports_count_each_outcome() {
+    let state = run_state(
+        Path::new("/rustc"),
+        [
+            ("tests/ui/a.rs", record(RunOutcome::RemoveOk)),
+            ("tests/ui/b.rs", record(RunOutcome::RemoveOk)),
+            ("tests/ui/c.rs", record(RunOutcome::UnmodifiedOk)),
+        ],
+    );
+
+    let report = report::format_report(&config(), &state);
+    assert!(report.contains("- RemoveOk: 2\n"), "{report}");
+    assert!(report.contains("- UnmodifiedOk: 1\n"), "{report}");
+    assert!(report.contains("- ReplaceOk: 0\n"), "{report}");
+}
+
+#[test]
+fn flags_tests_failing_again_after_a_previous_run_removed_their_directive() {
+    let mut state = run_state(
+        Path::new("/rustc"),
+        [
+            ("tests/ui/a.rs", record(RunOutcome::UnmodifiedOk)),
+            ("tests/ui/b.rs", record(RunOutcome::UnmodifiedOk)),
+            ("tests/ui/c.rs", record(RunOutcome::RemoveOk)),
+        ],
+    );
+    let previous = BTreeMap::from([
+        (
+            "tests/ui/a.rs".to_string(),
+            ("old".to_string(), RunOutcome::RemoveOk),
+        ),
+        (
+            "tests/ui/b.rs".to_string(),
+            ("old".to_string(), RunOutcome::UnmodifiedOk),
+        ),
+        (
+            "tests/ui/c.rs".to_string(),
+            ("old".to_string(), RunOutcome::RemoveOk),
+        ),
+    ]);
+
+    let found = regressions(&state.records, &previous);
+    assert_eq!(
+        found,
+        BTreeMap::from([("tests/ui/a.rs".to_string(), "old".to_string())])
+    );
+
+    state.status = Some(ExitStatus::Regression);
+    state.regressions = found;
+    let report = report::format_report(&config(), &state);
+    assert!(report.contains("## Regressions"), "{report}");
+    assert!(
+        report.contains("`tests/ui/a.rs` (verified by run `old`)"),
+        "{report}"
+    );
+}
+
+#[test]
+fn rolls_back_the_edits_a_dead_run_left_open() {
+    let repo = Repo::new("journal");
+    let runs_root = repo.0.join("runs");
+    let journal = runs_root.join("journal");
+    std::fs::create_dir_all(&journal).unwrap();
+    // The run died right after blessing the snapshots of `a.rs`, while someone changed `b.rs`.
+    let a = repo.add("tests/ui/a.rs", "//@ check-pass\n");
+    let a_stderr = repo.add("tests/ui/a.stderr", "blessed\n");
+    let a_stdout = repo.add("tests/ui/a.stdout", "new\n");
+    let b = repo.add("tests/ui/b.rs", "// changed by hand\n");
+    std::fs::write(journal.join("0.orig"), TEST).unwrap();
+    std::fs::write(journal.join("0.0.snapshot"), "original\n").unwrap();
+    std::fs::write(journal.join("1.orig"), TEST).unwrap();
+    let entries = [
+        serde_json::json!({
+            "entry": "edit",
+            "id": 0,
+            "path": a,
+            "original_sha256": manifest::sha256_hex(TEST.as_bytes()),
+            "edited_sha256": manifest::sha256_hex(b"//@ check-pass\n"),
+            "backup": journal.join("0.orig"),
+            "snapshots": { a_stderr.to_str().unwrap(): journal.join("0.0.snapshot") },
+            "changes": [],
+        }),
+        serde_json::json!({
+            "entry": "edit",
+            "id": 1,
+            "path": b,
+            "original_sha256": manifest::sha256_hex(TEST.as_bytes()),
+            "edited_sha256": manifest::sha256_hex(b"//@ check-pass\n"),
+            "backup": journal.join("1.orig"),
+            "snapshots": {},
+            "changes": [],
+        }),
+    ];
+    let lines: String = entries.iter().map(|entry| format!("{entry}\n")).collect();
+    // Along with an entry torn by the crash.
+    std::fs::write(journal.join("journal.jsonl"), lines + "{\"entry\":\"sett").unwrap();
+
+    assert_eq!(
+        journal::interrupted(&runs_root).unwrap(),
+        [a.clone(), b.clone()]
+    );
+    assert_eq!(
+        journal::roll_back(&runs_root).unwrap(),
+        std::slice::from_ref(&a)
+    );
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), TEST);
+    assert_eq!(std::fs::read_to_string(&a_stderr).unwrap(), "original\n");
+    assert!(!a_stdout.exists());
+    assert_eq!(std::fs::read_to_string(&b).unwrap(), "// changed by hand\n");
+    assert!(journal::interrupted(&runs_root).unwrap().is_empty());
+}
+
+#[test]
+fn explains_why_tests_were_left_for_review() {
+    let record = TestRecord {
+        review_hint: Some(
+            "the test has 2 FileCheck `CHECK` line(s) which may need adjusting for debug \
+             assertions, set `try_filecheck_tests` to try it anyway"
+                .to_string(),
+        ),
+        ..record(RunOutcome::NeedsManualReview)
+    };
+    let mut state = run_state(Path::new("/rustc"), [("tests/codegen/a.rs", record)]);
+    state.skipped = BTreeMap::from([("tests/ui/b.rs".to_string(), SkipReason::NoDirective)]);
+
+    let explanation = explain::format_explanation("tests/codegen/a.rs", &state);
+    assert!(
+        explanation.starts_with("`tests/codegen/a.rs`: Needs manual review (run test)"),
+        "{explanation}"
+    );
+    assert!(
+        explanation.contains("2 FileCheck `CHECK` line(s)"),
+        "{explanation}"
+    );
+    assert!(
+        explanation.contains("inspect its `CHECK` lines"),
+        "{explanation}"
+    );
+    let explanation = explain::format_explanation("tests/ui/b.rs", &state);
+    assert!(explanation.contains("skipped"), "{explanation}");
+    let explanation = explain::format_explanation("tests/ui/c.rs", &state);
+    assert!(explanation.contains("did not come across"), "{explanation}");
+}
+
+#[test]
+fn writes_partial_results_after_every_test() {
+    let repo = Repo::new("checkpoint");
+    let mut state = run_state(&repo.0, []);
+    state.pending = ["tests/ui/a.rs", "tests/ui/b.rs"].map(String::from).into();
+    let config = config();
+    let (state_path, report_path) = (repo.0.join("state.json"), repo.0.join("report.md"));
+    let mut checkpoint =
+        checkpoint::Checkpoint::new(&config, state, state_path.clone(), report_path.clone());
+    assert_eq!(RunState::load(&state_path).unwrap().pending.len(), 2);
+
+    checkpoint.record("tests/ui/a.rs", &record(RunOutcome::RemoveOk));
+    let state = RunState::load(&state_path).unwrap();
+    assert!(state.records.contains_key("tests/ui/a.rs"));
+    assert_eq!(state.pending, ["tests/ui/b.rs".to_string()].into());
+    assert!(state.status.is_none());
+    let report = std::fs::read_to_string(&report_path).unwrap();
+    assert!(report.contains("- RemoveOk: 1\n"), "{report}");
+    assert!(!repo.0.join("state.json.tmp").exists());
+}
+
+#[test]
+fn drafts_issues_for_tests_left_with_their_directive() {
+    let repo = Repo::new("issue-drafts");
+    repo.add("tests/ui/a.rs", TEST);
+    let logs = repo.0.join("logs");
+    std::fs::create_dir_all(logs.join("tests/ui")).unwrap();
+    std::fs::write(
+        logs.join("tests/ui/a.rs.log"),
+        "==== `x test` on the edited test (exit status: 1) ====\n---- stdout ----\n\
+         { \"type\": \"test\", \"name\": \"[ui] tests/ui/a.rs\", \"event\": \"failed\", \
+         \"stdout\": \"error: attempt to add with overflow\\n\" }\n---- stderr ----\n\n",
+    )
+    .unwrap();
+    let mut state = run_state(
+        &repo.0,
+        [
+            ("tests/ui/a.rs", record(RunOutcome::UnmodifiedOk)),
+            ("tests/ui/b.rs", record(RunOutcome::RemoveOk)),
+        ],
+    );
+    state.logs = Some(logs);
+
+    let drafts = issue_drafts::drafts_of(&repo.0, &state);
+    assert_eq!(drafts.len(), 1);
+    assert_eq!(
+        drafts[0].title,
+        "`tests/ui/a.rs` still fails without `ignore-debug`"
+    );
+    let body = &drafts[0].body;
+    assert!(body.contains("-//@ ignore-debug\n"), "{body}");
+    assert!(
+        body.contains("error: attempt to add with overflow"),
+        "{body}"
+    );
+}
+
+/// Lines of a test around its `ignore-debug` directives, with any line endings, or none.
+fn any_source() -> impl Strategy<Value = String> {
+    let line = prop_oneof![
+        Just("//@ ignore-debug".to_string()),
+        Just("// ignore-debug: reason".to_string()),
+        Just("//@ compile-flags: -O".to_string()),
+        "[^\r\n]{0,30}",
+    ];
+    let ending = prop_oneof![Just(""), Just("\n"), Just("\r\n"), Just("\r")];
+    prop::collection::vec((line, ending), 0..10).prop_map(|lines| {
+        lines
+            .into_iter()
+            .map(|(line, ending)| line + ending)
+            .collect()
+    })
+}
+
+proptest! {
+    #[test]
+    fn reverted_edits_restore_tests_and_snapshots_byte_for_byte(
+        src in any_source(),
+        snapshot in "\\PC*",
+        replace in any::<bool>(),
+    ) {
+        let repo = Repo::new("revert");
+        let target = repo.add("tests/ui/a.rs", &src);
+        let stderr = repo.add("tests/ui/a.stderr", &snapshot);
+        let rule = &rules::default_rules()[usize::from(replace)];
+
+        let applied = AppliedEdit::apply(&target, |src| rule.apply(src, Syntax::of(&target))).unwrap();
+        // As `--bless` would, along with a new snapshot.
+        std::fs::write(&stderr, "blessed").unwrap();
+        std::fs::write(target.with_extension("stdout"), "new").unwrap();
+        applied.revert().unwrap();
+
+        prop_assert_eq!(std::fs::read_to_string(&target).unwrap(), src);
+        prop_assert_eq!(std::fs::read_to_string(&stderr).unwrap(), snapshot);
+        prop_assert!(!target.with_extension("stdout").exists());
+    }
+}
@@ -0,0 +1,46 @@
+//! `x test tidy` after each edit which passed, so that the changes kept pass CI's style checks
+//! (line length, directive syntax) the first time, see `tidy` in the config.
+
+use std::path::Path;
+
+use miette::{Context, IntoDiagnostic, Result};
+use tracing::*;
+
+use super::{build_lock, paths, process, x_command};
+use crate::config::Config;
+
+/// The tidy errors about `target`, after running tidy over the `rustc` repo. Bootstrap offers no
+/// way to run tidy on some files only, so errors about other files (e.g. already in the tree) are
+/// only logged.
+pub(super) async fn errors_in(
+    config: &Config,
+    rustc_repo_path: &Path,
+    target: &Path,
+) -> Result<Vec<String>> {
+    build_lock::wait_until_free(config, rustc_repo_path).await;
+    let output = process::run(x_command(config, rustc_repo_path, "test").arg("tidy"))
+        .await
+        .into_diagnostic()
+        .wrap_err("error trying to invoke `x test tidy`")?;
+    if output.status.success() {
+        return Ok(Vec::new());
+    }
+
+    let rel_path = paths::repo_relative(rustc_repo_path, target);
+    let (ours, others): (Vec<&str>, Vec<&str>) = output
+        .stderr
+        .lines()
+        .chain(output.stdout.lines())
+        .filter(|line| line.contains("tidy error"))
+        .partition(|line| line.contains(&rel_path));
+    if !others.is_empty() {
+        debug!(
+            "ignoring {} tidy error(s) about other files than `{rel_path}`",
+            others.len()
+        );
+    }
+    Ok(ours
+        .into_iter()
+        .map(|line| line.trim().to_string())
+        .collect())
+}
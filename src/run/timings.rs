@@ -0,0 +1,121 @@
+//! Where the time of a run goes, see `--timings`.
+//!
+//! Phases are timed wherever they happen, for every test, and summed up: with several jobs, they
+//! can add up to more than the duration of the run.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use console::style;
+
+use super::summary::format_duration;
+
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Phase {
+    /// Finding the candidate tests.
+    Scan,
+    /// Computing and writing edits, along with capturing the snapshots to revert them.
+    Edit,
+    /// Running `x`, from spawning it to its exit.
+    Invocation,
+    /// Parsing the test results out of the output of `x`.
+    Parse,
+    /// Restoring tests and their snapshots.
+    Revert,
+}
+
+impl Phase {
+    const ALL: [Phase; 5] = [
+        Phase::Scan,
+        Phase::Edit,
+        Phase::Invocation,
+        Phase::Parse,
+        Phase::Revert,
+    ];
+
+    fn description(self) -> &'static str {
+        match self {
+            Phase::Scan => "scan",
+            Phase::Edit => "edit",
+            Phase::Invocation => "x invocation",
+            Phase::Parse => "parse",
+            Phase::Revert => "revert",
+        }
+    }
+}
+
+/// Nanoseconds spent in each phase, indexed like [`Phase::ALL`].
+static NANOS: [AtomicU64; 5] = [const { AtomicU64::new(0) }; 5];
+
+/// Times each phase was entered.
+static COUNTS: [AtomicU64; 5] = [const { AtomicU64::new(0) }; 5];
+
+/// Start timing a run.
+pub(super) fn reset() {
+    for counter in NANOS.iter().chain(&COUNTS) {
+        counter.store(0, Ordering::SeqCst);
+    }
+}
+
+/// Time `phase` until the returned guard is dropped.
+pub(super) fn start(phase: Phase) -> Timer {
+    Timer {
+        phase,
+        start: Instant::now(),
+    }
+}
+
+pub(super) struct Timer {
+    phase: Phase,
+    start: Instant,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let idx = self.phase as usize;
+        let nanos = u64::try_from(self.start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        NANOS[idx].fetch_add(nanos, Ordering::SeqCst);
+        COUNTS[idx].fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Print the time spent in each phase to stderr, alongside the summary of the run, which took
+/// `total`.
+pub(super) fn print_timings(total: Duration) {
+    eprint!("{}", format_timings(total));
+}
+
+fn format_timings(total: Duration) -> String {
+    let label_width = Phase::ALL
+        .iter()
+        .map(|phase| phase.description().len())
+        .max()
+        .unwrap_or(0);
+    let mut out = format!(
+        "{}\n",
+        style("Timings (summed over tests)")
+            .for_stderr()
+            .bold()
+            .underlined()
+    );
+    for phase in Phase::ALL {
+        let spent = Duration::from_nanos(NANOS[phase as usize].load(Ordering::SeqCst));
+        let count = COUNTS[phase as usize].load(Ordering::SeqCst);
+        out.push_str(&format!(
+            "  {:<label_width$}  {:>9}  {:>6}x  {:>5.1}%\n",
+            phase.description(),
+            format_duration(spent),
+            count,
+            100.0 * spent.as_secs_f64() / total.as_secs_f64().max(f64::EPSILON),
+        ));
+    }
+    out.push_str(&format!(
+        "  {}  {:>9}\n",
+        style(format!("{:<label_width$}", "run"))
+            .for_stderr()
+            .bold(),
+        format_duration(total)
+    ));
+    out.push('\n');
+    out
+}
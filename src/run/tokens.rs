@@ -0,0 +1,106 @@
+//! Cooperation with a GNU make jobserver, so that the tool doesn't oversubscribe cores when run as
+//! part of a larger build.
+//!
+//! A token is held for the duration of every `x` invocation. Under `make`, tokens come from the
+//! inherited jobserver, the first one being the implicit token of this process. Otherwise, an
+//! explicit `--jobs` budget gets a jobserver of its own. Either way, the jobserver is handed down
+//! to `x` so that the cargo invocations of bootstrap draw from the same pool.
+
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use jobserver::{Acquired, Client};
+use miette::{Context, IntoDiagnostic, Result};
+use tokio::process::Command;
+use tracing::*;
+
+pub(super) struct Jobserver {
+    client: Client,
+    /// Whether the implicit token of this process is free to be used by an invocation. Only
+    /// inherited jobservers grant one.
+    implicit_available: AtomicBool,
+}
+
+static INHERITED: OnceLock<Option<Client>> = OnceLock::new();
+/// The jobserver of the current run, see [`configure`].
+static JOBSERVER: Mutex<Option<Arc<Jobserver>>> = Mutex::new(None);
+
+/// Pick up the jobserver of a parent `make`, if any. Must be called at the very start of `main`,
+/// before any file descriptor is opened, see [`Client::from_env`].
+pub fn inherit_jobserver() {
+    // SAFETY: nothing has opened a file descriptor yet, so any jobserver descriptors named in the
+    // environment can only have been inherited from the parent.
+    let client = unsafe { Client::from_env() };
+    if client.is_some() {
+        debug!("inherited a jobserver from the environment");
+    }
+    let _ = INHERITED.set(client);
+}
+
+/// Set up the jobserver for this run: the inherited one, or one with `budget` tokens.
+pub(super) fn configure(budget: Option<NonZeroUsize>) -> Result<()> {
+    let jobserver = match (INHERITED.get().cloned().flatten(), budget) {
+        (Some(client), _) => {
+            info!("acquiring jobserver tokens for `x` invocations");
+            Some(Arc::new(Jobserver {
+                client,
+                implicit_available: AtomicBool::new(true),
+            }))
+        }
+        (None, Some(budget)) => {
+            let client = Client::new(budget.get())
+                .into_diagnostic()
+                .wrap_err("failed to create a jobserver")?;
+            Some(Arc::new(Jobserver {
+                client,
+                implicit_available: AtomicBool::new(false),
+            }))
+        }
+        (None, None) => None,
+    };
+    *JOBSERVER.lock().unwrap() = jobserver;
+    Ok(())
+}
+
+/// A jobserver token, released on drop.
+pub(super) enum Token {
+    /// No jobserver is in use.
+    Unlimited,
+    Implicit(Arc<Jobserver>),
+    Acquired(#[allow(dead_code)] Acquired),
+}
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        if let Token::Implicit(jobserver) = self {
+            jobserver.implicit_available.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Wait for a token to run an `x` invocation with.
+pub(super) async fn acquire() -> std::io::Result<Token> {
+    let Some(jobserver) = JOBSERVER.lock().unwrap().clone() else {
+        return Ok(Token::Unlimited);
+    };
+    if jobserver
+        .implicit_available
+        .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
+        .is_ok()
+    {
+        return Ok(Token::Implicit(jobserver));
+    }
+    let client = jobserver.client.clone();
+    let acquired = tokio::task::spawn_blocking(move || client.acquire())
+        .await
+        .map_err(std::io::Error::other)??;
+    Ok(Token::Acquired(acquired))
+}
+
+/// Let `command` draw from the same jobserver, if any.
+pub(super) fn hand_down(command: &mut Command) {
+    if let Some(jobserver) = JOBSERVER.lock().unwrap().as_ref() {
+        jobserver.client.configure(command.as_std_mut());
+    }
+}
@@ -0,0 +1,62 @@
+//! Posting a summary of each run as a comment on a tracking issue or PR, see `tracking_issue` in
+//! the config. The tool keeps a single comment up to date rather than adding one per run.
+
+use miette::{bail, Result};
+use serde_json::{json, Value};
+
+use super::report;
+use super::state::RunState;
+use crate::github::{self, GitHub};
+
+/// Hidden marker identifying the comment maintained by the tool.
+const MARKER: &str = "<!-- rustc-less-ignore-debug status -->";
+
+/// Post the summary of `state` on `issue` (`<owner>/<repo>#<number>`), or update the comment
+/// posted by a previous run. Returns the URL of the comment.
+pub(super) fn post_summary(issue: &str, state: &RunState) -> Result<String> {
+    let Some((repo, number)) = github::parse_issue(issue) else {
+        bail!("`tracking_issue` must look like `<owner>/<repo>#<number>`, not `{issue}`");
+    };
+    let github = GitHub::new()?;
+    let body = json!({
+        "body": format!("{MARKER}\n{}", report::format_status_comment(state)),
+    });
+
+    let comment = match find_comment(&github, repo, number)? {
+        Some(id) => github.request(
+            "PATCH",
+            &format!("repos/{repo}/issues/comments/{id}"),
+            Some(&body),
+        )?,
+        None => github.request(
+            "POST",
+            &format!("repos/{repo}/issues/{number}/comments"),
+            Some(&body),
+        )?,
+    };
+    Ok(comment["html_url"].as_str().unwrap_or_default().to_string())
+}
+
+/// The id of the comment on `number` carrying [`MARKER`], if any.
+fn find_comment(github: &GitHub, repo: &str, number: u64) -> Result<Option<u64>> {
+    for page in 1.. {
+        let comments = github.request(
+            "GET",
+            &format!("repos/{repo}/issues/{number}/comments?per_page=100&page={page}"),
+            None,
+        )?;
+        let Value::Array(comments) = comments else {
+            bail!("unexpected list of comments: {comments}");
+        };
+        if comments.is_empty() {
+            break;
+        }
+        let ours = comments
+            .iter()
+            .find(|c| c["body"].as_str().is_some_and(|b| b.starts_with(MARKER)));
+        if let Some(comment) = ours {
+            return Ok(comment["id"].as_u64());
+        }
+    }
+    Ok(None)
+}
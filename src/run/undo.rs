@@ -0,0 +1,115 @@
+//! The `undo-last-run` command: putting back what the most recent run changed in the `rustc` repo,
+//! from its journal if it died midway, or else from its backup archive.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use miette::{bail, Context, IntoDiagnostic, Result};
+use tracing::*;
+
+use super::manifest::sha256_hex;
+use super::{journal, write_guard};
+use crate::history;
+
+/// Revert the tests the most recent run under `runs_root` edited in `rustc_repo_path`, along with
+/// the snapshots it blessed for them, printing each file restored or removed. Tests changed since
+/// are left alone. Returns the files restored or removed.
+pub fn undo_last_run(rustc_repo_path: &Path, runs_root: &Path) -> Result<Vec<PathBuf>> {
+    if !journal::interrupted(runs_root)?.is_empty() {
+        info!("the last run died while tests were edited, rolling back its journal");
+        let rolled_back = journal::roll_back(runs_root)?;
+        for path in &rolled_back {
+            println!("restored {}", path.display());
+        }
+        return Ok(rolled_back);
+    }
+
+    let Some((run_dir, state)) = history::list_runs(runs_root)?
+        .into_iter()
+        .rev()
+        .find_map(|(run_dir, state)| Some((run_dir, state?)))
+    else {
+        bail!("no run under `{}`", runs_root.display());
+    };
+    let Some(backup) = &state.backup else {
+        bail!(
+            help = "its changes can still be reverted with git",
+            "run `{}` has no backup archive",
+            run_dir.id
+        );
+    };
+    info!("undoing run `{}`", run_dir.id);
+    let originals = read_archive(backup)?;
+
+    let mut undone = Vec::new();
+    for (rel_path, edited_sha256) in &state.migrated {
+        let (Some(original), Some(record)) = (originals.get(rel_path), state.records.get(rel_path))
+        else {
+            warn!("`{rel_path}` is missing from the backup archive, leaving it alone");
+            continue;
+        };
+        let path = rustc_repo_path.join(rel_path);
+        let current = std::fs::read(&path)
+            .into_diagnostic()
+            .wrap_err(format!("failed to read `{}`", path.display()))?;
+        if current == *original {
+            continue;
+        }
+        if sha256_hex(&current) != *edited_sha256 {
+            warn!("`{rel_path}` changed since the run edited it, leaving it alone");
+            continue;
+        }
+        write_guard::restore(&path, original)
+            .into_diagnostic()
+            .wrap_err(format!("failed to restore `{}`", path.display()))?;
+        println!("restored {rel_path}");
+        undone.push(path);
+
+        for snapshot in &record.snapshot_changes {
+            let path = rustc_repo_path.join(&snapshot.path);
+            match originals.get(&snapshot.path) {
+                Some(original) => {
+                    write_guard::restore(&path, original)
+                        .into_diagnostic()
+                        .wrap_err(format!("failed to restore `{}`", path.display()))?;
+                    println!("restored {}", snapshot.path);
+                }
+                // Blessed into existence by the run.
+                None if path.exists() => {
+                    write_guard::remove_file(&path)
+                        .into_diagnostic()
+                        .wrap_err(format!("failed to remove `{}`", path.display()))?;
+                    println!("removed {}", snapshot.path);
+                }
+                None => continue,
+            }
+            undone.push(path);
+        }
+    }
+    if undone.is_empty() {
+        info!("nothing to undo");
+    }
+    Ok(undone)
+}
+
+/// Contents of the files in the backup archive at `path`, by repo-relative path.
+fn read_archive(path: &Path) -> Result<BTreeMap<String, Vec<u8>>> {
+    let read = || -> std::io::Result<BTreeMap<String, Vec<u8>>> {
+        let mut archive = tar::Archive::new(GzDecoder::new(File::open(path)?));
+        let mut files = BTreeMap::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let rel_path = entry.path()?.to_string_lossy().into_owned();
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            files.insert(rel_path, contents);
+        }
+        Ok(files)
+    };
+    read()
+        .into_diagnostic()
+        .wrap_err(format!("failed to read `{}`", path.display()))
+}
@@ -0,0 +1,174 @@
+//! The `verify` command: run the tests a run removed or replaced the directive of again, as they
+//! are in the working tree, without blessing anything, to confirm that the applied changes are
+//! green before pushing them. One `x test` invocation per suite.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use miette::{bail, Result};
+use tracing::*;
+
+use super::manifest::sha256_hex;
+use super::test_results::{self, TestStatus};
+use super::{paths, platform, remote, suites, test_logs, x_test, RunOutcome};
+use crate::config::Config;
+use crate::history;
+
+/// How a test fared in [`verify`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyResult {
+    Passed,
+    Failed,
+    /// Reported as ignored, e.g. because the directive is still there.
+    Ignored,
+    /// No result was reported for the test, e.g. because the build failed.
+    NotRun,
+    /// The test no longer exists.
+    Missing,
+}
+
+/// The outcome of [`verify`].
+#[derive(Debug)]
+pub struct Verification {
+    /// Id of the run whose changes were verified.
+    pub run: String,
+    /// By repo-relative path.
+    pub results: BTreeMap<String, VerifyResult>,
+    /// Tests whose contents differ from what the run left them as, e.g. because its changes were
+    /// not applied.
+    pub changed: Vec<String>,
+}
+
+impl Verification {
+    pub fn is_green(&self) -> bool {
+        self.results.values().all(|r| *r == VerifyResult::Passed)
+    }
+}
+
+/// Run the tests the latest run under `runs_root` which changed any (or the run with id `run`)
+/// removed or replaced the directive of, in `rustc_repo_path` as it is now.
+pub async fn verify(
+    config: &Config,
+    rustc_repo_path: &Path,
+    runs_root: &Path,
+    run: Option<&str>,
+) -> Result<Verification> {
+    let found = history::list_runs(runs_root)?
+        .into_iter()
+        .rev()
+        .filter(|(run_dir, _)| run.is_none_or(|id| run_dir.id == id))
+        .filter_map(|(run_dir, state)| Some((run_dir, state?)))
+        .find(|(_, state)| run.is_some() || state.records.values().any(|r| is_change(r.outcome)));
+    let Some((run_dir, state)) = found else {
+        match run {
+            Some(id) => bail!("no run `{id}` with a state under `{}`", runs_root.display()),
+            None => bail!(
+                "no run under `{}` removed or replaced any directive",
+                runs_root.display()
+            ),
+        }
+    };
+    if platform::launcher().is_none() {
+        bail!("bootstrap `x` is not available");
+    }
+    test_logs::configure(run_dir.logs_path());
+
+    let mut results = BTreeMap::new();
+    let mut changed = Vec::new();
+    let mut suites: BTreeMap<&str, Vec<PathBuf>> = BTreeMap::new();
+    for (rel_path, record) in &state.records {
+        if !is_change(record.outcome) {
+            continue;
+        }
+        let path = rustc_repo_path.join(rel_path);
+        let Ok(contents) = std::fs::read(&path) else {
+            results.insert(rel_path.clone(), VerifyResult::Missing);
+            continue;
+        };
+        if state
+            .migrated
+            .get(rel_path)
+            .is_some_and(|sha| *sha != sha256_hex(&contents))
+        {
+            changed.push(rel_path.clone());
+        }
+        suites
+            .entry(paths::suite_of(rel_path))
+            .or_default()
+            .push(path);
+    }
+
+    for (suite, targets) in suites {
+        info!("verifying {} test(s) of `{suite}`", targets.len());
+        let targets: Vec<&Path> = targets.iter().map(PathBuf::as_path).collect();
+        let filters: Vec<String> = targets
+            .iter()
+            .map(|t| paths::suite_filter(&paths::repo_relative(rustc_repo_path, t), suite))
+            .collect();
+        remote::push(rustc_repo_path, &targets).await?;
+        let output = x_test(
+            config,
+            rustc_repo_path,
+            &[Path::new(suite)],
+            &filters,
+            suites::profile(config, suite).stage,
+            false,
+        )
+        .await?;
+        let reported = test_results::parse_events(&output.stdout);
+        for target in targets {
+            let rel_path = paths::repo_relative(rustc_repo_path, target);
+            test_logs::append(&rel_path, "the test as applied (`verify`)", &output);
+            let result = match reported.get(paths::test_name(&rel_path)).map(|r| r.status) {
+                Some(TestStatus::Passed) => VerifyResult::Passed,
+                Some(TestStatus::Failed) => VerifyResult::Failed,
+                Some(TestStatus::Ignored) => VerifyResult::Ignored,
+                None => VerifyResult::NotRun,
+            };
+            results.insert(rel_path, result);
+        }
+    }
+    Ok(Verification {
+        run: state.id,
+        results,
+        changed,
+    })
+}
+
+fn is_change(outcome: RunOutcome) -> bool {
+    matches!(outcome, RunOutcome::RemoveOk | RunOutcome::ReplaceOk)
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for rel_path in &self.changed {
+            writeln!(
+                f,
+                "note: `{rel_path}` differs from what run {} left it as",
+                self.run
+            )?;
+        }
+        for (rel_path, result) in &self.results {
+            let result = match result {
+                VerifyResult::Passed => "ok",
+                VerifyResult::Failed => "FAILED",
+                VerifyResult::Ignored => "ignored",
+                VerifyResult::NotRun => "not run",
+                VerifyResult::Missing => "missing",
+            };
+            writeln!(f, "{result:<8} {rel_path}")?;
+        }
+        let passed = self
+            .results
+            .values()
+            .filter(|r| **r == VerifyResult::Passed)
+            .count();
+        writeln!(
+            f,
+            "{passed} of {} test(s) changed by run {} pass",
+            self.results.len(),
+            self.run
+        )
+    }
+}
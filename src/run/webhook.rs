@@ -0,0 +1,83 @@
+//! Notifying a webhook (chat bot, dashboard, ...) with a JSON summary when a run finishes or
+//! aborts, see `webhook_url` in the config.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use miette::{IntoDiagnostic, Result};
+use serde::Serialize;
+
+use super::state::RunState;
+use super::RunOutcome;
+use crate::exit_status::ExitStatus;
+use crate::history::RunDir;
+
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    run_id: &'a str,
+    status: ExitStatus,
+    exit_code: u8,
+    started_at: DateTime<Local>,
+    finished_at: DateTime<Local>,
+    duration_secs: f64,
+    /// Number of tests per outcome. Missing if the run aborted before recording any outcome.
+    counts: Option<BTreeMap<String, usize>>,
+    /// Tests left unprocessed, e.g. because of `--deadline`.
+    pending: usize,
+    report_path: Option<PathBuf>,
+    /// Why the run aborted, if it did.
+    error: Option<String>,
+}
+
+/// POST the summary of the run in `run_dir` to `url`. `result` is the outcome of the run, whose
+/// state (if it got that far) is read back from the run directory.
+pub(super) fn notify(
+    url: &str,
+    run_dir: &RunDir,
+    started_at: DateTime<Local>,
+    result: &Result<ExitStatus>,
+) -> Result<()> {
+    let state = RunState::load(&run_dir.state_path()).ok();
+    let report_path = run_dir.report_path();
+    let finished_at = Local::now();
+    let status = match result {
+        Ok(status) => *status,
+        Err(_) => ExitStatus::Error,
+    };
+    let payload = Payload {
+        run_id: &run_dir.id,
+        status,
+        exit_code: status as u8,
+        started_at,
+        finished_at,
+        duration_secs: (finished_at - started_at)
+            .to_std()
+            .unwrap_or_default()
+            .as_secs_f64(),
+        counts: state.as_ref().map(|state| {
+            RunOutcome::ALL
+                .into_iter()
+                .map(|outcome| {
+                    let count = state.records.values().filter(|r| r.outcome == outcome);
+                    (format!("{outcome:?}"), count.count())
+                })
+                .collect()
+        }),
+        pending: state.as_ref().map_or(0, |state| state.pending.len()),
+        report_path: report_path.exists().then_some(report_path),
+        error: result.as_ref().err().map(ToString::to_string),
+    };
+
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(Duration::from_secs(30)))
+        .build()
+        .into();
+    agent
+        .post(url)
+        .content_type("application/json")
+        .send(serde_json::to_string(&payload).into_diagnostic()?)
+        .map(drop)
+        .into_diagnostic()
+}
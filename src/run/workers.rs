@@ -0,0 +1,312 @@
+//! Processing tests concurrently, see `--jobs`.
+//!
+//! Every worker runs its own `x test` invocations against the stage 1 compiler shared by all
+//! workers. Bootstrap normally serializes invocations on a build directory with a lock, which
+//! workers bypass when there is more than one of them; compiletest already gives each test its own
+//! output directory under `build/<host>/test`, and each worker gets a scratch directory of its own
+//! for temporary files.
+//!
+//! Edits are serialized per group of tests whose snapshot files may overlap: `foo.rs` and
+//! `foo.bar.rs` both claim `foo.bar.stderr` as a companion (see [`super::snapshots`]), so
+//! reverting one while the other is being blessed would clobber its snapshot. Such tests are
+//! always processed one after the other.
+//!
+//! With more than one job, groups are started longest first going by the durations of their
+//! tests in previous runs, so that a slow test doesn't start last and hold up the end of the run.
+//!
+//! With a `--deadline`, tests whose duration in a previous run exceeds the remaining budget (or
+//! which have less than [`MIN_TIME_LEFT`] left, without a previous duration) are not started, in
+//! case shorter tests still fit.
+//!
+//! Groups are read and their edits computed ahead of time by a separate task, up to one group per
+//! job, so that a worker can start its next `x` invocation as soon as the previous one is done.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use futures_util::{stream, StreamExt as _, TryStreamExt as _};
+use miette::{Context, IntoDiagnostic, Result};
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tracing::*;
+
+use super::rules::{self, Rule};
+use super::runner::TestRunner;
+use super::{control, hooks, paths, try_run, Decision, Prepared, DEADLINE_REACHED, INTERRUPTED};
+use crate::config::Config;
+
+tokio::task_local! {
+    /// Scratch directory of the worker running the current task, if isolated.
+    static SCRATCH_DIR: PathBuf;
+}
+
+/// Reported by workers as tests are processed, in completion order.
+pub(super) enum WorkerEvent {
+    /// A test was started, with the number of groups prepared and waiting for a worker.
+    Started(PathBuf, usize),
+    Finished(PathBuf, Decision, Duration),
+}
+
+/// Scope an `x` invocation to the worker running the current task. Does nothing outside of
+/// isolated workers.
+pub(super) fn scope(command: &mut Command) {
+    let _ = SCRATCH_DIR.try_with(|scratch_dir| {
+        command
+            .arg("--bypass-bootstrap-lock")
+            .env("TMPDIR", scratch_dir);
+    });
+}
+
+/// Tests without a previous duration are not started with less than this much of the
+/// `--deadline` budget left.
+const MIN_TIME_LEFT: Duration = Duration::from_secs(30);
+
+/// The tests to process, with what is known about them from previous runs.
+#[derive(Clone, Copy)]
+pub(super) struct Work<'a> {
+    pub(super) targets: &'a BTreeSet<PathBuf>,
+    /// Tests whose unmodified version is known to pass, see [`super::cache`].
+    pub(super) verified_baselines: &'a BTreeSet<PathBuf>,
+    /// Durations in previous runs, by repo-relative path.
+    pub(super) estimates: &'a BTreeMap<String, Duration>,
+    /// See `--deadline`.
+    pub(super) deadline: Option<Instant>,
+    pub(super) runner: &'a dyn TestRunner,
+}
+
+/// Process `work` with up to `jobs` tests in flight, calling `on_event` as tests start and
+/// finish. With more than one job, each group of tests gets a scratch directory under
+/// `scratch_root`, which is removed once the group is done.
+///
+/// On the first error, no new tests are started and the error is returned once the tests in
+/// flight have been restored.
+pub(super) async fn run_parallel(
+    config: &Config,
+    rustc_repo_path: &Path,
+    work: Work<'_>,
+    jobs: NonZeroUsize,
+    scratch_root: &Path,
+    mut on_event: impl FnMut(WorkerEvent),
+) -> Result<()> {
+    let isolate = jobs.get() > 1;
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut groups = edit_groups(work.targets);
+    if isolate {
+        schedule_longest_first(&mut groups, rustc_repo_path, work.estimates);
+    }
+
+    let (queue_tx, queue_rx) = mpsc::channel(jobs.get());
+    let queued = &AtomicUsize::new(0);
+    let rules = &rules::resolve(config);
+    let prepare = async move {
+        for group in groups {
+            let rules = rules.clone();
+            let prepared = tokio::task::spawn_blocking(move || prepare_group(group, &rules))
+                .await
+                .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+            queued.fetch_add(1, Ordering::SeqCst);
+            if queue_tx.send(prepared).await.is_err() {
+                // The workers stopped early.
+                break;
+            }
+        }
+    };
+
+    let work = async move {
+        let queue = stream::unfold(queue_rx, |mut queue_rx| async move {
+            let group = queue_rx.recv().await?;
+            Some((group, queue_rx))
+        });
+        let result = queue
+            .enumerate()
+            .map(|(i, group)| {
+                queued.fetch_sub(1, Ordering::SeqCst);
+                let tx = tx.clone();
+                let scratch_dir = isolate.then(|| scratch_root.join(format!("group-{i}")));
+                async move {
+                    let group = run_group(config, rustc_repo_path, group, work, queued, &tx);
+                    let Some(scratch_dir) = scratch_dir else {
+                        return group.await;
+                    };
+                    std::fs::create_dir_all(&scratch_dir)
+                        .into_diagnostic()
+                        .wrap_err(format!(
+                            "failed to create worker directory `{}`",
+                            scratch_dir.display()
+                        ))?;
+                    let result = SCRATCH_DIR.scope(scratch_dir.clone(), group).await;
+                    if let Err(e) = std::fs::remove_dir_all(&scratch_dir) {
+                        debug!("failed to remove `{}`: {e}", scratch_dir.display());
+                    }
+                    result
+                }
+            })
+            .buffer_unordered(jobs.get())
+            .try_collect::<()>()
+            .await;
+        // Only succeeds if every group cleaned up after itself.
+        let _ = std::fs::remove_dir(scratch_root);
+        result
+    };
+    let events = async {
+        while let Some(event) = rx.recv().await {
+            on_event(event);
+        }
+    };
+    let ((), result, ()) = tokio::join!(prepare, work, events);
+    result
+}
+
+/// A group of tests, each read and edited ahead of time.
+type PreparedGroup = Vec<(PathBuf, Result<Prepared>)>;
+
+fn prepare_group(group: Vec<PathBuf>, rules: &[Rule]) -> PreparedGroup {
+    group
+        .into_iter()
+        .map(|target| (target.clone(), Prepared::new(target, rules)))
+        .collect()
+}
+
+async fn run_group(
+    config: &Config,
+    rustc_repo_path: &Path,
+    group: PreparedGroup,
+    work: Work<'_>,
+    queued: &AtomicUsize,
+    tx: &mpsc::UnboundedSender<WorkerEvent>,
+) -> Result<()> {
+    for (target, prepared) in group {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        if let Some(deadline) = work.deadline {
+            let rel_path = paths::repo_relative(rustc_repo_path, &target);
+            let estimate = work.estimates.get(&rel_path).copied();
+            if deadline.saturating_duration_since(Instant::now())
+                < estimate.unwrap_or(MIN_TIME_LEFT)
+            {
+                if !DEADLINE_REACHED.swap(true, Ordering::SeqCst) {
+                    warn!("running out of time, only starting tests which fit in the budget");
+                }
+                debug!("`{rel_path}` does not fit in the remaining budget");
+                continue;
+            }
+        }
+        let _ = tx.send(WorkerEvent::Started(
+            target.clone(),
+            queued.load(Ordering::SeqCst),
+        ));
+        let start = Instant::now();
+        let baseline_verified = work.verified_baselines.contains(&target);
+        let rel_path = paths::repo_relative(rustc_repo_path, &target);
+        let span = info_span!(
+            "test",
+            path = %rel_path,
+            strategy = field::Empty,
+            outcome = field::Empty,
+        );
+        let decision = match prepared {
+            Ok(prepared) => {
+                control::scope(
+                    rel_path,
+                    try_run(
+                        config,
+                        rustc_repo_path,
+                        work.runner,
+                        prepared,
+                        baseline_verified,
+                    ),
+                )
+                .instrument(span.clone())
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        let duration = start.elapsed();
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            // `x` was stopped by the interruption (or refused to start), so the outcome can't be
+            // trusted. The test has been restored either way.
+            return Ok(());
+        }
+        let decision = decision?;
+        span.record("strategy", decision.rule.as_deref().unwrap_or("none"));
+        span.record("outcome", field::debug(decision.outcome));
+        hooks::post_test(config, rustc_repo_path, &target, &decision, duration)
+            .instrument(span)
+            .await;
+        let _ = tx.send(WorkerEvent::Finished(target, decision, duration));
+    }
+    Ok(())
+}
+
+/// Group tests which may share snapshot files: those in the same directory whose file names agree
+/// up to the first `.`.
+fn edit_groups(targets: &BTreeSet<PathBuf>) -> Vec<Vec<PathBuf>> {
+    let mut groups: BTreeMap<(Option<&Path>, String), Vec<PathBuf>> = BTreeMap::new();
+    for target in targets {
+        let file_name = target
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let base = file_name.split('.').next().unwrap_or_default().to_string();
+        groups
+            .entry((target.parent(), base))
+            .or_default()
+            .push(target.clone());
+    }
+    groups.into_values().collect()
+}
+
+/// Order `groups` by decreasing estimated duration. Tests without a previous duration are assumed
+/// to take the average.
+fn schedule_longest_first(
+    groups: &mut [Vec<PathBuf>],
+    rustc_repo_path: &Path,
+    estimates: &BTreeMap<String, Duration>,
+) {
+    let known: Vec<Duration> = groups
+        .iter()
+        .flatten()
+        .filter_map(|t| estimates.get(&paths::repo_relative(rustc_repo_path, t)))
+        .copied()
+        .collect();
+    if known.is_empty() {
+        return;
+    }
+    let average = known.iter().sum::<Duration>() / known.len() as u32;
+    groups.sort_by_cached_key(|group| {
+        let estimate: Duration = group
+            .iter()
+            .map(|t| {
+                estimates
+                    .get(&paths::repo_relative(rustc_repo_path, t))
+                    .copied()
+                    .unwrap_or(average)
+            })
+            .sum();
+        std::cmp::Reverse(estimate)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_tests_which_may_share_snapshots() {
+        let targets: BTreeSet<PathBuf> = ["a/foo.rs", "a/foo.bar.rs", "a/baz.rs", "b/foo.rs"]
+            .map(PathBuf::from)
+            .into();
+        assert_eq!(
+            edit_groups(&targets),
+            [
+                vec![PathBuf::from("a/baz.rs")],
+                vec![PathBuf::from("a/foo.bar.rs"), PathBuf::from("a/foo.rs")],
+                vec![PathBuf::from("b/foo.rs")],
+            ]
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! Verifying every change against other checkouts of rust-lang/rust as well (e.g. other branches
+//! or build configs), see `worktrees` in the config.
+//!
+//! Once an edit passes in the main checkout, the edited test is written to each worktree, run
+//! there and restored, so the worktrees are left as they were. The edit is only kept if it fails
+//! in none of them.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use miette::{Context, IntoDiagnostic};
+use serde::{Deserialize, Serialize};
+
+use super::runner::TestRunner;
+use super::snapshots::Snapshots;
+use super::{paths, write_guard, RunError, RunOutcome};
+use crate::config::Config;
+
+/// How an edited test fared in one of the worktrees.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WorktreeResult {
+    Passed,
+    Failed,
+    /// The test is ignored there, e.g. on that branch's targets.
+    Ignored,
+    /// The test does not exist in the worktree.
+    Missing,
+}
+
+/// Results by worktree, keyed by the path given in the config.
+pub(super) type WorktreeResults = BTreeMap<String, WorktreeResult>;
+
+/// The configured worktrees, relative paths being relative to the main checkout.
+pub(super) fn resolve(config: &Config, rustc_repo_path: &Path) -> Vec<(String, PathBuf)> {
+    config
+        .worktrees
+        .iter()
+        .map(|worktree| {
+            (
+                worktree.display().to_string(),
+                rustc_repo_path.join(worktree),
+            )
+        })
+        .collect()
+}
+
+/// Run `target` of the main checkout, edited to `src`, in every worktree.
+pub(super) async fn check(
+    config: &Config,
+    rustc_repo_path: &Path,
+    runner: &dyn TestRunner,
+    target: &Path,
+    src: &str,
+) -> Result<WorktreeResults, RunError> {
+    let rel_path = paths::repo_relative(rustc_repo_path, target);
+    let mut results = WorktreeResults::new();
+    for (name, worktree) in resolve(config, rustc_repo_path) {
+        let result = check_one(config, &worktree, runner, &worktree.join(&rel_path), src)
+            .await
+            .map_err(|e| match e {
+                RunError::Other(e) => {
+                    RunError::Other(e.wrap_err(format!("failed to check `{rel_path}` in `{name}`")))
+                }
+                e => e,
+            })?;
+        results.insert(name, result);
+    }
+    Ok(results)
+}
+
+async fn check_one(
+    config: &Config,
+    worktree: &Path,
+    runner: &dyn TestRunner,
+    target: &Path,
+    src: &str,
+) -> Result<WorktreeResult, RunError> {
+    if !target.exists() {
+        return Ok(WorktreeResult::Missing);
+    }
+    let original = std::fs::read(target)
+        .into_diagnostic()
+        .wrap_err(format!("failed to read `{}`", target.display()))
+        .map_err(RunError::Other)?;
+    let snapshots = Snapshots::capture(target)
+        .into_diagnostic()
+        .wrap_err(format!(
+            "failed to read snapshots of `{}`",
+            target.display()
+        ))
+        .map_err(RunError::Other)?;
+    write_guard::write(target, src)
+        .into_diagnostic()
+        .wrap_err(format!("failed to write `{}`", target.display()))
+        .map_err(RunError::Other)?;
+
+    let what = format!("the edited test in `{}`", worktree.display());
+    let outcome = super::check(config, worktree, runner, target, &what).await;
+
+    write_guard::restore(target, &original)
+        .into_diagnostic()
+        .and_then(|()| snapshots.restore().into_diagnostic())
+        .wrap_err(format!("failed to restore `{}`", target.display()))
+        .map_err(RunError::Other)?;
+    match outcome {
+        Ok(RunOutcome::Ignored) => Ok(WorktreeResult::Ignored),
+        Ok(_) => Ok(WorktreeResult::Passed),
+        Err(RunError::TestFailure) => Ok(WorktreeResult::Failed),
+        Err(e) => Err(e),
+    }
+}
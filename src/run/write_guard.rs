@@ -0,0 +1,121 @@
+//! Guard against rewriting files outside of the target directories, e.g. through a symlink in the
+//! test tree or a `target_directories` entry pointing elsewhere by mistake.
+//!
+//! Every path is canonicalized before being written to, and must lie under one of the target
+//! directories of the `rustc` repo, or of one of its `worktrees`, themselves canonicalized once
+//! per run and required to lie within their checkout.
+//!
+//! Files put back the way they were are read back and checksummed, see [`restore`].
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use miette::{bail, Context, IntoDiagnostic, Result};
+
+use super::manifest::sha256_hex;
+use super::{platform, worktrees};
+use crate::config::Config;
+
+/// The canonicalized target directories of the current run, `None` outside of runs.
+static ALLOWED: Mutex<Option<Vec<PathBuf>>> = Mutex::new(None);
+
+/// Resolve the target directories of the `rustc` repo and of its `worktrees`, refusing any which
+/// resolves to somewhere outside of its checkout. Target directories which don't exist are left
+/// out, as nothing is written there.
+pub(super) fn configure(config: &Config, rustc_repo_path: &Path) -> Result<()> {
+    let checkouts = std::iter::once(rustc_repo_path.to_path_buf()).chain(
+        worktrees::resolve(config, rustc_repo_path)
+            .into_iter()
+            .map(|(_, worktree)| worktree),
+    );
+    let mut allowed = Vec::new();
+    for checkout in checkouts {
+        let root = checkout
+            .canonicalize()
+            .into_diagnostic()
+            .wrap_err(format!("failed to resolve `{}`", checkout.display()))?;
+        for dir in &config.target_directories {
+            let Ok(resolved) = checkout.join(dir).canonicalize() else {
+                continue;
+            };
+            if !resolved.starts_with(&root) {
+                bail!(
+                    help = "target directories must be relative paths within the `rustc` repo, \
+                            check for symlinks and `..`",
+                    "target directory `{}` resolves to `{}`, outside of `{}`",
+                    dir.display(),
+                    resolved.display(),
+                    root.display()
+                );
+            }
+            allowed.push(resolved);
+        }
+    }
+    *ALLOWED.lock().unwrap() = Some(allowed);
+    Ok(())
+}
+
+/// Refuse to write to `path` unless it lies under one of the target directories once
+/// canonicalized. Paths which don't exist yet are resolved through their parent directory.
+pub(super) fn check(path: &Path) -> io::Result<()> {
+    let allowed = ALLOWED.lock().unwrap();
+    let Some(allowed) = allowed.as_ref() else {
+        return Ok(());
+    };
+    let resolved = match path.canonicalize() {
+        Ok(resolved) => resolved,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let (Some(parent), Some(file_name)) = (path.parent(), path.file_name()) else {
+                return Err(e);
+            };
+            parent.canonicalize()?.join(file_name)
+        }
+        Err(e) => return Err(e),
+    };
+    if allowed.iter().any(|dir| resolved.starts_with(dir)) {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!(
+            "refusing to write to `{}`, which resolves to `{}` outside of the target directories",
+            path.display(),
+            resolved.display()
+        ),
+    ))
+}
+
+/// [`std::fs::write`], after [`check`]ing `path`.
+pub(super) fn write(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    check(path)?;
+    let contents = contents.as_ref();
+    platform::retry_if_locked(|| std::fs::write(path, contents))
+}
+
+/// [`write`] the original `contents` of `path` back, and check that the file now has the same
+/// SHA-256 as them. A mismatch fails the run rather than leaving a test the tool decided not to
+/// change silently corrupted.
+pub(super) fn restore(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let contents = contents.as_ref();
+    write(path, contents)?;
+    let expected = sha256_hex(contents);
+    let actual = sha256_hex(&std::fs::read(path)?);
+    if actual != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "`{}` does not match its original contents once restored (SHA-256 `{actual}`, \
+                 expected `{expected}`)",
+                path.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// [`std::fs::remove_file`], after [`check`]ing `path`.
+pub(super) fn remove_file(path: &Path) -> io::Result<()> {
+    check(path)?;
+    platform::retry_if_locked(|| std::fs::remove_file(path))
+}
@@ -0,0 +1,137 @@
+//! Posting the report of each run to a Zulip stream, where `rustc` contributors coordinate, see
+//! `zulip_site` and friends in the config. The bot's API key is read from `ZULIP_API_KEY`.
+
+use std::time::Duration;
+
+use base64::Engine as _;
+use miette::{bail, Context, IntoDiagnostic, Result};
+
+use super::state::RunState;
+use crate::config::Config;
+use crate::exit_status::ExitStatus;
+use crate::history::RunDir;
+
+/// Zulip rejects messages longer than this many characters.
+const MAX_MESSAGE_LEN: usize = 10_000;
+
+/// Post the report of the run in `run_dir` to the configured stream and topic, split into as
+/// many messages as needed. Does nothing unless Zulip is configured.
+pub(super) fn post_report(
+    config: &Config,
+    run_dir: &RunDir,
+    result: &Result<ExitStatus>,
+) -> Result<()> {
+    let (Some(site), Some(stream), Some(email)) = (
+        &config.zulip_site,
+        &config.zulip_stream,
+        &config.zulip_bot_email,
+    ) else {
+        return Ok(());
+    };
+    let Ok(api_key) = std::env::var("ZULIP_API_KEY") else {
+        bail!("`ZULIP_API_KEY` is not set");
+    };
+
+    let content = match result {
+        Ok(_) => std::fs::read_to_string(run_dir.report_path())
+            .into_diagnostic()
+            .wrap_err("failed to read the report")?,
+        Err(e) => {
+            let mut content = format!("Run `{}` failed: {e}", run_dir.id);
+            if let Ok(state) = RunState::load(&run_dir.state_path()) {
+                content.push_str(&format!(" ({} test(s) done)", state.records.len()));
+            }
+            content
+        }
+    };
+
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(Duration::from_secs(30)))
+        .build()
+        .into();
+    let url = format!("{}/api/v1/messages", site.trim_end_matches('/'));
+    let credentials =
+        base64::engine::general_purpose::STANDARD.encode(format!("{email}:{api_key}"));
+    for message in split_message(&content, MAX_MESSAGE_LEN) {
+        agent
+            .post(&url)
+            .header("Authorization", &format!("Basic {credentials}"))
+            .send_form([
+                ("type", "stream"),
+                ("to", stream.as_str()),
+                ("topic", config.zulip_topic.as_str()),
+                ("content", message.as_str()),
+            ])
+            .into_diagnostic()
+            .wrap_err(format!("failed to post to `{url}`"))?;
+    }
+    Ok(())
+}
+
+/// Split `content` at line boundaries into messages of at most `max_len` characters. Code
+/// blocks cut in two are closed at the end of one message and reopened in the next.
+fn split_message(content: &str, max_len: usize) -> Vec<String> {
+    const FENCE: &str = "```";
+    // Room for closing and reopening a code block around the content of each message.
+    let budget = max_len.saturating_sub(2 * (FENCE.len() + 1)).max(1);
+
+    let mut messages = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+    // Length of the reopened code block at the start of `current`.
+    let mut reopened_len = 0;
+    // The line opening the code block we are in, if any.
+    let mut open_fence: Option<&str> = None;
+    for full_line in content.split_inclusive('\n') {
+        let is_fence = full_line.trim_start().starts_with(FENCE);
+        // A closing fence can use the room reserved for closing the code block.
+        let limit = match open_fence {
+            Some(_) if is_fence => budget + FENCE.len() + 1,
+            _ => budget,
+        };
+        let mut line = full_line;
+        loop {
+            let line_len = line.chars().count();
+            if current_len + line_len <= limit {
+                current.push_str(line);
+                current_len += line_len;
+                break;
+            }
+            if current_len == reopened_len {
+                // A single line longer than a whole message, cut it.
+                let cut = line
+                    .char_indices()
+                    .nth(budget.saturating_sub(reopened_len).max(1))
+                    .map_or(line.len(), |(idx, _)| idx);
+                current.push_str(&line[..cut]);
+                line = &line[cut..];
+            }
+            if open_fence.is_some() {
+                if !current.ends_with('\n') {
+                    current.push('\n');
+                }
+                current.push_str(FENCE);
+            }
+            messages.push(std::mem::take(&mut current));
+            current_len = 0;
+            if let Some(fence) = open_fence {
+                current.push_str(fence);
+                current_len = fence.chars().count();
+            }
+            reopened_len = current_len;
+            if line.is_empty() {
+                break;
+            }
+        }
+        if is_fence {
+            open_fence = match open_fence {
+                Some(_) => None,
+                None => Some(full_line),
+            };
+        }
+    }
+    if !current.trim().is_empty() {
+        messages.push(current);
+    }
+    messages
+}
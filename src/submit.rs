@@ -0,0 +1,117 @@
+//! The `submit` command: pushing the migration branch and opening a draft PR for it, using the
+//! commit message and PR description drafted by a run.
+
+use std::path::Path;
+
+use miette::{bail, Context, IntoDiagnostic, Result};
+use tracing::*;
+
+use crate::git;
+use crate::github::GitHub;
+use crate::history::{self, RunDir};
+
+/// Where and how to open the PR.
+#[derive(Debug)]
+pub struct SubmitOptions {
+    /// Run whose drafts to use, the latest finished one if `None`.
+    pub run: Option<String>,
+    /// Remote the branch is pushed to.
+    pub remote: String,
+    /// `<owner>/<repo>` to open the PR against.
+    pub repo: String,
+    /// Branch the PR is to be merged into.
+    pub base: String,
+}
+
+pub fn submit(rustc_repo_path: &Path, runs_root: &Path, options: &SubmitOptions) -> Result<()> {
+    let run_dir = find_run(runs_root, options.run.as_deref())?;
+    info!("using the drafts of run `{}`", run_dir.id);
+    let report_path = run_dir.report_path();
+    let read = |path: &Path| {
+        std::fs::read_to_string(path)
+            .into_diagnostic()
+            .wrap_err(format!("failed to read `{}`", path.display()))
+    };
+    let commit_message = read(&report_path.with_extension("commit.txt"))?;
+    let title = commit_message.lines().next().unwrap_or_default().trim();
+    let body = read(&report_path.with_extension("pr.md"))?;
+
+    let Some(branch) = git::current_branch(rustc_repo_path) else {
+        bail!(
+            "no branch is checked out in `{}`, check out the migration branch first",
+            rustc_repo_path.display()
+        );
+    };
+    if branch == options.base {
+        bail!(
+            "`{branch}` is the base branch, run with `--branch` or `--commit-branch` to work on a \
+             dedicated branch"
+        );
+    }
+    if git::has_uncommitted_changes(rustc_repo_path)? {
+        bail!(
+            "`{}` has uncommitted changes, commit them to `{branch}` first (or run with \
+             `--commit-branch`)",
+            rustc_repo_path.display()
+        );
+    }
+
+    info!("pushing `{branch}` to `{}`", options.remote);
+    git::push(rustc_repo_path, &options.remote, &branch)?;
+
+    // Pushing to a fork needs `<fork owner>:<branch>` as the head of the PR.
+    let head = match git::remote_url(rustc_repo_path, &options.remote)
+        .as_deref()
+        .and_then(github_owner)
+    {
+        Some(owner) => format!("{owner}:{branch}"),
+        None => branch.clone(),
+    };
+
+    let github = GitHub::new()?;
+    let request = serde_json::json!({
+        "title": title,
+        "head": head,
+        "base": options.base,
+        "body": body,
+        "draft": true,
+    });
+    let response = github.request(
+        "POST",
+        &format!("repos/{}/pulls", options.repo),
+        Some(&request),
+    )?;
+    let Some(url) = response["html_url"].as_str() else {
+        bail!("no `html_url` in the response of the GitHub API: {response}");
+    };
+    info!("opened draft PR {url}");
+    println!("{url}");
+    Ok(())
+}
+
+/// The run with id `id`, or the latest one which finished.
+fn find_run(runs_root: &Path, id: Option<&str>) -> Result<RunDir> {
+    let runs = history::list_runs(runs_root)?;
+    let found = match id {
+        Some(id) => runs.into_iter().find(|(run_dir, _)| run_dir.id == id),
+        None => runs
+            .into_iter()
+            .rev()
+            .find(|(_, state)| state.as_ref().is_some_and(|s| s.finished_at.is_some())),
+    };
+    match (found, id) {
+        (Some((run_dir, _)), _) => Ok(run_dir),
+        (None, Some(id)) => bail!("no run `{id}` under `{}`", runs_root.display()),
+        (None, None) => bail!("no finished run under `{}`", runs_root.display()),
+    }
+}
+
+/// The owner of a GitHub repo from its `https://github.com/<owner>/<repo>` or
+/// `git@github.com:<owner>/<repo>` remote URL.
+fn github_owner(url: &str) -> Option<&str> {
+    let path = url
+        .strip_prefix("https://github.com/")
+        .or_else(|| url.strip_prefix("git@github.com:"))
+        .or_else(|| url.strip_prefix("ssh://git@github.com/"))?;
+    path.split('/').next().filter(|owner| !owner.is_empty())
+}
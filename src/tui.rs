@@ -0,0 +1,379 @@
+//! `run --tui`: a live table of the tests in flight and completed, the output of `x` for the
+//! selected test, and keybindings to skip a test or abort the run.
+//!
+//! The run goes on in a background thread, reporting to the UI thread through a channel: events
+//! of the [`Engine`], output lines of `x` and log lines (see [`forward_log`]), which are printed
+//! to stderr once the terminal is restored.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use miette::{miette, IntoDiagnostic};
+use ratatui::crossterm::event::{self, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+
+use rustc_less_ignore_debug::exit_status::ExitStatus;
+use rustc_less_ignore_debug::history::RunDir;
+use rustc_less_ignore_debug::run::control::Controls;
+use rustc_less_ignore_debug::run::events::Event;
+use rustc_less_ignore_debug::{Engine, Outcome};
+
+/// Lines of output kept for each test.
+const OUTPUT_LINES: usize = 500;
+
+/// Log lines kept on screen. Every log line is printed once the TUI exits.
+const LOG_LINES: usize = 100;
+
+/// Where log lines go while the TUI is shown, see [`forward_log`].
+static LOG: Mutex<Option<Sender<Update>>> = Mutex::new(None);
+
+/// Send `line` to the TUI if it is shown, returning `false` otherwise.
+pub(crate) fn forward_log(line: &str) -> bool {
+    match LOG.lock().unwrap().as_ref() {
+        Some(tx) => tx.send(Update::Log(line.to_string())).is_ok(),
+        None => false,
+    }
+}
+
+enum Update {
+    Event(Event),
+    Output(String, String),
+    Log(String),
+    Finished(miette::Result<ExitStatus>),
+}
+
+/// Run `engine` in the background, showing its progress until the user quits.
+pub(crate) fn run(
+    mut engine: Engine,
+    run_dir: RunDir,
+    runtime: tokio::runtime::Runtime,
+) -> miette::Result<ExitStatus> {
+    let (tx, rx) = mpsc::channel();
+    let events = tx.clone();
+    engine.on_event(move |event| drop(events.send(Update::Event(event.clone()))));
+    let output = tx.clone();
+    engine.on_output(move |test, line| {
+        drop(output.send(Update::Output(test.to_string(), line.to_string())))
+    });
+    let controls = engine.controls();
+    *LOG.lock().unwrap() = Some(tx.clone());
+
+    let worker = std::thread::spawn(move || {
+        let result = runtime.block_on(engine.run(&run_dir));
+        drop(tx.send(Update::Finished(result)));
+    });
+
+    let mut app = App::new(controls);
+    let mut terminal = ratatui::init();
+    let shown = app.show(&mut terminal, &rx);
+    ratatui::restore();
+
+    *LOG.lock().unwrap() = None;
+    // Whatever was still in flight when the user quit.
+    app.drain(&rx);
+    for line in &app.all_logs {
+        eprint!("{line}");
+    }
+    shown?;
+    worker.join().map_err(|_| miette!("the run panicked"))?;
+    app.finished
+        .take()
+        .unwrap_or_else(|| Err(miette!("the run did not finish")))
+}
+
+enum Status {
+    Running(Instant),
+    Skipping(Instant),
+    Done(Outcome, Duration),
+}
+
+struct Test {
+    path: String,
+    status: Status,
+    output: VecDeque<String>,
+}
+
+struct App {
+    controls: Controls,
+    /// In the order they were started (or decided, for tests which were not run).
+    tests: Vec<Test>,
+    index: BTreeMap<String, usize>,
+    table: TableState,
+    /// Keep the most recently started test selected.
+    follow: bool,
+    logs: VecDeque<String>,
+    all_logs: Vec<String>,
+    aborting: bool,
+    finished: Option<miette::Result<ExitStatus>>,
+}
+
+impl App {
+    fn new(controls: Controls) -> Self {
+        App {
+            controls,
+            tests: Vec::new(),
+            index: BTreeMap::new(),
+            table: TableState::default(),
+            follow: true,
+            logs: VecDeque::new(),
+            all_logs: Vec::new(),
+            aborting: false,
+            finished: None,
+        }
+    }
+
+    /// Draw and handle keys until the user quits after the run finished.
+    fn show(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        rx: &Receiver<Update>,
+    ) -> miette::Result<()> {
+        loop {
+            self.drain(rx);
+            terminal.draw(|frame| self.draw(frame)).into_diagnostic()?;
+            if !event::poll(Duration::from_millis(100)).into_diagnostic()? {
+                continue;
+            }
+            if let event::Event::Key(key) = event::read().into_diagnostic()? {
+                if key.kind == KeyEventKind::Press && self.on_key(key) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn drain(&mut self, rx: &Receiver<Update>) {
+        while let Ok(update) = rx.try_recv() {
+            match update {
+                Update::Event(event) => self.on_event(event),
+                Update::Output(path, line) => {
+                    let test = self.test(&path);
+                    if test.output.len() == OUTPUT_LINES {
+                        test.output.pop_front();
+                    }
+                    test.output.push_back(strip_ansi(&line));
+                }
+                Update::Log(line) => {
+                    if self.logs.len() == LOG_LINES {
+                        self.logs.pop_front();
+                    }
+                    self.logs.push_back(strip_ansi(line.trim_end()));
+                    self.all_logs.push(line);
+                }
+                Update::Finished(result) => self.finished = Some(result),
+            }
+        }
+        if self.follow && !self.tests.is_empty() {
+            self.table.select(Some(self.tests.len() - 1));
+        }
+    }
+
+    fn on_event(&mut self, event: Event) {
+        match event {
+            Event::TestStarted { path } => {
+                self.test(&path).status = Status::Running(Instant::now());
+            }
+            Event::OutcomeDecided {
+                path,
+                outcome,
+                duration_secs,
+                ..
+            } => {
+                self.test(&path).status =
+                    Status::Done(outcome, Duration::from_secs_f64(duration_secs));
+            }
+            _ => {}
+        }
+    }
+
+    /// The row of the test at `path`, added if new.
+    fn test(&mut self, path: &str) -> &mut Test {
+        let idx = *self.index.entry(path.to_string()).or_insert_with(|| {
+            self.tests.push(Test {
+                path: path.to_string(),
+                status: Status::Running(Instant::now()),
+                output: VecDeque::new(),
+            });
+            self.tests.len() - 1
+        });
+        &mut self.tests[idx]
+    }
+
+    /// Handle `key`, returning whether to quit.
+    fn on_key(&mut self, key: KeyEvent) -> bool {
+        let ctrl_c =
+            key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c');
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.select(|idx| idx.saturating_sub(1)),
+            KeyCode::Down | KeyCode::Char('j') => self.select(|idx| idx + 1),
+            KeyCode::Home | KeyCode::Char('g') => self.select(|_| 0),
+            KeyCode::End | KeyCode::Char('G') => {
+                self.follow = true;
+            }
+            KeyCode::Char('s') => {
+                let selected = self
+                    .table
+                    .selected()
+                    .and_then(|idx| self.tests.get_mut(idx));
+                if let Some(test) = selected {
+                    if let Status::Running(started) = test.status {
+                        self.controls.skip(&test.path);
+                        test.status = Status::Skipping(started);
+                    }
+                }
+            }
+            KeyCode::Char('a') => self.abort(),
+            KeyCode::Char('q') | KeyCode::Esc if self.finished.is_some() => return true,
+            KeyCode::Char('q') | KeyCode::Esc => self.abort(),
+            _ if ctrl_c => self.abort(),
+            _ => {}
+        }
+        false
+    }
+
+    fn select(&mut self, f: impl FnOnce(usize) -> usize) {
+        if self.tests.is_empty() {
+            return;
+        }
+        let idx = f(self.table.selected().unwrap_or(0)).min(self.tests.len() - 1);
+        self.table.select(Some(idx));
+        self.follow = false;
+    }
+
+    fn abort(&mut self) {
+        if !self.aborting && self.finished.is_none() {
+            self.aborting = true;
+            self.controls.abort();
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame<'_>) {
+        let [tests, output, logs, help] = Layout::vertical([
+            Constraint::Percentage(40),
+            Constraint::Percentage(40),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        self.draw_tests(frame, tests);
+        self.draw_output(frame, output);
+        let logs_height = logs.height.saturating_sub(2) as usize;
+        let lines: Vec<Line<'_>> = self
+            .logs
+            .iter()
+            .skip(self.logs.len().saturating_sub(logs_height))
+            .map(|line| Line::raw(line.as_str()))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("Log")),
+            logs,
+        );
+        frame.render_widget(Paragraph::new(self.help()), help);
+    }
+
+    fn draw_tests(&mut self, frame: &mut Frame<'_>, area: Rect) {
+        let done = self
+            .tests
+            .iter()
+            .filter(|t| matches!(t.status, Status::Done(..)))
+            .count();
+        let rows = self.tests.iter().map(|test| {
+            let (status, duration, color) = match &test.status {
+                Status::Running(started) => ("running".to_string(), started.elapsed(), Color::Cyan),
+                Status::Skipping(started) => {
+                    ("skipping".to_string(), started.elapsed(), Color::DarkGray)
+                }
+                Status::Done(outcome, duration) => {
+                    let color = match outcome {
+                        Outcome::RemoveOk | Outcome::ReplaceOk => Color::Green,
+                        Outcome::NeedsManualReview => Color::Magenta,
+                        _ => Color::Yellow,
+                    };
+                    (format!("{outcome:?}"), *duration, color)
+                }
+            };
+            Row::new([
+                test.path.clone(),
+                status,
+                format!("{:.1}s", duration.as_secs_f64()),
+            ])
+            .style(Style::default().fg(color))
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(1),
+                Constraint::Length(18),
+                Constraint::Length(9),
+            ],
+        )
+        .header(
+            Row::new(["Test", "Status", "Time"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("Tests ({done}/{} done)", self.tests.len())),
+        );
+        frame.render_stateful_widget(table, area, &mut self.table);
+    }
+
+    fn draw_output(&self, frame: &mut Frame<'_>, area: Rect) {
+        let selected = self.table.selected().and_then(|idx| self.tests.get(idx));
+        let height = area.height.saturating_sub(2) as usize;
+        let (title, lines): (String, Vec<Line<'_>>) = match selected {
+            Some(test) => (
+                format!("Output of `x` for `{}`", test.path),
+                test.output
+                    .iter()
+                    .skip(test.output.len().saturating_sub(height))
+                    .map(|line| Line::raw(line.as_str()))
+                    .collect(),
+            ),
+            None => ("Output of `x`".to_string(), Vec::new()),
+        };
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)),
+            area,
+        );
+    }
+
+    fn help(&self) -> String {
+        match &self.finished {
+            Some(Ok(status)) => format!("Finished: {status:?}. q: quit, ↑/↓: select"),
+            Some(Err(_)) => "The run failed, see the log. q: quit, ↑/↓: select".to_string(),
+            None if self.aborting => "Aborting, restoring the tests in flight...".to_string(),
+            None => "↑/↓: select, End: follow, s: skip the selected test, a/q: abort the run"
+                .to_string(),
+        }
+    }
+}
+
+/// `line` without ANSI escape sequences, which would garble the layout.
+fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        // CSI sequences end with a letter.
+        if chars.next() == Some('[') {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        }
+    }
+    out
+}
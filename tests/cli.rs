@@ -0,0 +1,452 @@
+//! End-to-end tests driving the CLI (and the [`Engine`] behind it) against a miniature `rustc`
+//! repo, built from `tests/fixtures/ui`, with `tests/fixtures/x` standing in for bootstrap.
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::{Arc, Mutex};
+
+use confique::Config as _;
+use futures_util::FutureExt as _;
+use rustc_less_ignore_debug::config::Config;
+use rustc_less_ignore_debug::exit_status::ExitStatus;
+use rustc_less_ignore_debug::run::events::Event;
+use rustc_less_ignore_debug::Engine;
+use serde_json::Value;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+/// A temporary directory holding a copy of the tool (which reads its config and writes its runs
+/// next to itself), the fake `x`, and the fake `rustc` repo. Removed on drop.
+struct Fixture {
+    root: PathBuf,
+}
+
+impl Fixture {
+    fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!(
+            "rustc-less-ignore-debug-cli-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let bin = root.join("bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        let x = bin.join("x");
+        std::fs::copy(Path::new(FIXTURES).join("x"), &x).unwrap();
+        std::fs::set_permissions(&x, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::copy(
+            env!("CARGO_BIN_EXE_rustc-less-ignore-debug"),
+            root.join("rustc-less-ignore-debug"),
+        )
+        .unwrap();
+
+        let fixture = Fixture { root };
+        fixture.reset_repo();
+        fixture
+    }
+
+    /// (Re)create the fake `rustc` repo from `tests/fixtures/ui`.
+    fn reset_repo(&self) {
+        let repo = self.root.join("repo");
+        let _ = std::fs::remove_dir_all(&repo);
+        std::fs::create_dir_all(repo.join("src/bootstrap")).unwrap();
+        std::fs::create_dir_all(repo.join("tests/ui")).unwrap();
+        std::fs::write(repo.join("x.py"), "").unwrap();
+        for entry in std::fs::read_dir(Path::new(FIXTURES).join("ui")).unwrap() {
+            let entry = entry.unwrap();
+            std::fs::copy(entry.path(), repo.join("tests/ui").join(entry.file_name())).unwrap();
+        }
+    }
+
+    fn with_config(self, config: &str) -> Self {
+        std::fs::write(self.root.join("config.toml"), config).unwrap();
+        self
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        let path = std::env::join_paths(
+            std::iter::once(self.root.join("bin"))
+                .chain(std::env::split_paths(&std::env::var_os("PATH").unwrap())),
+        )
+        .unwrap();
+        Command::new(self.root.join("rustc-less-ignore-debug"))
+            .args(args)
+            .current_dir(&self.root)
+            .env("PATH", path)
+            .output()
+            .unwrap()
+    }
+
+    fn test(&self, name: &str) -> String {
+        std::fs::read_to_string(self.root.join("repo/tests/ui").join(name)).unwrap()
+    }
+
+    /// The directory of the only run so far.
+    fn run_dir(&self) -> PathBuf {
+        let mut runs: Vec<PathBuf> = std::fs::read_dir(self.root.join("runs"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(runs.len(), 1, "{runs:?}");
+        runs.pop().unwrap()
+    }
+
+    fn state(&self) -> Value {
+        serde_json::from_str(&std::fs::read_to_string(self.run_dir().join("state.json")).unwrap())
+            .unwrap()
+    }
+
+    fn report(&self) -> String {
+        std::fs::read_to_string(self.run_dir().join("report.md")).unwrap()
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+const CONFIG: &str = "target_directories = [\"tests/ui\"]\n";
+
+fn outcome(state: &Value, name: &str) -> String {
+    state["records"][format!("tests/ui/{name}")]["outcome"]
+        .as_str()
+        .unwrap_or_else(|| panic!("no record for `{name}` in {state:#}"))
+        .to_string()
+}
+
+fn assert_decisions(fixture: &Fixture, output: &Output) {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // `conflicting.rs` is left to a human.
+    assert_eq!(output.status.code(), Some(1), "{stderr}");
+
+    assert_eq!(
+        fixture.test("removable.rs"),
+        "//@ run-pass\n\nfn main() {}\n"
+    );
+    assert_eq!(
+        fixture.test("replaceable.rs"),
+        "// compile-flags: -Cdebug-assertions=no\n// FAIL_REMOVE\n\nfn main() {}\n"
+    );
+    assert_eq!(
+        fixture.test("broken.rs"),
+        "//@ ignore-debug\n// FAIL_ALL\n\nfn main() {}\n"
+    );
+    assert_eq!(
+        fixture.test("no_directive.rs"),
+        "//@ run-pass\n\nfn main() {}\n"
+    );
+    assert_eq!(fixture.test("blessed.rs"), "// BLESS\n\nfn main() {}\n");
+    assert_eq!(fixture.test("blessed.stderr"), "blessed\n");
+    assert!(fixture
+        .test("conflicting.rs")
+        .starts_with("//@ ignore-debug\n"));
+
+    let state = fixture.state();
+    assert_eq!(outcome(&state, "removable.rs"), "RemoveOk");
+    assert_eq!(outcome(&state, "replaceable.rs"), "ReplaceOk");
+    assert_eq!(outcome(&state, "broken.rs"), "UnmodifiedOk");
+    assert_eq!(outcome(&state, "blessed.rs"), "RemoveOk");
+    assert_eq!(outcome(&state, "conflicting.rs"), "NeedsManualReview");
+    assert_eq!(state["skipped"]["tests/ui/no_directive.rs"], "NoDirective");
+    assert_eq!(state["status"], "NeedsManualReview");
+}
+
+#[test]
+fn run_decides_each_test() {
+    let fixture = Fixture::new("run").with_config(CONFIG);
+    let output = fixture.run(&["run", "repo", "--no-cache"]);
+    assert_decisions(&fixture, &output);
+
+    let report = fixture.report();
+    assert!(report.contains("- RemoveOk: 2\n"), "{report}");
+    assert!(report.contains("- ReplaceOk: 1\n"), "{report}");
+    assert!(report.contains("- NeedsManualReview: 1\n"), "{report}");
+    assert!(report.contains("- UnmodifiedOk: 1\n"), "{report}");
+    assert!(report.contains("Reason given: slow"), "{report}");
+    assert!(
+        report.contains("- `tests/ui/conflicting.rs`: the test already sets debug assertions"),
+        "{report}"
+    );
+
+    // Without a report path, the report also lands in the current directory.
+    let summaries: Vec<String> = std::fs::read_dir(&fixture.root)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| {
+            name.starts_with("run_summary-")
+                && name
+                    .strip_suffix(".md")
+                    .is_some_and(|stem| !stem.contains('.'))
+        })
+        .collect();
+    assert_eq!(summaries.len(), 1, "{summaries:?}");
+    assert_eq!(
+        std::fs::read_to_string(fixture.root.join(&summaries[0])).unwrap(),
+        report
+    );
+}
+
+#[test]
+fn batch_runs_reach_the_same_decisions() {
+    let fixture = Fixture::new("batch").with_config(CONFIG);
+    let output = fixture.run(&["run", "repo", "--no-cache", "--batch"]);
+    assert_decisions(&fixture, &output);
+}
+
+#[test]
+fn auto_discovery_finds_the_suites_with_candidates() {
+    let fixture = Fixture::new("discover").with_config("");
+    let output = fixture.run(&["run", "repo", "--no-cache", "--auto-discover"]);
+    assert_decisions(&fixture, &output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("tests/ui: 5 candidate(s)"));
+}
+
+#[test]
+fn verify_reruns_the_changed_tests() {
+    let fixture = Fixture::new("verify").with_config(CONFIG);
+    let output = fixture.run(&["run", "repo", "--no-cache"]);
+    assert_decisions(&fixture, &output);
+
+    let output = fixture.run(&["verify", "repo"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(0), "{stdout}");
+    assert!(stdout.contains("3 of 3 test(s) changed by run"), "{stdout}");
+
+    std::fs::write(
+        fixture.root.join("repo/tests/ui/removable.rs"),
+        "//@ run-pass\n// FAIL_ALL\n\nfn main() {}\n",
+    )
+    .unwrap();
+    let output = fixture.run(&["verify", "repo"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{stdout}");
+    assert!(
+        stdout.contains("FAILED   tests/ui/removable.rs"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("note: `tests/ui/removable.rs` differs from what run"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn direct_compiletest_runs_reach_the_same_decisions() {
+    let fixture = Fixture::new("direct");
+    let compiletest = fixture.root.join("bin/compiletest");
+    std::fs::copy(Path::new(FIXTURES).join("compiletest"), &compiletest).unwrap();
+    std::fs::set_permissions(&compiletest, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let sysroot = fixture.root.join("stage1");
+    std::fs::create_dir_all(sysroot.join("bin")).unwrap();
+    std::fs::write(sysroot.join("bin/rustc"), "").unwrap();
+    let fixture = fixture.with_config(&format!(
+        "{CONFIG}compiletest_path = {:?}\nstage1_sysroot = {:?}\n",
+        compiletest, sysroot
+    ));
+
+    let output = fixture.run(&["run", "repo", "--no-cache", "--direct-compiletest"]);
+    assert_decisions(&fixture, &output);
+    let args = std::fs::read_to_string(fixture.root.join("bin/compiletest.args")).unwrap();
+    assert!(args.contains("--mode\nui\n"), "{args}");
+    assert!(
+        args.contains(&format!(
+            "--rustc-path\n{}\n",
+            sysroot.join("bin/rustc").display()
+        )),
+        "{args}"
+    );
+}
+
+/// Options of the runs of an [`Engine`] over `fixture` with compiletest run directly.
+fn engine_options(fixture: &Fixture) -> rustc_less_ignore_debug::RunOptions {
+    rustc_less_ignore_debug::RunOptions {
+        runs_root: fixture.root.join("runs"),
+        report_path: None,
+        github_annotations: false,
+        progress_fd: None,
+        progress_bar: false,
+        events_fd: None,
+        batch: false,
+        jobs: std::num::NonZeroUsize::MIN,
+        job_budget: None,
+        cache_path: None,
+        metrics_path: None,
+        incremental: false,
+        show_output: false,
+        log_output: false,
+        record: None,
+        replay: None,
+        direct_compiletest: true,
+        deadline: None,
+        dedup: false,
+        branch: None,
+        commit_changes: false,
+        allow_detached: true,
+        emit: Default::default(),
+        notify: false,
+        json_report: false,
+        strict: false,
+        timings: false,
+    }
+}
+
+#[tokio::test]
+async fn one_engine_runs_again_after_an_aborted_run() {
+    let fixture = Fixture::new("engine");
+    let compiletest = fixture.root.join("bin/compiletest");
+    std::fs::copy(Path::new(FIXTURES).join("compiletest"), &compiletest).unwrap();
+    std::fs::set_permissions(&compiletest, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let sysroot = fixture.root.join("stage1");
+    std::fs::create_dir_all(sysroot.join("bin")).unwrap();
+    std::fs::write(sysroot.join("bin/rustc"), "").unwrap();
+    // Without `x` on the `PATH` of the test process, nothing may invoke bootstrap.
+    let fixture = fixture.with_config(&format!(
+        "{CONFIG}prebuild = false\ncompiletest_path = {:?}\nstage1_sysroot = {:?}\n",
+        compiletest, sysroot
+    ));
+    let config = Config::from_file(fixture.root.join("config.toml")).unwrap();
+    let mut engine = Engine::new(config, fixture.root.join("repo"), engine_options(&fixture));
+    // Another engine in the same process cannot run meanwhile.
+    let other = Engine::new(
+        Config::from_file(fixture.root.join("config.toml")).unwrap(),
+        fixture.root.join("repo"),
+        engine_options(&fixture),
+    );
+    let other_run_dir = other.preflight().unwrap();
+    let other_result = Arc::new(Mutex::new(None));
+    // Abort the first run as soon as it starts on a test.
+    let abort = std::sync::atomic::AtomicBool::new(true);
+    let controls = engine.controls();
+    engine.on_event({
+        let other_result = other_result.clone();
+        move |event| {
+            if matches!(event, Event::TestStarted { .. })
+                && abort.swap(false, std::sync::atomic::Ordering::SeqCst)
+            {
+                *other_result.lock().unwrap() = other.run(&other_run_dir).now_or_never();
+                controls.abort();
+            }
+        }
+    });
+
+    let run_dir = engine.preflight().unwrap();
+    let status = engine.run(&run_dir).await.unwrap();
+    assert_eq!(status, ExitStatus::Interrupted);
+    let other_result = other_result.lock().unwrap().take();
+    assert!(matches!(other_result, Some(Err(_))), "{other_result:?}");
+    assert!(fixture
+        .test("removable.rs")
+        .starts_with("//@ ignore-debug\n"));
+
+    // Nothing of the first run carries over, the second one runs to completion.
+    let run_dir = engine.preflight().unwrap();
+    let status = engine.run(&run_dir).await.unwrap();
+    assert_eq!(status, ExitStatus::NeedsManualReview);
+    assert_eq!(
+        fixture.test("removable.rs"),
+        "//@ run-pass\n\nfn main() {}\n"
+    );
+}
+
+#[test]
+fn replayed_runs_reach_the_recorded_decisions_without_x() {
+    let fixture = Fixture::new("replay").with_config(CONFIG);
+    let output = fixture.run(&["run", "repo", "--no-cache", "--record", "recording"]);
+    assert_decisions(&fixture, &output);
+
+    fixture.reset_repo();
+    std::fs::remove_dir_all(fixture.root.join("runs")).unwrap();
+    std::fs::remove_file(fixture.root.join("bin/x")).unwrap();
+    let output = fixture.run(&["run", "repo", "--no-cache", "--replay", "recording"]);
+    assert_decisions(&fixture, &output);
+
+    // Invocations which were not recorded fail the run rather than guessing.
+    fixture.reset_repo();
+    std::fs::remove_dir_all(fixture.root.join("runs")).unwrap();
+    std::fs::write(
+        fixture.root.join("repo/tests/ui/removable.rs"),
+        "//@ ignore-debug\n// changed\n",
+    )
+    .unwrap();
+    let output = fixture.run(&["run", "repo", "--no-cache", "--replay", "recording"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("`x test tests/ui/removable.rs` with the tests as they are now"));
+}
+
+#[test]
+fn json_logs_carry_the_fields_of_each_test() {
+    let fixture = Fixture::new("json-logs").with_config(CONFIG);
+    let output = fixture.run(&["run", "repo", "--no-cache", "--log-format", "json"]);
+    assert_decisions(&fixture, &output);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let outcome = stderr
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .find(|line| line["span"]["path"] == "tests/ui/replaceable.rs")
+        .unwrap_or_else(|| panic!("no log line about `replaceable.rs` in {stderr}"));
+    assert_eq!(outcome["span"]["strategy"], "replace");
+    assert!(outcome["span"]["duration_secs"].is_f64(), "{outcome:#}");
+}
+
+#[test]
+fn runs_without_a_config_fail_preflight() {
+    let fixture = Fixture::new("no-config");
+    let output = fixture.run(&["run", "repo", "--no-cache"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(fixture
+        .test("removable.rs")
+        .starts_with("//@ ignore-debug\n"));
+    assert!(!fixture.root.join("runs").exists());
+}
+
+#[test]
+fn runs_with_a_config_which_does_not_parse_fail_preflight() {
+    let fixture = Fixture::new("bad-config").with_config("target_directories = \"tests/ui\n");
+    let output = fixture.run(&["run", "repo", "--no-cache"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(fixture
+        .test("removable.rs")
+        .starts_with("//@ ignore-debug\n"));
+    assert!(!fixture.root.join("runs").exists());
+}
+
+#[test]
+fn progress_and_events_cannot_share_an_fd() {
+    let fixture = Fixture::new("shared-fd").with_config(CONFIG);
+    let output = fixture.run(&[
+        "run",
+        "repo",
+        "--no-cache",
+        "--progress-fd",
+        "5",
+        "--events-fd",
+        "5",
+    ]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("cannot both write to fd 5"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(fixture
+        .test("removable.rs")
+        .starts_with("//@ ignore-debug\n"));
+}
+
+#[test]
+fn generate_config_writes_a_template_once() {
+    let fixture = Fixture::new("generate-config");
+    let output = fixture.run(&["generate-config"]);
+    assert!(output.status.success());
+    let config = std::fs::read_to_string(fixture.root.join("config.toml")).unwrap();
+    assert!(config.contains("target_directories"), "{config}");
+
+    // Refuses to overwrite it.
+    let output = fixture.run(&["generate-config"]);
+    assert!(!output.status.success());
+}
@@ -0,0 +1,4 @@
+//@ ignore-debug
+// BLESS
+
+fn main() {}
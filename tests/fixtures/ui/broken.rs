@@ -0,0 +1,4 @@
+//@ ignore-debug
+// FAIL_ALL
+
+fn main() {}
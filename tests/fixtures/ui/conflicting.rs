@@ -0,0 +1,4 @@
+//@ ignore-debug
+//@ compile-flags: -Cdebug-assertions=yes
+
+fn main() {}
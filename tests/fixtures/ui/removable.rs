@@ -0,0 +1,4 @@
+//@ ignore-debug
+//@ run-pass
+
+fn main() {}
@@ -0,0 +1,4 @@
+// ignore-debug: slow
+// FAIL_REMOVE
+
+fn main() {}